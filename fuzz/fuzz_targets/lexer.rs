@@ -0,0 +1,23 @@
+//! Lexes arbitrary source and checks that tokens of error free source
+//! reproduce it together with skipped trivia.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use metaflow::lexer::{Ctx, SourceEnt};
+
+fuzz_target!(|data: &[u8]| {
+    let content = match std::str::from_utf8(data) {
+        Ok(content) => content.to_string(),
+        Err(_) => return,
+    };
+
+    let mut ctx = Ctx::new();
+    let source = ctx.add_source(SourceEnt::new("fuzz.mf".to_string(), content));
+    let mut tokens = vec![];
+    let mut errors = vec![];
+    ctx.tokenize(source, &mut tokens, &mut errors);
+
+    if errors.is_empty() {
+        ctx.round_trip(source, &tokens).unwrap();
+    }
+});
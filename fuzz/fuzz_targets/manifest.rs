@@ -0,0 +1,26 @@
+//! Parses arbitrary source as a package manifest.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use metaflow::ast::{Collector, Ctx, Data, DataCollector, Parser, Reloc, State};
+use metaflow::lexer::SourceEnt;
+
+fuzz_target!(|data: &[u8]| {
+    let content = match std::str::from_utf8(data) {
+        Ok(content) => content.to_string(),
+        Err(_) => return,
+    };
+
+    let mut ctx = Ctx::default();
+    let source = ctx.add_source(SourceEnt::new("fuzz.mfm".to_string(), content));
+    let mut state = match State::new(source, &ctx) {
+        Ok(state) => state,
+        Err(_) => return,
+    };
+    let mut temp_data = Data::default();
+    let mut saved_data = Data::default();
+    let mut reloc = Reloc::default();
+    let mut collector = Collector::default();
+    let mut data = DataCollector::new(&mut temp_data, &mut saved_data, &mut reloc);
+    let _ = Parser::new(&mut state, &mut data, &mut ctx, &mut collector).parse_manifest();
+});
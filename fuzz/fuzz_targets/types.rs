@@ -0,0 +1,62 @@
+//! Runs arbitrary source through module loading, parsing and the types
+//! stage. Module is written as a root of a throwaway package because
+//! modules are loaded from disk.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use metaflow::ast;
+use metaflow::modules::CACHE_VAR;
+use metaflow::types::Ctx;
+
+fuzz_target!(|data: &[u8]| {
+    let content = match std::str::from_utf8(data) {
+        Ok(content) => content,
+        Err(_) => return,
+    };
+
+    let dir = std::env::temp_dir().join(format!("metaflow-fuzz-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("project.mfm"), "root = \"main.mf\"").unwrap();
+    std::fs::write(dir.join("main.mf"), content).unwrap();
+    std::env::set_var(CACHE_VAR, dir.join("deps"));
+
+    let _ = check(dir.to_str().unwrap());
+});
+
+fn check(path: &str) -> Result<(), ()> {
+    let mut ctx = Ctx::default();
+    let mut item_buffer = vec![];
+    let mut temp_ast_data = ast::Data::default();
+    let mut saved_ast_data = ast::Data::default();
+    let mut collector = ast::Collector::default();
+    let mut reloc = ast::Reloc::default();
+
+    let order = ctx.compute_module_tree(path).map_err(drop)?;
+
+    for &module in &order {
+        ctx.collect_imported_items(module, &mut item_buffer);
+        for &(id, item) in item_buffer.iter() {
+            ctx.import_item(module, id, item).map_err(drop)?;
+        }
+        item_buffer.clear();
+
+        loop {
+            let mut data =
+                ast::DataCollector::new(&mut temp_ast_data, &mut saved_ast_data, &mut reloc);
+            let more = ctx
+                .compute_ast(module, &mut data, &mut collector)
+                .map_err(drop)?;
+            let ast_data = ast::DataSwitch::new(&temp_ast_data, &saved_ast_data);
+            ctx.collect(module, ast_data, &mut collector).map_err(drop)?;
+            ctx.compute_types(&ast_data).map_err(drop)?;
+            if !more {
+                break;
+            }
+        }
+
+        reloc.clear();
+        ctx.clear_after_module();
+    }
+
+    Ok(())
+}
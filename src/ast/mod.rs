@@ -10,11 +10,12 @@ use quick_proc::{QuickDefault, QuickSer, RealQuickSer};
 use crate::{
     lexer::{
         self, token, DisplayError, ErrorDisplay, ErrorDisplayState, LineData, Source, SourceEnt,
-        Span, Token,
+        Span, Token, Trivia,
     },
     util::{
         pool::{Pool, PoolRef},
         sdbm::ID,
+        storage::Map,
     },
 };
 
@@ -33,6 +34,7 @@ pub struct Parser<'a> {
     state: &'a mut State,
     data: &'a mut DataCollector<'a>,
     collector: &'a mut Collector,
+    errors: Vec<Error>,
 }
 
 /// The methods in parser are documented in following way:
@@ -58,6 +60,7 @@ impl<'a> Parser<'a> {
             state,
             data,
             collector,
+            errors: vec![],
         }
     }
 
@@ -90,10 +93,7 @@ impl<'a> Parser<'a> {
                     attrs.push((
                         ID::new(self.ctx.display(name)),
                         name,
-                        self.state
-                            .current()
-                            .span()
-                            .slice(1..self.state.current().len() - 1),
+                        self.string_content(),
                     ));
 
                     self.next()?;
@@ -111,7 +111,7 @@ impl<'a> Parser<'a> {
                             if s.state.current_kind() != token::Kind::String {
                                 return Err(s.unexpected_str("expected string literal as repository link with version or local path"));
                             }
-                            let path_and_version = s.state.current().span().slice(1..s.state.current().len() - 1);
+                            let path_and_version = s.string_content();
                             s.next()?;
 
                             let (path_end, version_start) = s
@@ -202,10 +202,7 @@ impl<'a> Parser<'a> {
         };
 
         let path = if let token::Kind::String = self.state.current_kind() {
-            self.state
-                .current()
-                .span()
-                .slice(1..self.state.current().len() - 1)
+            self.string_content()
         } else {
             return Err(self.unexpected_str("expected string literal as import path"));
         };
@@ -219,21 +216,72 @@ impl<'a> Parser<'a> {
     }
 
     /// Parses rest of the file. It expects state with which the
-    /// [`Self::parse_imports()`] was called.
+    /// [`Self::parse_imports()`] was called. Parser does not stop on
+    /// the first error, it skips to the next top level item instead. All
+    /// errors are returned at the end, wrapped if there is more of them.
     pub fn parse(&mut self) -> Result<bool> {
+        let mut result = Ok(false);
         while self.state.current_kind() != token::Kind::Eof {
-            if self.top_item(
+            let frames = self.ctx.attrib_frames.len();
+            match self.top_item(
                 Ast::reserved_value(),
                 concat!(
                     "expected 'break' | 'fun' | 'attr' | 'struct' | 'enum'",
-                    " | 'union' | 'bound' | 'let' | 'var' | 'impl' | '##'",
+                    " | 'union' | 'bound' | 'type' | 'let' | 'var' | 'const' | 'impl' | '##'",
                 ),
-            )? {
-                return Ok(true);
+            ) {
+                Ok(true) => {
+                    result = Ok(true);
+                    break;
+                }
+                Ok(false) => (),
+                Err(err) => {
+                    self.report(err);
+                    self.ctx.attrib_frames.truncate(frames);
+                    self.ctx.current_attributes.clear();
+                    self.state.level = 0;
+                    self.synchronize();
+                }
             }
         }
 
-        Ok(false)
+        let mut errors = std::mem::take(&mut self.errors);
+        match errors.len() {
+            0 => result,
+            1 => Err(errors.pop().unwrap()),
+            _ => Err(Error::new(error::Kind::Multiple(errors), Token::default())),
+        }
+    }
+
+    /// Records the error unless it is caused by token lexer already
+    /// reported. Multiple errors are recorded one by one.
+    fn report(&mut self, err: Error) {
+        match err.kind {
+            error::Kind::Multiple(errors) => {
+                for err in errors {
+                    self.report(err);
+                }
+            }
+            kind if err.token.kind() != token::Kind::Error => {
+                self.errors.push(Error::new(kind, err.token));
+            }
+            _ => (),
+        }
+    }
+
+    /// Skips tokens until indentation of at most [`State::level`] or end
+    /// of file. Errors of lexer are recorded along the way.
+    fn synchronize(&mut self) {
+        loop {
+            match self.state.current_kind() {
+                token::Kind::Indent(level) if level <= self.state.level => break,
+                token::Kind::Eof => break,
+                _ => (),
+            }
+            if let Err(err) = self.next() {
+                self.report(err);
+            }
+        }
     }
 
     /// Parses impl block.
@@ -265,23 +313,45 @@ impl<'a> Parser<'a> {
         let result = if maybe_target.is_reserved_value() {
             self.expect_str(token::Kind::Colon, "expected ':' after 'impl' type")?;
             self.next()?;
+            let swapped = self.data.swapped();
             self.walk_block(|s| {
-                s.top_item(impl_ast, "expected 'fun' | 'attr' | 'let' | 'var' | '##'")
+                s.data.set_swapped(swapped);
+                s.top_item(
+                    impl_ast,
+                    "expected 'fun' | 'attr' | 'let' | 'var' | 'const' | '##'",
+                )
             })?;
             Ast::reserved_value()
         } else {
-            // construct the impl-bound body
+            // construct the impl-bound body, it lists names of provided
+            // methods, methods themselves are collected as with regular impl
             if self.state.current_kind() == token::Kind::Colon {
                 let token = self.state.current();
                 self.next()?;
                 let mut elements = self.ctx.temp_vec();
+                let swapped = self.data.swapped();
                 self.walk_block(|s| {
+                    s.data.set_swapped(swapped);
                     let kind = s.state.current_kind();
-                    elements.push(s.pop_attributes(kind));
+                    let attributes = s.pop_attributes(kind);
                     match kind {
                         // all that can be in the impl-bound body
-                        token::Kind::Ident => drop(s.bound_alias()?),
-                        token::Kind::Fun => drop(s.fun()?),
+                        token::Kind::Ident => elements.push(s.bound_alias()?),
+                        token::Kind::Fun => {
+                            let fun = s.fun()?;
+                            let name = s.data.son_ent(s.data.son(fun, 0), 1).token();
+                            let (attributes, impl_ast) = if swapped != s.data.swapped() {
+                                (s.data.relocate(attributes), s.data.relocate(impl_ast))
+                            } else {
+                                (attributes, impl_ast)
+                            };
+                            s.declare_precedence(fun, attributes)?;
+                            s.collector
+                                .funs
+                                .push((s.data.swapped(), fun, attributes, impl_ast));
+                            s.data.set_swapped(swapped);
+                            elements.push(s.data.add(AstEnt::sonless(Kind::Ident, name)));
+                        }
                         token::Kind::Attr => s.attr()?,
                         token::Kind::Comment(_) => s.comment()?,
                         _ => {
@@ -292,6 +362,7 @@ impl<'a> Parser<'a> {
                     }
                     Ok(false)
                 })?;
+                self.data.set_swapped(swapped);
                 let content = self.ast(Kind::Group, elements.as_slice(), token);
                 let sons_len = 4;
                 sons.as_mut_slice(&mut self.data.connections)[sons_len - 1] = content;
@@ -329,7 +400,11 @@ impl<'a> Parser<'a> {
                 }
                 self.ctx.pop_attribute_frame();
             }
-            token::Kind::Struct | token::Kind::Union | token::Kind::Enum | token::Kind::Bound
+            token::Kind::Struct
+            | token::Kind::Union
+            | token::Kind::Enum
+            | token::Kind::Bound
+            | token::Kind::Type
                 if impl_ast.is_reserved_value() =>
             {
                 let item = match kind {
@@ -337,6 +412,7 @@ impl<'a> Parser<'a> {
                     token::Kind::Union => self.structure_declaration(true)?,
                     token::Kind::Enum => self.enum_declaration()?,
                     token::Kind::Bound => self.bound_declaration()?,
+                    token::Kind::Type => self.type_alias_declaration()?,
                     _ => unreachable!(),
                 };
                 self.collector
@@ -344,7 +420,19 @@ impl<'a> Parser<'a> {
                     .push((self.data.swapped(), item, attributes));
             }
             token::Kind::Fun => {
+                let swapped = self.data.swapped();
                 let item = self.fun()?;
+                // generic function moves parser to saved data, attributes and
+                // impl block have to follow so that the function sees them
+                let (attributes, impl_ast) = if swapped != self.data.swapped() {
+                    (
+                        self.data.relocate(attributes),
+                        self.data.relocate(impl_ast),
+                    )
+                } else {
+                    (attributes, impl_ast)
+                };
+                self.declare_precedence(item, attributes)?;
                 self.collector
                     .funs
                     .push((self.data.swapped(), item, attributes, impl_ast));
@@ -355,6 +443,12 @@ impl<'a> Parser<'a> {
                     .globals
                     .push((self.data.swapped(), item, attributes, impl_ast));
             }
+            token::Kind::Const => {
+                let item = self.const_declaration()?;
+                self.collector
+                    .consts
+                    .push((self.data.swapped(), item, impl_ast));
+            }
             token::Kind::Attr => {
                 self.attr()?;
             }
@@ -371,12 +465,68 @@ impl<'a> Parser<'a> {
         Ok(false)
     }
 
+    /// Declares precedence of binary operator function `fun` if `attributes`
+    /// contain `prec(precedence[, left | right | none])`.
+    fn declare_precedence(&mut self, fun: Ast, attributes: Ast) -> Result {
+        if attributes.is_reserved_value() {
+            return Ok(());
+        }
+        let attr = self.data.sons(attributes).iter().copied().find(|&attr| {
+            self.data.kind(attr) == Kind::AttributeElement
+                && self.display(self.data.son_ent(attr, 0).token()) == "prec"
+        });
+        let attr = match attr {
+            Some(attr) => attr,
+            None => return Ok(()),
+        };
+        let token = self.data.ent(attr).token();
+
+        let header = self.data.son(fun, 0);
+        let is_binary = matches!(
+            self.data.kind(header),
+            Kind::FunHeader(OpKind::Binary, ..)
+        );
+        let sons = self.data.sons(attr);
+        let precedence = match sons.get(1) {
+            Some(&son) if self.data.kind(son) == Kind::Lit => {
+                self.display(self.data.ent(son).token()).parse::<u8>().ok()
+            }
+            _ => None,
+        };
+        let assoc = match sons.get(2) {
+            None => Some(Assoc::Left),
+            Some(&son) if self.data.sons(son).len() == 1 => {
+                match self.display(self.data.son_ent(son, 0).token()) {
+                    "left" => Some(Assoc::Left),
+                    "right" => Some(Assoc::Right),
+                    "none" => Some(Assoc::None),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        let info = match (precedence, assoc) {
+            (Some(precedence), Some(assoc))
+                if is_binary && sons.len() <= 3 && (1..ASSIGN_PRECEDENCE).contains(&precedence) =>
+            {
+                OpInfo::new(precedence, assoc)
+            }
+            _ => return Err(Error::new(error::Kind::InvalidPrecedence, token)),
+        };
+
+        let op = self.display(self.data.son_ent(header, 1).token()).to_string();
+        self.ctx
+            .declare_precedence(&op, info, token)
+            .map_err(|existing| Error::new(error::Kind::PrecedenceConflict(existing), token))
+    }
+
     pub fn pop_attributes(&mut self, kind: token::Kind) -> Ast {
         let collect_attributes = matches!(
             kind,
             token::Kind::Union
                 | token::Kind::Enum
                 | token::Kind::Struct
+                | token::Kind::Type
                 | token::Kind::Fun
                 | token::Kind::Var
                 | token::Kind::Let
@@ -447,6 +597,51 @@ impl<'a> Parser<'a> {
         Ok(self.ast(Kind::Bound(vis), &[generics, name, bounds, content], token))
     }
 
+    /// Parses type alias declaration. Generic parameters follow
+    /// the name so the alias reads the same as its instantiation.
+    pub fn type_alias_declaration(&mut self) -> Result<Ast> {
+        let token = self.state.current();
+        self.next()?;
+
+        let vis = self.vis()?;
+
+        let swapped = self.data.swapped();
+        let mut name = self.ident()?;
+        let generics = self.generics()?;
+        if swapped != self.data.swapped() {
+            name = self.data.relocate(name);
+        }
+
+        if self.state.current() != token::Kind::Op || self.display(self.state.current()) != "=" {
+            return Err(self.unexpected_str("expected '=' after type alias name"));
+        }
+        self.next()?;
+        let target = self.type_expr()?;
+
+        Ok(self.ast(Kind::TypeAlias(vis), &[generics, name, target], token))
+    }
+
+    /// Parses constant declaration, type is mandatory.
+    pub fn const_declaration(&mut self) -> Result<Ast> {
+        let token = self.state.current();
+        self.next()?;
+
+        let vis = self.vis()?;
+        let name = self.ident()?;
+
+        self.expect_str(token::Kind::Colon, "expected ':' after constant name")?;
+        self.next()?;
+        let ty = self.type_expr()?;
+
+        if self.state.current() != token::Kind::Op || self.display(self.state.current()) != "=" {
+            return Err(self.unexpected_str("expected '=' after constant type"));
+        }
+        self.next()?;
+        let value = self.expr()?;
+
+        Ok(self.ast(Kind::Const(vis), &[name, ty, value], token))
+    }
+
     /// Parses enum declaration.
     pub fn enum_declaration(&mut self) -> Result<Ast> {
         let token = self.state.current();
@@ -457,7 +652,7 @@ impl<'a> Parser<'a> {
         let name = self.ident()?;
 
         let variants = if self.state.current() == token::Kind::Colon {
-            self.block(Self::ident)?
+            self.block(Self::enum_variant)?
         } else {
             Ast::reserved_value()
         };
@@ -465,6 +660,23 @@ impl<'a> Parser<'a> {
         Ok(self.ast(Kind::Enum(vis), &[name, variants], token))
     }
 
+    /// Parses enum variant with optional explicit value.
+    pub fn enum_variant(&mut self) -> Result<Ast> {
+        let token = self.state.current();
+        let name = self.ident()?;
+
+        let value = if self.state.current() == token::Kind::Op
+            && self.display(self.state.current()) == "="
+        {
+            self.next()?;
+            self.expr()?
+        } else {
+            Ast::reserved_value()
+        };
+
+        Ok(self.ast(Kind::EnumVariant, &[name, value], token))
+    }
+
     /// Parses structure declaration, which can be either struct or union.
     pub fn structure_declaration(&mut self, union: bool) -> Result<Ast> {
         let token = self.state.current();
@@ -535,8 +747,14 @@ impl<'a> Parser<'a> {
     }
 
     /// parses singular attribute element that is directly recursive.
+    /// Integer literals are accepted as leaf elements.
     pub fn attr_element(&mut self) -> Result<Ast> {
         let token = self.state.current();
+        if let token::Kind::Int(_) = token.kind() {
+            self.next()?;
+            return Ok(self.data.add(AstEnt::sonless(Kind::Lit, token)));
+        }
+
         let mut sons = self.ctx.temp_vec();
         sons.push(self.ident()?);
 
@@ -591,7 +809,21 @@ impl<'a> Parser<'a> {
 
         let is_op = self.state.current_kind() == token::Kind::Op;
         let ident = match self.state.current_kind() {
-            token::Kind::Ident | token::Kind::Op if !anonymous => {
+            token::Kind::Op if !anonymous => {
+                // operator that is not known yet is lexed in pieces
+                let mut token = self.state.current();
+                while self.state.peeked_kind() == token::Kind::Op
+                    && self.state.peeked().range().start == token.range().end
+                {
+                    self.next()?;
+                    token = token.join(self.state.current());
+                }
+                self.ctx.add_operator(token.span());
+                let ast = self.data.add(AstEnt::sonless(Kind::Ident, token));
+                self.next()?;
+                ast
+            }
+            token::Kind::Ident if !anonymous => {
                 let ast = self
                     .data
                     .add(AstEnt::sonless(Kind::Ident, self.state.current()));
@@ -618,7 +850,7 @@ impl<'a> Parser<'a> {
         }
 
         let kind = if is_op {
-            let arg_count = sons[1..]
+            let arg_count = sons[2..]
                 .iter()
                 .fold(0, |acc, &i| acc + self.data.sons(i).len() - 1);
             match arg_count {
@@ -835,71 +1067,84 @@ impl<'a> Parser<'a> {
 
     pub fn expr(&mut self) -> Result<Ast> {
         let expr = self.simple_expr()?;
-        self.expr_low(expr)
+        self.expr_low(expr, u8::MAX)
     }
 
-    pub fn expr_low(&mut self, previous: Ast) -> Result<Ast> {
+    /// Parses chain of binary operators following `previous`. Only operators
+    /// with precedence lower then `limit` are consumed, the rest is left to
+    /// the caller.
+    pub fn expr_low(&mut self, previous: Ast, limit: u8) -> Result<Ast> {
         let mut result = previous;
+        let mut last: Option<OpInfo> = None;
         while self.state.current() == token::Kind::Op {
             let token = self.state.current();
-            let op = AstEnt::sonless(Kind::Ident, token);
-            let pre = self.precedence(token);
-
-            self.next()?;
-            self.ignore_newlines()?;
-
-            let mut next = self.simple_expr()?;
-
-            if self.state.current() == token::Kind::Op {
-                let dif = pre - self.precedence(self.state.current());
-
-                if dif > 0 {
-                    next = self.expr_low(next)?;
+            let info = self.op_info(token);
+            if info.precedence() >= limit {
+                break;
+            }
+            if let Some(last) = last.filter(|last| last.precedence() == info.precedence()) {
+                if last.assoc() != info.assoc() || info.assoc() == Assoc::None {
+                    return Err(Error::new(error::Kind::NonAssociative, token));
                 }
             }
+            last = Some(info);
 
-            let token = self
-                .data
-                .ent(previous)
-                .token()
-                .join(self.data.ent(next).token());
+            self.next()?;
+            self.ignore_newlines()?;
 
-            // this handles the '{op}=' sugar
-            result = if pre == ASSIGN_PRECEDENCE
-                && op.len() != 1
-                && self.ctx.display(op.span()).chars().last().unwrap() == '='
-            {
-                let op_token = Token::new(
-                    token::Kind::Op,
-                    op.span().slice(0..op.len() - 1),
-                    op.line_data(),
-                );
-                let operator = self.data.add(AstEnt::sonless(Kind::Ident, op_token));
-
-                let eq_token = Token::new(
-                    token::Kind::Op,
-                    op.span().slice(op.len() - 1..op.len()),
-                    token.line_data(),
-                );
-                let eq = self.data.add(AstEnt::sonless(Kind::Ident, eq_token));
-
-                let sons = self.data.add_slice(&[operator, result, next]);
-                let expr = self.data.add(AstEnt::new(Kind::Binary, sons, token));
-
-                let sons = self.data.add_slice(&[eq, result, expr]);
-                self.data.add(AstEnt::new(Kind::Binary, sons, token))
-            } else {
-                let op = self.data.add(op);
-                let sons = self.data.add_slice(&[op, result, next]);
-                self.data.add(AstEnt::new(Kind::Binary, sons, token))
+            let next = self.simple_expr()?;
+            // right operand takes operators that bind tighter, and the
+            // same ones if they associate to the right
+            let limit = match info.assoc() {
+                Assoc::Right => info.precedence() + 1,
+                Assoc::Left | Assoc::None => info.precedence(),
             };
+            let next = self.expr_low(next, limit)?;
+
+            result = self.binary(result, token, info, next);
         }
 
         Ok(result)
     }
 
-    pub fn precedence(&self, token: Token) -> i64 {
-        precedence(self.ctx.display_token(token))
+    /// Creates binary expression, '{op}=' sugar is expanded to `a = a op b`.
+    fn binary(&mut self, left: Ast, op: Token, info: OpInfo, right: Ast) -> Ast {
+        let token = self
+            .data
+            .ent(left)
+            .token()
+            .join(self.data.ent(right).token());
+
+        if info.precedence() == ASSIGN_PRECEDENCE && op.len() != 1 {
+            let op_token = Token::new(
+                token::Kind::Op,
+                op.span().slice(0..op.len() - 1),
+                op.line_data(),
+            );
+            let operator = self.data.add(AstEnt::sonless(Kind::Ident, op_token));
+
+            let eq_token = Token::new(
+                token::Kind::Op,
+                op.span().slice(op.len() - 1..op.len()),
+                token.line_data(),
+            );
+            let eq = self.data.add(AstEnt::sonless(Kind::Ident, eq_token));
+
+            let sons = self.data.add_slice(&[operator, left, right]);
+            let expr = self.data.add(AstEnt::new(Kind::Binary, sons, token));
+
+            let sons = self.data.add_slice(&[eq, left, expr]);
+            self.data.add(AstEnt::new(Kind::Binary, sons, token))
+        } else {
+            let op = self.data.add(AstEnt::sonless(Kind::Ident, op));
+            let sons = self.data.add_slice(&[op, left, right]);
+            self.data.add(AstEnt::new(Kind::Binary, sons, token))
+        }
+    }
+
+    /// Returns binding of operator `token`.
+    pub fn op_info(&self, token: Token) -> OpInfo {
+        self.ctx.op_info(self.display(token))
     }
 
     pub fn simple_expr(&mut self) -> Result<Ast> {
@@ -915,7 +1160,10 @@ impl<'a> Parser<'a> {
             | token::Kind::Bool(..)
             | token::Kind::Char
             | token::Kind::Float(..)
-            | token::Kind::String => {
+            | token::Kind::String
+            | token::Kind::RawString
+            | token::Kind::ByteString
+            | token::Kind::MultilineString => {
                 self.next()?;
                 self.data.add(AstEnt::sonless(Kind::Lit, token))
             }
@@ -943,6 +1191,7 @@ impl<'a> Parser<'a> {
             }
             token::Kind::If => return self.if_expr(),
             token::Kind::For => return self.loop_expr(),
+            token::Kind::Match => return self.match_expr(),
             token::Kind::Op => {
                 let mut sons = self.ctx.temp_vec();
                 let kind = match self.ctx.display(token.span()) {
@@ -961,7 +1210,7 @@ impl<'a> Parser<'a> {
                     _ => {
                         sons.push(self.data.add(AstEnt::sonless(Kind::Ident, token)));
                         self.next()?;
-                        Kind::Ident
+                        Kind::Unary
                     }
                 };
                 let ast = self.simple_expr()?;
@@ -983,8 +1232,15 @@ impl<'a> Parser<'a> {
                 )?;
                 self.ast(Kind::Array, sons.as_slice(), token)
             }
-            token::Kind::Fun => return self.fun_header(true),
-            _ => todo!("unmatched simple expr pattern {:?}", self.state.current()),
+            token::Kind::Fun => {
+                let header = self.fun_header(true)?;
+                if self.state.is_type_expr || self.state.current() != token::Kind::Colon {
+                    return Ok(header);
+                }
+                let body = self.stmt_block()?;
+                return Ok(self.ast(Kind::Lambda, &[header, body], token));
+            }
+            _ => return Err(self.unexpected_str("expected expression")),
         };
 
         if !nested && !self.state.is_type_expr {
@@ -1067,9 +1323,27 @@ impl<'a> Parser<'a> {
         self.next()?;
 
         let label = self.optional_tag()?;
+
+        if self.state.current_kind() == token::Kind::Colon {
+            let body = self.stmt_block()?;
+            return Ok(self.ast(Kind::Loop, &[label, body], token));
+        }
+
+        let condition = self.expr()?;
+
+        if self.state.current_kind() == token::Kind::In {
+            if self.data.kind(condition) != Kind::Ident {
+                return Err(self.unexpected_str("expected ident before 'in'"));
+            }
+            self.next()?;
+            let iterator = self.expr()?;
+            let body = self.stmt_block()?;
+            return Ok(self.ast(Kind::ForIn, &[label, condition, iterator, body], token));
+        }
+
         let body = self.stmt_block()?;
 
-        Ok(self.ast(Kind::Loop, &[label, body], token))
+        Ok(self.ast(Kind::While, &[label, condition, body], token))
     }
 
     pub fn optional_tag(&mut self) -> Result<Ast> {
@@ -1167,6 +1441,63 @@ impl<'a> Parser<'a> {
         Ok(self.ast(Kind::If, &[condition, then_block, else_block], token))
     }
 
+    pub fn match_expr(&mut self) -> Result<Ast> {
+        let token = self.state.current();
+        self.next()?;
+        let value = self.expr()?;
+        let arms = self.block(Self::match_arm)?;
+
+        Ok(self.ast(Kind::Match, &[value, arms], token))
+    }
+
+    pub fn match_arm(&mut self) -> Result<Ast> {
+        let token = self.state.current();
+        let pattern = self.match_pattern()?;
+        let body = self.stmt_block()?;
+
+        Ok(self.ast(Kind::MatchArm, &[pattern, body], token))
+    }
+
+    /// Parses pattern of match arm, which is either a single value
+    /// or a range of values, `..` excludes the upper bound and `..=`
+    /// includes it.
+    pub fn match_pattern(&mut self) -> Result<Ast> {
+        let token = self.state.current();
+        let low = self.pattern_bound()?;
+
+        if self.state.current_kind() != token::Kind::Dot
+            || self.state.peeked_kind() != token::Kind::Dot
+        {
+            return Ok(low);
+        }
+        self.next()?;
+        self.next()?;
+
+        let inclusive = self.state.current_kind() == token::Kind::Op
+            && self.ctx.display(self.state.current().span()) == "=";
+        if inclusive {
+            self.next()?;
+        }
+
+        let high = self.pattern_bound()?;
+
+        Ok(self.ast(Kind::Range(inclusive), &[low, high], token))
+    }
+
+    /// Parses expression without postfix operators so that `..`
+    /// is not mistaken for field access. Negation is allowed.
+    fn pattern_bound(&mut self) -> Result<Ast> {
+        let token = self.state.current();
+        if self.state.current_kind() == token::Kind::Op && self.ctx.display(token.span()) == "-" {
+            let op = self.data.add(AstEnt::sonless(Kind::Ident, token));
+            self.next()?;
+            let value = self.simple_expr_low(true)?;
+            return Ok(self.ast(Kind::Unary, &[op, value], token));
+        }
+
+        self.simple_expr_low(true)
+    }
+
     pub fn ident(&mut self) -> Result<Ast> {
         self.expect_str(token::Kind::Ident, "expected ident")?;
         let token = self.state.current();
@@ -1213,13 +1544,25 @@ impl<'a> Parser<'a> {
         Ok(false)
     }
 
+    /// Parses block of elements. Element that fails to parse is replaced
+    /// with [`Kind::Error`] and parsing continues with the next line of
+    /// the same or lower indentation.
     pub fn block<F: FnMut(&mut Self) -> Result<Ast>>(&mut self, mut parser: F) -> Result<Ast> {
         self.expect_str(token::Kind::Colon, "expected ':' as a start of block")?;
         let token = self.state.current();
         let mut sons = self.ctx.temp_vec();
         self.next()?;
         self.walk_block(|s| {
-            let expr = parser(s)?;
+            let (level, token) = (s.state.level, s.state.current());
+            let expr = match parser(s) {
+                Ok(expr) => expr,
+                Err(err) => {
+                    s.report(err);
+                    s.state.level = level;
+                    s.synchronize();
+                    s.data.add(AstEnt::sonless(Kind::Error, token))
+                }
+            };
             sons.push(expr);
             Ok(false)
         })?;
@@ -1311,7 +1654,15 @@ impl<'a> Parser<'a> {
     }
 
     pub fn next(&mut self) -> Result {
-        self.state.advance(self.ctx)
+        if self.data.keeps_trivia() {
+            self.data
+                .record_trivia(self.state.current(), self.state.current_trivia());
+        }
+        self.state.advance(self.ctx)?;
+        if let Some(warning) = self.ctx.check_ident(self.state.current()) {
+            self.ctx.warnings.push(warning);
+        }
+        Ok(())
     }
 
     pub fn expect_str(&self, kind: token::Kind, message: &str) -> Result {
@@ -1338,6 +1689,15 @@ impl<'a> Parser<'a> {
         self.ctx.display(token.span())
     }
 
+    /// Returns span of current string literal without quotes.
+    pub fn string_content(&self) -> Span {
+        let span = self.state.current().span();
+        match span.len() {
+            len @ (0 | 1) => span.slice(len..len),
+            len => span.slice(1..len - 1),
+        }
+    }
+
     pub fn ast(&mut self, kind: Kind, sons: &[Ast], token: Token) -> Ast {
         let token = token.join_trimmed(self.state.current());
         let sons = self.data.add_slice(sons);
@@ -1396,12 +1756,42 @@ impl Reloc {
 pub struct Data {
     entities: PrimaryMap<Ast, AstEnt>,
     connections: ListPool<Ast>,
+    /// Tokens consumed by parser with their trivia, in source order.
+    keep_trivia: bool,
+    trivia: Vec<(Token, Trivia)>,
     #[cfg(debug_assertions)]
     #[default(RelocSafety::new())]
     safety: RelocSafety,
 }
 
 impl Data {
+    /// Creates data that also records [`Trivia`] of every token parser
+    /// consumes, so that tooling can print the tree with comments. It
+    /// has to be the temporary data of [`DataCollector`].
+    pub fn with_trivia() -> Self {
+        Self {
+            keep_trivia: true,
+            ..Self::default()
+        }
+    }
+
+    /// Returns consumed tokens with their trivia. Empty unless data
+    /// was created with [`Self::with_trivia`].
+    pub fn trivia(&self) -> &[(Token, Trivia)] {
+        &self.trivia
+    }
+
+    /// Returns trivia of consumed token starting at the same position
+    /// as `token`. Compound ast starts with its first token.
+    pub fn trivia_of(&self, token: Token) -> Option<Trivia> {
+        let start = token.range().start;
+        let index = self
+            .trivia
+            .binary_search_by(|(t, _)| t.range().start.cmp(&start))
+            .ok()?;
+        Some(self.trivia[index].1)
+    }
+
     pub fn add(&mut self, ast_ent: AstEnt) -> Ast {
         self.entities.push(ast_ent)
     }
@@ -1476,6 +1866,12 @@ impl Data {
         self.entities[ast]
     }
 
+    /// Returns all asts in the data. Relocated trees are still
+    /// present in the data they were relocated from.
+    pub fn iter(&self) -> impl Iterator<Item = (Ast, AstEnt)> + '_ {
+        self.entities.iter().map(|(ast, &ent)| (ast, ent))
+    }
+
     /// Relocates the ast tree from `source` to `self`.
     /// When first transferring between the two, context has to be clear.
     /// But clearing context after each relocation can create duplicate
@@ -1523,6 +1919,7 @@ impl Data {
     pub fn clear(&mut self) {
         self.entities.clear();
         self.connections.clear();
+        self.trivia.clear();
     }
 
     pub fn get_ent(&self, sons: EntityList<Ast>, arg: usize) -> AstEnt {
@@ -1565,6 +1962,30 @@ impl<'a> DataCollector<'a> {
     pub fn relocate(&mut self, ast: Ast) -> Ast {
         self.a.relocate(ast, self.b, self.reloc)
     }
+
+    /// Returns true if temporary data records trivia.
+    pub fn keeps_trivia(&self) -> bool {
+        self.temp().keep_trivia
+    }
+
+    /// Records trivia of consumed token into temporary data, so that
+    /// it stays in one place when parser swaps.
+    pub fn record_trivia(&mut self, token: Token, trivia: Trivia) {
+        let temp = if self.swapped {
+            &mut *self.b
+        } else {
+            &mut *self.a
+        };
+        temp.trivia.push((token, trivia));
+    }
+
+    fn temp(&self) -> &Data {
+        if self.swapped {
+            self.b
+        } else {
+            self.a
+        }
+    }
 }
 
 impl Deref for DataCollector<'_> {
@@ -1626,6 +2047,7 @@ pub struct Collector {
     funs: Vec<(bool, Ast, Ast, Ast)>,
     types: Vec<(bool, Ast, Ast)>,
     globals: Vec<(bool, Ast, Ast, Ast)>,
+    consts: Vec<(bool, Ast, Ast)>,
     bound_impls: Vec<(bool, Ast)>,
 }
 
@@ -1651,6 +2073,7 @@ impl Collector {
     impl_use_items!(
         use_types, types, saved: bool, ty: Ast, attrs: Ast;
         use_globals, globals, saved: bool, global: Ast, attrs: Ast, scope: Ast;
+        use_consts, consts, saved: bool, constant: Ast, scope: Ast;
         use_bound_impls, bound_impls, saved: bool, imp: Ast;
         use_funs, funs, saved: bool, fun: Ast, attrs: Ast, scope: Ast;
     );
@@ -1664,11 +2087,51 @@ pub struct Ctx {
     attrib_frames: Vec<usize>,
     current_attributes: Vec<(bool, Ast)>,
 
+    precedence: Precedence,
+
+    warnings: Vec<lexer::Warning>,
+
     pool: Pool,
 }
 
 impl Ctx {
+    /// Returns warnings reported while parsing since last call.
+    pub fn take_warnings(&mut self) -> Vec<lexer::Warning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Returns binding of binary operator `op`. Assignment variants of
+    /// known operators have [`ASSIGN_PRECEDENCE`].
+    pub fn op_info(&self, op: &str) -> OpInfo {
+        if let Some(info) = self.precedence.get(op) {
+            return info;
+        }
+        match op.strip_suffix('=') {
+            Some(base) if self.operators().contains(base) => {
+                OpInfo::new(ASSIGN_PRECEDENCE, Assoc::Right)
+            }
+            _ => OpInfo::new(DEFAULT_PRECEDENCE, Assoc::Left),
+        }
+    }
+
+    /// Declares binding of operator, see [`Precedence::declare`].
+    pub fn declare_precedence(
+        &mut self,
+        op: &str,
+        info: OpInfo,
+        token: Token,
+    ) -> std::result::Result<(), OpInfo> {
+        self.precedence.declare(op, info, token)
+    }
+
+    /// Replaces table of operator bindings, previous table is returned.
+    pub fn swap_precedence(&mut self, precedence: Precedence) -> Precedence {
+        std::mem::replace(&mut self.precedence, precedence)
+    }
+
     pub fn clear_after_module(&mut self) {
+        self.reset_operators();
+        self.precedence = Precedence::new();
         self.attrib_stack.clear();
         self.attrib_frames.clear();
         self.current_attributes.clear();
@@ -1699,8 +2162,9 @@ impl Ctx {
                     self.attrib_stack.push((data.swapped(), ast));
                 }
             } else if name == "pop" {
-                let len = self.attrib_frames.pop().unwrap();
-                self.attrib_stack.truncate(len);
+                if let Some(len) = self.attrib_frames.pop() {
+                    self.attrib_stack.truncate(len);
+                }
             } else {
                 self.current_attributes.push((data.swapped(), ast));
             }
@@ -1748,13 +2212,22 @@ pub struct State {
     is_type_expr: bool,
     level: u16,
     inner: lexer::State,
+    /// Lexer states before current and peeked token, used by [`Self::relex()`].
+    current_state: lexer::State,
+    peeked_state: lexer::State,
+    /// Token before current one, used by [`Self::current_trivia()`].
+    previous: Option<Token>,
 }
 
 impl State {
     pub fn new(source: Source, sources: &Ctx) -> Result<Self> {
+        Self::with_indent_width(source, lexer::DEFAULT_INDENT_WIDTH, sources)
+    }
+
+    pub fn with_indent_width(source: Source, indent_width: u8, sources: &Ctx) -> Result<Self> {
         let mut s = Self::default();
 
-        s.inner = lexer::State::new(source);
+        s.inner = lexer::State::with_indent_width(source, indent_width);
 
         s.advance(sources)?;
         s.advance(sources)?;
@@ -1778,16 +2251,46 @@ impl State {
         self.peeked.kind()
     }
 
+    /// Returns trivia of current token. Comments skipped by [`Self::advance()`]
+    /// are part of it.
+    pub fn current_trivia(&self) -> Trivia {
+        Trivia::around(self.previous, self.current, Some(self.peeked))
+    }
+
+    /// Lexes current and peeked token again. Has to be called when
+    /// known operators change as lookahead could be split differently.
+    pub fn relex(&mut self, sources: &Ctx) -> Result {
+        let previous = self.previous;
+        self.inner = self.current_state;
+        let result = self.advance(sources).and_then(|_| self.advance(sources));
+        self.previous = previous;
+        result
+    }
+
+    /// Moves to the next token. All lexer errors met are returned,
+    /// wrapped if there is more of them.
     pub fn advance(&mut self, sources: &Ctx) -> Result {
+        self.previous = Some(self.current).filter(|t| t.kind() != token::Kind::None);
         self.current = self.peeked;
+        self.current_state = self.peeked_state;
+        self.peeked_state = self.inner;
+        let mut errors = vec![];
         self.peeked = loop {
-            let token = self.token_err(sources)?;
+            let token = sources.token_recovering(&mut self.inner, &mut errors);
             if token.kind() == token::Kind::Comment(false) {
                 continue;
             }
             break token;
         };
-        Ok(())
+        let mut errors = errors
+            .into_iter()
+            .map(|err| Error::new(error::Kind::LError(err), Token::default()))
+            .collect::<Vec<_>>();
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.pop().unwrap()),
+            _ => Err(Error::new(error::Kind::Multiple(errors), Token::default())),
+        }
     }
 
     pub fn token_err(&mut self, sources: &Ctx) -> Result<Token> {
@@ -1898,31 +2401,163 @@ impl Dep {
     }
 }
 
-const ASSIGN_PRECEDENCE: i64 = 15;
-
-fn precedence(op: &str) -> i64 {
-    match op {
-        "as" => 2,
-        "*" | "/" | "%" => 3,
-        "+" | "-" => 4,
-        "<<" | ">>" => 5,
-        "<" | "<=" | ">" | ">=" => 6,
-        "==" | "!=" => 7,
-        "&" => 8,
-        "^" => 9,
-        "|" => 10,
-        "&&" => 11,
-        "||" => 12,
-        _ => {
-            if op.chars().last().unwrap() == '=' {
-                ASSIGN_PRECEDENCE
-            } else {
-                14
+/// Precedence of `=` and `{op}=` sugar, it binds the weakest.
+pub const ASSIGN_PRECEDENCE: u8 = 15;
+/// Precedence of operators that did not declare any.
+pub const DEFAULT_PRECEDENCE: u8 = 14;
+
+/// Bindings of builtin binary operators, lower precedence binds tighter.
+pub const BUILTIN_PRECEDENCE: &[(&str, u8, Assoc)] = &[
+    ("as", 2, Assoc::Left),
+    ("*", 3, Assoc::Left),
+    ("/", 3, Assoc::Left),
+    ("%", 3, Assoc::Left),
+    ("+", 4, Assoc::Left),
+    ("-", 4, Assoc::Left),
+    ("<<", 5, Assoc::Left),
+    (">>", 5, Assoc::Left),
+    ("<", 6, Assoc::None),
+    ("<=", 6, Assoc::None),
+    (">", 6, Assoc::None),
+    (">=", 6, Assoc::None),
+    ("==", 7, Assoc::None),
+    ("!=", 7, Assoc::None),
+    ("&", 8, Assoc::Left),
+    ("^", 9, Assoc::Left),
+    ("|", 10, Assoc::Left),
+    ("&&", 11, Assoc::Left),
+    ("||", 12, Assoc::Left),
+    ("min", DEFAULT_PRECEDENCE, Assoc::Left),
+    ("max", DEFAULT_PRECEDENCE, Assoc::Left),
+    ("=", ASSIGN_PRECEDENCE, Assoc::Right),
+];
+
+/// Associativity decides how operators of the same precedence chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assoc {
+    /// `a - b - c` parses as `(a - b) - c`.
+    Left,
+    /// `a = b = c` parses as `a = (b = c)`.
+    Right,
+    /// `a < b < c` is an error.
+    None,
+}
+
+impl Assoc {
+    /// Name used in `prec` attribute.
+    pub fn name(self) -> &'static str {
+        match self {
+            Assoc::Left => "left",
+            Assoc::Right => "right",
+            Assoc::None => "none",
+        }
+    }
+}
+
+impl Default for Assoc {
+    fn default() -> Self {
+        Assoc::Left
+    }
+}
+
+/// Binding of binary operator.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpInfo {
+    precedence: u8,
+    assoc: Assoc,
+}
+
+impl OpInfo {
+    pub fn new(precedence: u8, assoc: Assoc) -> Self {
+        Self { precedence, assoc }
+    }
+
+    /// Precedence getter.
+    pub fn precedence(&self) -> u8 {
+        self.precedence
+    }
+
+    /// Associativity getter.
+    pub fn assoc(&self) -> Assoc {
+        self.assoc
+    }
+}
+
+/// Table of binary operator bindings. It starts with [`BUILTIN_PRECEDENCE`]
+/// and operator functions extend it with `attr prec(precedence[, assoc])`.
+/// Each module has its own table, declarations are shared with importers
+/// by [`Self::import`].
+#[derive(Debug, Clone)]
+pub struct Precedence {
+    lookup: Map<OpInfo>,
+    /// Declarations made in this table, along with their attribute.
+    declared: Vec<(ID, OpInfo, Token)>,
+}
+
+impl Precedence {
+    /// Creates table with [`BUILTIN_PRECEDENCE`].
+    pub fn new() -> Self {
+        let mut precedence = Self {
+            lookup: Map::new(),
+            declared: vec![],
+        };
+        for &(op, level, assoc) in BUILTIN_PRECEDENCE {
+            precedence
+                .lookup
+                .insert(ID::new(op), OpInfo::new(level, assoc));
+        }
+        precedence
+    }
+
+    /// Returns binding declared for `op`.
+    pub fn get(&self, op: &str) -> Option<OpInfo> {
+        self.lookup.get(ID::new(op)).copied()
+    }
+
+    /// Declares binding of `op`, `token` points to the declaration.
+    /// Declaration that does not agree with the existing one is rejected
+    /// and the existing binding is returned.
+    pub fn declare(
+        &mut self,
+        op: &str,
+        info: OpInfo,
+        token: Token,
+    ) -> std::result::Result<(), OpInfo> {
+        let id = ID::new(op);
+        self.insert(id, info)?;
+        self.declared.push((id, info, token));
+        Ok(())
+    }
+
+    /// Adds declarations made in `other` table, declarations `other`
+    /// imported itself are not included. Conflict is reported at the
+    /// imported declaration.
+    pub fn import(&mut self, other: &Self) -> Result {
+        for &(id, info, token) in other.declared.iter() {
+            self.insert(id, info).map_err(|existing| {
+                Error::new(error::Kind::PrecedenceConflict(existing), token)
+            })?;
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, id: ID, info: OpInfo) -> std::result::Result<(), OpInfo> {
+        match self.lookup.get(id) {
+            Some(&existing) if existing != info => Err(existing),
+            _ => {
+                self.lookup.insert(id, info);
+                Ok(())
             }
         }
     }
 }
 
+impl Default for Precedence {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 crate::impl_entity!(Ast);
 
 #[derive(Debug, Clone, Copy, Default, PartialEq, RealQuickSer)]
@@ -2026,6 +2661,7 @@ pub enum Kind {
     Comment,
 
     Fun,
+    Lambda,
     Impl(Vis),
     FunHeader(OpKind, Vis, CallConv),
     FunArgument(bool),
@@ -2040,6 +2676,11 @@ pub enum Kind {
     StructField(Vis, bool),
 
     Enum(Vis),
+    EnumVariant,
+
+    TypeAlias(Vis),
+
+    Const(Vis),
 
     Attribute,
     AttributeElement,
@@ -2065,6 +2706,11 @@ pub enum Kind {
     Generics,
 
     Loop,
+    While,
+    ForIn,
+    Match,
+    MatchArm,
+    Range(bool), // true if inclusive
     Break,
     Continue,
 
@@ -2075,6 +2721,8 @@ pub enum Kind {
     Ident,
     Instantiation,
     Lit,
+
+    Error,
 }
 
 impl Default for Kind {
@@ -2102,6 +2750,33 @@ impl ErrorDisplayState<Error> for Ctx {
             error::Kind::InvalidCallConv => {
                 CallConv::error(f)?;
             }
+            error::Kind::Multiple(errors) => {
+                for error in errors {
+                    writeln!(f, "{}", ErrorDisplay::new(self, error))?;
+                }
+            }
+            error::Kind::NonAssociative => {
+                writeln!(
+                    f,
+                    "operators of same precedence cannot be chained here, use parentheses"
+                )?;
+            }
+            error::Kind::InvalidPrecedence => {
+                writeln!(
+                    f,
+                    "expected 'prec(precedence[, left | right | none])' on binary operator \
+                    function, precedence goes from 1 to {}",
+                    ASSIGN_PRECEDENCE - 1
+                )?;
+            }
+            error::Kind::PrecedenceConflict(existing) => {
+                writeln!(
+                    f,
+                    "operator already has precedence 'prec({}, {})', declarations have to agree",
+                    existing.precedence(),
+                    existing.assoc().name()
+                )?;
+            }
         }
 
         Ok(())
@@ -2151,6 +2826,10 @@ mod error {
         LError(lexer::Error),
         UnexpectedToken(String),
         InvalidCallConv,
+        Multiple(Vec<super::Error>),
+        NonAssociative,
+        InvalidPrecedence,
+        PrecedenceConflict(super::OpInfo),
     }
 }
 
@@ -2216,7 +2895,7 @@ impl CallConv {
 
     pub fn to_cr_call_conv(&self, isa: &dyn TargetIsa) -> CrCallConv {
         match self {
-            Self::Platform => isa.default_call_conv(),
+            Self::Platform => CrCallConv::triple_default(isa.triple()),
             _ => unsafe { std::mem::transmute(*self) },
         }
     }
@@ -2227,7 +2906,7 @@ impl CallConv {
             "Invalid call convention, list of valid call conventions:"
         )?;
         for cc in [
-            "platform - picks call convention based of target platform",
+            "platform - picks call convention based of target platform (system_v on linux)",
             "fast",
             "cold",
             "system_v",
@@ -2287,6 +2966,17 @@ pub fn test() {
         })
         .unwrap();
 
+    collector
+        .use_consts(|saved, constant, scope| {
+            println!("===const {} ===", saved);
+            data.set_swapped(saved);
+            print!("{}", Display::new(&ctx, &data, scope));
+            print!("{}", Display::new(&ctx, &data, constant));
+
+            Result::Ok(())
+        })
+        .unwrap();
+
     collector
         .use_types(|saved, ty, attrs| {
             println!("===type {} ===", saved);
@@ -2319,4 +3009,248 @@ pub fn test() {
             Result::Ok(())
         })
         .unwrap();
+    let source = SourceEnt::new(
+        "recovery.mf".to_string(),
+        concat!(
+            "fun a:\n  let x = )\n  return 1\n\nstruct\n\n",
+            "fun b:\n  var y = $\n  if y:\n    ]\n  pass\n\nfun c -> int: 1\n",
+        )
+        .to_string(),
+    );
+    let source = ctx.add_source(source);
+    let mut temp_data = Data::default();
+    let mut saved_data = Data::default();
+    let mut reloc = Reloc::default();
+    let mut state = State::new(source, &ctx).unwrap();
+    let mut collector = Collector::default();
+    let mut data = DataCollector::new(&mut temp_data, &mut saved_data, &mut reloc);
+    let mut parser = Parser::new(&mut state, &mut data, &mut ctx, &mut collector);
+    let nested = (0..2)
+        .map(|_| Error::new(error::Kind::InvalidCallConv, Token::default()))
+        .collect();
+    parser.report(Error::new(error::Kind::Multiple(nested), Token::default()));
+    assert_eq!(parser.errors.len(), 2);
+    parser.errors.clear();
+    let err = parser.parse().unwrap_err();
+    match err.kind() {
+        error::Kind::Multiple(errors) => assert_eq!(errors.len(), 4),
+        kind => panic!("{:?}", kind),
+    }
+    println!("{}", ErrorDisplay::new(&ctx, &err));
+    assert_eq!(collector.funs.len(), 3);
+
+    // inputs that used to panic
+    for &(content, valid) in [
+        ("a +", false),
+        ("fun a:\n  1 +=", false),
+        ("fun\n", true),
+        ("struct A:\n  b:", false),
+        ("))\n  ]", false),
+        ("fun a(:", false),
+    ]
+    .iter()
+    {
+        let source = ctx.add_source(SourceEnt::new("bad.mf".to_string(), content.to_string()));
+        let mut temp_data = Data::default();
+        let mut saved_data = Data::default();
+        let mut reloc = Reloc::default();
+        let mut state = State::new(source, &ctx).unwrap();
+        let mut collector = Collector::default();
+        let mut data = DataCollector::new(&mut temp_data, &mut saved_data, &mut reloc);
+        let mut parser = Parser::new(&mut state, &mut data, &mut ctx, &mut collector);
+        let mut imports = vec![];
+        parser.parse_imports(&mut imports).unwrap();
+        assert_eq!(parser.parse().is_ok(), valid, "{:?}", content);
+    }
+
+    for &(first, first_level, first_assoc) in BUILTIN_PRECEDENCE {
+        for &(second, second_level, second_assoc) in BUILTIN_PRECEDENCE {
+            let text = format!("a {} b {} c", first, second);
+            let left = format!("((a {} b) {} c)", first, second);
+            let right = format!("(a {} (b {} c))", first, second);
+            let result = parse_expr(&mut ctx, &text);
+            match first_level.cmp(&second_level) {
+                std::cmp::Ordering::Less => assert_eq!(result.unwrap(), left),
+                std::cmp::Ordering::Greater => assert_eq!(result.unwrap(), right),
+                std::cmp::Ordering::Equal => match (first_assoc, second_assoc) {
+                    (Assoc::Left, Assoc::Left) => assert_eq!(result.unwrap(), left),
+                    (Assoc::Right, Assoc::Right) => assert_eq!(result.unwrap(), right),
+                    _ => assert!(result.is_err(), "{}", text),
+                },
+            }
+        }
+    }
+
+    for &(text, expected) in [
+        ("a + b * c - d", "((a + (b * c)) - d)"),
+        ("-a * -b", "((-a) * (-b))"),
+        ("a += b * c", "(a = (a + (b * c)))"),
+        ("a < b == c < d", "((a < b) == (c < d))"),
+    ]
+    .iter()
+    {
+        assert_eq!(parse_expr(&mut ctx, text).unwrap(), expected);
+    }
+
+    let declarations = [
+        (
+            concat!(
+                "attr prec(3, right)\nfun <+>(a, b: int) -> int: a\n",
+                "attr prec(3, right)\nfun <+>(a, b: f32) -> f32: a\n",
+            ),
+            true,
+        ),
+        (
+            concat!(
+                "attr prec(3, right)\nfun <+>(a, b: int) -> int: a\n",
+                "attr prec(5)\nfun <+>(a, b: f32) -> f32: a\n",
+            ),
+            false,
+        ),
+        ("attr prec(15)\nfun <*>(a, b: int) -> int: a\n", false),
+        ("attr prec(3, up)\nfun <*>(a, b: int) -> int: a\n", false),
+        ("attr prec(3)\nfun add(a, b: int) -> int: a\n", false),
+    ];
+    for &(content, valid) in declarations.iter() {
+        assert_eq!(parse_module(&mut ctx, content).is_ok(), valid, "{:?}", content);
+        ctx.clear_after_module();
+    }
+
+    // declarations are visible only in the module and its importers
+    parse_module(&mut ctx, declarations[0].0).unwrap();
+    assert_eq!(
+        parse_expr(&mut ctx, "a <+> b <+> c + d").unwrap(),
+        "((a <+> (b <+> c)) + d)"
+    );
+    let declared = ctx.swap_precedence(Precedence::new());
+    let mut importer = Precedence::new();
+    importer.import(&declared).unwrap();
+    assert_eq!(importer.get("<+>"), Some(OpInfo::new(3, Assoc::Right)));
+    let mut conflicting = Precedence::new();
+    conflicting
+        .declare("<+>", OpInfo::new(5, Assoc::Left), Token::default())
+        .unwrap();
+    assert!(importer.import(&conflicting).is_err());
+    ctx.clear_after_module();
+    assert_eq!(ctx.op_info("<+>"), OpInfo::new(DEFAULT_PRECEDENCE, Assoc::Left));
+
+    // trivia is recorded only on request and prints the file back
+    let content = concat!(
+        "# header\n",
+        "## doc\n",
+        "fun a(b: int) -> int: # after colon\n",
+        "  # inside\n",
+        "  return b #[ block ]# + 1 # after return\n",
+        "\n",
+        "# footer\n",
+    );
+    let source = ctx.add_source(SourceEnt::new("trivia.mf".to_string(), content.to_string()));
+    let mut temp_data = Data::with_trivia();
+    let mut saved_data = Data::default();
+    let mut reloc = Reloc::default();
+    let mut state = State::new(source, &ctx).unwrap();
+    let mut collector = Collector::default();
+    {
+        let mut data = DataCollector::new(&mut temp_data, &mut saved_data, &mut reloc);
+        Parser::new(&mut state, &mut data, &mut ctx, &mut collector)
+            .parse()
+            .unwrap();
+    }
+    assert!(saved_data.trivia().is_empty());
+
+    let mut printed = String::new();
+    let mut comments = vec![];
+    for &(token, trivia) in temp_data.trivia() {
+        printed.push_str(ctx.display(trivia.leading()));
+        printed.push_str(ctx.display(token.span()));
+        printed.push_str(ctx.display(trivia.trailing()));
+        ctx.trivia_comments(trivia.leading(), &mut comments);
+        ctx.trivia_comments(trivia.trailing(), &mut comments);
+    }
+    assert_eq!(printed, content);
+    let comments = comments
+        .iter()
+        .map(|&comment| ctx.display(comment))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        comments,
+        [
+            "# header",
+            "# after colon",
+            "# inside",
+            "#[ block ]#",
+            "# after return",
+            "# footer"
+        ]
+    );
+
+    let comments_of = |text: &str| {
+        let start = content.find(text).unwrap();
+        let &(token, trivia) = temp_data
+            .trivia()
+            .iter()
+            .find(|(token, _)| token.range().start == start)
+            .unwrap();
+        assert_eq!(temp_data.trivia_of(token), Some(trivia));
+        let mut comments = vec![];
+        ctx.trivia_comments(trivia.leading(), &mut comments);
+        ctx.trivia_comments(trivia.trailing(), &mut comments);
+        comments
+            .iter()
+            .map(|&comment| ctx.display(comment).to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(comments_of(": #"), ["# after colon"]);
+    assert_eq!(comments_of("+ 1"), ["#[ block ]#"]);
+    assert_eq!(comments_of("1 #"), ["# after return"]);
+}
+
+/// Parses `content` as a module body.
+fn parse_module(ctx: &mut Ctx, content: &str) -> Result<bool> {
+    let source = ctx.add_source(SourceEnt::new("module.mf".to_string(), content.to_string()));
+    let mut temp_data = Data::default();
+    let mut saved_data = Data::default();
+    let mut reloc = Reloc::default();
+    let mut state = State::new(source, ctx)?;
+    let mut collector = Collector::default();
+    let mut data = DataCollector::new(&mut temp_data, &mut saved_data, &mut reloc);
+    Parser::new(&mut state, &mut data, ctx, &mut collector).parse()
+}
+
+/// Parses `text` as expression and returns it with every operation
+/// parenthesized.
+fn parse_expr(ctx: &mut Ctx, text: &str) -> Result<String> {
+    let source = ctx.add_source(SourceEnt::new("expr.mf".to_string(), text.to_string()));
+    let mut temp_data = Data::default();
+    let mut saved_data = Data::default();
+    let mut reloc = Reloc::default();
+    let mut state = State::new(source, ctx)?;
+    let mut collector = Collector::default();
+    let ast = {
+        let mut data = DataCollector::new(&mut temp_data, &mut saved_data, &mut reloc);
+        Parser::new(&mut state, &mut data, ctx, &mut collector).expr()?
+    };
+
+    let mut out = String::new();
+    parenthesized(ctx, &temp_data, ast, &mut out);
+    Ok(out)
+}
+
+fn parenthesized(ctx: &Ctx, data: &Data, ast: Ast, out: &mut String) {
+    match data.kind(ast) {
+        Kind::Binary => {
+            out.push('(');
+            parenthesized(ctx, data, data.son(ast, 1), out);
+            write!(out, " {} ", ctx.display(data.son_ent(ast, 0).span())).unwrap();
+            parenthesized(ctx, data, data.son(ast, 2), out);
+            out.push(')');
+        }
+        Kind::Unary => {
+            out.push('(');
+            out.push_str(ctx.display(data.son_ent(ast, 0).span()));
+            parenthesized(ctx, data, data.son(ast, 1), out);
+            out.push(')');
+        }
+        _ => out.push_str(ctx.display(data.span(ast))),
+    }
 }
@@ -0,0 +1,1321 @@
+//! Module format prints source files in canonical style. The file is parsed
+//! into [`ast::Data`] created with [`Data::with_trivia`] and the printer walks
+//! the tree. The tree decides the layout: indentation of blocks, spacing
+//! around operators, where lists break and in which order `use` lines go.
+//! Text of the tokens is taken from the tokens parser consumed, in source
+//! order, so literals, parentheses the tree does not keep and `a op= b` sugar
+//! are printed as written. Comments come from the recorded [`lexer::Trivia`]
+//! and are printed before the token they precede.
+use cranelift::codegen::packed_option::ReservedValue;
+
+use crate::{
+    ast::{self, Ast, Collector, Data, DataCollector, Kind, Parser, Reloc},
+    lexer::{self, token, Source, SourceEnt, Span, Token},
+};
+
+type Result<T = ()> = std::result::Result<T, ast::Error>;
+
+/// Lines longer then this are wrapped at the outermost list.
+pub const MAX_WIDTH: usize = 100;
+
+/// Formats `source` and appends the result to `out`. `indent_width` has the
+/// same meaning as in manifest, zero means [`lexer::DEFAULT_INDENT_WIDTH`].
+/// Only operators declared in the file itself are known to the lexer.
+pub fn format(ctx: &mut ast::Ctx, source: Source, indent_width: u8, out: &mut String) -> Result {
+    let mut operators = vec![];
+    ctx.declared_operators(source, &mut operators);
+    for op in operators {
+        ctx.add_operator(op);
+    }
+
+    let mut temp_data = Data::with_trivia();
+    let mut saved_data = Data::default();
+    let result = parse(ctx, source, indent_width, &mut temp_data, &mut saved_data);
+    ctx.clear_after_module();
+    ctx.take_warnings();
+    let eof = result?;
+
+    let ctx: &lexer::Ctx = ctx;
+    let content = ctx.source(source).content();
+    let mut tokens = temp_data
+        .trivia()
+        .iter()
+        .map(|&(token, _)| token)
+        .collect::<Vec<_>>();
+
+    let mut comments = vec![];
+    let mut end = 0;
+    for &(_, trivia) in temp_data.trivia() {
+        ctx.trivia_comments(trivia.leading(), &mut comments);
+        ctx.trivia_comments(trivia.trailing(), &mut comments);
+        end = trivia.trailing().range().end;
+    }
+    // indentation before the end of file is not consumed
+    ctx.trivia_comments(Span::new(source, end..content.len()), &mut comments);
+    comments.sort_by_key(|comment| comment.range().start);
+    comments.dedup();
+    tokens.push(eof);
+
+    let mut asts = vec![];
+    for &(saved, data) in [(false, &temp_data), (true, &saved_data)].iter() {
+        asts.extend(
+            data.iter()
+                .map(|(ast, ent)| (ent.token().range().start, saved, ast)),
+        );
+    }
+    asts.sort_by_key(|&(start, ..)| start);
+
+    let width = lexer::State::with_indent_width(source, indent_width).indent_width();
+    let mut printer = Printer {
+        ctx,
+        content,
+        temp: &temp_data,
+        saved: &saved_data,
+        data: &temp_data,
+        asts,
+        tokens,
+        comments,
+        indent_width: width as usize,
+
+        out: String::new(),
+        line: String::new(),
+        line_level: 0,
+        line_width: 0,
+        line_scope: usize::MAX,
+        pending: Some(0),
+        blank: false,
+        last_end: 0,
+        cursor: 0,
+        comment: 0,
+        level: 0,
+
+        lists: 0,
+        wrap: 0,
+        deepest: 0,
+        overflow: vec![],
+    };
+
+    printer.items(0);
+    printer.newline(0);
+    printer.flush(usize::MAX);
+    printer.finish_line();
+    out.push_str(&printer.out);
+
+    Ok(())
+}
+
+/// Parses the whole file, tokens parser consumes are recorded in
+/// `temp_data`. Returns the end of file token.
+fn parse(
+    ctx: &mut ast::Ctx,
+    source: Source,
+    indent_width: u8,
+    temp_data: &mut Data,
+    saved_data: &mut Data,
+) -> Result<Token> {
+    let mut reloc = Reloc::default();
+    let mut collector = Collector::default();
+    let mut state = ast::State::with_indent_width(source, indent_width, ctx)?;
+    {
+        let mut data = DataCollector::new(temp_data, saved_data, &mut reloc);
+        let mut parser = Parser::new(&mut state, &mut data, ctx, &mut collector);
+        parser.parse_imports(&mut vec![])?;
+        while parser.parse()? {}
+    }
+    Ok(state.current())
+}
+
+/// Printer of parsed file. Ast is walked while cursor moves over consumed
+/// tokens, every printed token is taken from the cursor.
+struct Printer<'a> {
+    ctx: &'a lexer::Ctx,
+    content: &'a str,
+    temp: &'a Data,
+    saved: &'a Data,
+    /// Data of the item being printed.
+    data: &'a Data,
+    /// All asts ordered by start of their token, flag is true for saved data.
+    asts: Vec<(usize, bool, Ast)>,
+    /// Consumed tokens followed by the end of file.
+    tokens: Vec<Token>,
+    comments: Vec<Span>,
+    indent_width: usize,
+
+    out: String,
+    /// Line being printed, without indentation.
+    line: String,
+    line_level: usize,
+    /// Width of code on the line, comments are not counted.
+    line_width: usize,
+    /// Index of [`Self::overflow`] the line belongs to.
+    line_scope: usize,
+    /// Level of the next line if current line is finished.
+    pending: Option<usize>,
+    /// Empty line should precede the next line.
+    blank: bool,
+    /// End of the last printed token or comment.
+    last_end: usize,
+    cursor: usize,
+    comment: usize,
+    /// Level of the block being printed.
+    level: usize,
+
+    /// Depth of lists in the current line.
+    lists: usize,
+    /// Lists less nested then this are broken.
+    wrap: usize,
+    /// Deepest list met in the current line.
+    deepest: usize,
+    /// Flag for each line being fit, true if some of its lines is too long.
+    overflow: Vec<bool>,
+}
+
+/// State of [`Printer`] to return to when line does not fit.
+struct Mark<'a> {
+    out: usize,
+    line: String,
+    line_level: usize,
+    line_width: usize,
+    line_scope: usize,
+    pending: Option<usize>,
+    blank: bool,
+    last_end: usize,
+    cursor: usize,
+    comment: usize,
+    level: usize,
+    data: &'a Data,
+}
+
+impl<'a> Printer<'a> {
+    /// Prints items of the file or `impl` block until the block ends.
+    fn items(&mut self, level: usize) {
+        loop {
+            let index = self.next_index();
+            if self.tokens[index].kind() == token::Kind::Eof
+                || level != 0 && self.indent_before(index) < level
+            {
+                break;
+            }
+            self.line(level);
+            self.fit(|s| s.item(level));
+        }
+    }
+
+    fn item(&mut self, level: usize) {
+        let token = self.peek();
+        let start = token.range().start;
+        match token.kind() {
+            token::Kind::Use => self.use_block(),
+            token::Kind::Attr => self.attr(),
+            token::Kind::Comment(_) | token::Kind::Break => {
+                self.token();
+            }
+            token::Kind::Impl => {
+                let ast = self.find(start, |kind| matches!(kind, Kind::Impl(_)));
+                self.impl_block(ast, level);
+            }
+            token::Kind::Ident => {
+                let ast = self.find(start, |kind| kind == Kind::BoundAlias);
+                let sons = self.data.sons(ast);
+                self.leaf(sons[0]);
+                self.space();
+                self.token();
+                self.space();
+                self.leaf(sons[1]);
+            }
+            _ => {
+                let ast = self.find(start, |kind| {
+                    matches!(
+                        kind,
+                        Kind::Fun
+                            | Kind::Struct(_)
+                            | Kind::Union(_)
+                            | Kind::Enum(_)
+                            | Kind::Bound(_)
+                            | Kind::TypeAlias(_)
+                            | Kind::Const(_)
+                            | Kind::VarStatement(..)
+                    )
+                });
+                match self.data.kind(ast) {
+                    Kind::Fun => self.fun(ast),
+                    Kind::Struct(_) | Kind::Union(_) => self.structure(ast),
+                    Kind::Enum(_) => self.enum_declaration(ast),
+                    Kind::Bound(_) => self.bound_declaration(ast),
+                    Kind::TypeAlias(_) => self.type_alias(ast),
+                    Kind::Const(_) => self.const_declaration(ast),
+                    _ => self.var_statement(ast),
+                }
+            }
+        }
+    }
+
+    /// Prints `use` block. Lines are sorted by path unless comments
+    /// are among them.
+    fn use_block(&mut self) {
+        self.expect(token::Kind::Use);
+        if self.same_line() {
+            self.space();
+            self.import();
+            return;
+        }
+
+        let start = self.cursor;
+        let mut lines = vec![];
+        loop {
+            let index = self.next_index();
+            let token = self.tokens[index];
+            if token.kind() == token::Kind::Eof || self.indent_before(index) < 1 {
+                break;
+            }
+            self.cursor = index + 1;
+            let nickname = if token.kind() == token::Kind::Ident {
+                self.cursor += 1;
+                Some(token)
+            } else {
+                None
+            };
+            lines.push((nickname, self.tokens[self.cursor - 1]));
+        }
+        let end = self.tokens[self.cursor - 1].range().end;
+
+        let commented = matches!(
+            self.comments.get(self.comment),
+            Some(comment) if comment.range().start < end
+        );
+        if commented {
+            self.cursor = start;
+            self.body(lines.len(), |s, _| s.import());
+            return;
+        }
+
+        let ctx = self.ctx;
+        lines.sort_by_key(|&(_, path)| ctx.display_token(path));
+        for (nickname, path) in lines {
+            self.newline(1);
+            if let Some(nickname) = nickname {
+                self.write(ctx.display_token(nickname));
+                self.write(" ");
+            }
+            self.write(ctx.display_token(path));
+        }
+        self.last_end = end;
+        self.flush_block(1);
+        self.newline(0);
+    }
+
+    fn import(&mut self) {
+        if self.peek().kind() == token::Kind::Ident {
+            self.token();
+            self.space();
+        }
+        self.token();
+    }
+
+    fn attr(&mut self) {
+        self.expect(token::Kind::Attr);
+        self.space();
+        loop {
+            let start = self.peek().range().start;
+            let ast = self.find(start, |kind| {
+                matches!(
+                    kind,
+                    Kind::AttributeElement | Kind::AttributeAssign | Kind::Lit
+                )
+            });
+            self.attr_element(ast);
+            if self.tokens[self.cursor].kind() != token::Kind::Comma {
+                break;
+            }
+            self.token();
+            self.space();
+        }
+    }
+
+    fn attr_element(&mut self, ast: Ast) {
+        let data = self.data;
+        let sons = data.sons(ast);
+        match data.kind(ast) {
+            Kind::AttributeElement => {
+                self.leaf(sons[0]);
+                if self.tokens[self.cursor].kind() == token::Kind::LPar {
+                    self.list(token::Kind::LPar, &sons[1..], token::Kind::RPar);
+                }
+            }
+            Kind::AttributeAssign => {
+                self.leaf(sons[0]);
+                self.space();
+                self.token();
+                self.space();
+                self.expr(sons[1]);
+            }
+            _ => self.leaf(ast),
+        }
+    }
+
+    fn impl_block(&mut self, ast: Ast, level: usize) {
+        let data = self.data;
+        let sons = data.sons(ast);
+        self.expect(token::Kind::Impl);
+        self.generics(sons[0]);
+        self.space();
+        self.expr(sons[1]);
+        if !sons[2].is_reserved_value() {
+            self.space();
+            self.expect(token::Kind::For);
+            self.space();
+            self.expr(sons[2]);
+        }
+        if self.tokens[self.cursor].kind() != token::Kind::Colon {
+            return;
+        }
+        self.token();
+        if self.same_line() {
+            self.space();
+            self.item(level + 1);
+            return;
+        }
+
+        let outer = self.level;
+        self.level = level + 1;
+        self.items(level + 1);
+        self.flush_block(level + 1);
+        self.level = outer;
+        self.newline(outer);
+    }
+
+    fn fun(&mut self, ast: Ast) {
+        let sons = self.data.sons(ast);
+        self.fun_header(sons[0]);
+        if !sons[1].is_reserved_value() {
+            self.block(sons[1], Self::statement);
+        }
+    }
+
+    fn fun_header(&mut self, ast: Ast) {
+        let data = self.data;
+        let sons = data.sons(ast);
+        let end = data.token(ast).range().end;
+        self.expect(token::Kind::Fun);
+        self.generics(sons[0]);
+        if !sons[1].is_reserved_value() {
+            self.space();
+            self.leaf(sons[1]);
+        }
+        if self.tokens[self.cursor].kind() == token::Kind::LPar {
+            self.list(
+                token::Kind::LPar,
+                &sons[2..sons.len() - 1],
+                token::Kind::RPar,
+            );
+        }
+        let ret = sons[sons.len() - 1];
+        if !ret.is_reserved_value() {
+            self.space();
+            self.expect(token::Kind::RArrow);
+            self.space();
+            self.expr(ret);
+        }
+        let call_conv = self.tokens[self.cursor];
+        if call_conv.kind() == token::Kind::Ident && call_conv.range().start < end {
+            self.space();
+            self.token();
+        }
+    }
+
+    fn fun_argument(&mut self, ast: Ast) {
+        if self.peek().kind() == token::Kind::Var {
+            self.token();
+            self.space();
+        }
+        self.typed(self.data.sons(ast));
+    }
+
+    /// Prints identifiers separated by commas followed by type, which
+    /// is the last of `sons`.
+    fn typed(&mut self, sons: &[Ast]) {
+        let (&ty, idents) = sons.split_last().unwrap();
+        for (i, &ident) in idents.iter().enumerate() {
+            if i != 0 {
+                self.expect(token::Kind::Comma);
+                self.space();
+            }
+            self.leaf(ident);
+        }
+        self.expect(token::Kind::Colon);
+        self.space();
+        self.expr(ty);
+    }
+
+    /// Prints visibility and generic parameters following the keyword,
+    /// parameters are attached to the keyword if there is no visibility.
+    fn generics(&mut self, generics: Ast) {
+        let vis = self.vis();
+        if !generics.is_reserved_value() {
+            if vis {
+                self.space();
+            }
+            self.list(
+                token::Kind::LBra,
+                self.data.sons(generics),
+                token::Kind::RBra,
+            );
+        }
+    }
+
+    fn vis(&mut self) -> bool {
+        let vis = matches!(self.peek().kind(), token::Kind::Pub | token::Kind::Priv);
+        if vis {
+            self.space();
+            self.token();
+        }
+        vis
+    }
+
+    fn generic_param(&mut self, ast: Ast) {
+        let sons = self.data.sons(ast);
+        self.leaf(sons[0]);
+        if !sons[1].is_reserved_value() {
+            self.expect(token::Kind::Colon);
+            self.space();
+            self.expr(sons[1]);
+        }
+    }
+
+    fn structure(&mut self, ast: Ast) {
+        let sons = self.data.sons(ast);
+        self.token();
+        self.generics(sons[0]);
+        self.space();
+        self.leaf(sons[1]);
+        if !sons[2].is_reserved_value() {
+            self.block(sons[2], Self::field);
+        }
+    }
+
+    fn field(&mut self, ast: Ast) {
+        self.vis();
+        if let Kind::StructField(_, true) = self.data.kind(ast) {
+            self.space();
+            self.expect(token::Kind::Embed);
+        }
+        self.space();
+        self.typed(self.data.sons(ast));
+    }
+
+    fn enum_declaration(&mut self, ast: Ast) {
+        let sons = self.data.sons(ast);
+        self.token();
+        self.vis();
+        self.space();
+        self.leaf(sons[0]);
+        if !sons[1].is_reserved_value() {
+            self.block(sons[1], Self::variant);
+        }
+    }
+
+    fn variant(&mut self, ast: Ast) {
+        let sons = self.data.sons(ast);
+        self.leaf(sons[0]);
+        if !sons[1].is_reserved_value() {
+            self.space();
+            self.token();
+            self.space();
+            self.expr(sons[1]);
+        }
+    }
+
+    fn bound_declaration(&mut self, ast: Ast) {
+        let sons = self.data.sons(ast);
+        self.token();
+        self.generics(sons[0]);
+        self.space();
+        self.leaf(sons[1]);
+        if !sons[2].is_reserved_value() {
+            self.space();
+            self.expect(token::Kind::Embed);
+            self.space();
+            self.expr(sons[2]);
+        }
+        if !sons[3].is_reserved_value() {
+            self.block(sons[3], Self::fun);
+        }
+    }
+
+    fn type_alias(&mut self, ast: Ast) {
+        let sons = self.data.sons(ast);
+        self.token();
+        self.vis();
+        self.space();
+        self.leaf(sons[1]);
+        if !sons[0].is_reserved_value() {
+            self.list(
+                token::Kind::LBra,
+                self.data.sons(sons[0]),
+                token::Kind::RBra,
+            );
+        }
+        self.space();
+        self.token();
+        self.space();
+        self.expr(sons[2]);
+    }
+
+    fn const_declaration(&mut self, ast: Ast) {
+        let sons = self.data.sons(ast);
+        self.token();
+        self.vis();
+        self.space();
+        self.leaf(sons[0]);
+        self.expect(token::Kind::Colon);
+        self.space();
+        self.expr(sons[1]);
+        self.space();
+        self.token();
+        self.space();
+        self.expr(sons[2]);
+    }
+
+    fn var_statement(&mut self, ast: Ast) {
+        let sons = self.data.sons(ast);
+        self.token();
+        self.vis();
+        if self.same_line() {
+            self.space();
+            self.var_assign(sons[0]);
+        } else {
+            self.body(sons.len(), |s, i| s.var_assign(sons[i]));
+        }
+    }
+
+    fn var_assign(&mut self, ast: Ast) {
+        let data = self.data;
+        let sons = data.sons(ast);
+        let idents = data.sons(sons[0]);
+        for (i, &ident) in idents.iter().enumerate() {
+            if i != 0 {
+                self.expect(token::Kind::Comma);
+                self.space();
+            }
+            self.leaf(ident);
+        }
+        if !sons[1].is_reserved_value() {
+            self.expect(token::Kind::Colon);
+            self.space();
+            self.expr(sons[1]);
+        }
+        if !sons[2].is_reserved_value() {
+            self.space();
+            self.token();
+            self.space();
+            // single value is repeated for the other identifiers
+            for (i, &value) in data.sons(sons[2]).iter().enumerate() {
+                if i != 0 {
+                    if value == idents[0] {
+                        break;
+                    }
+                    self.expect(token::Kind::Comma);
+                    self.space();
+                }
+                self.expr(value);
+            }
+        }
+    }
+
+    fn statement(&mut self, ast: Ast) {
+        let data = self.data;
+        let sons = data.sons(ast);
+        match data.kind(ast) {
+            Kind::Return | Kind::Break | Kind::Continue => {
+                self.token();
+                for (i, &son) in sons.iter().enumerate() {
+                    if son.is_reserved_value() {
+                        continue;
+                    }
+                    self.space();
+                    // label of break and continue is first
+                    if i == 0 && data.kind(ast) != Kind::Return {
+                        self.leaf(son);
+                    } else {
+                        self.expr(son);
+                    }
+                }
+            }
+            Kind::VarStatement(..) => self.var_statement(ast),
+            _ => self.expr(ast),
+        }
+    }
+
+    /// Prints the block of `group` with `element` for each son. Single
+    /// element stays on the line of the block header if it was there.
+    fn block(&mut self, group: Ast, element: fn(&mut Self, Ast)) {
+        let sons = self.data.sons(group);
+        self.expect(token::Kind::Colon);
+        if !self.same_line() {
+            self.body(sons.len(), |s, i| element(s, sons[i]));
+        } else if let Some(&son) = sons.first() {
+            self.space();
+            element(self, son);
+        }
+    }
+
+    /// Prints `len` lines of block one level deeper then current one.
+    fn body(&mut self, len: usize, mut element: impl FnMut(&mut Self, usize)) {
+        let outer = self.level;
+        self.level += 1;
+        for i in 0..len {
+            self.line(self.level);
+            self.fit(|s| element(s, i));
+        }
+        self.flush_block(self.level);
+        self.level = outer;
+        self.newline(outer);
+    }
+
+    fn element(&mut self, ast: Ast) {
+        match self.data.kind(ast) {
+            Kind::FunArgument(_) => self.fun_argument(ast),
+            Kind::GenericParam => self.generic_param(ast),
+            Kind::AttributeElement | Kind::AttributeAssign => self.attr_element(ast),
+            _ => self.expr(ast),
+        }
+    }
+
+    fn expr(&mut self, ast: Ast) {
+        let data = self.data;
+        let (kind, _, token) = data.parts(ast);
+
+        // parentheses are not part of the tree, they wrap the expression
+        // if they close after its end
+        let index = self.next_index();
+        if self.tokens[index].kind() == token::Kind::LPar {
+            let close = self.matching(index);
+            if self.tokens[close].range().start >= token.range().end {
+                self.token();
+                self.expr(ast);
+                self.expect(token::Kind::RPar);
+                return;
+            }
+        }
+
+        let sons = data.sons(ast);
+        match kind {
+            Kind::Path => {
+                for (i, &son) in sons.iter().enumerate() {
+                    if i != 0 {
+                        self.expect(token::Kind::DoubleColon);
+                    }
+                    self.leaf(son);
+                }
+            }
+            Kind::Instantiation => {
+                self.expr(sons[0]);
+                if self.tokens[self.cursor].kind() == token::Kind::DoubleColon {
+                    self.token();
+                }
+                self.list(token::Kind::LBra, &sons[1..], token::Kind::RBra);
+            }
+            Kind::Binary => self.binary(sons),
+            Kind::Unary => {
+                let op = self.token();
+                self.operand(op, sons[1]);
+            }
+            Kind::Ref(mutable) => {
+                let op = self.token();
+                if mutable {
+                    self.expect(token::Kind::Var);
+                    self.space();
+                }
+                self.operand(op, sons[0]);
+            }
+            Kind::Deref => {
+                let op = self.token();
+                self.operand(op, sons[0]);
+            }
+            Kind::Dot => {
+                self.expr(sons[0]);
+                self.expect(token::Kind::Dot);
+                self.expr(sons[1]);
+            }
+            Kind::Call(false) => {
+                self.expr(sons[0]);
+                self.list(token::Kind::LPar, &sons[1..], token::Kind::RPar);
+            }
+            Kind::Call(true) => {
+                self.expr(sons[1]);
+                self.expect(token::Kind::Dot);
+                self.expr(sons[0]);
+                self.list(token::Kind::LPar, &sons[2..], token::Kind::RPar);
+            }
+            Kind::Index => {
+                self.expr(sons[0]);
+                self.expect(token::Kind::LBra);
+                self.expr(sons[1]);
+                self.expect(token::Kind::RBra);
+            }
+            Kind::Array => self.list(token::Kind::LBra, sons, token::Kind::RBra),
+            Kind::Tuple => self.tuple(sons),
+            Kind::If => self.if_expr(ast),
+            Kind::Loop | Kind::While | Kind::ForIn => self.loop_expr(ast),
+            Kind::Match => {
+                self.expect(token::Kind::Match);
+                self.space();
+                self.expr(sons[0]);
+                self.block(sons[1], Self::match_arm);
+            }
+            Kind::Range(inclusive) => {
+                self.expr(sons[0]);
+                self.expect(token::Kind::Dot);
+                self.expect(token::Kind::Dot);
+                if inclusive {
+                    self.token();
+                }
+                self.expr(sons[1]);
+            }
+            Kind::Lambda => {
+                self.fun_header(sons[0]);
+                self.block(sons[1], Self::statement);
+            }
+            Kind::FunHeader(..) => self.fun_header(ast),
+            Kind::Bounds => {
+                for (i, &son) in sons.iter().enumerate() {
+                    if i != 0 {
+                        self.space();
+                        self.token();
+                        self.space();
+                    }
+                    self.expr(son);
+                }
+            }
+            _ => self.leaf(ast),
+        }
+    }
+
+    fn binary(&mut self, sons: &[Ast]) {
+        let data = self.data;
+        let (op, left, right) = (sons[0], sons[1], sons[2]);
+        // `a op= b` is expanded to `a = a op b` with operator token split
+        let sugar = data.kind(right) == Kind::Binary
+            && data.son_ent(right, 0).token().range().end == data.token(op).range().start;
+        self.expr(left);
+        self.space();
+        if sugar {
+            self.token();
+        } else {
+            self.leaf(op);
+        }
+        self.space();
+        self.expr(if sugar { data.son(right, 2) } else { right });
+    }
+
+    /// Prints operand of prefix operator `op`. Operator characters
+    /// are kept apart only if they were apart in the source.
+    fn operand(&mut self, op: Token, ast: Ast) {
+        let next = self.peek();
+        let alphabetic = self.ctx.display_token(op).starts_with(char::is_alphabetic);
+        if alphabetic || next.kind() == token::Kind::Op && next.range().start != self.last_end {
+            self.space();
+        }
+        self.expr(ast);
+    }
+
+    fn tuple(&mut self, sons: &[Ast]) {
+        self.expect(token::Kind::LPar);
+        for (i, &son) in sons.iter().enumerate() {
+            if i != 0 {
+                self.expect(token::Kind::Comma);
+                self.space();
+            }
+            self.expr(son);
+        }
+        // tuple of one element needs the comma
+        if self.peek().kind() == token::Kind::Comma {
+            if sons.len() == 1 {
+                self.token();
+            } else {
+                self.skip();
+            }
+        }
+        self.expect(token::Kind::RPar);
+    }
+
+    fn if_expr(&mut self, ast: Ast) {
+        let data = self.data;
+        let sons = data.sons(ast);
+        self.token();
+        self.space();
+        self.expr(sons[0]);
+        self.block(sons[1], Self::statement);
+
+        let other = sons[2];
+        if other.is_reserved_value() {
+            return;
+        }
+        if self.same_line() {
+            self.space();
+        } else {
+            self.newline(self.level);
+        }
+        if data.kind(other) == Kind::Elif {
+            self.if_expr(data.son(other, 0));
+        } else {
+            self.expect(token::Kind::Else);
+            self.block(other, Self::statement);
+        }
+    }
+
+    fn loop_expr(&mut self, ast: Ast) {
+        let data = self.data;
+        let sons = data.sons(ast);
+        self.expect(token::Kind::For);
+        if !sons[0].is_reserved_value() {
+            self.space();
+            self.leaf(sons[0]);
+        }
+        let body = match data.kind(ast) {
+            Kind::While => {
+                self.space();
+                self.expr(sons[1]);
+                sons[2]
+            }
+            Kind::ForIn => {
+                self.space();
+                self.expr(sons[1]);
+                self.space();
+                self.expect(token::Kind::In);
+                self.space();
+                self.expr(sons[2]);
+                sons[3]
+            }
+            _ => sons[1],
+        };
+        self.block(body, Self::statement);
+    }
+
+    fn match_arm(&mut self, ast: Ast) {
+        let sons = self.data.sons(ast);
+        self.expr(sons[0]);
+        self.block(sons[1], Self::statement);
+    }
+
+    /// Prints `sons` as a list enclosed in `open` and `close`. List is
+    /// broken to one element per line if it is nested less then
+    /// [`Self::wrap`] or comment inside it ends the line.
+    fn list(&mut self, open: token::Kind, sons: &[Ast], close: token::Kind) {
+        let index = self.next_index();
+        let end = self.tokens[self.matching(index)].range().start;
+        let depth = self.lists;
+        self.deepest = self.deepest.max(depth + 1);
+        let broken = !sons.is_empty()
+            && (depth < self.wrap || self.has_line_comment(self.tokens[index].range().start, end));
+
+        self.expect(open);
+        let level = self.line_level;
+        self.lists += 1;
+        for (i, &son) in sons.iter().enumerate() {
+            if broken {
+                self.newline(level + 1);
+            }
+            self.element(son);
+            if i + 1 < sons.len() {
+                self.expect(token::Kind::Comma);
+                self.space();
+            } else if self.peek().kind() == token::Kind::Comma {
+                if broken {
+                    self.token();
+                } else {
+                    self.skip();
+                }
+            } else if broken {
+                self.write(",");
+            }
+        }
+        self.lists -= 1;
+        if broken {
+            self.newline(level);
+        }
+        self.expect(close);
+    }
+
+    /// Prints the line with `print`. Lists are broken, outermost
+    /// first, until all lines fit into [`MAX_WIDTH`].
+    fn fit(&mut self, mut print: impl FnMut(&mut Self)) {
+        let mark = self.mark();
+        let outer = (self.lists, self.wrap, self.deepest);
+        let mut wrap = 0;
+        loop {
+            self.lists = 0;
+            self.wrap = wrap;
+            self.deepest = 0;
+            self.overflow.push(false);
+            print(self);
+            self.measure();
+            let overflow = self.overflow.pop().unwrap();
+            if !overflow || wrap >= self.deepest {
+                break;
+            }
+            wrap += 1;
+            self.reset(&mark);
+        }
+        self.lists = outer.0;
+        self.wrap = outer.1;
+        self.deepest = outer.2;
+    }
+
+    fn mark(&self) -> Mark<'a> {
+        Mark {
+            out: self.out.len(),
+            line: self.line.clone(),
+            line_level: self.line_level,
+            line_width: self.line_width,
+            line_scope: self.line_scope,
+            pending: self.pending,
+            blank: self.blank,
+            last_end: self.last_end,
+            cursor: self.cursor,
+            comment: self.comment,
+            level: self.level,
+            data: self.data,
+        }
+    }
+
+    fn reset(&mut self, mark: &Mark<'a>) {
+        self.out.truncate(mark.out);
+        self.line.clone_from(&mark.line);
+        self.line_level = mark.line_level;
+        self.line_width = mark.line_width;
+        self.line_scope = mark.line_scope;
+        self.pending = mark.pending;
+        self.blank = mark.blank;
+        self.last_end = mark.last_end;
+        self.cursor = mark.cursor;
+        self.comment = mark.comment;
+        self.level = mark.level;
+        self.data = mark.data;
+    }
+
+    /// Finds ast of `kind` starting at `start` and makes its data current.
+    fn find(&mut self, start: usize, kind: fn(Kind) -> bool) -> Ast {
+        let first = self.asts.partition_point(|&(s, ..)| s < start);
+        let (temp, saved) = (self.temp, self.saved);
+        let data_of = |saved_data| if saved_data { saved } else { temp };
+        let &(_, in_saved, ast) = self.asts[first..]
+            .iter()
+            .take_while(|&&(s, ..)| s == start)
+            .find(|&&(_, in_saved, ast)| kind(data_of(in_saved).kind(ast)))
+            .expect("parser keeps every item in data");
+        self.data = data_of(in_saved);
+        ast
+    }
+
+    /// Prints tokens of leaf `ast`. Name of operator function can
+    /// be made of more tokens.
+    fn leaf(&mut self, ast: Ast) {
+        let end = self.data.token(ast).range().end;
+        self.token();
+        while self.tokens[self.cursor].range().start < end
+            && self.tokens[self.cursor].range().start == self.last_end
+        {
+            let token = self.tokens[self.cursor];
+            self.write(self.ctx.display_token(token));
+            self.cursor += 1;
+            self.last_end = token.range().end;
+        }
+    }
+
+    /// Prints the next token.
+    fn token(&mut self) -> Token {
+        let index = self.next_index();
+        let token = self.tokens[index];
+        self.flush(token.range().start);
+        self.write(self.ctx.display_token(token));
+        self.cursor = index + 1;
+        self.last_end = token.range().end;
+        token
+    }
+
+    fn expect(&mut self, kind: token::Kind) {
+        debug_assert_eq!(self.peek().kind(), kind);
+        self.token();
+    }
+
+    /// Moves over the next token without printing it.
+    fn skip(&mut self) {
+        let index = self.next_index();
+        let token = self.tokens[index];
+        self.flush(token.range().start);
+        self.cursor = index + 1;
+        self.last_end = token.range().end;
+    }
+
+    fn peek(&self) -> Token {
+        self.tokens[self.next_index()]
+    }
+
+    /// Returns index of the next token that is not indentation.
+    fn next_index(&self) -> usize {
+        let mut index = self.cursor;
+        while let token::Kind::Indent(_) = self.tokens[index].kind() {
+            index += 1;
+        }
+        index
+    }
+
+    /// Returns true if next token is on the line of the last one.
+    fn same_line(&self) -> bool {
+        !matches!(self.tokens[self.cursor].kind(), token::Kind::Indent(_))
+    }
+
+    /// Returns the level of line token at `index` is on, or [`usize::MAX`]
+    /// if it does not start the line.
+    fn indent_before(&self, index: usize) -> usize {
+        match self.tokens[..index].last().map(|token| token.kind()) {
+            Some(token::Kind::Indent(level)) => level as usize,
+            Some(_) => usize::MAX,
+            None => 0,
+        }
+    }
+
+    /// Returns index of bracket closing the one at `index`.
+    fn matching(&self, index: usize) -> usize {
+        let mut depth = 0;
+        for (i, token) in self.tokens.iter().enumerate().skip(index) {
+            match token.kind() {
+                token::Kind::LPar | token::Kind::LBra | token::Kind::LCurly => depth += 1,
+                token::Kind::RPar | token::Kind::RBra | token::Kind::RCurly => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return i;
+                    }
+                }
+                _ => (),
+            }
+        }
+        self.tokens.len() - 1
+    }
+
+    /// Starts a line of block on `level`. Comments before it are printed
+    /// and an empty line is kept if there was one.
+    fn line(&mut self, level: usize) {
+        self.newline(level);
+        let next = self.peek().range().start;
+        self.flush(next);
+        if self.has_blank(next) {
+            self.blank = true;
+        }
+    }
+
+    fn newline(&mut self, level: usize) {
+        self.pending = Some(level);
+    }
+
+    fn space(&mut self) {
+        if self.pending.is_none() && !self.line.is_empty() && !self.line.ends_with(' ') {
+            self.write(" ");
+        }
+    }
+
+    fn write(&mut self, text: &str) {
+        if let Some(level) = self.pending.take() {
+            self.start_line(level);
+        }
+        let text = if self.line.is_empty() {
+            text.trim_start()
+        } else {
+            text
+        };
+        self.line.push_str(text);
+        self.line_width += text.chars().count();
+    }
+
+    fn start_line(&mut self, level: usize) {
+        self.finish_line();
+        if self.blank && !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.blank = false;
+        self.line_level = level;
+        self.line_scope = self.overflow.len().wrapping_sub(1);
+    }
+
+    fn finish_line(&mut self) {
+        if self.line.is_empty() {
+            return;
+        }
+        self.measure();
+        for _ in 0..self.line_level * self.indent_width {
+            self.out.push(' ');
+        }
+        self.out.push_str(self.line.trim_end());
+        self.out.push('\n');
+        self.line.clear();
+        self.line_width = 0;
+    }
+
+    /// Marks line being fit as overflowing if current line is too long.
+    fn measure(&mut self) {
+        if self.line_level * self.indent_width + self.line_width > MAX_WIDTH {
+            if let Some(overflow) = self.overflow.get_mut(self.line_scope) {
+                *overflow = true;
+            }
+        }
+        self.line_scope = usize::MAX;
+    }
+
+    /// Prints comments starting before `pos`.
+    fn flush(&mut self, pos: usize) {
+        while let Some(&comment) = self.comments.get(self.comment) {
+            if comment.range().start >= pos {
+                break;
+            }
+            self.emit(comment);
+        }
+    }
+
+    /// Prints comments that belong to block on `level` that just ended.
+    fn flush_block(&mut self, level: usize) {
+        self.newline(level);
+        let next = self.peek().range().start;
+        while let Some(&comment) = self.comments.get(self.comment) {
+            let start = comment.range().start;
+            if start >= next || self.own_line(start) && self.source_level(start) < level {
+                break;
+            }
+            self.emit(comment);
+        }
+    }
+
+    /// Prints the comment. Comment on its own line stays there, other
+    /// comments follow code of the current line.
+    fn emit(&mut self, comment: Span) {
+        self.comment += 1;
+        let start = comment.range().start;
+        let text = self.ctx.display(comment);
+        if self.own_line(start) {
+            let level = match self.pending {
+                Some(level) => {
+                    if self.has_blank(start) {
+                        self.blank = true;
+                    }
+                    level
+                }
+                None => self.line_level + 1,
+            };
+            self.start_line(level);
+            self.line.push_str(text);
+            let rest = self.content[comment.range().end..].lines().next();
+            if Self::is_block_comment(text) && matches!(rest, Some(rest) if !rest.trim().is_empty())
+            {
+                // code after block comment stays on its line
+                self.line.push(' ');
+                self.pending = None;
+            } else {
+                self.newline(level);
+            }
+        } else {
+            if !self.line.is_empty() && !self.line.ends_with(' ') {
+                self.line.push(' ');
+            }
+            self.line.push_str(text);
+            if Self::is_block_comment(text) {
+                self.line.push(' ');
+            } else if self.pending.is_none() {
+                self.newline(self.line_level + 1);
+            }
+        }
+        self.last_end = comment.range().end;
+    }
+
+    /// Returns true if line comment or comment on its own line is
+    /// between `start` and `end`.
+    fn has_line_comment(&self, start: usize, end: usize) -> bool {
+        self.comments[self.comment..]
+            .iter()
+            .map(|comment| comment.range().start)
+            .take_while(|&pos| pos < end)
+            .any(|pos| {
+                pos > start && (self.own_line(pos) || !Self::is_block_comment(&self.content[pos..]))
+            })
+    }
+
+    fn is_block_comment(text: &str) -> bool {
+        text.starts_with("#[") || text.starts_with("##[")
+    }
+
+    /// Returns true if only whitespace precedes `pos` on its line.
+    fn own_line(&self, pos: usize) -> bool {
+        self.line_prefix(pos).trim().is_empty()
+    }
+
+    /// Returns indentation level of line `pos` is on.
+    fn source_level(&self, pos: usize) -> usize {
+        let prefix = self.line_prefix(pos);
+        let tabs = prefix.matches('\t').count();
+        tabs + prefix.matches(' ').count() / self.indent_width.max(1)
+    }
+
+    fn line_prefix(&self, pos: usize) -> &'a str {
+        let content = self.content;
+        let start = content[..pos].rfind('\n').map_or(0, |i| i + 1);
+        &content[start..pos]
+    }
+
+    /// Returns true if there is an empty line between last printed
+    /// text and `pos`.
+    fn has_blank(&self, pos: usize) -> bool {
+        self.content[self.last_end.min(pos)..pos]
+            .matches('\n')
+            .count()
+            >= 2
+    }
+}
+
+pub fn test() {
+    let mut ctx = ast::Ctx::default();
+
+    let expected = include_str!("format_test_out.mf");
+    let source = ctx.add_source(SourceEnt::new(
+        "format_test.mf".to_string(),
+        include_str!("format_test.mf").to_string(),
+    ));
+    let mut out = String::new();
+    format(&mut ctx, source, 0, &mut out).unwrap();
+    assert_eq!(out, expected);
+
+    for &(name, content) in [
+        ("format_test_out.mf", expected),
+        ("ast_test.mf", include_str!("../ast/ast_test.mf")),
+        ("builtin.mf", include_str!("../modules/builtin.mf")),
+        ("root.mf", include_str!("../gen/test_project/root.mf")),
+        ("main.mf", include_str!("../types/test_project/main.mf")),
+    ]
+    .iter()
+    {
+        let source = ctx.add_source(SourceEnt::new(name.to_string(), content.to_string()));
+        let mut once = String::new();
+        format(&mut ctx, source, 0, &mut once).unwrap();
+
+        let formatted = ctx.add_source(SourceEnt::new(name.to_string(), once.clone()));
+        let mut twice = String::new();
+        format(&mut ctx, formatted, 0, &mut twice).unwrap();
+        assert_eq!(once, twice, "{} is not formatted idempotently", name);
+
+        let mut original = vec![];
+        let mut result = vec![];
+        let mut errors = vec![];
+        ctx.tokenize(source, &mut original, &mut errors);
+        ctx.tokenize(formatted, &mut result, &mut errors);
+        assert!(errors.is_empty());
+        // wrapping adds trailing commas
+        let texts = |tokens: &[Token]| {
+            tokens
+                .iter()
+                .filter(|token| {
+                    !matches!(token.kind(), token::Kind::Indent(_) | token::Kind::Comma)
+                })
+                .map(|&token| ctx.display_token(token).to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(texts(&original), texts(&result), "{} changed tokens", name);
+    }
+}
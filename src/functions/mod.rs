@@ -1,10 +1,13 @@
 use std::ops::{Deref, DerefMut};
 
 use crate::ast::{self, Ast, CallConv, Vis};
-use crate::lexer::{self, token, DisplayError, ErrorDisplay, ErrorDisplayState, Span, Token};
+use crate::lexer::{
+    self, token, DisplayError, ErrorDisplay, ErrorDisplayState, Span, Token, POINTER_WIDTH_MARKER,
+};
 use crate::modules::{self, *};
 use crate::types::{self, *};
 use crate::util::sdbm::ID;
+use crate::util::storage::Map;
 use crate::util::Size;
 
 use cranelift::codegen::ir::GlobalValue;
@@ -18,22 +21,26 @@ use quick_proc::{QuickDefault, QuickSer, RealQuickSer};
 type Result<T = ()> = std::result::Result<T, Error>;
 type ExprResult = Result<Option<Local>>;
 
+/// Last layer of the context, it translates function bodies into simple
+/// intermediate representation that is lowered by the generator.
 #[derive(Default)]
 pub struct Ctx {
     ctx: types::Ctx,
 
+    data: Data,
+
     vars: Vec<(ID, Local)>,
     loops: Vec<Loop>,
     frames: Vec<usize>,
-    labels: Vec<(ID, EntityList<Cmd>, Option<Section>)>,
-    label_insts: ListPool<Cmd>,
+    ret_ty: PackedOption<Ty>,
 
     in_assign: bool,
     in_var_ref: bool,
+    /// ID of function being translated, lambdas inside derive their ID from it.
+    fun_id: ID,
 
-    unresolved_globals: Vec<Global>,
     resolved_globals: Vec<Global>,
-    unresolved: Vec<Fun>,
+    unresolved: Vec<(Fun, bool)>,
     entry_points: Vec<Fun>,
 
     entry_point_data: MainFunData,
@@ -44,2346 +51,3224 @@ pub struct Ctx {
     bounds: PoolMap<Bound, BoundEnt>,
     bound_slices: ListPool<Bound>,
     fun_slices: ListPool<Fun>,
+    vtables: PrimaryMap<VTable, VTableEnt>,
+    vtable_lookup: Map<VTable>,
 
     do_stacktrace: bool,
 }
 
 impl Ctx {
-    /*pub fn parse(&mut self, module: Mod) -> Result {
-        TParser::new(self.state, self.context)
-            .parse(module)
-            .map_err(|err| Error::new(error::Kind::TypeError(err), Token::default()))?;
+    /// Runs all stages up to functions on project with root manifest in
+    /// `root`. Each stage is run module by module in order computed by
+    /// module tree. Warnings are left in context, entry point is not
+    /// finished.
+    pub fn compile_project(&mut self, root: &str) -> Result {
+        let mut item_buffer = vec![];
+        let mut temp_ast_data = ast::Data::default();
+        let mut saved_ast_data = ast::Data::default();
+        let mut collector = ast::Collector::default();
+        let mut reloc = ast::Reloc::default();
+
+        let order = self.compute_module_tree(root).map_err(Into::into)?;
+
+        for &module in &order {
+            self.collect_imported_items(module, &mut item_buffer);
+            for &(id, item) in item_buffer.iter() {
+                self.import_item(module, id, item).map_err(Into::into)?;
+            }
+            item_buffer.clear();
+
+            loop {
+                let mut data =
+                    ast::DataCollector::new(&mut temp_ast_data, &mut saved_ast_data, &mut reloc);
+                let more = self
+                    .compute_ast(module, &mut data, &mut collector)
+                    .map_err(Into::into)?;
+                let ast_data = ast::DataSwitch::new(&temp_ast_data, &saved_ast_data);
+                self.collect(module, ast_data, &mut collector)
+                    .map_err(Into::into)?;
+                self.compute_types(&ast_data).map_err(Into::into)?;
+                self.compute_funs(module, ast_data, &mut collector)?;
+                if !more {
+                    break;
+                }
+            }
+
+            reloc.clear();
+
+            self.clear_after_module();
+        }
+
+        Ok(())
+    }
+
+    /// Collects functions and global variables of the `module` and translates
+    /// all function bodies that are ready. Generic functions are translated
+    /// only once instantiated.
+    pub fn compute_funs(
+        &mut self,
+        module: Mod,
+        mut ast_data: ast::DataSwitch,
+        collector: &mut ast::Collector,
+    ) -> Result {
+        if module == BUILTIN_MODULE {
+            self.add_builtin_functions();
+            self.add_entry_point();
+        }
 
-        self.module = module;
+        let mut state = ScopeState::default();
+        let result = collector.use_funs(|saved, fun, attrs, scope| {
+            ast_data.set_swapped(saved);
+            self.parse_scope(&ast_data, module, scope, saved, &mut state)?;
+            self.collect_fun(
+                &ast_data, module, fun, attrs, scope, state.id, state.vis, saved,
+            )
+        });
+        self.leave_scope(module, &mut state);
+        result?;
+
+        let main = self.entry_point_data.id;
+        self.fun_id = self.funs[main].id;
+        let mut data = std::mem::take(&mut self.data);
+        let mut body = self.funs[main].body;
+        let result = self.collect_globals(
+            module,
+            ast_data,
+            collector,
+            &mut Builder::new(&mut data, &mut body),
+        );
+        self.data = data;
+        self.funs[main].body = body;
+        result?;
 
-        self.init_module(module);
-        self.collect(module)?;
-        self.translate()?;
-        self.finalize_module(module);
+        while let Some((fun, saved)) = self.unresolved.pop() {
+            ast_data.set_swapped(saved);
+            self.translate_fun(&ast_data, fun)?;
+        }
 
         Ok(())
     }
 
-    pub fn finalize_module(&mut self, module: Mod) {
+    /// Terminates the body of the entry point. Entry points can be declared
+    /// in any module so this has to be called after all modules are computed.
+    pub fn finish_entry_point(&mut self) {
         let MainFunData {
             id, return_value, ..
-        } = self.context.entry_point_data;
+        } = self.entry_point_data;
+        let mut data = std::mem::take(&mut self.data);
         let mut body = self.funs[id].body;
-        self.modules[module].add_return_stmt(Some(return_value), Token::default(), &mut body);
+        Builder::new(&mut data, &mut body).add_return_stmt(Some(return_value));
+        self.data = data;
         self.funs[id].body = body;
-        //self.context.entry_points.push(id);
     }
 
-    pub fn init_module(&mut self, module: Mod) {
-        let int = self.builtin_repo.int;
-        let module_ent = &mut self.modules[module];
-        let args = module_ent.add_type_slice(&[int, int]);
-        let ret = PackedOption::from(int);
-        let sig = Signature {
-            call_conv: CallConv::Fast,
-            args,
-            ret,
-        };
-        let id = FUN_SALT.add(ID::new("--entry--")).add(module_ent.id);
-        let fnu_ent = FunEnt {
-            id,
-            sig,
-            module,
-            kind: FKind::Represented,
-            untraced: true,
-            inline: true,
-
-            ..Default::default()
-        };
-        let (shadow, id) = self.add_fun(module, fnu_ent);
-        debug_assert!(shadow.is_none());
-        let module_ent = &mut self.modules[module];
-        module_ent.entry_point = PackedOption::from(id);
-        let mut body = FunBody::default();
-        let block = module_ent.new_block(&mut body);
-        module_ent.select_block(block, &mut body);
-        let arg1 = module_ent.add_temp_local(int);
-        let arg2 = module_ent.add_temp_local(int);
-        module_ent.push_swection_arg(block, arg1);
-        module_ent.push_swection_arg(block, arg2);
-        let init = module_ent.add_zero_value(int, &mut body);
-        let return_value = module_ent.add_local(int, true);
-        module_ent.add_var_decl(init, return_value, Token::default(), &mut body);
-        let data = MainFunData {
-            id,
-            arg1,
-            arg2,
-            return_value,
-        };
-        self.context.entry_point_data = data;
-        self.funs[id].body = body;
-    }
+    fn collect_globals(
+        &mut self,
+        module: Mod,
+        mut ast_data: ast::DataSwitch,
+        collector: &mut ast::Collector,
+        builder: &mut Builder,
+    ) -> Result {
+        self.vars.clear();
+        let mut state = ScopeState::default();
+        let result = collector.use_globals(|saved, global, attrs, scope| {
+            ast_data.set_swapped(saved);
+            self.parse_scope(&ast_data, module, scope, saved, &mut state)?;
+            if !state.generics.is_reserved_value() {
+                return Err(Error::new(
+                    error::Kind::VarInsideGenericScope(ast_data.token(scope)),
+                    ast_data.token(global),
+                ));
+            }
+            self.collect_global_var(
+                &ast_data, module, global, attrs, state.id, state.vis, builder,
+            )
+        });
+        self.leave_scope(module, &mut state);
+        result?;
 
-    fn translate(&mut self) -> Result {
-        while let Some(fun) = self.context.unresolved.pop() {
-            self.translate_fun(fun)?;
+        let mut entry_points = std::mem::take(&mut self.entry_points);
+        for fun in entry_points.drain(..) {
+            self.add_entry(fun, builder)?;
         }
+        self.entry_points = entry_points;
 
         Ok(())
     }
 
-    fn translate_fun(&mut self, fun: Fun) -> Result {
-        let mut shadowed = self.temp_vec();
-        let FunEnt {
-            module,
-            scope,
-            ast,
-            params,
-            sig,
-            base_fun,
-            mut body,
-            ..
-        } = self.funs[fun];
-        let module_ent = &self.modules[module];
-        let header = module_ent.son(ast, 0);
-        let ast_body = module_ent.son(ast, 1);
-        let header_len = module_ent.ast_len(header);
-
-        if module_ent.son(ast, 1).is_reserved_value() {
-            self.finalize_fun(fun, body);
+    fn parse_scope(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        saved: bool,
+        state: &mut ScopeState,
+    ) -> Result {
+        if ast == state.previous && saved == state.saved {
             return Ok(());
         }
-
-        if !params.is_empty() {
-            let param_len = module_ent.type_slice(params).len();
-            for i in 0..param_len {
-                let module_ent = &self.modules[module];
-                let ty = module_ent.type_slice(params)[i];
-                let id = self.generic_funs.get(base_fun.unwrap()).unwrap().sig.params[i];
-                let id = TYPE_SALT.add(id).add(module_ent.id);
-                shadowed.push((id, self.state.types.link(id, ty)))
-            }
-        }
-
-        if !scope.is_reserved_value() {
-            let ty = self.modules[module].son(scope, 1);
-            let ty = self.parse_type(module, ty)?;
-            let id = TYPE_SALT
-                .add(ID::new("Self"))
-                .add(self.state.modules[module].id);
-
-            shadowed.push((id, self.state.types.link(id, ty)));
+        state.previous = ast;
+        state.saved = saved;
+        state.generics = Ast::reserved_value();
+        state.id = ID(0);
+        state.vis = Vis::None;
+        self.leave_scope(module, state);
+        if ast.is_reserved_value() {
+            return Ok(());
         }
 
-        let module_ent = &mut self.modules[module];
-        let entry_point = module_ent.new_block(&mut body);
-        module_ent.select_block(entry_point, &mut body);
+        let (kind, sons, _) = ast_data.ent(ast).parts();
+        let sons = ast_data.slice(sons);
+        let (generics, ty) = (sons[0], impl_target(ast_data, ast));
+        let ty_kind = ast_data.kind(ty);
 
-        self.context.vars.clear();
+        state.vis = match kind {
+            ast::Kind::Impl(vis) => vis,
+            _ => unreachable!(),
+        };
 
-        // create arguments for signature
-        let mut i = 0;
-        for arg_group in 1..header_len - 2 {
-            let module_ent = &mut self.modules[module];
-            let &AstEnt { sons, kind, token } = module_ent.son_ent(header, arg_group);
-            let arg_sons_len = module_ent.len(sons);
-            for arg in 0..arg_sons_len - 1 {
-                let module_ent = &mut self.modules[module];
-                let id = module_ent.get_ent(sons, arg).token.span.hash;
-                let ty = module_ent.type_slice(sig.args)[i];
-                let var = module_ent.add_temp_local(ty);
-                module_ent.push_swection_arg(entry_point, var);
-                let var = if kind == ast::Kind::FunArgument(true) {
-                    let carrier = module_ent.add_local(ty, true);
-                    module_ent.add_var_decl(var, carrier, token, &mut body);
-                    carrier
-                } else {
-                    var
-                };
-                self.context.vars.push((id, var));
-                i += 1;
-            }
-        }
+        state.generics = generics;
 
-        // check if return value is too big and allocate additional argument for struct pointer
-        // if thats the case
-        let has_ret = sig.ret.is_some();
-        if has_ret {
-            let ty = sig.ret.unwrap();
-            let ty_ent = &self.types[ty];
-            if ty_ent.on_stack(self.ptr_ty) {
-                let ty = self.pointer_of(ty, true);
-                let module_ent = &mut self.modules[module];
-                let value = module_ent.add_temp_local(ty);
-                module_ent.push_swection_arg(entry_point, value);
+        let ty = match ty_kind {
+            ast::Kind::Ident | ast::Kind::Instantiation if generics.is_reserved_value() => {
+                self.parse_type(ast_data, module, ty)?
             }
-        }
-
-        let (value, hint) = self.block(fun, ast_body, &mut body)?;
 
-        let closed = self.body.current_block.is_some();
-
-        let module_ent = &mut self.modules[module];
-        match (value, closed, has_ret) {
-            (Some(value), true, true) => {
-                let ret_ty = sig.ret.unwrap();
-                let value_ty = module_ent.type_of_value(value);
-                assert_type(value_ty, ret_ty, &hint)?;
-                self.gen_return(fun, Some(value), hint, &mut body);
-            }
-            (_, true, true) => {
-                let zero_value = module_ent.add_zero_value(sig.ret.unwrap(), &mut body);
-                self.gen_return(fun, Some(zero_value), Token::default(), &mut body);
+            ast::Kind::Instantiation => {
+                let base = ast_data.sons(ty)[0];
+                self.parse_type(ast_data, module, base)?
             }
-            (_, true, _) => self.gen_return(fun, None, Token::default(), &mut body),
-            _ => (),
-        }
-
-        for (id, shadow) in shadowed.drain(..) {
-            self.state.types.remove_link(id, shadow);
-        }
+            ast::Kind::Array => ARRAY_TY,
+            kind => unreachable!("{:?}", kind),
+        };
 
-        self.finalize_fun(fun, body);
+        state.id = self.ctx.type_base_id(ty);
+        let id = ID::new("Self");
+        state.shadow = Some((id, self.push_item(module, id, item::Kind::Ty(ty))));
 
         Ok(())
     }
 
-    fn finalize_fun(&mut self, fun: Fun, body: FunBody) {
-        self.funs[fun].body = body;
-        self.funs[fun].kind = FKind::Represented;
+    fn leave_scope(&mut self, module: Mod, state: &mut ScopeState) {
+        if let Some((id, item)) = state.shadow.take() {
+            self.pop_item(module, id, item);
+        }
     }
 
-    fn gen_return(&mut self, fun: Fun, value: Option<Local>, token: Token, body: &mut FunBody) {
-        let module = self.funs[fun].module;
-
-        let value = if let Some(value) = value {
-            // if type is no stack we have to load current value into provided stack pointer from caller
-            let ty = self.modules[module].type_of_value(value);
-            if self.types[ty].on_stack(self.ptr_ty) {
-                let module_ent = &mut self.modules[module];
-                let entry_block = self.body.entry_block.unwrap();
-                let struct_ptr = module_ent.last_section_arg(entry_block).unwrap();
-                let deref = module_ent.add_local(ty, true);
-                module_ent.add_cmd(cmd::Kind::Deref(struct_ptr, false), deref, token, body);
-                module_ent.add_cmd(cmd::Kind::Assign(deref), value, token, body);
-                Some(struct_ptr)
-            } else {
-                Some(value)
-            }
-        } else {
-            None
+    pub fn collect_global_var(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        attrs: Ast,
+        scope: ID,
+        vis: Vis,
+        builder: &mut Builder,
+    ) -> Result {
+        let (kind, sons, _) = ast_data.ent(ast).parts();
+        let sons = ast_data.slice(sons);
+        let (vis, mutable) = match kind {
+            ast::Kind::VarStatement(a_vis, mutable) => (vis.join(a_vis), mutable),
+            _ => unreachable!(),
         };
 
-        self.modules[module].add_return_stmt(value, token, body);
-    }
+        let (linkage, alias) = self.resolve_linkage(ast_data, attrs)?;
 
-    fn block(&mut self, fun: Fun, ast: Ast, body: &mut FunBody) -> Result<(Option<Local>, Token)> {
-        if ast.is_reserved_value() {
-            return Ok((None, Token::default()));
-        }
-
-        self.context.push_scope();
-
-        let module = self.funs[fun].module;
-        let module_ent = &self.modules[module];
-        let sons = module_ent.sons(ast);
-        let sons_len = module_ent.len(sons);
-
-        // block can be treated as expression so we take result from the loop, if any
-        let mut result = None;
-        let mut token = Token::default();
-        for i in 0..sons_len {
-            if self.body.current_block.is_none() {
-                self.context.pop_scope();
-                return Ok((None, token));
-            }
-            let module_ent = &self.modules[module];
-            let stmt = module_ent.get(sons, i);
-            token = *module_ent.token(stmt);
-            match module_ent.kind(stmt) {
-                ast::Kind::VarStatement(..) => self.var_statement(fun, stmt, body)?,
-                ast::Kind::ReturnStatement => self.return_statement(fun, stmt, body)?,
-                ast::Kind::Break => self.break_statement(fun, stmt, body)?,
-                ast::Kind::Continue => self.continue_statement(fun, stmt, body)?,
-                _ => result = self.expr_low(fun, stmt, body)?,
+        for &ast in sons {
+            let sons = ast_data.sons(ast);
+            let (ident_group, ty, value_group) = (sons[0], sons[1], sons[2]);
+            let ty = if ty.is_reserved_value() {
+                None
+            } else {
+                Some(self.parse_type(ast_data, module, ty)?)
             };
-        }
-
-        self.context.pop_scope();
-
-        Ok((result, token))
-    }
 
-    fn continue_statement(&mut self, fun: Fun, ast: Ast, body: &mut FunBody) -> Result {
-        let module = self.funs[fun].module;
-        let module_ent = &mut self.state.modules[module];
-        let label = module_ent.son(ast, 0);
-        let label = if label.is_reserved_value() {
-            Token::default()
-        } else {
-            *module_ent.token(label)
-        };
-        let token = *module_ent.token(ast);
-        let loop_header = self.context.find_loop(&label).map_err(|outside| {
-            if outside {
-                Error::new(error::Kind::ContinueOutsideLoop, token)
+            let value_group = if value_group.is_reserved_value() {
+                &[]
             } else {
-                Error::new(error::Kind::WrongLabel, token)
-            }
-        })?;
+                ast_data.sons(value_group)
+            };
+            for (i, &ident) in ast_data.sons(ident_group).iter().enumerate() {
+                let hint = ast_data.token(ident);
+                let id = self.hash_token(hint).add(scope);
 
-        module_ent.add_valueless_cmd(
-            cmd::Kind::Jump(loop_header.start_block, EntityList::new()),
-            token,
-            body,
-        );
+                let g_ent = GlobalEnt {
+                    vis,
+                    mutable,
+                    id,
+                    module,
+                    ast,
+                    hint,
+                    attrs,
+                    linkage,
+                    alias,
 
-        Ok(())
-    }
+                    ..Default::default()
+                };
 
-    fn break_statement(&mut self, fun: Fun, ast: Ast, body: &mut FunBody) -> Result {
-        let module = self.funs[fun].module;
-        let module_ent = &mut self.state.modules[module];
-        let label = module_ent.son(ast, 0);
-        let label = if label.is_reserved_value() {
-            Token::default()
-        } else {
-            *module_ent.token(label)
-        };
-        let token = *module_ent.token(ast);
+                let id = self.add_global(module, g_ent)?;
 
-        let loop_header = self.context.find_loop(&label).map_err(|outside| {
-            if outside {
-                Error::new(error::Kind::BreakOutsideLoop, token)
-            } else {
-                Error::new(error::Kind::WrongLabel, token)
-            }
-        })?;
+                let ty = if let Some(&value) = value_group.get(i) {
+                    let token = ast_data.token(value);
+                    let value = self.expr(ast_data, module, value, builder)?;
+                    let actual = builder.type_of_value(value);
+                    if let Some(ty) = ty {
+                        assert_type(actual, ty, token)?;
+                    }
+                    self.globals[id].ty = actual;
+                    builder.assign_global(id, value)
+                } else {
+                    ty.unwrap()
+                };
 
-        let return_value_ast = module_ent.son(ast, 1);
-
-        if return_value_ast != Ast::reserved_value() {
-            let return_value = self.expr(fun, return_value_ast, body)?;
-            let module_ent = &mut self.state.modules[module];
-            let current_return_value = module_ent.last_section_arg(loop_header.end_block);
-            if let Some(current_return_value) = current_return_value {
-                let token = module_ent.token(return_value_ast);
-                let return_ty = module_ent.type_of_value(return_value);
-                let current_return_ty = module_ent.type_of_value(current_return_value);
-                assert_type(current_return_ty, return_ty, token)?;
-            } else {
-                let ty = module_ent.type_of_value(return_value);
-                let value = module_ent.add_temp_local(ty);
-                module_ent.push_swection_arg(loop_header.end_block, value);
-            }
+                self.globals[id].ty = ty;
 
-            let args = module_ent.add_locals(&[return_value]);
-            module_ent.add_valueless_cmd(cmd::Kind::Jump(loop_header.end_block, args), token, body);
-        } else {
-            self.state.modules[module].add_valueless_cmd(
-                cmd::Kind::Jump(loop_header.end_block, EntityList::new()),
-                token,
-                body,
-            );
+                self.resolved_globals.push(id);
+            }
         }
 
         Ok(())
     }
 
-    fn return_statement(&mut self, fun: Fun, ast: Ast, body: &mut FunBody) -> Result {
-        let fun_ent = &self.funs[fun];
-        let module = fun_ent.module;
-        let ty = fun_ent.sig.ret;
-        let module_ent = &mut self.state.modules[module];
-        let token = *module_ent.token(ast);
-        let return_value_ast = module_ent.son(ast, 0);
+    pub fn collect_fun(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        attrs: Ast,
+        scope: Ast,
+        scope_id: ID,
+        vis: Vis,
+        saved: bool,
+    ) -> Result {
+        let header = ast_data.son(ast, 0);
+        let (kind, sons, hint) = ast_data.ent(header).parts();
+        let sons = ast_data.slice(sons);
 
-        if return_value_ast.is_reserved_value() {
-            let value = if ty.is_some() {
-                Some(module_ent.add_zero_value(ty.unwrap(), body))
-            } else {
-                None
-            };
-            self.gen_return(fun, value, token, body);
-        } else {
-            let token = *module_ent.token(return_value_ast);
-            if ty.is_none() {
-                return Err(Error::new(error::Kind::UnexpectedReturnValue, token));
-            }
-            let value = self.expr(fun, return_value_ast, body)?;
-            let module_ent = &mut self.state.modules[module];
-            let actual_type = module_ent.type_of_value(value);
-            assert_type(actual_type, ty.unwrap(), &token)?;
-            self.gen_return(fun, Some(value), token, body)
+        let (op_kind, vis, mut call_conv) = match kind {
+            ast::Kind::FunHeader(op_kind, a_vis, call_conv) => {
+                (op_kind, vis.join(a_vis), call_conv)
+            }
+            _ => unreachable!(),
         };
 
-        Ok(())
-    }
-
-    fn var_statement(&mut self, fun: Fun, ast: Ast, body: &mut FunBody) -> Result {
-        let module = self.funs[fun].module;
-        let module_ent = &self.state.modules[module];
-        let &AstEnt { kind, sons, .. } = module_ent.load(ast);
-        let mutable = kind == ast::Kind::VarStatement(Vis::None, true);
-        let sons_len = module_ent.len(sons);
+        if let Some(attr) = self.find_attribute(ast_data, attrs, "call_conv") {
+            let sons = ast_data.sons(attr);
+            let token = ast_data.token(attr);
+            let name = sons
+                .get(1)
+                .ok_or_else(|| {
+                    Error::new(error::Kind::TooShortAttribute(sons.len() - 1, 1), token)
+                })?
+                .clone();
+            let token = ast_data.token(name);
+            let str = self.display(token.span());
+            call_conv = CallConv::from_str(str)
+                .ok_or_else(|| Error::new(error::Kind::InvalidCallConv, token))?;
+        }
 
-        for i in 0..sons_len {
-            let module_ent = &self.state.modules[module];
-            let var_line = module_ent.get(sons, i);
-            let name_group = module_ent.son(var_line, 0);
-            let ty = module_ent.son(var_line, 1);
-            let value_group = module_ent.son(var_line, 2);
-            let ty = if ty.is_reserved_value() {
-                None
-            } else {
-                Some(self.parse_type(module, ty)?)
-            };
+        let entry = self.find_attribute(ast_data, attrs, "entry").is_some();
+        let untraced = self.find_attribute(ast_data, attrs, "untraced").is_some();
+        let inline = self.find_attribute(ast_data, attrs, "inline").is_some();
+        let terminating = self.find_attribute(ast_data, attrs, "terminating").is_some();
 
-            let module_ent = &mut self.state.modules[module];
-            let sons = module_ent.sons(name_group);
-            let sons_len = module_ent.len(sons);
-            if value_group.is_reserved_value() {
-                // in this case we just assign zero locals to all variables
-                for i in 0..sons_len {
-                    let ty = ty.unwrap();
-                    let token = *module_ent.token(module_ent.get(sons, i));
-                    let zero_value = module_ent.add_zero_value(ty, body);
-
-                    let var = module_ent.add_local(ty, mutable);
-                    self.context.vars.push((token.span.hash, var));
-                    module_ent.add_var_decl(zero_value, var, token, body)
-                }
-            } else {
-                let locals = module_ent.sons(value_group);
-                // ast parser takes care of the one value multiple variables case
-                // all missing locals are pushed as pointer to the first one
-                for i in 0..sons_len {
-                    let module_ent = &self.state.modules[module];
-                    let name_token = *module_ent.token(module_ent.get(sons, i));
-                    let raw_value = module_ent.get(locals, i);
-                    let value_token = *module_ent.token(raw_value);
-                    let value = self.expr(fun, raw_value, body)?;
-
-                    let module_ent = &mut self.state.modules[module];
-                    let actual_datatype = module_ent.type_of_value(value);
-
-                    // consistency check
-                    if let Some(ty) = ty {
-                        assert_type(actual_datatype, ty, &value_token)?;
-                    }
+        let (linkage, mut alias) = self.resolve_linkage(ast_data, attrs)?;
 
-                    let var = module_ent.add_local(actual_datatype, mutable);
-                    self.context.vars.push((name_token.span.hash, var));
-                    module_ent.add_var_decl(value, var, name_token, body);
-                }
-            }
+        let (generics, name) = (sons[0], sons[1]);
+        if name.is_reserved_value() {
+            return Err(Error::new(error::Kind::InvalidFunctionHeader, hint));
         }
+        let name = ast_data.token(name).span();
+        let id = self.hash_span(name).add(scope_id);
+        let id = match op_kind {
+            ast::OpKind::Normal => id,
+            ast::OpKind::Unary => id.add(ID::new("-u-")),
+            ast::OpKind::Binary => id.add(ID::new("-b-")),
+        };
 
-        Ok(())
-    }*/
-
-    fn expr(&mut self, ast_data: &ast::DataSwitch, fun: Fun, ast: Ast, builder: &mut Builder) -> Result<Local> {
-        // common case is that we expect a value out of the expression though
-        // mode specific errors may be useful (TODO)
-        /*self.expr_low(fun, ast, body)?.ok_or_else(|| {
-            let module = self.funs[fun].module;
-            let module_ent = &self.state.modules[module];
-            let token = *module_ent.token(ast);
-            Error::new(error::Kind::ExpectedValue, token)
-        })*/
-        todo!()
-    }
-    /*
-    fn expr_low(&mut self, fun: Fun, ast: Ast, body: &mut FunBody) -> ExprResult {
-        let module = self.funs[fun].module;
-        let module_ent = &self.state.modules[module];
-        let kind = module_ent.kind(ast);
-        match kind {
-            ast::Kind::BinaryOp => self.binary_op(fun, ast, body),
-            ast::Kind::Lit => self.lit(fun, ast, body),
-            ast::Kind::Ident => self.ident(fun, ast, body),
-            ast::Kind::Call(_) => self.call(fun, ast, body),
-            ast::Kind::IfExpr => self.if_expr(fun, ast, body),
-            ast::Kind::Loop => self.loop_expr(fun, ast, body),
-            ast::Kind::DotExpr => self.dot_expr(fun, ast, body),
-            ast::Kind::Deref => self.deref_expr(fun, ast, body),
-            ast::Kind::Ref(mutable) => self.ref_expr(fun, ast, mutable, body),
-            ast::Kind::UnaryOp => self.unary_op(fun, ast, body),
-            ast::Kind::Pass => Ok(None),
-            ast::Kind::Array => self.array(fun, ast, body),
-            ast::Kind::Index => self.index(fun, ast, body),
-            _ => todo!(
-                "unmatched expr ast {}",
-                AstDisplay::new(self.state, &module_ent.a_state, ast)
-            ),
+        if linkage == Linkage::Import && alias.is_none() {
+            alias = Some(name);
         }
-    }
 
-    fn index(&mut self, fun: Fun, ast: Ast, body: &mut FunBody) -> ExprResult {
-        let module = self.funs[fun].module;
-        let module_ent = &self.state.modules[module];
-        let &AstEnt { token, sons, .. } = module_ent.load(ast);
-        let target = module_ent.get(sons, 0);
-        let index = module_ent.get(sons, 1);
-        let target = self.expr(fun, target, body)?;
-        let index = self.expr(fun, index, body)?;
-        let args = &[target, index];
-        let span = if self.context.in_var_ref {
-            self.state.index_var_span
+        let scope_generics = if scope.is_reserved_value() {
+            Ast::reserved_value()
         } else {
-            self.state.index_span
+            ast_data.son(scope, 0)
         };
-        let result = self
-            .call_low(fun, None, None, FUN_SALT, span, &[], args, token, body)?
-            .ok_or_else(|| Error::new(error::Kind::ExpectedValue, token))?;
+        let generic = !generics.is_reserved_value() || !scope_generics.is_reserved_value();
 
-        Ok(Some(self.deref_expr_low(fun, result, token, body)?))
-    }
-
-    fn array(&mut self, fun: Fun, ast: Ast, body: &mut FunBody) -> ExprResult {
-        let mut locals = self.temp_vec();
-        let mut element_ty = None;
-        let module = self.funs[fun].module;
-        let module_ent = &self.state.modules[module];
-        let &AstEnt { sons, token, .. } = module_ent.load(ast);
-        let length = module_ent.len(sons);
-
-        for i in 0..length {
-            let module_ent = &self.state.modules[module];
-            let raw_value = module_ent.get(sons, i);
-            let token = *module_ent.token(raw_value);
-            let value = self.expr(fun, raw_value, body)?;
-            let module_ent = &self.state.modules[module];
-            let ty = module_ent.type_of_value(value);
-            if let Some(real_ty) = element_ty {
-                assert_type(ty, real_ty, &token)?;
+        let (sig, kind) = if generic {
+            (Signature::default(), FKind::Generic)
+        } else {
+            let sig = self.parse_signature(ast_data, module, header, call_conv)?;
+            let body = ast_data.son(ast, 1);
+            let kind = if body.is_reserved_value() && linkage != Linkage::Import {
+                FKind::Builtin
             } else {
-                element_ty = Some(ty);
-            }
-            locals.push((value, token));
-        }
-
-        if element_ty.is_none() {
-            return Err(Error::new(error::Kind::EmptyArray, token));
-        }
-
-        let element_ty = element_ty.unwrap();
-        let element_size = self.types[element_ty].size;
+                FKind::Normal
+            };
+            (sig, kind)
+        };
 
-        let ty = self.state.array_of(self.module, element_ty, length);
-        let module_ent = &mut self.state.modules[module];
+        let fun_ent = FunEnt {
+            vis,
+            id,
+            module,
+            hint,
+            kind,
+            name,
+            untraced,
+            inline,
+            terminating,
+            attrs,
+            scope,
+            linkage,
+            alias,
+            sig,
+            ast,
 
-        let temp = module_ent.add_temp_local(ty);
-        module_ent.add_cmd(cmd::Kind::Uninitialized, temp, token, body);
-        let result = module_ent.add_local(ty, true);
-        module_ent.add_var_decl(temp, result, token, body);
+            ..Default::default()
+        };
+        let fun = self.add_fun(module, fun_ent)?;
 
-        for (i, &(value, token)) in locals.iter().enumerate() {
-            let offset = element_size.mul(Size::new(i as u32, i as u32));
-            let offset = module_ent.offset_value(result, element_ty, offset, token, body);
-            module_ent.assign(offset, value, token, body);
+        if generic {
+            let mut params = vec![];
+            for &generics in [scope_generics, generics].iter() {
+                if generics.is_reserved_value() {
+                    continue;
+                }
+                for &param in ast_data.sons(generics) {
+                    params.push(self.hash_token(ast_data.son_ent(param, 0).token()));
+                }
+            }
+            let arg_count = sons[2..sons.len() - 1]
+                .iter()
+                .map(|&arg| ast_data.sons(arg).len() - 1)
+                .sum();
+            self.generic_funs.insert(GFun {
+                id: fun,
+                call_conv,
+                sig: GenericSignature { params, arg_count },
+            });
+        } else if kind == FKind::Normal {
+            if entry {
+                self.entry_points.push(fun);
+            }
+            self.unresolved.push((fun, saved));
         }
 
-        Ok(Some(result))
+        Ok(())
     }
 
-    fn ref_expr(&mut self, fun: Fun, ast: Ast, mutable: bool, body: &mut FunBody) -> ExprResult {
-        let module = self.funs[fun].module;
-        let module_ent = &self.state.modules[module];
-        let &AstEnt { sons, token, .. } = module_ent.load(ast);
-        let value = module_ent.get(sons, 0);
-
-        let prev_in_var_ref = self.context.in_var_ref;
-        let prev_in_assign = self.context.in_assign;
-        self.context.in_assign = true;
-        self.context.in_var_ref = mutable;
-        let value = self.expr(fun, value, body)?;
-        self.context.in_assign = prev_in_assign;
-        self.context.in_var_ref = prev_in_var_ref;
-
-        self.ref_expr_low(fun, value, token, mutable, body)
-            .map(|v| Some(v))
-    }
+    crate::impl_item_adder!(
+        add_fun, funs, FunEnt, Fun
+        add_global, globals, GlobalEnt, Global
+    );
 
-    fn ref_expr_low(
-        &mut self,
-        fun: Fun,
-        value: Local,
-        token: Token,
-        mutable: bool,
+    fn add_entry_point(&mut self) {
+        let module = BUILTIN_MODULE;
+        let byte_ptr = self.pointer_of(module, U8_TY, false);
+        let args_ty = self.pointer_of(module, byte_ptr, false);
+        let args = self.add_type_slice(&[INT_TY, args_ty]);
+        let sig = Signature {
+            call_conv: CallConv::Platform,
+            args,
+            ret: PackedOption::from(INT_TY),
+        };
+        let name = self.builtin_span("main");
+        let id = self.funs.push(FunEnt {
+            id: ID::new("main"),
+            module,
+            name,
+            sig,
+            kind: FKind::Normal,
+            vis: Vis::Public,
+            linkage: Linkage::Export,
+            untraced: true,
 
-    ) -> Result<Local> {
-        let module = self.funs[fun].module;
-        let module_ent = &self.state.modules[module];
-        let LocalEnt {
-            ty,
-            mutable: prev_mutable,
-            ..
-        } = module_ent.locals[value];
+            ..Default::default()
+        });
 
-        if !prev_mutable && mutable {
-            return Err(Error::new(error::Kind::MutableToImmutable, token));
-        }
+        let mut data = std::mem::take(&mut self.data);
+        let mut body = Body::default();
+        let mut builder = Builder::new(&mut data, &mut body);
+        let entry = builder.new_block();
+        builder.select_block(entry);
+        let arg1 = builder.add_local(INT_TY, false);
+        let arg2 = builder.add_local(args_ty, false);
+        builder.push_section_arg(entry, arg1);
+        builder.push_section_arg(entry, arg2);
+        let zero = builder.add_zero_value(INT_TY);
+        let return_value = builder.add_local(INT_TY, true);
+        builder.add_var_decl(zero, return_value);
+        self.data = data;
+        self.funs[id].body = body;
 
-        let ty = self.pointer_of(ty, mutable);
-        let module_ent = &mut self.state.modules[module];
-        let reference = module_ent.reference(ty, value, token, body);
+        self.entry_point_data = MainFunData {
+            id,
+            arg1,
+            arg2,
+            return_value,
+        };
+    }
 
-        // we need to allocate it since register cannot be referenced
-        let mut current = reference;
-        loop {
-            let value = module_ent.load_value_mut(current);
-            if value.on_stack {
-                break;
-            }
-            value.on_stack = true;
+    pub fn add_entry(&mut self, id: Fun, builder: &mut Builder) -> Result {
+        let MainFunData {
+            arg1,
+            arg2,
+            return_value,
+            ..
+        } = self.entry_point_data;
+        let FunEnt {
+            module, sig, hint, ..
+        } = self.funs[id];
 
-            if value.inst.is_some() {
-                let inst = value.inst.unwrap();
-                match module_ent.inst_kind(inst) {
-                    cmd::Kind::Ref(value) | cmd::Kind::Offset(value) | cmd::Kind::Cast(value) => {
-                        current = value;
-                        continue;
-                    }
-                    _ => (),
+        let args = match self.type_slice(sig.args) {
+            &[] => EntityList::new(),
+            &[count, args] => {
+                let byte_ptr = self.pointer_of(module, U8_TY, false);
+                if count != INT_TY || args != self.pointer_of(module, byte_ptr, false) {
+                    return Err(Error::new(error::Kind::InvalidEntrySignature, hint));
                 }
+                builder.add_locals(&[arg1, arg2])
+            }
+            _ => {
+                return Err(Error::new(error::Kind::InvalidEntrySignature, hint));
             }
+        };
 
-            break;
+        if let Some(ret) = sig.ret.expand() {
+            let value = builder.add_temp_local(ret);
+            builder.add_cmd(cmd::Kind::Call(id, args), value);
+            if ret == INT_TY {
+                builder.assign(return_value, value);
+            }
+        } else {
+            builder.add_valueless_cmd(cmd::Kind::Call(id, args));
         }
 
-        Ok(reference)
-    }
-
-    fn deref_expr(&mut self, fun: Fun, ast: Ast, body: &mut FunBody) -> ExprResult {
-        let module = self.funs[fun].module;
-        let module_ent = &self.state.modules[module];
-        let &AstEnt { token, sons, .. } = module_ent.load(ast);
-        let value = module_ent.get(sons, 0);
-        let expr = self.expr(fun, value, body)?;
-
-        let value = self.deref_expr_low(fun, expr, token, body)?;
-
-        Ok(Some(value))
+        Ok(())
     }
 
-    fn deref_expr_low(
+    pub fn parse_signature(
         &mut self,
-        fun: Fun,
-        target: Local,
-        token: Token,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        header: Ast,
+        call_conv: CallConv,
+    ) -> Result<Signature> {
+        let mut args = self.temp_vec();
+        let sons = ast_data.sons(header);
 
-    ) -> Result<Local> {
-        let in_assign = self.context.in_assign;
-        let module = self.funs[fun].module;
-        let module_ent = &self.state.modules[module];
-        let ty = module_ent.type_of_value(target);
-        let pointed = self.pointer_base(ty, token)?;
-        let mutable = self.pointer_mutability(ty);
+        for &argument in &sons[2..sons.len() - 1] {
+            let sons = ast_data.sons(argument);
+            let ty = self.parse_type(ast_data, module, sons[sons.len() - 1])?;
+            args.extend(std::iter::repeat(ty).take(sons.len() - 1));
+        }
 
-        let module_ent = &mut self.state.modules[module];
-        let value = module_ent.add_local(pointed, mutable);
-        module_ent.add_cmd(cmd::Kind::Deref(target, in_assign), value, token, body);
+        let raw_ret_ty = sons[sons.len() - 1];
+        let ret = if raw_ret_ty.is_reserved_value() {
+            PackedOption::default()
+        } else {
+            PackedOption::from(self.parse_type(ast_data, module, raw_ret_ty)?)
+        };
 
-        Ok(value)
+        Ok(Signature {
+            call_conv,
+            args: self.ctx.add_type_slice(args.as_slice()),
+            ret,
+        })
     }
 
-    fn unary_op(&mut self, fun: Fun, ast: Ast, body: &mut FunBody) -> ExprResult {
-        let module = self.funs[fun].module;
-        let module_ent = &self.state.modules[module];
-        let &AstEnt { sons, token, .. } = module_ent.load(ast);
-        let expr = module_ent.get(sons, 1);
-        let op = module_ent.get(sons, 0);
-        let op = module_ent.token(op).span;
-        let value = self.expr(fun, expr, body)?;
+    /// Instantiates generic function if `arg_types` and `explicit_params` match
+    /// the generic signature. Already existing instance is reused.
+    fn create(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        source_module: Mod,
+        fun: Fun,
+        explicit_params: &[Ty],
+        arg_types: &[Ty],
+    ) -> Result<Option<Fun>> {
+        let mut ast_data = *ast_data;
+        ast_data.set_swapped(true);
 
-        self.call_low(fun, None, None, UNARY_SALT, op, &[], &[value], token, body)
-    }
+        let fun_ent = self.funs[fun];
+        let FunEnt {
+            id,
+            module,
+            ast,
+            scope,
+            linkage,
+            ..
+        } = fun_ent;
+        let mut ids = self.temp_vec();
+        let (call_conv, arg_count) = {
+            let g_fun = self.generic_funs.get(fun).unwrap();
+            ids.extend_from_slice(&g_fun.sig.params);
+            (g_fun.call_conv, g_fun.sig.arg_count)
+        };
 
-    fn dot_expr(&mut self, fun: Fun, ast: Ast, body: &mut FunBody) -> ExprResult {
-        let module = self.funs[fun].module;
-        let module_ent = &self.state.modules[module];
-        let &AstEnt { sons, token, .. } = module_ent.load(ast);
-        let header = module_ent.get(sons, 0);
-        let field = module_ent.get(sons, 1);
-        let header = self.expr(fun, header, body)?;
-        let module_ent = &mut self.state.modules[module];
-        let field = *module_ent.token(field);
+        if arg_count != arg_types.len() || explicit_params.len() > ids.len() {
+            return Ok(None);
+        }
 
-        Ok(Some(self.field_access(
-            fun,
-            header,
-            field.span.hash,
-            token,
-            body,
-        )?))
-    }
+        let mut params = self.temp_vec();
+        params.resize(ids.len(), None);
+        for (param, &explicit) in params.iter_mut().zip(explicit_params) {
+            *param = Some(explicit);
+        }
 
-    fn find_field(&mut self, ty: Ty, field_id: ID, path: &mut Vec<usize>) -> bool {
-        let mut frontier = self.temp_vec();
-        frontier.push((usize::MAX, 0, ty));
+        let header = ast_data.son(ast, 0);
+        let scope_ty = if scope.is_reserved_value() {
+            Ast::reserved_value()
+        } else {
+            impl_target(&ast_data, scope)
+        };
 
         let mut i = 0;
-        while i < frontier.len() {
-            let stype = match &self.types[frontier[i].2].kind {
-                ty::Kind::Structure(stype) => stype,
-                &ty::Kind::Pointer(pointed, _) => match &self.types[pointed].kind {
-                    ty::Kind::Structure(stype) => stype,
-                    _ => continue,
-                },
-                _ => continue,
-            };
-            for (j, field) in stype.fields.iter().enumerate() {
-                if field.id == field_id {
-                    path.push(j);
-                    let mut k = i;
-                    loop {
-                        let (index, ptr, _) = frontier[k];
-                        if index == usize::MAX {
-                            break;
-                        }
-                        path.push(ptr);
-                        k = index;
-                    }
-                    return true;
+        let sons = ast_data.sons(header);
+        for &arg in &sons[2..sons.len() - 1] {
+            let sons = ast_data.sons(arg);
+            let pattern = sons[sons.len() - 1];
+            for _ in 0..sons.len() - 1 {
+                if !self.infer(
+                    &ast_data,
+                    module,
+                    scope_ty,
+                    pattern,
+                    arg_types[i],
+                    &ids,
+                    &mut params,
+                )? {
+                    return Ok(None);
                 }
-                if field.embedded {
-                    frontier.push((i, j, field.ty));
+                i += 1;
+            }
+        }
+
+        let mut id = id;
+        let mut param_types = self.temp_vec();
+        for &param in params.iter() {
+            match param {
+                Some(ty) => {
+                    id = id.add(self.type_id(ty));
+                    param_types.push(ty);
                 }
+                None => return Ok(None),
             }
-            i += 1;
         }
 
-        false
-    }
+        if let Some(item) = self.find_item_unchecked(module, id) {
+            if let item::Kind::Fun(instance) = item.kind() {
+                return Ok(Some(instance));
+            }
+        }
 
-    fn loop_expr(&mut self, fun: Fun, ast: Ast, body: &mut FunBody) -> ExprResult {
-        let module = self.funs[fun].module;
-        let module_ent = &mut self.state.modules[module];
-        let &AstEnt { sons, token, .. } = module_ent.load(ast);
-        let label = module_ent.get(sons, 0);
-        let name = if label.is_reserved_value() {
-            ID(0)
+        let param_types = self.add_type_slice(param_types.as_slice());
+
+        let mut shadowed = self.temp_vec();
+        let sig = self
+            .push_params(&ast_data, module, scope, &ids, param_types, &mut shadowed)
+            .and_then(|_| self.check_fun_bounds(&ast_data, module, scope, header, param_types))
+            .and_then(|_| self.parse_signature(&ast_data, module, header, call_conv));
+        self.pop_params(module, &mut shadowed);
+        let sig = sig?;
+
+        let body = ast_data.son(ast, 1);
+        let kind = if body.is_reserved_value() && linkage != Linkage::Import {
+            FKind::Builtin
         } else {
-            module_ent.token(label).span.hash
+            FKind::Normal
         };
 
-        let start_block = module_ent.new_block(body);
-        let end_block = module_ent.new_block(body);
+        let fun_ent = FunEnt {
+            id,
+            sig,
+            kind,
+            params: param_types,
+            base_fun: PackedOption::from(fun),
 
-        let header = Loop {
-            name,
-            start_block,
-            end_block,
+            ..fun_ent
         };
+        let instance = self.add_fun(source_module, fun_ent)?;
 
-        module_ent.add_valueless_cmd(cmd::Kind::Jump(start_block, EntityList::new()), token, body);
-
-        self.context.loops.push(header);
-        module_ent.select_block(start_block, body);
-        let block_ast = module_ent.get(sons, 1);
-        self.block(fun, block_ast, body)?;
-        self.context
-            .loops
-            .pop()
-            .expect("expected previously pushed header");
-
-        let module_ent = &mut self.state.modules[module];
-        if self.body.current_block.is_some() {
-            module_ent.add_valueless_cmd(cmd::Kind::Jump(start_block, EntityList::new()), token, body);
+        if kind == FKind::Normal {
+            self.unresolved.push((instance, true));
         }
-        module_ent.select_block(end_block, body);
 
-        Ok(module_ent.last_section_arg(end_block))
+        Ok(Some(instance))
     }
 
-    fn if_expr(&mut self, fun: Fun, ast: Ast, body: &mut FunBody) -> ExprResult {
-        let module = self.funs[fun].module;
-        let bool_type = self.state.builtin_repo.bool;
-        let module_ent = &mut self.state.modules[module];
-        let &AstEnt { sons, token, .. } = module_ent.load(ast);
-        let cond_ast = module_ent.get(sons, 0);
-        let cond_ast_token = *module_ent.token(cond_ast);
-        let cond_val = self.expr(fun, cond_ast, body)?;
-        let module_ent = &mut self.state.modules[module];
-        let cond_type = module_ent.type_of_value(cond_val);
-
-        assert_type(cond_type, bool_type, &cond_ast_token)?;
-
-        let then_block = module_ent.new_block(body);
-        module_ent.add_valueless_cmd(
-            cmd::Kind::JumpIfTrue(cond_val, then_block, EntityList::new()),
-            cond_ast_token,
-            body,
-        );
-
-        let merge_block = module_ent.new_block(body);
-
-        let else_branch_ast = module_ent.get(sons, 2);
-        let (else_block, jump_to, else_branch_token) = if else_branch_ast.is_reserved_value() {
-            (None, merge_block, Token::default())
-        } else {
-            let some_block = module_ent.new_block(body);
-            (
-                Some(some_block),
-                some_block,
-                *module_ent.token(else_branch_ast),
-            )
-        };
-
-        module_ent.add_valueless_cmd(
-            cmd::Kind::Jump(jump_to, EntityList::new()),
-            else_branch_token,
-            body,
-        );
-
-        module_ent.select_block(then_block, body);
-
-        let then_branch = module_ent.get(sons, 1);
-
-        let (then_result, _) = self.block(fun, then_branch, body)?;
-
-        let module_ent = &mut self.state.modules[module];
-        let mut result = None;
-        let mut jump_inst = None;
-        let mut then_filled = false;
-        if let (Some(val), Some(_)) = (then_result, else_block) {
-            let args = module_ent.add_locals(&[val]);
-            jump_inst =
-                Some(module_ent.add_valueless_cmd(cmd::Kind::Jump(merge_block, args), token, body));
-            let ty = module_ent.type_of_value(val);
-            let value = module_ent.add_temp_local(ty);
-            let args = module_ent.add_locals(&[value]);
-            module_ent.set_block_args(merge_block, args);
-            result = Some(value);
-        } else if self.body.current_block.is_some() {
-            module_ent.add_valueless_cmd(cmd::Kind::Jump(merge_block, EntityList::new()), token, body);
-        } else {
-            then_filled = true;
-        }
-
-        if !else_branch_ast.is_reserved_value() {
-            let else_block = else_block.unwrap();
-            module_ent.select_block(else_block, body);
-            let (else_result, hint) = self.block(fun, else_branch_ast, body)?;
-            let module_ent = &mut self.state.modules[module];
-            if let Some(value) = else_result {
-                let args = module_ent.add_locals(&[value]);
-                module_ent.add_valueless_cmd(cmd::Kind::Jump(merge_block, args), hint, body);
-                if result.is_some() {
-                    // do nothing
-                } else if then_filled {
-                    let ty = module_ent.type_of_value(value);
-                    let value = module_ent.add_temp_local(ty);
-                    module_ent.push_swection_arg(merge_block, value);
-                    result = Some(value);
-                } else {
-                    return Err(Error::new(error::Kind::UnexpectedValue, token));
+    /// Matches type `pattern` from generic signature against concrete type
+    /// and binds encountered parameters. Returns false if types does not match.
+    fn infer(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        scope_ty: Ast,
+        pattern: Ast,
+        ty: Ty,
+        ids: &[ID],
+        params: &mut [Option<Ty>],
+    ) -> Result<bool> {
+        let (kind, sons, token) = ast_data.ent(pattern).parts();
+        let sons = ast_data.slice(sons);
+        match kind {
+            ast::Kind::Ident => {
+                let id = self.hash_token(token);
+                if let Some(index) = ids.iter().position(|&p| p == id) {
+                    return Ok(match params[index] {
+                        Some(param) => param == ty,
+                        None => {
+                            params[index] = Some(ty);
+                            true
+                        }
+                    });
                 }
-            } else {
-                if self.body.current_block.is_some() {
-                    if let Some(jump_inst) = jump_inst {
-                        module_ent.insts[jump_inst].kind =
-                            cmd::Kind::Jump(merge_block, EntityList::new());
-                        module_ent.set_block_args(merge_block, EntityList::new());
+                if id == ID::new("Self") && !scope_ty.is_reserved_value() {
+                    let reserved = Ast::reserved_value();
+                    return self.infer(ast_data, module, reserved, scope_ty, ty, ids, params);
+                }
+            }
+            ast::Kind::Ref(mutable) => {
+                return Ok(match self.type_kind(ty) {
+                    ty::Kind::Pointer(base, actual) if actual || !mutable => {
+                        self.infer(ast_data, module, scope_ty, sons[0], base, ids, params)?
                     }
-                    if result.is_some() {
-                        return Err(Error::new(error::Kind::ExpectedValue, token));
+                    _ => false,
+                });
+            }
+            ast::Kind::Instantiation => {
+                let mut actual = self.temp_vec();
+                actual.extend_from_slice(self.type_slice(self.type_params(ty)));
+                if actual.len() != sons.len() {
+                    return Ok(false);
+                }
+                if self.parse_type(ast_data, module, sons[0])? != actual[0] {
+                    return Ok(false);
+                }
+                for (&son, &param) in sons[1..].iter().zip(actual[1..].iter()) {
+                    if !self.infer(ast_data, module, scope_ty, son, param, ids, params)? {
+                        return Ok(false);
                     }
-                    module_ent.add_valueless_cmd(
-                        cmd::Kind::Jump(merge_block, EntityList::new()),
-                        token,
-                        body,
-                    );
                 }
+                return Ok(true);
             }
-        }
+            ast::Kind::Array => {
+                let (element, length) = match self.type_kind(ty) {
+                    ty::Kind::Array(element, length) => (element, length),
+                    _ => return Ok(false),
+                };
+                if !self.infer(ast_data, module, scope_ty, sons[0], element, ids, params)? {
+                    return Ok(false);
+                }
 
-        self.modules[module].select_block(merge_block, body);
+                let length_ast = sons[1];
+                let id = self.hash_token(ast_data.token(length_ast));
+                let index = ids.iter().position(|&p| p == id);
+                return Ok(match index {
+                    Some(index) if ast_data.kind(length_ast) == ast::Kind::Ident => {
+                        let constant = self.add_constant(constant::Kind::Int(
+                            length as i64,
+                            POINTER_WIDTH_MARKER,
+                        ));
+                        let length = self.constant_of(module, constant);
+                        match params[index] {
+                            Some(param) => param == length,
+                            None => {
+                                params[index] = Some(length);
+                                true
+                            }
+                        }
+                    }
+                    _ => {
+                        self.ctx
+                            .fold_array_length(ast_data, module, length_ast)
+                            .map_err(Into::into)?
+                            == length
+                    }
+                });
+            }
+            _ => (),
+        }
 
-        Ok(result)
+        Ok(self.parse_type(ast_data, module, pattern)? == ty)
     }
 
-    fn call(&mut self, fun: Fun, ast: Ast, body: &mut FunBody) -> ExprResult {
-        let mut locals = self.temp_vec();
-        let mut types = self.temp_vec();
-        let do_stacktrace = self.state.do_stacktrace;
-        let module = self.funs[fun].module;
-        let module_ent = &self.modules[module];
-        let &AstEnt { sons, token, kind } = module_ent.load(ast);
-        let dot_call = kind == ast::Kind::Call(true);
-        let sons_len = module_ent.len(sons);
-        let caller = module_ent.get(sons, 0);
-        let caller_kind = module_ent.kind(caller);
-        for i in 1..sons_len {
-            let value = self.modules[module].get(sons, i);
-            let value = self.expr(fun, value, body)?;
-            locals.push(value);
-            types.push(self.modules[module].type_of_value(value));
-        }
-
-        let mut generic_params = self.temp_vec();
-        let module_ent = &mut self.modules[module];
-        let name = match caller_kind {
-            ast::Kind::Ident | ast::Kind::Path => caller,
-            ast::Kind::Deref => {
-                let pointer_ast = module_ent.son(caller, 0);
-                let pointer_ast_token = *module_ent.token(pointer_ast);
-                let pointer = self.expr(fun, pointer_ast, body)?;
-                let module_ent = &self.state.modules[module];
-                let ty = module_ent.type_of_value(pointer);
-
-                let (mismatched, ret) = {
-                    let fp_module = self.types[ty].module;
-                    let fp = match self.types[ty].kind {
-                        ty::Kind::FunPointer(fp) => fp,
-                        _ => {
-                            return Err(Error::new(
-                                error::Kind::ExpectedFunctionPointer,
-                                pointer_ast_token,
-                            ));
-                        }
-                    };
+    /// Pushes generic parameters and `Self` of the function scope. Constant
+    /// parameters are pushed as constants so they can be used as values.
+    fn push_params(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        scope: Ast,
+        ids: &[ID],
+        params: EntityList<Ty>,
+        shadowed: &mut Vec<(ID, Option<Item>)>,
+    ) -> Result {
+        for (i, &id) in ids.iter().enumerate() {
+            let ty = self.type_slice(params)[i];
+            let kind = match self.type_kind(ty) {
+                ty::Kind::Constant(constant) => item::Kind::Const(constant),
+                _ => item::Kind::Ty(ty),
+            };
+            shadowed.push((id, self.push_item(module, id, kind)));
+        }
 
-                    (
-                        self.modules[fp_module].verify_args(&self.types, &types, fp.args),
-                        fp.ret,
-                    )
-                };
+        if !scope.is_reserved_value() {
+            let ty = self.parse_type(ast_data, module, impl_target(ast_data, scope))?;
+            let id = ID::new("Self");
+            shadowed.push((id, self.push_item(module, id, item::Kind::Ty(ty))));
+        }
 
-                if mismatched {
-                    return Err(Error::new(
-                        error::Kind::FunPointerArgMismatch(
-                            ty,
-                            locals
-                                .iter()
-                                .map(|&v| module_ent.type_of_value(v))
-                                .collect(),
-                        ),
-                        token,
-                    ));
-                }
+        Ok(())
+    }
 
-                if do_stacktrace {
-                    self.gen_frame_push(fun, token, body);
-                }
+    /// Verifies that instantiation parameters satisfy bounds of both
+    /// the impl block and the function itself.
+    fn check_fun_bounds(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        scope: Ast,
+        header: Ast,
+        params: EntityList<Ty>,
+    ) -> Result {
+        let scope_generics = if scope.is_reserved_value() {
+            Ast::reserved_value()
+        } else {
+            ast_data.son(scope, 0)
+        };
+        let hint = ast_data.token(header);
 
-                let module_ent = &mut self.state.modules[module];
+        let mut param_types = self.temp_vec();
+        param_types.extend_from_slice(self.type_slice(params));
+        let mut offset = 0;
+        for &generics in [scope_generics, ast_data.son(header, 0)].iter() {
+            if generics.is_reserved_value() {
+                continue;
+            }
+            self.ctx
+                .check_bounds(ast_data, module, generics, &param_types[offset..], hint)
+                .map_err(Into::into)?;
+            offset += ast_data.sons(generics).len();
+        }
 
-                let args = module_ent.add_locals(&locals);
-                let value = if ret.is_none() {
-                    module_ent.add_valueless_cmd(
-                        cmd::Kind::FunPointerCall(pointer, args),
-                        token,
-                        body,
-                    );
-                    None
-                } else {
-                    let value = module_ent.add_temp_local(ret.unwrap());
-                    module_ent.add_cmd(cmd::Kind::FunPointerCall(pointer, args), value, token, body);
-                    Some(value)
-                };
-
-                if do_stacktrace {
-                    self.gen_frame_pop(fun, token, body);
-                }
-
-                return Ok(value);
-            }
-            ast::Kind::Instantiation => {
-                let len = module_ent.ast_len(caller);
-                for i in 1..len {
-                    let param = self.modules[module].son(caller, i);
-                    let ty = self.parse_type(module, param)?;
-                    generic_params.push(ty);
-                }
-                self.modules[module].son(caller, 0)
-            }
-            _ => unreachable!(),
-        };
-
-        let (module, caller, name) = self.parse_ident(module, name)?;
-
-        let caller = if dot_call { None } else { Some(caller) };
+        Ok(())
+    }
 
-        self.call_low(
-            fun,
-            module,
-            caller,
-            FUN_SALT,
-            name.span,
-            &generic_params,
-            &locals,
-            token,
-            body,
-        )
+    fn pop_params(&mut self, module: Mod, shadowed: &mut Vec<(ID, Option<Item>)>) {
+        for (id, shadow) in shadowed.drain(..).rev() {
+            self.pop_item(module, id, shadow);
+        }
     }
 
-    fn call_low(
-        &mut self,
-        fun: Fun,
-        target: Option<Mod>,
-        caller: Option<Option<Ty>>,
-        salt: ID,
-        name: Span,
-        params: &[Ty],
-        original_values: &[Local],
-        token: Token,
+    fn translate_fun(&mut self, ast_data: &ast::DataSwitch, fun: Fun) -> Result {
+        let FunEnt {
+            id,
+            module,
+            ast,
+            scope,
+            sig,
+            params,
+            base_fun,
+            ..
+        } = self.funs[fun];
 
-    ) -> ExprResult {
-        let mut module_buffer = self.temp_vec();
-        let mut locals = self.temp_vec();
-        let mut types = self.temp_vec();
-        let module = self.funs[fun].module;
-        let module_ent = &self.modules[module];
-        locals.extend_from_slice(original_values);
-        types.extend(locals.iter().map(|&v| module_ent.type_of_value(v)));
-
-        let id = salt.add(name.hash);
-
-        let (result, field, final_type) = if let Some(caller) = caller {
-            let caller = caller.map(|caller| self.types[caller].id).unwrap_or(ID(0));
-            let id = id.add(caller);
-            (
-                self.find_item(module, id, target, &mut module_buffer),
-                None,
-                None,
-            )
-        } else {
-            let v = original_values[0];
-            let ty = module_ent.type_of_value(v);
-
-            let mut result = (Ok(None), None, None);
-            let mut fields = self.temp_vec();
-            let mut i = 0;
-            fields.push((None, ty));
-            while i < fields.len() {
-                let (field, ty) = fields[i];
-                let ty = self.t_state.pointer_base(ty).unwrap_or(ty);
-                let base_ty = self.t_state.base_of(ty);
-                let ty_id = self.types[base_ty].id;
-                module_buffer.clear();
-                let pre_result = self.find_item(module, id.add(ty_id), target, &mut module_buffer);
-                match pre_result {
-                    Ok(Some(_)) => {
-                        result = (pre_result, field, Some(ty));
-                        break;
-                    }
-                    Err(_) if i == 0 => {
-                        result = (pre_result, None, Some(ty));
-                        break;
-                    }
-                    _ => (),
-                }
-                match &self.types[ty].kind {
-                    ty::Kind::Structure(stype) => {
-                        fields.extend(
-                            stype
-                                .fields
-                                .iter()
-                                .filter(|f| f.embedded)
-                                .map(|f| (Some(f.id), f.ty)),
-                        );
-                    }
-                    _ => (),
-                }
-                i += 1;
-            }
-            result
-        };
+        let body_ast = ast_data.son(ast, 1);
+        if body_ast.is_reserved_value() {
+            return Ok(());
+        }
+        let header = ast_data.son(ast, 0);
 
-        if let Some(ty) = final_type {
-            types[0] = ty;
+        let mut ids = self.temp_vec();
+        if let Some(base_fun) = base_fun.expand() {
+            ids.extend_from_slice(&self.generic_funs.get(base_fun).unwrap().sig.params);
         }
 
-        let other_fun = result
-            .map_err(|(a, b)| Error::new(error::Kind::AmbiguousFunction(a, b), token))?
-            .ok_or_else(|| {
-                Error::new(
-                    error::Kind::FunctionNotFound(name.clone(), types.to_vec()),
-                    token,
-                )
-            })?;
-
-        let other_fun = if let FKind::Generic = self.funs[other_fun].kind {
-            if caller.is_none() {
-                let g_fun = self.generic_funs.get(other_fun).unwrap();
-                // figure out whether receiver is pointer
-                let (pointer, mutable) = match g_fun.sig.elements[1] {
-                    GenericElement::Pointer(mutable) => (true, mutable),
-                    _ => (false, false),
-                };
-                let (other_pointer, other_mutable) = match &self.types[types[0]].kind {
-                    &ty::Kind::Pointer(_, mutable) => (true, mutable),
-                    _ => (false, false),
-                };
+        let mut shadowed = self.temp_vec();
+        let mut result = self.push_params(ast_data, module, scope, &ids, params, &mut shadowed);
+        if result.is_ok() {
+            self.fun_id = id;
+            let mut data = std::mem::take(&mut self.data);
+            let mut body = Body::default();
+            result = self.translate_fun_low(
+                ast_data,
+                module,
+                header,
+                body_ast,
+                sig,
+                &[],
+                &mut Builder::new(&mut data, &mut body),
+            );
+            self.data = data;
+            self.funs[fun].body = body;
+        }
+        self.pop_params(module, &mut shadowed);
 
-                match (pointer, other_pointer) {
-                    (true, false) => {
-                        types[0] = self.pointer_of(types[0], mutable);
-                        self.create(other_fun, params, &types)?
-                    }
-                    (false, true) => {
-                        types[0] = self.t_state.pointer_base(types[0]).unwrap();
-                        self.create(other_fun, params, &types)?
-                    }
-                    (true, true) if mutable && !other_mutable => None,
-                    _ => self.create(other_fun, params, &types)?,
-                }
-            } else {
-                self.create(other_fun, params, &types)?
-            }
-            .ok_or_else(|| {
-                Error::new(error::Kind::GenericMismatch(name.clone(), types.to_vec()), token)
-            })?
-        } else {
-            other_fun
-        };
+        result
+    }
 
-        let FunEnt {
-            kind: other_kind,
-            module: other_module,
-            untraced,
-            sig:
-                Signature {
-                    args: sig_args,
-                    ret,
-                    ..
-                },
-            vis,
-            ..
-        } = self.funs[other_fun];
+    fn translate_fun_low(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        header: Ast,
+        body: Ast,
+        sig: Signature,
+        captures: &[(ID, bool)],
+        builder: &mut Builder,
+    ) -> Result {
+        self.vars.clear();
+        self.frames.clear();
+        self.loops.clear();
+        self.ret_ty = sig.ret;
 
-        let value = if ret.is_some() {
-            let ret = ret.unwrap();
-            let on_stack = self.types[ret].on_stack(self.ptr_ty);
-            let value = self.modules[module].add_local(ret, on_stack);
-            if on_stack {
-                locals.push(value);
-            }
-            Some(value)
-        } else {
-            None
-        };
+        let entry = builder.new_block();
+        builder.select_block(entry);
 
-        let mut changed = caller.is_none();
-        if changed {
-            if let Some(field) = field {
-                locals[0] = self.field_access(fun, locals[0], field, token, body)?;
-            }
+        let mut args = self.temp_vec();
+        args.extend_from_slice(self.type_slice(sig.args));
+        let mut args = args.iter();
 
-            let first_arg_ty = self.modules[other_module].type_slice(sig_args)[0];
-            let first_real_arg_ty = self.modules[module].type_of_value(locals[0]);
+        if !captures.is_empty() {
+            let env = builder.add_local(*args.next().unwrap(), false);
+            builder.push_section_arg(entry, env);
+            self.unpack_env(env, captures, ast_data.token(header), builder)?;
+        }
 
-            if first_real_arg_ty != first_arg_ty {
-                if self.t_state.pointer_base(first_real_arg_ty) == Some(first_arg_ty) {
-                    locals[0] = self.deref_expr_low(fun, locals[0], token, body)?;
-                } else if self.t_state.pointer_base(first_arg_ty) == Some(first_real_arg_ty) {
-                    let mutable = self.pointer_mutability(first_arg_ty);
-                    locals[0] = self.ref_expr_low(fun, locals[0], token, mutable, body)?;
+        let sons = ast_data.sons(header);
+        for &arg in &sons[2..sons.len() - 1] {
+            let (kind, sons, _) = ast_data.ent(arg).parts();
+            let sons = ast_data.slice(sons);
+            let mutable = kind == ast::Kind::FunArgument(true);
+            for &name in &sons[..sons.len() - 1] {
+                let ty = *args.next().unwrap();
+                let value = builder.add_local(ty, false);
+                builder.push_section_arg(entry, value);
+                let value = if mutable {
+                    let var = builder.add_local(ty, true);
+                    builder.add_var_decl(value, var);
+                    var
                 } else {
-                    // adjust mutability if '&var' instead of '&' is passed
-                    let mutability_mismatch = matches!(
-                        (
-                            &self.types[first_real_arg_ty].kind,
-                            &self.types[first_arg_ty].kind
-                        ),
-                        (&ty::Kind::Pointer(_, a), &ty::Kind::Pointer(_, b)) if a != b && b && !a
-                    );
-                    if mutability_mismatch {
-                        types[0] = first_arg_ty; // just overwrite to so it matches later check
-                    }
-                    changed = false;
-                }
-
-                debug_assert!(
-                    self.modules[module].type_of_value(locals[0]) == first_arg_ty || !changed,
-                    "{}({:?}) != {}({:?})",
-                    TypeDisplay::new(self.state, self.modules[module].type_of_value(locals[0])),
-                    self.modules[module].type_of_value(locals[0]),
-                    TypeDisplay::new(self.state, first_arg_ty),
-                    first_arg_ty,
-                );
+                    value
+                };
+                self.vars.push((self.hash_token(ast_data.token(name)), value));
             }
         }
 
-        if changed {
-            types[0] = self.modules[module].type_of_value(locals[0]);
-        }
-
-        let mismatched = self.modules[other_module].verify_args(&self.types, &types, sig_args);
+        let value = self.block(ast_data, module, body, builder)?;
 
-        if mismatched {
-            return Err(Error::new(
-                error::Kind::FunArgMismatch(other_fun, types.to_vec()),
-                token,
-            ));
+        if !builder.is_closed() {
+            match (sig.ret.expand(), value) {
+                (Some(ret), Some(value)) => {
+                    let token = ast_data.sons(body).last().map(|&s| ast_data.token(s));
+                    assert_type(
+                        builder.type_of_value(value),
+                        ret,
+                        token.unwrap_or(ast_data.token(body)),
+                    )?;
+                    builder.add_return_stmt(Some(value));
+                }
+                (Some(ret), None) => {
+                    let value = builder.add_zero_value(ret);
+                    builder.add_return_stmt(Some(value));
+                }
+                (None, _) => builder.add_return_stmt(None),
+            }
         }
 
-        let do_stacktrace =
-            self.state.do_stacktrace && !untraced && !matches!(other_kind, FKind::Builtin);
+        Ok(())
+    }
 
-        if do_stacktrace {
-            self.gen_frame_push(fun, token, body);
+    /// Binds captured variables to the fields of closure environment. Variables
+    /// captured by reference are dereferenced so they behave as the originals.
+    fn unpack_env(
+        &mut self,
+        env: Local,
+        captures: &[(ID, bool)],
+        token: Token,
+        builder: &mut Builder,
+    ) -> Result {
+        let env = self.dereference(env, token, builder)?;
+        for (i, &(id, by_ref)) in captures.iter().enumerate() {
+            let value = self.nth_field(env, i, builder);
+            let value = if by_ref {
+                self.dereference(value, token, builder)?
+            } else {
+                value
+            };
+            self.vars.push((id, value));
         }
 
-        if !self.state.can_access(module, other_module, vis) {
-            return Err(Error::new(error::Kind::VisibilityViolation, token));
-        }
+        Ok(())
+    }
 
-        let module_ent = &mut self.modules[module];
-        let args = module_ent.add_locals(&locals);
+    fn block(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        builder: &mut Builder,
+    ) -> ExprResult {
+        self.push_scope();
 
-        if let Some(value) = value {
-            module_ent.add_cmd(cmd::Kind::Call(other_fun, args), value, token, body);
-        } else {
-            module_ent.add_valueless_cmd(cmd::Kind::Call(other_fun, args), token, body);
+        let mut value = None;
+        let mut result = Ok(());
+        for &statement in ast_data.sons(ast) {
+            if builder.is_closed() {
+                break;
+            }
+            match self.expr_low(ast_data, module, statement, builder) {
+                Ok(v) => value = v,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
         }
 
-        module_ent.add_dependant_function(other_fun, body);
+        self.pop_scope();
+        result?;
 
-        if do_stacktrace {
-            self.gen_frame_pop(fun, token, body);
-        }
-
-        Ok(value)
+        Ok(if builder.is_closed() { None } else { value })
     }
 
-    fn gen_frame_pop(&mut self, fun: Fun, token: Token, body: &mut FunBody) {
-        let module = self.funs[fun].module;
-        let pop = self.state.funs.index(self.pop_fun_hahs).unwrap().clone();
-        self.modules[module].add_valueless_call(pop, &[], token, body);
+    fn expr(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        builder: &mut Builder,
+    ) -> Result<Local> {
+        self.expr_low(ast_data, module, ast, builder)?
+            .ok_or_else(|| Error::new(error::Kind::ExpectedValue, ast_data.token(ast)))
     }
 
-    fn gen_frame_push(&mut self, fun: Fun, token: Token, body: &mut FunBody) {
-        let push = self.state.funs.index(self.push_fun_hash).unwrap().clone();
-        let int = self.state.builtin_repo.int;
-        let u8 = self.state.builtin_repo.u8;
-        let module = self.funs[fun].module;
-        let module_ent = &mut self.modules[module];
-
-        // line argument
-        let line = module_ent.add_temp_local(int);
-        module_ent.add_cmd(
-            cmd::Kind::Lit(LTKind::Int(token.span.line as i64, 0)),
-            line,
-            token,
-            body,
-        );
-
-        // column argument
-        let column = module_ent.add_temp_local(int);
-        module_ent.add_cmd(
-            cmd::Kind::Lit(LTKind::Int(token.span.column as i64, 0)),
-            column,
-            token,
-            body,
-        );
-
-        // file name argument
-        let file_name = &self.state.sources[token.span.source].name;
-        let mut final_file_name = String::with_capacity(file_name.len() + 1);
-        final_file_name.push_str(file_name);
-        final_file_name.push('\x00');
-        let span = self.state.builtin_span(&final_file_name);
-        let ptr = self.pointer_of(u8, false);
-        let module_ent = &mut self.modules[module];
-        let file_name = module_ent.add_temp_local(ptr);
-        module_ent.add_cmd(cmd::Kind::Lit(LTKind::String(span)), file_name, token, body);
-
-        module_ent.add_valueless_call(push, &[line, column, file_name], token, body);
-    }
-
-    fn ident(&mut self, fun: Fun, ast: Ast, body: &mut FunBody) -> ExprResult {
-        let module = self.funs[fun].module;
-        let (target, caller, name) = self.parse_ident(module, ast)?;
-        let can_be_local = target.is_none() && caller.is_none();
-        let caller = caller.map(|c| self.types[c].id).unwrap_or(ID(0));
-        let token = *self.modules[module].token(ast);
-
-        let result = (|| {
-            if can_be_local {
-                if let Some(var) = self
-                    .context
-                    .find_variable(name.span.hash)
-                    .or_else(|| self.find_constant_parameter(fun, name, body))
-                {
-                    return Ok(Some(var));
-                }
+    fn expr_low(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        builder: &mut Builder,
+    ) -> ExprResult {
+        match ast_data.kind(ast) {
+            ast::Kind::VarStatement(..) => {
+                self.var_statement(ast_data, module, ast, builder)?;
+                Ok(None)
             }
-
-            if let Some(global) = self.find_global(fun, target, caller, name, body)? {
-                return Ok(Some(global));
+            ast::Kind::Return => {
+                self.return_statement(ast_data, module, ast, builder)?;
+                Ok(None)
             }
-
-            if let Some(pointer) = self.find_function_pointer(fun, target, caller, name, body)? {
-                return Ok(Some(pointer));
+            ast::Kind::Break => {
+                self.break_statement(ast_data, module, ast, builder)?;
+                Ok(None)
+            }
+            ast::Kind::Continue => {
+                self.continue_statement(ast_data, ast, builder)?;
+                Ok(None)
+            }
+            ast::Kind::Loop => self.loop_expr(ast_data, module, ast, builder),
+            ast::Kind::While => self.while_expr(ast_data, module, ast, builder),
+            ast::Kind::ForIn => self.for_in_expr(ast_data, module, ast, builder),
+            ast::Kind::If => self.if_expr(ast_data, module, ast, builder),
+            ast::Kind::Match => self.match_expr(ast_data, module, ast, builder),
+            ast::Kind::Group => self.block(ast_data, module, ast, builder),
+            ast::Kind::Call(_) => self.call(ast_data, module, ast, builder),
+            ast::Kind::Binary => self.binary_expr(ast_data, module, ast, builder),
+            ast::Kind::Unary => self.unary_expr(ast_data, module, ast, builder),
+            ast::Kind::Dot => self.dot_expr(ast_data, module, ast, builder).map(Some),
+            ast::Kind::Index => self.index_expr(ast_data, module, ast, builder).map(Some),
+            ast::Kind::Ref(mutable) => self
+                .ref_expr(ast_data, module, ast, mutable, builder)
+                .map(Some),
+            ast::Kind::Deref => {
+                let token = ast_data.token(ast);
+                let prev = std::mem::replace(&mut self.in_assign, false);
+                let value = self.expr(ast_data, module, ast_data.son(ast, 0), builder);
+                self.in_assign = prev;
+                self.dereference(value?, token, builder).map(Some)
+            }
+            ast::Kind::Ident | ast::Kind::Path => {
+                self.ident_expr(ast_data, module, ast, builder).map(Some)
             }
+            ast::Kind::Lit => {
+                let token = ast_data.token(ast);
+                let constant =
+                    constant::Kind::from_literal(self, token, false).map_err(Into::into)?;
+                let constant = self.add_constant(constant);
+                Ok(Some(self.lit(module, constant, builder)))
+            }
+            ast::Kind::Array => self.array_expr(ast_data, module, ast, builder).map(Some),
+            ast::Kind::Lambda => self.lambda_expr(ast_data, module, ast, builder).map(Some),
+            ast::Kind::Pass => Ok(None),
+            _ => Err(Error::new(
+                error::Kind::UnsupportedExpression,
+                ast_data.token(ast),
+            )),
+        }
+    }
 
-            Ok(None)
-        })()?
-        .ok_or_else(|| Error::new(error::Kind::UndefinedVariable, token))?;
+    fn var_statement(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        builder: &mut Builder,
+    ) -> Result {
+        let (kind, sons, _) = ast_data.ent(ast).parts();
+        let mutable = match kind {
+            ast::Kind::VarStatement(_, mutable) => mutable,
+            _ => unreachable!(),
+        };
 
-        Ok(Some(result))
-    }
+        for &line in ast_data.slice(sons) {
+            let sons = ast_data.sons(line);
+            let (ident_group, ty, values) = (sons[0], sons[1], sons[2]);
+            let ty = if ty.is_reserved_value() {
+                None
+            } else {
+                Some(self.parse_type(ast_data, module, ty)?)
+            };
 
-    fn parse_ident(&mut self, module: Mod, ast: Ast) -> Result<(Option<Mod>, Option<Ty>, Token)> {
-        let module_ent = &self.modules[module];
-        let &AstEnt { kind, sons, token } = module_ent.load(ast);
-        if kind == ast::Kind::Ident {
-            Ok((None, None, token))
-        } else {
-            match module_ent.slice(sons) {
-                &[module_name, caller_name, name] => {
-                    let module_name_token = *module_ent.token(module_name);
-                    let name_token = *module_ent.token(name);
-                    let dep = self
-                        .find_dep(module, &module_name_token)
-                        .ok_or_else(|| Error::new(error::Kind::UnknownModule, module_name_token))?;
-                    let caller = self.parse_type(dep, caller_name)?;
-                    let caller = self.t_state.base_of(caller);
-                    Ok((Some(dep), Some(caller), name_token))
-                }
-                &[package_or_caller, name] => {
-                    let package_or_caller_token = *module_ent.token(package_or_caller);
-                    let name_token = *module_ent.token(name);
-                    if let Some(dep) = self.find_dep(module, &package_or_caller_token) {
-                        Ok((Some(dep), None, name_token))
+            for (i, &ident) in ast_data.sons(ident_group).iter().enumerate() {
+                let value = if values.is_reserved_value() {
+                    builder.add_zero_value(ty.unwrap())
+                } else {
+                    let value_ast = ast_data.son(values, i);
+                    let value = self.expr(ast_data, module, value_ast, builder)?;
+                    if let Some(ty) = ty {
+                        let token = ast_data.token(value_ast);
+                        let value = self.coerce(ast_data, module, value, ty, token, builder)?;
+                        assert_type(builder.type_of_value(value), ty, token)?;
+                        value
                     } else {
-                        let caller = self.parse_type(module, package_or_caller)?;
-                        let caller = self.t_state.base_of(caller);
-                        Ok((None, Some(caller), name_token))
+                        value
                     }
-                }
-                _ => unreachable!(),
+                };
+
+                let ty = builder.type_of_value(value);
+                let var = builder.add_local(ty, mutable);
+                builder.add_var_decl(value, var);
+                self.vars.push((self.hash_token(ast_data.token(ident)), var));
             }
         }
+
+        Ok(())
     }
 
-    fn find_function_pointer(
+    fn return_statement(
         &mut self,
-        fun: Fun,
-        target: Option<Mod>,
-        caller: ID,
-        ident: Token,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        builder: &mut Builder,
+    ) -> Result {
+        let value = ast_data.son(ast, 0);
+        match (self.ret_ty.expand(), value.is_reserved_value()) {
+            (Some(ret), false) => {
+                let token = ast_data.token(value);
+                let value = self.expr(ast_data, module, value, builder)?;
+                let value = self.coerce(ast_data, module, value, ret, token, builder)?;
+                assert_type(builder.type_of_value(value), ret, token)?;
+                builder.add_return_stmt(Some(value));
+            }
+            (Some(ret), true) => {
+                let value = builder.add_zero_value(ret);
+                builder.add_return_stmt(Some(value));
+            }
+            (None, false) => {
+                return Err(Error::new(
+                    error::Kind::UnexpectedReturnValue,
+                    ast_data.token(value),
+                ));
+            }
+            (None, true) => builder.add_return_stmt(None),
+        }
 
-    ) -> ExprResult {
-        let mut module_buffer = self.temp_vec();
-        let module = self.funs[fun].module;
-        let id = FUN_SALT.add(ident.span.hash).add(caller);
-        Ok(
-            match self
-                .find_item(module, id, target, &mut module_buffer)
-                .map_err(|(a, b)| Error::new(error::Kind::AmbiguousFunction(a, b), ident))?
-            {
-                Some(f) => {
-                    let FunEnt {
-                        vis,
-                        sig,
-                        module: other_module,
-                        ..
-                    } = self.funs[f];
-                    if !self.state.can_access(module, other_module, vis) {
-                        return Err(Error::new(error::Kind::VisibilityViolation, ident));
-                    }
-                    // we store the pointers in the module function comes from,
-                    // this makes it more likely we will reuse already instantiated
-                    // pointers, it also means we don't have to reallocate signature
-                    // to modules pool
-                    let ty = self.state.function_type_of(self.module, other_module, sig);
-                    let module_ent = &mut self.modules[module];
-                    let value = module_ent.add_temp_local(ty);
-                    module_ent.add_cmd(cmd::Kind::FunPointer(f), value, ident, body);
-                    Some(value)
-                }
-                None => None,
-            },
-        )
+        Ok(())
     }
 
-    fn find_global(
+    fn loop_expr(
         &mut self,
-        fun: Fun,
-        target: Option<Mod>,
-        caller: ID,
-        name: Token,
-
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        builder: &mut Builder,
     ) -> ExprResult {
-        let module = self.funs[fun].module;
-
-        let id = GLOBAL_SALT.add(name.span.hash).add(caller);
+        let sons = ast_data.sons(ast);
+        let (label, body) = (sons[0], sons[1]);
 
-        let mut module_buffer = self.temp_vec();
-        let global = self
-            .find_item(module, id, target, &mut module_buffer)
-            .map_err(|(a, b)| Error::new(error::Kind::AmbiguousGlobal(a, b), name))?;
+        let start_block = builder.new_block();
+        let end_block = builder.new_block();
+        builder.add_valueless_cmd(cmd::Kind::Jump(start_block, EntityList::new()));
+        builder.select_block(start_block);
 
-        let found = if let Some(found) = global {
-            let GlobalEnt { vis, module, .. } = self.globals[found];
-            if !self.state.can_access(module, module, vis) {
-                return Err(Error::new(error::Kind::GlobalVisibilityViolation, name));
-            }
-            found
-        } else {
-            return Ok(None);
-        };
-
-        let GlobalEnt { ty, mutable, .. } = self.state.globals[found];
-        let module_ent = &mut self.modules[module];
-        let value = module_ent.add_local(ty, mutable);
-        module_ent.add_cmd(cmd::Kind::GlobalLoad(found), value, name, body);
-        module_ent.add_dependant_global(found, body);
+        self.loop_body(
+            ast_data,
+            module,
+            label,
+            body,
+            start_block,
+            end_block,
+            false,
+            builder,
+        )?;
 
-        Ok(Some(value))
+        Ok(builder.last_section_arg(end_block))
     }
 
-    fn find_constant_parameter(
+    /// Translates `for cond:` loop. Condition is evaluated in the start
+    /// block so `continue` also reevaluates it.
+    fn while_expr(
         &mut self,
-        fun: Fun,
-        token: Token,
-
-    ) -> Option<Local> {
-        let FunEnt {
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        builder: &mut Builder,
+    ) -> ExprResult {
+        let sons = ast_data.sons(ast);
+        let (label, condition, body) = (sons[0], sons[1], sons[2]);
+
+        let start_block = builder.new_block();
+        let body_block = builder.new_block();
+        let end_block = builder.new_block();
+        builder.add_valueless_cmd(cmd::Kind::Jump(start_block, EntityList::new()));
+        builder.select_block(start_block);
+
+        let token = ast_data.token(condition);
+        let condition = self.expr(ast_data, module, condition, builder)?;
+        assert_type(builder.type_of_value(condition), BOOL_TY, token)?;
+        builder.add_valueless_cmd(cmd::Kind::JumpIfTrue(
+            condition,
+            body_block,
+            EntityList::new(),
+        ));
+        builder.add_valueless_cmd(cmd::Kind::Jump(end_block, EntityList::new()));
+
+        builder.select_block(body_block);
+        self.loop_body(
+            ast_data,
             module,
-            params,
-            base_fun,
-            ..
-        } = self.funs[fun];
-        if base_fun.is_none() {
-            return None;
-        }
-        let module_ent = &self.modules[module];
-        let name = TYPE_SALT.add(token.span.hash).add(module_ent.id);
-        let sig_params = &self.generic_funs.get(base_fun.unwrap()).unwrap().sig.params;
-        let ty = sig_params
-            .iter()
-            .position(|&e| {
-                let hash = TYPE_SALT.add(e).add(module_ent.id);
-                hash == name
-            })
-            .map(|i| module_ent.type_slice(params)[i]);
-        if let Some(ty) = ty {
-            match &self.types[ty].kind {
-                &ty::Kind::Constant(t) => {
-                    let repo = self.state.builtin_repo;
-                    let (kind, ty) = match t {
-                        TypeConst::Bool(val) => (LTKind::Bool(val), repo.bool),
-                        TypeConst::Int(val) => (LTKind::Int(val, 0), repo.int),
-                        TypeConst::Float(val) => (LTKind::Float(val, 64), repo.f64),
-                        TypeConst::Char(val) => (LTKind::Char(val), repo.u32),
-                        TypeConst::String(val) => {
-                            (LTKind::String(val), self.pointer_of(repo.u8, false))
-                        }
-                    };
+            label,
+            body,
+            start_block,
+            end_block,
+            true,
+            builder,
+        )?;
 
-                    let module_ent = &mut self.modules[module];
-                    let value = module_ent.add_temp_local(ty);
-                    module_ent.add_cmd(cmd::Kind::Lit(kind), value, token, body);
-                    Some(value)
-                }
-                _ => None,
-            }
-        } else {
-            None
-        }
+        Ok(None)
     }
 
-    fn lit(&mut self, fun: Fun, ast: Ast, body: &mut FunBody) -> ExprResult {
-        let module = self.funs[fun].module;
-        let module_ent = &self.modules[module];
-        let token = *module_ent.token(ast);
-        let repo = self.state.builtin_repo;
-        let ty = match token.kind {
-            LTKind::Int(_, base) => match base {
-                8 => repo.i8,
-                16 => repo.i16,
-                32 => repo.i32,
-                64 => repo.i64,
-                _ => repo.int,
-            },
-            LTKind::Uint(_, base) => match base {
-                8 => repo.u8,
-                16 => repo.u16,
-                32 => repo.u32,
-                64 => repo.u64,
-                _ => repo.uint,
-            },
-            LTKind::Float(_, base) => match base {
-                32 => repo.f32,
-                _ => repo.f64,
-            },
-            LTKind::Bool(_) => repo.bool,
-            LTKind::Char(_) => repo.u32,
-            LTKind::String(_) => self.pointer_of(repo.u8, false),
-            _ => unreachable!(
-                "{}",
-                AstDisplay::new(self.state, &self.modules[module].a_state, ast)
-            ),
-        };
-
-        let module_ent = &mut self.modules[module];
-        let value = module_ent.add_temp_local(ty);
-
-        module_ent.add_cmd(cmd::Kind::Lit(token.kind.clone()), value, token, body);
+    /// Translates `for item in iter:` loop. Iterator is stored in mutable
+    /// variable and each iteration calls `iter.next()` which has to return
+    /// `Option`-like value, `is_some` decides whether loop continues and
+    /// `unwrap` produces the item.
+    fn for_in_expr(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        builder: &mut Builder,
+    ) -> ExprResult {
+        let sons = ast_data.sons(ast);
+        let (label, item, iterator, body) = (sons[0], sons[1], sons[2], sons[3]);
+        let token = ast_data.token(iterator);
+
+        let iterator = self.expr(ast_data, module, iterator, builder)?;
+        let ty = builder.type_of_value(iterator);
+        let var = builder.add_local(ty, true);
+        builder.add_var_decl(iterator, var);
+
+        let start_block = builder.new_block();
+        let body_block = builder.new_block();
+        let end_block = builder.new_block();
+        builder.add_valueless_cmd(cmd::Kind::Jump(start_block, EntityList::new()));
+        builder.select_block(start_block);
+
+        let next = self.method_call(ast_data, module, "next", &[var], token, builder)?;
+        let is_some = self.method_call(ast_data, module, "is_some", &[next], token, builder)?;
+        assert_type(builder.type_of_value(is_some), BOOL_TY, token)?;
+        builder.add_valueless_cmd(cmd::Kind::JumpIfTrue(
+            is_some,
+            body_block,
+            EntityList::new(),
+        ));
+        builder.add_valueless_cmd(cmd::Kind::Jump(end_block, EntityList::new()));
+
+        builder.select_block(body_block);
+        self.push_scope();
+        let result = self
+            .method_call(ast_data, module, "unwrap", &[next], token, builder)
+            .and_then(|value| {
+                let item_var = builder.add_local(builder.type_of_value(value), false);
+                builder.add_var_decl(value, item_var);
+                self.vars
+                    .push((self.hash_token(ast_data.token(item)), item_var));
+                self.loop_body(
+                    ast_data,
+                    module,
+                    label,
+                    body,
+                    start_block,
+                    end_block,
+                    true,
+                    builder,
+                )
+            });
+        self.pop_scope();
+        result?;
 
-        Ok(Some(value))
+        Ok(None)
     }
 
-    fn binary_op(&mut self, fun: Fun, ast: Ast, body: &mut FunBody) -> ExprResult {
-        let module = self.funs[fun].module;
-        let module_ent = &mut self.modules[module];
-        let &AstEnt { sons, token, .. } = module_ent.load(ast);
-        let op = module_ent.get_ent(sons, 0).token;
+    /// Translates loop body into the current block. Body jumps back to the
+    /// `start_block` and end block is selected after.
+    fn loop_body(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        label: Ast,
+        body: Ast,
+        start_block: Section,
+        end_block: Section,
+        conditional: bool,
+        builder: &mut Builder,
+    ) -> Result {
+        let name = if label.is_reserved_value() {
+            ID(0)
+        } else {
+            self.hash_token(ast_data.token(label))
+        };
+
+        self.loops.push(Loop {
+            name,
+            start_block,
+            end_block,
+            conditional,
+        });
+        let result = self.block(ast_data, module, body, builder);
+        self.loops.pop();
+        result?;
 
-        if op.span.hash == ID::new("=") {
-            return self.assignment(fun, ast, body);
-        } else if op.span.hash == ID::new("as") {
-            return self.bit_cast(fun, ast, body);
+        if !builder.is_closed() {
+            builder.add_valueless_cmd(cmd::Kind::Jump(start_block, EntityList::new()));
         }
 
-        let left = module_ent.get(sons, 1);
-        let right = module_ent.get(sons, 2);
+        builder.select_block(end_block);
 
-        let left = self.expr(fun, left, body)?;
-        let right = self.expr(fun, right, body)?;
+        Ok(())
+    }
 
-        self.call_low(
-            fun,
-            None,
-            None,
-            BINARY_SALT,
-            op.span,
-            &[],
-            &[left, right],
-            token,
-            body,
-        )
+    /// Calls method on `args[0]` that is required to return a value.
+    fn method_call(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        name: &str,
+        args: &[Local],
+        token: Token,
+        builder: &mut Builder,
+    ) -> Result<Local> {
+        let mut args_vec = self.temp_vec();
+        args_vec.extend_from_slice(args);
+        let fun = match self.find_method(module, ID::new(name), &mut args_vec, token, builder)? {
+            Some(fun) => fun,
+            None => {
+                let types = args_vec.iter().map(|&a| builder.type_of_value(a)).collect();
+                let name = self.builtin_span(name);
+                return Err(Error::new(
+                    error::Kind::FunctionNotFound(name, types),
+                    token,
+                ));
+            }
+        };
+
+        self.call_low(ast_data, module, fun, &[], &mut args_vec, true, token, builder)?
+            .ok_or_else(|| Error::new(error::Kind::ExpectedValue, token))
     }
 
-    fn bit_cast(&mut self, fun: Fun, ast: Ast, body: &mut FunBody) -> ExprResult {
-        let module = self.funs[fun].module;
-        let module_ent = &mut self.modules[module];
-        let &AstEnt { sons, token, .. } = module_ent.load(ast);
-        let target = module_ent.get(sons, 1);
-        let ty = module_ent.get(sons, 2);
-        let target = self.expr(fun, target, body)?;
-        let ty = self.parse_type(module, ty)?;
+    fn break_statement(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        builder: &mut Builder,
+    ) -> Result {
+        let token = ast_data.token(ast);
+        let sons = ast_data.sons(ast);
+        let (label, value) = (sons[0], sons[1]);
+        let label = if label.is_reserved_value() {
+            Token::default()
+        } else {
+            ast_data.token(label)
+        };
 
-        let original_datatype = self.modules[module].type_of_value(target);
-        let original_size = self.types[original_datatype].size;
-        let datatype_size = self.types[ty].size;
+        let Loop {
+            end_block,
+            conditional,
+            ..
+        } = self.find_loop(label).map_err(|outside| {
+            if outside {
+                Error::new(error::Kind::BreakOutsideLoop, token)
+            } else {
+                Error::new(error::Kind::WrongLabel, label)
+            }
+        })?;
 
-        if original_size != datatype_size {
+        if conditional && !value.is_reserved_value() {
             return Err(Error::new(
-                error::Kind::InvalidBitCast(original_size, datatype_size),
-                token,
+                error::Kind::BreakValueInConditionalLoop,
+                ast_data.token(value),
             ));
         }
 
-        Ok(Some(self.modules[module].cast(target, ty, token, body)))
+        let args = if value.is_reserved_value() {
+            if builder.last_section_arg(end_block).is_some() {
+                return Err(Error::new(error::Kind::ExpectedValue, token));
+            }
+            EntityList::new()
+        } else {
+            let value_token = ast_data.token(value);
+            let value = self.expr(ast_data, module, value, builder)?;
+            let ty = builder.type_of_value(value);
+            if let Some(arg) = builder.last_section_arg(end_block) {
+                assert_type(ty, builder.type_of_value(arg), value_token)?;
+            } else {
+                let arg = builder.add_temp_local(ty);
+                builder.push_section_arg(end_block, arg);
+            }
+            builder.add_locals(&[value])
+        };
+
+        builder.add_valueless_cmd(cmd::Kind::Jump(end_block, args));
+
+        Ok(())
     }
 
-    fn field_access(
+    fn continue_statement(
         &mut self,
-        fun: Fun,
-        mut header: Local,
-        field: ID,
-        token: Token,
-
-    ) -> Result<Local> {
-        let in_assign = self.context.in_assign;
-        let module = self.funs[fun].module;
-        let module_ent = &self.state.modules[module];
-        let ty = module_ent.type_of_value(header);
-        let mut mutable = module_ent.is_mutable(header) || self.t_state.pointer_mutability(ty);
-        let mut path = self.temp_vec();
-        let success = self.find_field(ty, field, &mut path);
-        if !success {
-            return Err(Error::new(error::Kind::UnknownField(ty), token));
+        ast_data: &ast::DataSwitch,
+        ast: Ast,
+        builder: &mut Builder,
+    ) -> Result {
+        let token = ast_data.token(ast);
+        let label = ast_data.son(ast, 0);
+        let label = if label.is_reserved_value() {
+            Token::default()
+        } else {
+            ast_data.token(label)
         };
 
-        let mut offset = Size::ZERO;
-        let mut current_type = ty;
-        for &i in path.iter().rev() {
-            let ty = &self.types[current_type];
-            match &ty.kind {
-                ty::Kind::Structure(stype) => {
-                    let s_field = &stype.fields[i];
-                    if !self.state.can_access(module, ty.module, s_field.vis) {
-                        return Err(Error::new(error::Kind::FieldVisibilityViolation, token));
-                    }
-                    offset = offset.add(s_field.offset);
-                    current_type = s_field.ty;
-                }
-                &ty::Kind::Pointer(pointed, p_mutable) => {
-                    mutable &= p_mutable;
-                    let value =
-                        self.modules[module].offset_value(header, pointed, offset, token, body);
-                    let ty = &self.types[pointed];
-                    match &ty.kind {
-                        ty::Kind::Structure(stype) => {
-                            let s_field = &stype.fields[i];
-                            if !self.state.can_access(module, ty.module, s_field.vis) {
-                                return Err(Error::new(error::Kind::FieldVisibilityViolation, token));
-                            }
-                            offset = s_field.offset;
-                            current_type = s_field.ty;
-                            let module_ent = &mut self.modules[module];
-                            let loaded = module_ent.add_value_ent(LocalEnt {
-                                ty: current_type,
-                                mutable,
-                                offset,
-
-                                ..Default::default()
-                            });
-                            module_ent.add_cmd(
-                                cmd::Kind::Deref(value, in_assign),
-                                loaded,
-                                token,
-                                body,
-                            );
-                            header = loaded;
-                        }
-                        _ => unreachable!(),
-                    }
-                }
-                _ => todo!("{}", TypeDisplay::new(self.state, current_type)),
+        let Loop { start_block, .. } = self.find_loop(label).map_err(|outside| {
+            if outside {
+                Error::new(error::Kind::ContinueOutsideLoop, token)
+            } else {
+                Error::new(error::Kind::WrongLabel, label)
             }
-        }
+        })?;
+
+        builder.add_valueless_cmd(cmd::Kind::Jump(start_block, EntityList::new()));
 
-        Ok(self.modules[module].offset_value(header, current_type, offset, token, body))
+        Ok(())
     }
 
-    fn assignment(&mut self, fun: Fun, ast: Ast, body: &mut FunBody) -> ExprResult {
-        let module = self.funs[fun].module;
-        let module_ent = &mut self.modules[module];
-        let &AstEnt { sons, token, .. } = module_ent.load(ast);
-        let target = module_ent.get(sons, 1);
-        let value = module_ent.get(sons, 2);
+    /// Translates if expression. Value is produced only if all branches
+    /// that do not jump away produce value of the same type, otherwise
+    /// values are discarded.
+    fn if_expr(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        builder: &mut Builder,
+    ) -> ExprResult {
+        let sons = ast_data.sons(ast);
+        let (condition, then_branch, else_branch) = (sons[0], sons[1], sons[2]);
 
-        let prev_in_var_ref = self.context.in_var_ref;
-        let prev_in_assign = self.context.in_assign;
-        self.context.in_assign = true;
-        self.context.in_var_ref = true;
-        let target = self.expr(fun, target, body)?;
-        self.context.in_assign = prev_in_assign;
-        self.context.in_var_ref = prev_in_var_ref;
+        let token = ast_data.token(condition);
+        let condition = self.expr(ast_data, module, condition, builder)?;
+        assert_type(builder.type_of_value(condition), BOOL_TY, token)?;
 
-        let value = self.expr(fun, value, body)?;
+        let then_block = builder.new_block();
+        let else_block = if else_branch.is_reserved_value() {
+            None
+        } else {
+            Some(builder.new_block())
+        };
+        let merge_block = builder.new_block();
+
+        builder.add_valueless_cmd(cmd::Kind::JumpIfTrue(
+            condition,
+            then_block,
+            EntityList::new(),
+        ));
+        builder.add_valueless_cmd(cmd::Kind::Jump(
+            else_block.unwrap_or(merge_block),
+            EntityList::new(),
+        ));
+
+        let mut ends = self.temp_vec();
+
+        builder.select_block(then_block);
+        let value = self.block(ast_data, module, then_branch, builder)?;
+        if let Some(block) = builder.take_block() {
+            ends.push((block, value));
+        }
 
-        let module_ent = &mut self.modules[module];
-        let target_datatype = module_ent.type_of_value(target);
-        let value_datatype = module_ent.type_of_value(value);
-        let mutable = module_ent.is_mutable(target);
-        if !mutable {
-            return Err(Error::new(error::Kind::AssignToImmutable, token));
+        if let Some(else_block) = else_block {
+            builder.select_block(else_block);
+            let value = if ast_data.kind(else_branch) == ast::Kind::Elif {
+                let branch = ast_data.son(else_branch, 0);
+                self.if_expr(ast_data, module, branch, builder)?
+            } else {
+                self.block(ast_data, module, else_branch, builder)?
+            };
+            if let Some(block) = builder.take_block() {
+                ends.push((block, value));
+            }
         }
 
-        assert_type(value_datatype, target_datatype, &token)?;
-        module_ent.assign(target, value, token, body);
-        Ok(Some(value))
+        Ok(self.merge_branches(&ends, else_block.is_some(), merge_block, builder))
     }
 
-    fn create(&mut self, fun: Fun, explicit_params: &[Ty], locals: &[Ty]) -> Result<Option<Fun>> {
-        let mut arg_buffer = self.temp_vec();
-        let mut stack = self.temp_vec();
-        let mut params = self.temp_vec();
-        let mut g_params = self.temp_vec();
+    /// Joins branch ends into the `merge_block`. Value is passed as block
+    /// argument only if branches are `exhaustive` and all of them produce
+    /// value of the same type.
+    fn merge_branches(
+        &mut self,
+        ends: &[(Section, Option<Local>)],
+        exhaustive: bool,
+        merge_block: Section,
+        builder: &mut Builder,
+    ) -> Option<Local> {
+        let ty = match ends.first() {
+            Some(&(_, Some(value))) if exhaustive => {
+                let ty = builder.type_of_value(value);
+                let all_match = ends.iter().all(|&(_, value)| {
+                    value.map(|value| builder.type_of_value(value)) == Some(ty)
+                });
+                if all_match {
+                    Some(ty)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
 
-        let g_ent = self.generic_funs.get_mut(fun).unwrap();
-        g_params.extend_from_slice(&g_ent.sig.params);
-        let g_ent_len = g_ent.sig.elements.len();
-        let call_conv = g_ent.call_conv;
-        let elements = std::mem::take(&mut g_ent.sig.elements);
+        let result = ty.map(|ty| {
+            let value = builder.add_temp_local(ty);
+            builder.push_section_arg(merge_block, value);
+            value
+        });
 
-        params.resize(g_ent.sig.params.len(), None);
-        for (exp, param) in explicit_params.iter().zip(params.iter_mut()) {
-            *param = Some(exp.clone());
+        for &(block, value) in ends.iter() {
+            builder.select_block(block);
+            let args = if result.is_some() {
+                builder.add_locals(&[value.unwrap()])
+            } else {
+                EntityList::new()
+            };
+            builder.add_valueless_cmd(cmd::Kind::Jump(merge_block, args));
         }
 
-        // perform pattern matching
-        let mut i = 0;
-        let mut j = 0;
-        let ok = 'o: loop {
-            if i >= g_ent_len {
-                break true;
-            }
-            let (amount, length) = match elements[i] {
-                GenericElement::NextArgument(amount, length) => (amount, length),
-                _ => unreachable!("{:?}", elements[i]),
-            };
+        builder.select_block(merge_block);
 
-            for _ in 0..amount {
-                self.load_arg_from_datatype(locals[j], &mut arg_buffer, &mut stack);
-                let arg = &elements[i + 1..i + 1 + length];
-                {
-                    let mut i = 0;
-                    let mut j = 0;
-                    while i + j < arg.len() + arg_buffer.len() {
-                        let a = arg[i].clone();
-                        let b = arg_buffer[j].clone();
-                        if !a.compare(&b) {
-                            match a {
-                                GenericElement::Parameter(i) => match b {
-                                    GenericElement::Element(_, Some(ty))
-                                    | GenericElement::Array(Some(ty)) => {
-                                        if let Some(inferred_ty) = params[i] {
-                                            if ty != inferred_ty {
-                                                break 'o false;
-                                            }
-                                        } else {
-                                            params[i] = Some(ty);
-                                        }
-                                        if let GenericElement::Array(_) = b {
-                                            j += 2;
-                                        } else if let Some(&GenericElement::ScopeStart) =
-                                            arg_buffer.get(j + 1)
-                                        {
-                                            loop {
-                                                if let Some(&GenericElement::ScopeEnd) =
-                                                    arg_buffer.get(j)
-                                                {
-                                                    break;
-                                                }
-                                                j += 1;
-                                            }
-                                        }
-                                    }
-                                    _ => break 'o false,
-                                },
-                                _ => break 'o false,
-                            }
-                        }
-                        j += 1;
-                        i += 1;
-                    }
-                }
-                arg_buffer.clear();
-                j += 1;
+        result
+    }
+
+    /// Translates match expression into compare chain. Arms are tested
+    /// in order, arm that can never match is an error, and so is a match
+    /// that does not cover all values without a wildcard. Value is produced
+    /// the same way as with [`Self::if_expr`].
+    fn match_expr(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        builder: &mut Builder,
+    ) -> ExprResult {
+        let sons = ast_data.sons(ast);
+        let (value, arms) = (sons[0], sons[1]);
+
+        let token = ast_data.token(value);
+        let mut value = self.expr(ast_data, module, value, builder)?;
+        let ty = builder.type_of_value(value);
+
+        // enums are matched by their repr
+        let compare_ty = match self.type_kind(ty) {
+            ty::Kind::Enumeration(repr, _) => {
+                value = builder.cast(value, repr);
+                repr
             }
-            i += length + 1;
+            _ if ty == BOOL_TY || ty == CHAR_TY || is_integer(ty) => ty,
+            _ => return Err(Error::new(error::Kind::InvalidMatchType(ty), token)),
         };
+        let bounds = self.value_bounds(compare_ty);
 
-        self.generic_funs.get_mut(fun).unwrap().sig.elements = elements;
-
-        if !ok {
-            return Ok(None);
+        let merge_block = builder.new_block();
+        let mut covered = self.temp_vec();
+        if compare_ty == CHAR_TY {
+            // surrogates are not chars, there is nothing to cover
+            covered.push((0xD800, 0xDFFF));
         }
+        let mut wildcard = false;
+        let mut ends = self.temp_vec();
 
-        let FunEnt {
-            module,
-            untraced,
-            vis,
-            scope,
-            inline,
-            attrs,
-            hint,
-            name,
-            linkage,
-            ast,
-            ..
-        } = self.funs[fun];
-        let mut id = FUN_SALT.add(name.hash);
-        let fun_module_id = self.state.modules[module].id;
+        for &arm in ast_data.sons(arms) {
+            let sons = ast_data.sons(arm);
+            let (pattern, body) = (sons[0], sons[1]);
+            let token = ast_data.token(pattern);
 
-        let mut shadowed = self.temp_vec();
-        let mut final_params = EntityList::new();
-        for i in 0..params.len() {
-            if let Some(ty) = params[i] {
-                id = id.add(self.types[ty].id);
-                let id = TYPE_SALT.add(g_params[i]).add(fun_module_id);
-                shadowed.push((id, self.state.types.link(id, ty)));
-                self.modules[module].push_type(&mut final_params, ty);
-            } else {
-                return Ok(None);
+            let range = self.match_pattern(ast_data, module, pattern, ty, bounds)?;
+            if wildcard || range.map_or(false, |range| range_covered(&covered, range)) {
+                return Err(Error::new(error::Kind::UnreachableArm, token));
             }
-        }
-        id = id.add(self.state.modules[module].id);
 
-        if let Some(fun) = self.state.find_computed(self.module, id) {
-            self.modules[module].clear_types(&mut final_params);
-            return Ok(Some(fun));
-        }
+            let arm_block = builder.new_block();
+            let next_block = builder.new_block();
+            match range {
+                None => {
+                    wildcard = true;
+                    builder.add_valueless_cmd(cmd::Kind::Jump(arm_block, EntityList::new()));
+                }
+                Some((expected, high)) if compare_ty == BOOL_TY => {
+                    // ranges over bool are rejected by `match_pattern`
+                    debug_assert_eq!(expected, high);
+                    let (on_true, on_false) = if expected == 1 {
+                        (arm_block, next_block)
+                    } else {
+                        (next_block, arm_block)
+                    };
+                    builder.add_valueless_cmd(cmd::Kind::JumpIfTrue(
+                        value,
+                        on_true,
+                        EntityList::new(),
+                    ));
+                    builder.add_valueless_cmd(cmd::Kind::Jump(on_false, EntityList::new()));
+                }
+                Some((low, high)) if low == high => {
+                    let condition =
+                        self.compare_const(ast_data, module, "==", value, low, token, builder)?;
+                    builder.add_valueless_cmd(cmd::Kind::JumpIfTrue(
+                        condition,
+                        arm_block,
+                        EntityList::new(),
+                    ));
+                    builder.add_valueless_cmd(cmd::Kind::Jump(next_block, EntityList::new()));
+                }
+                Some((low, high)) => {
+                    let high_block = builder.new_block();
+                    let condition =
+                        self.compare_const(ast_data, module, ">=", value, low, token, builder)?;
+                    builder.add_valueless_cmd(cmd::Kind::JumpIfTrue(
+                        condition,
+                        high_block,
+                        EntityList::new(),
+                    ));
+                    builder.add_valueless_cmd(cmd::Kind::Jump(next_block, EntityList::new()));
+
+                    builder.select_block(high_block);
+                    let condition =
+                        self.compare_const(ast_data, module, "<=", value, high, token, builder)?;
+                    builder.add_valueless_cmd(cmd::Kind::JumpIfTrue(
+                        condition,
+                        arm_block,
+                        EntityList::new(),
+                    ));
+                    builder.add_valueless_cmd(cmd::Kind::Jump(next_block, EntityList::new()));
+                }
+            }
 
-        if !scope.is_reserved_value() {
-            let ty = self.modules[module].son(scope, 1);
-            let ty = self.parse_type(module, ty)?;
-            let id = TYPE_SALT.add(ID::new("Self")).add(fun_module_id);
-            shadowed.push((id, self.state.types.link(id, ty)));
-        }
+            if let Some(range) = range {
+                covered.push(range);
+            }
 
-        let header = self.modules[module].son(ast, 0);
-        let mut sig = self.parse_signature(module, header)?;
-        sig.call_conv = call_conv;
+            builder.select_block(arm_block);
+            let value = self.block(ast_data, module, body, builder)?;
+            if let Some(block) = builder.take_block() {
+                ends.push((block, value));
+            }
 
-        for (id, ty) in shadowed.drain(..) {
-            self.state.types.remove_link(id, ty);
+            builder.select_block(next_block);
         }
 
-        let new_fun_ent = FunEnt {
-            vis,
-            id,
-            module,
-            hint,
-            name,
-            attrs,
-            scope,
-            untraced,
-            inline,
-            linkage,
-            sig,
-            ast,
+        let exhaustive = wildcard
+            || match self.type_kind(ty) {
+                ty::Kind::Enumeration(_, variants) => {
+                    let mut covered_variants = self.temp_vec();
+                    covered_variants.extend(
+                        self.enum_variant_slice(variants)
+                            .iter()
+                            .filter(|&&variant| {
+                                let value = self.enum_variant(variant).value();
+                                range_covered(&covered, (value, value))
+                            })
+                            .cloned(),
+                    );
+                    let missing = self
+                        .missing_variants(ty, &covered_variants)
+                        .into_iter()
+                        .map(|variant| self.enum_variant(variant).hint().span())
+                        .collect::<Vec<_>>();
+                    if !missing.is_empty() {
+                        return Err(Error::new(
+                            error::Kind::NonExhaustiveMatch(missing),
+                            ast_data.token(ast),
+                        ));
+                    }
+                    true
+                }
+                _ => range_covered(&covered, bounds),
+            };
 
-            base_fun: PackedOption::from(fun),
-            params: final_params,
-            kind: FKind::Normal,
-            alias: None,
-            body: FunBody::default(),
-        };
+        if !exhaustive {
+            return Err(Error::new(
+                error::Kind::NonExhaustiveMatch(vec![]),
+                ast_data.token(ast),
+            ));
+        }
 
-        let (shadowed, id) = self.state.add_fun(self.module, new_fun_ent);
-        debug_assert!(shadowed.is_none());
+        // all values are covered so falling through is impossible
+        builder.add_valueless_cmd(cmd::Kind::Unreachable);
 
-        self.context.unresolved.push(id);
+        Ok(self.merge_branches(&ends, true, merge_block, builder))
+    }
 
-        Ok(Some(id))
+    /// Resolves pattern of match arm into inclusive range of values it
+    /// covers. `None` stands for wildcard.
+    fn match_pattern(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        ty: Ty,
+        bounds: (i128, i128),
+    ) -> Result<Option<(i128, i128)>> {
+        let (kind, sons, token) = ast_data.ent(ast).parts();
+        match kind {
+            ast::Kind::Ident if self.display_token(token) == "_" => Ok(None),
+            ast::Kind::Range(inclusive) => {
+                let sons = ast_data.slice(sons);
+                let low = self.pattern_value(ast_data, module, sons[0], ty, bounds)?;
+                let high = self.pattern_value(ast_data, module, sons[1], ty, bounds)?;
+                let high = if inclusive { high } else { high - 1 };
+                if ty == BOOL_TY || low > high {
+                    return Err(Error::new(error::Kind::InvalidRangePattern, token));
+                }
+                Ok(Some((low, high)))
+            }
+            _ => {
+                let value = self.pattern_value(ast_data, module, ast, ty, bounds)?;
+                Ok(Some((value, value)))
+            }
+        }
     }
 
-    fn collect(&mut self, module: Mod) -> Result {
-        let module_ent = &self.modules[module];
-        let funs_len = module_ent.funs().len();
-        let globals_len = module_ent.globals().len();
-        let mut scope_state = (
-            Ast::reserved_value(),
-            Ast::reserved_value(),
-            ID(0),
-            None,
-            Vis::None,
-        );
+    /// Folds pattern bound into constant and checks it against matched type.
+    fn pattern_value(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        ty: Ty,
+        (low, high): (i128, i128),
+    ) -> Result<i128> {
+        let token = ast_data.token(ast);
+        let constant = self
+            .fold_immediate_const(ast_data, module, ast)
+            .map_err(Into::into)?;
+
+        let value = match constant {
+            constant::Kind::Enum(enum_ty, variant) if enum_ty == ty => {
+                self.enum_variant(variant).value()
+            }
+            constant::Kind::Bool(value) if ty == BOOL_TY => value as i128,
+            constant::Kind::Char(value) if ty == CHAR_TY => value as i128,
+            constant::Kind::Int(value, _) if is_integer(ty) => value as i128,
+            constant::Kind::Uint(value, _) if is_integer(ty) => value as i128,
+            _ => return Err(Error::new(error::Kind::InvalidPattern(ty), token)),
+        };
 
-        for i in (0..funs_len).step_by(3) {
-            let funs = self.modules[module].funs();
-            let (ast, attrs, scope) = (funs[i], funs[i + 1], funs[i + 2]);
-            self.parse_scope(module, scope, &mut scope_state)?;
-            self.collect_fun(
-                module,
-                ast,
-                attrs,
-                scope,
-                scope_state.2,
-                scope_state.1,
-                scope_state.4,
-            )?
+        if value < low || value > high {
+            return Err(Error::new(error::Kind::PatternOutOfRange(ty), token));
         }
 
-        let main_fun_id = self.context.entry_point_data.id;
+        Ok(value)
+    }
+
+    /// Compares `value` with integer `constant` of the same type using
+    /// builtin operator `op`.
+    fn compare_const(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        op: &str,
+        value: Local,
+        constant: i128,
+        token: Token,
+        builder: &mut Builder,
+    ) -> Result<Local> {
+        let ty = builder.type_of_value(value);
+        let base = match ty {
+            INT_TY | UINT_TY => POINTER_WIDTH_MARKER,
+            _ => (self.type_size(ty).s64() * 8) as u8,
+        };
+        let constant = if ty == CHAR_TY {
+            constant::Kind::Char(char::from_u32(constant as u32).unwrap_or_default())
+        } else if matches!(ty, I8_TY | I16_TY | I32_TY | I64_TY | INT_TY) {
+            constant::Kind::Int(constant as i64, base)
+        } else {
+            constant::Kind::Uint(constant as u64, base)
+        };
+        let constant = self.add_constant(constant);
+        let constant = self.lit(module, constant, builder);
+
+        let id = ID::new(op).add(self.type_base_id(ty)).add(ID::new("-b-"));
+        let fun = self
+            .find_fun(module, None, id, token)?
+            .expect("builtin compare operator is missing");
 
-        let mut body = self.funs[main_fun_id].body;
+        let mut args = self.temp_vec();
+        args.extend_from_slice(&[value, constant]);
+        let result = self.call_low(ast_data, module, fun, &[], &mut args, false, token, builder)?;
+        Ok(result.unwrap())
+    }
 
-        for i in (0..globals_len).step_by(3) {
-            let globals = self.modules[module].globals();
-            let (ast, attrs, scope) = (globals[i], globals[i + 1], globals[i + 2]);
-            self.parse_scope(module, scope, &mut scope_state)?;
-            self.collect_global_var(module, ast, attrs, scope_state.2, &mut body, scope_state.4)?
+    /// Returns inclusive range of values representable by `ty`.
+    fn value_bounds(&self, ty: Ty) -> (i128, i128) {
+        if ty == BOOL_TY {
+            return (0, 1);
         }
-
-        if let Some((id, ty)) = scope_state.3 {
-            self.state.types.remove_link(id, ty);
+        if ty == CHAR_TY {
+            return (0, char::MAX as i128);
         }
-
-        for i in 0..self.context.entry_points.len() {
-            let entry_point = self.context.entry_points[i];
-            self.add_entry(entry_point, &mut body)?;
+        let bits = self.type_size(ty).s64() * 8;
+        if matches!(ty, I8_TY | I16_TY | I32_TY | I64_TY | INT_TY) {
+            (-(1 << (bits - 1)), (1 << (bits - 1)) - 1)
+        } else {
+            (0, (1 << bits) - 1)
         }
+    }
 
-        self.funs[main_fun_id].body = body;
-
-        Ok(())
-    }*/
-    
-    fn parse_scope(
+    fn call(
         &mut self,
         ast_data: &ast::DataSwitch,
         module: Mod,
         ast: Ast,
-        state: &mut ScopeState,
-    ) -> Result {
-        if ast == state.previous {
-            return Ok(());
+        builder: &mut Builder,
+    ) -> ExprResult {
+        let (kind, sons, token) = ast_data.ent(ast).parts();
+        let sons = ast_data.slice(sons);
+        let dot = kind == ast::Kind::Call(true);
+        let callee = sons[0];
+
+        let prev = std::mem::replace(&mut self.in_assign, false);
+        let mut args = self.temp_vec();
+        let mut result = Ok(());
+        for &arg in &sons[1..] {
+            match self.expr(ast_data, module, arg, builder) {
+                Ok(value) => args.push(value),
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
         }
-        state.previous = ast;
-        state.generics = Ast::reserved_value();
-        state.id = ID(0);
-        if let Some((id, item)) = state.shadow.take() {
-            self.pop_item(module, id, item);
+        self.in_assign = prev;
+        result?;
+
+        if !dot && ast_data.kind(callee) == ast::Kind::Deref {
+            return self.fun_pointer_call(ast_data, module, callee, &args, builder);
         }
-        if ast.is_reserved_value() {
-            return Ok(());
-        }
-
-        let (kind, sons, _) = ast_data.ent(ast).parts();
-        let sons = ast_data.slice(sons);
-        let (generics, ty) = (sons[0], sons[1]);
-        let ty_kind = ast_data.kind(ty);
 
-        state.vis = match kind {
-            ast::Kind::Impl(vis) => vis,
-            _ => unreachable!(),
+        let mut params = self.temp_vec();
+        let name = if ast_data.kind(callee) == ast::Kind::Instantiation {
+            let sons = ast_data.sons(callee);
+            for &param in &sons[1..] {
+                params.push(self.parse_type(ast_data, module, param)?);
+            }
+            sons[0]
+        } else {
+            callee
         };
 
-        state.generics = generics;
-
-        let ty = match ty_kind {
-            ast::Kind::Ident | ast::Kind::Instantiation if generics.is_reserved_value() => {
-                self.parse_type(ast_data, module, ty)?
+        let (target, scope, name) = self.parse_ident(ast_data, module, name)?;
+        let fun = if dot {
+            if target.is_some() || scope != ID(0) {
+                return Err(Error::new(error::Kind::InvalidDotCall, token));
+            }
+            if let Some(bound) = self.dyn_receiver(&args, builder) {
+                return self.dyn_call(ast_data, module, bound, name, &mut args, token, builder);
             }
+            let id = self.hash_token(name);
+            self.find_method(module, id, &mut args, name, builder)?
+        } else {
+            let id = self.hash_token(name).add(scope);
+            self.find_fun(module, target, id, name)?
+        };
 
-            ast::Kind::Instantiation => {
-                let base = ast_data.sons(ty)[0];
-                self.parse_type(ast_data, module, base)?
+        let fun = match fun {
+            Some(fun) => fun,
+            None => {
+                let types = args.iter().map(|&a| builder.type_of_value(a)).collect();
+                return Err(Error::new(
+                    error::Kind::FunctionNotFound(name.span(), types),
+                    name,
+                ));
             }
-            ast::Kind::Array => ARRAY_TY,
-            kind => unreachable!("{:?}", kind),
         };
 
-        state.id = self.ctx.type_base_id(ty);
-        let id = ID::new("Self");
-        state.shadow = Some((id, self.push_item(module, id, item::Kind::Ty(ty))));
+        if dot {
+            self.check_char_conversion(fun, args[0], ast_data.token(sons[1]), builder)?;
+        }
 
-        Ok(())
+        self.call_low(ast_data, module, fun, &params, &mut args, dot, token, builder)
     }
 
-    pub fn collect_global_var(
+    /// Conversion of constant char into byte is checked at compile time,
+    /// character has to be ASCII. Constant is recognized by the command
+    /// that produced the `receiver`, so literals and named constants
+    /// are both covered.
+    fn check_char_conversion(
+        &mut self,
+        fun: Fun,
+        receiver: Local,
+        token: Token,
+        builder: &Builder,
+    ) -> Result {
+        let FunEnt { kind, sig, .. } = self.funs[fun];
+        let ret = match sig.ret.expand() {
+            Some(ret @ (U8_TY | I8_TY)) if kind == FKind::Builtin => ret,
+            _ => return Ok(()),
+        };
+        let constant = match builder.local(receiver).inst.expand() {
+            Some(inst) => match builder.cmd(inst).kind {
+                cmd::Kind::Lit(constant) => constant,
+                _ => return Ok(()),
+            },
+            None => return Ok(()),
+        };
+        match self.constant_kind(constant) {
+            constant::Kind::Char(value) if !value.is_ascii() => Err(Error::new(
+                error::Kind::NonAsciiChar(value, ret),
+                token,
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn call_low(
         &mut self,
         ast_data: &ast::DataSwitch,
         module: Mod,
-        ast: Ast,
-        attrs: Ast,
-        scope: ID,
-        vis: Vis,
+        fun: Fun,
+        params: &[Ty],
+        args: &mut Vec<Local>,
+        dot: bool,
+        token: Token,
         builder: &mut Builder,
-    ) -> Result {
-        let fun = self.entry_point_data.id;
-        let (kind, sons, _) = ast_data.ent(ast).parts();
-        let sons = ast_data.slice(sons);
-        let (vis, mutable) = match kind {
-            ast::Kind::VarStatement(a_vis, mutable) => (vis.join(a_vis), mutable),
-            _ => unreachable!(),
+    ) -> ExprResult {
+        let fun = if self.funs[fun].kind == FKind::Generic {
+            if dot {
+                self.adjust_generic_receiver(ast_data, module, fun, args, token, builder)?;
+            }
+            let mut types = self.temp_vec();
+            types.extend(args.iter().map(|&a| builder.type_of_value(a)));
+            match self.create(ast_data, module, fun, params, &types)? {
+                Some(fun) => fun,
+                None => {
+                    return Err(Error::new(
+                        error::Kind::GenericMismatch(self.funs[fun].name, types.to_vec()),
+                        token,
+                    ))
+                }
+            }
+        } else {
+            fun
         };
 
-        let (linkage, alias) = self.resolve_linkage(ast_data, attrs)?;
+        let FunEnt {
+            sig,
+            vis,
+            module: fun_module,
+            kind,
+            untraced,
+            terminating,
+            ..
+        } = self.funs[fun];
 
-        for &ast in sons {
-            let sons = ast_data.sons(ast);
-            let (ident_group, ty, value_group) = (sons[0], sons[1], sons[2]);
-            let ty = if ty.is_reserved_value() {
-                None
-            } else {
-                Some(self.parse_type(ast_data, module, ty)?)
-            };
+        if !self.can_access(module, fun_module, vis) {
+            return Err(Error::new(error::Kind::VisibilityViolation, token));
+        }
 
-            let value_group = if value_group.is_reserved_value() { &[] } else { ast_data.sons(value_group) };
-            for (i, &ident) in ast_data.sons(ident_group).iter().enumerate() {
-                let hint = ast_data.token(ident);
-                let mut id = self.hash_token(hint);
-                if scope != ID(0) { 
-                    id = id.add(scope); 
-                }
+        let mut arg_types = self.temp_vec();
+        arg_types.extend_from_slice(self.type_slice(sig.args));
 
-                let g_ent = GlobalEnt {
-                    vis,
-                    mutable,
-                    id,
-                    module,
-                    ast,
-                    hint,
-                    attrs,
-                    linkage,
-                    alias,
+        if dot && !args.is_empty() && !arg_types.is_empty() {
+            args[0] = self.adjust_receiver(module, args[0], arg_types[0], token, builder)?;
+        }
 
-                    ..Default::default()
-                };
+        for (arg, &expected) in args.iter_mut().zip(arg_types.iter()) {
+            *arg = self.coerce(ast_data, module, *arg, expected, token, builder)?;
+        }
 
-                let id = self.add_global(module, g_ent)?;
+        let mismatch = arg_types.len() != args.len()
+            || args
+                .iter()
+                .zip(arg_types.iter())
+                .any(|(&arg, &expected)| builder.type_of_value(arg) != expected);
 
-                let ty = if !value_group.is_empty() {
-                    let value = self.expr(ast_data, fun, value_group[i], builder)?;
-                    builder.assign_global(id, value);
-                    builder.data.locals[value].ty
-                } else {
-                    ty.unwrap()
-                };
+        if mismatch {
+            let types = args.iter().map(|&a| builder.type_of_value(a)).collect();
+            return Err(Error::new(error::Kind::FunArgMismatch(fun, types), token));
+        }
 
-                self.globals[id].ty = ty;
+        let traced = self.do_stacktrace && !untraced && kind != FKind::Builtin;
+        if traced {
+            self.push_frame(module, token, builder)?;
+        }
 
-                self.resolved_globals.push(id);
-            }
+        let value = if let Some(ret) = sig.ret.expand() {
+            Some(builder.add_call(fun, args, ret))
+        } else {
+            builder.add_valueless_call(fun, args);
+            None
+        };
+
+        if traced {
+            self.pop_frame(module, token, builder)?;
         }
 
-        Ok(())
+        if terminating {
+            builder.add_valueless_cmd(cmd::Kind::Unreachable);
+        }
+
+        Ok(value)
     }
 
-    pub fn collect_fun(
+    fn fun_pointer_call(
         &mut self,
+        ast_data: &ast::DataSwitch,
         module: Mod,
-        mut ast: Ast,
-        mut attrs: Ast,
-        mut scope: Ast,
-        scope_id: ID,
-        generics: Ast,
-        vis: Vis,
-        temp_ast_data: &mut ast::Data,
-        saved_ast_data: &mut ast::Data,
-        reloc: &mut ast::Reloc,
-    ) -> Result {
-        let header = temp_ast_data.sons(ast)[0];
-        let (kind, sons, hint) = temp_ast_data.ent(header).parts();
-        let sons = temp_ast_data.slice(sons);
+        callee: Ast,
+        args: &[Local],
+        builder: &mut Builder,
+    ) -> ExprResult {
+        let token = ast_data.token(callee);
+        let mut pointer = self.expr(ast_data, module, ast_data.son(callee, 0), builder)?;
+        let ty = builder.type_of_value(pointer);
+
+        let mut all_args = self.temp_vec();
+        if let ty::Kind::Structure(StructureKind::Closure, _) = self.type_kind(ty) {
+            let env = self.nth_field(pointer, 0, builder);
+            all_args.push(self.reference(module, env, false, token, builder)?);
+            pointer = self.nth_field(pointer, 1, builder);
+        }
+        all_args.extend_from_slice(args);
 
-        let (op_kind, vis, mut call_conv) = match kind {
-            ast::Kind::FunHeader(op_kind, a_vis, call_conv) => {
-                (op_kind, vis.join(a_vis), call_conv)
-            }
-            _ => unreachable!(),
+        let sig = match self.type_kind(builder.type_of_value(pointer)) {
+            ty::Kind::FunPointer(sig) => sig,
+            _ => return Err(Error::new(error::Kind::ExpectedFunctionPointer, token)),
         };
 
-        if let Some(attr) = self.find_attribute(temp_ast_data, attrs, "call_conv") {
-            let sons = temp_ast_data.sons(attr);
-            let token = temp_ast_data.token(attr);
-            let name = sons
-                .get(1)
-                .ok_or_else(|| {
-                    Error::new(error::Kind::TooShortAttribute(sons.len() - 1, 1), token)
-                })?
-                .clone();
-            let token = temp_ast_data.token(name);
-            let str = self.display(token.span());
-            call_conv = CallConv::from_str(str)
-                .ok_or_else(|| Error::new(error::Kind::InvalidCallConv, token))?;
+        let types = all_args
+            .iter()
+            .map(|&a| builder.type_of_value(a))
+            .collect::<Vec<_>>();
+        if types.as_slice() != self.type_slice(sig.args) {
+            let types = types[all_args.len() - args.len()..].to_vec();
+            return Err(Error::new(
+                error::Kind::FunPointerArgMismatch(ty, types),
+                token,
+            ));
         }
 
-        let entry = self.find_attribute(temp_ast_data, attrs, "entry").is_some();
-        let untraced = self.find_attribute(temp_ast_data, attrs, "untraced").is_some();
-        let inline = self.find_attribute(temp_ast_data, attrs, "inline").is_some();
-
-        let (linkage, mut alias) =
-            self.resolve_linkage(&ast::DataSwitch::new(temp_ast_data, saved_ast_data), attrs)?;
-
-        let (name_kind, name_sons, name_token) = temp_ast_data.ent(sons[0]).parts();
-        let name_sons = temp_ast_data.slice(name_sons);
-        let (name, id, sig, kind, unresolved) =
-            if name_kind == ast::Kind::Ident && generics.is_reserved_value() {
-                let sig = self.parse_signature(
-                    &ast::DataSwitch::new(temp_ast_data, saved_ast_data),
-                    module,
-                    header,
-                    call_conv,
-                )?;
+        let args = builder.add_locals(all_args.as_slice());
+        let kind = cmd::Kind::FunPointerCall(pointer, args);
+        Ok(if let Some(ret) = sig.ret.expand() {
+            let value = builder.add_temp_local(ret);
+            builder.add_cmd(kind, value);
+            Some(value)
+        } else {
+            builder.add_valueless_cmd(kind);
+            None
+        })
+    }
 
-                let name = name_token.span();
-                let fun_id = self.hash_span(name).add(scope_id);
+    /// Finds method with `name` on type of `args[0]`. If type does not have
+    /// the method, embedded fields are searched and the receiver is replaced
+    /// with the field that has it.
+    fn find_method(
+        &mut self,
+        module: Mod,
+        name: ID,
+        args: &mut Vec<Local>,
+        token: Token,
+        builder: &mut Builder,
+    ) -> Result<Option<Fun>> {
+        let receiver = match args.first() {
+            Some(&receiver) => receiver,
+            None => return Ok(None),
+        };
+        let ty = builder.type_of_value(receiver);
+        let ty = self.pointer_base(ty).unwrap_or(ty);
 
-                if linkage == Linkage::Import && alias.is_none() {
-                    alias = Some(name);
+        let mut frontier = self.temp_vec();
+        frontier.push((ty, usize::MAX, Field::reserved_value()));
+        let mut i = 0;
+        while i < frontier.len() {
+            let (ty, ..) = frontier[i];
+            let ty = self.pointer_base(ty).unwrap_or(ty);
+            let id = name.add(self.type_base_id(ty));
+            if let Some(fun) = self.find_fun(module, None, id, token)? {
+                let mut path = self.temp_vec();
+                let mut j = i;
+                while j != 0 {
+                    path.push(frontier[j].2);
+                    j = frontier[j].1;
                 }
+                path.reverse();
+                args[0] = self.field_path(module, receiver, &path, token, builder)?;
+                return Ok(Some(fun));
+            }
 
-                (name, fun_id, Ok(sig), FKind::Normal, true)
-            } else if name_kind == ast::Kind::Instantiation || !generics.is_reserved_value() {
-                let mut args = self.temp_vec();
-                let mut params = self.temp_vec();
-                let name = if name_kind == ast::Kind::Instantiation {
-                    name_sons[0]
-                } else {
-                    sons[0]
-                };
-
-                if temp_ast_data.kind(name) != ast::Kind::Ident {
-                    return Err(Error::new(
-                        error::Kind::InvalidFunctionHeader,
-                        temp_ast_data.token(name),
-                    ));
+            if let ty::Kind::Structure(_, fields) = self.type_kind(ty) {
+                for &field in self.field_slice(fields) {
+                    let field_ent = self.field(field);
+                    if field_ent.embedded() {
+                        frontier.push((field_ent.ty(), i, field));
+                    }
                 }
+            }
+            i += 1;
+        }
 
-                if name_kind == ast::Kind::Instantiation {
-                    params.extend(
-                        name_sons[1..]
-                            .iter()
-                            .map(|&ast| self.hash_token(temp_ast_data.token(ast))),
-                    );
-                }
+        Ok(None)
+    }
 
-                if !generics.is_reserved_value() {
-                    params.extend(
-                        temp_ast_data
-                            .sons(generics)
-                            .iter()
-                            .map(|&ast| self.hash_token(temp_ast_data.token(ast))),
-                    );
+    /// Finds function item. When `target` module is specified, item is first
+    /// searched as if it was collided.
+    fn find_fun(
+        &mut self,
+        module: Mod,
+        target: Option<Mod>,
+        id: ID,
+        token: Token,
+    ) -> Result<Option<Fun>> {
+        let id = match target {
+            Some(target) => {
+                let collided = id.add(self.module_id(target));
+                if self.find_item_unchecked(module, collided).is_some() {
+                    collided
+                } else {
+                    id
                 }
+            }
+            None => id,
+        };
 
-                let mut arg_count = 0;
-                for &arg in &sons[1..sons.len() - 1] {
-                    let sons = temp_ast_data.sons(arg);
-                    let amount = sons.len() - 1;
-                    let ty = sons[amount];
-                    let idx = args.len();
-                    args.push(GenericElement::NextArgument(amount as u32, 0));
-                    self.load_arg(
-                        &ast::DataSwitch::new(temp_ast_data, saved_ast_data),
-                        module,
-                        scope,
-                        ty,
-                        &params,
-                        &mut args,
-                    )?;
-                    let additional = args.len() - idx - 1;
-                    args[idx] = GenericElement::NextArgument(amount as u32, additional as u32);
-                    arg_count += amount;
-                }
+        if self.find_item_unchecked(module, id).is_none() {
+            return Ok(None);
+        }
 
-                let name_span = temp_ast_data.token(name).span();
+        match self.find_item(module, id, token).map_err(Into::into)?.kind() {
+            item::Kind::Fun(fun) => Ok(Some(fun)),
+            _ => Ok(None),
+        }
+    }
 
-                let id = self.hash_span(name_span).add(scope_id);
+    /// Finds operator overload for type, free operator functions are
+    /// used as fallback.
+    fn find_op(&mut self, module: Mod, op: Token, ty: Ty, salt: ID) -> Result<Option<Fun>> {
+        let name = self.hash_token(op);
+        let id = name.add(self.type_base_id(ty)).add(salt);
+        if let Some(fun) = self.find_fun(module, None, id, op)? {
+            return Ok(Some(fun));
+        }
+        self.find_fun(module, None, name.add(salt), op)
+    }
 
-                let sig = GenericSignature {
-                    params: params.clone(),
-                    elements: args.clone(),
-                    arg_count,
-                };
+    /// Decomposes identifier into optional module, id of the type scope and
+    /// the name token.
+    fn parse_ident(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+    ) -> Result<(Option<Mod>, ID, Token)> {
+        let (kind, sons, token) = ast_data.ent(ast).parts();
+        let sons = ast_data.slice(sons);
+        match (kind, sons) {
+            (ast::Kind::Ident, _) => Ok((None, ID(0), token)),
+            (ast::Kind::Path, &[module_or_ty, name]) => {
+                let token = ast_data.token(module_or_ty);
+                let id = self.hash_token(token);
+                let name = ast_data.token(name);
+                match self.find_item(module, id, token).map_err(Into::into)?.kind() {
+                    item::Kind::Mod(target) => Ok((Some(target), ID(0), name)),
+                    item::Kind::Ty(ty) => Ok((None, self.type_base_id(ty), name)),
+                    _ => Err(Error::new(error::Kind::UnknownModule, token)),
+                }
+            }
+            (ast::Kind::Path, &[module_segment, ty, name]) => {
+                let token = ast_data.token(module_segment);
+                let id = self.hash_token(token);
+                let target = self.find_module(module, id, token).map_err(Into::into)?;
+                let token = ast_data.token(ty);
+                let id = self.hash_token(token);
+                let ty = self.find_type(module, id, token).map_err(Into::into)?;
+                Ok((Some(target), self.type_base_id(ty), ast_data.token(name)))
+            }
+            _ => Err(Error::new(error::Kind::InvalidFunctionHeader, token)),
+        }
+    }
 
-                ast = saved_ast_data.relocate(ast, temp_ast_data, reloc);
-                attrs = saved_ast_data.relocate(attrs, temp_ast_data, reloc);
-                scope = saved_ast_data.relocate(scope, temp_ast_data, reloc);
+    fn adjust_receiver(
+        &mut self,
+        module: Mod,
+        value: Local,
+        expected: Ty,
+        token: Token,
+        builder: &mut Builder,
+    ) -> Result<Local> {
+        let actual = builder.type_of_value(value);
+        if actual == expected {
+            return Ok(value);
+        }
 
-                (name_span, id, Err(sig), FKind::Generic, false)
-            } else {
-                return Err(Error::new(error::Kind::InvalidFunctionHeader, name_token));
-            };
+        if self.pointer_base(expected) == Some(actual) {
+            let mutable = self.pointer_mutability(expected);
+            return self.reference(module, value, mutable, token, builder);
+        }
 
-        let id = match op_kind {
-            ast::OpKind::Normal => id,
-            ast::OpKind::Unary => id.add(ID::new("-u-")),
-            ast::OpKind::Binary => id.add(ID::new("-b-")),
-        };
-        
-        let fun_ent = FunEnt {
-            vis,
-            id,
-            module,
-            hint,
-            kind,
-            name,
-            untraced,
-            inline,
-            attrs,
-            scope,
-            linkage,
-            alias,
-            sig: sig.as_ref().map(|&s| s).unwrap_or_default(),
-            ast,
+        if self.pointer_base(actual) == Some(expected) {
+            return self.dereference(value, token, builder);
+        }
 
-            ..Default::default()
-        };
-        let id = self.add_fun(module, fun_ent)?;
+        Ok(value)
+    }
 
-        if let Err(sig) = sig {
-            self.generic_funs.insert(GFun { id, sig, call_conv });
+    /// Adjusts the receiver by the pattern of the first argument since
+    /// exact type is not known before instantiation.
+    fn adjust_generic_receiver(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        fun: Fun,
+        args: &mut Vec<Local>,
+        token: Token,
+        builder: &mut Builder,
+    ) -> Result {
+        let mut ast_data = *ast_data;
+        ast_data.set_swapped(true);
+        let header = ast_data.son(self.funs[fun].ast, 0);
+        let sons = ast_data.sons(header);
+        if sons.len() <= 3 || args.is_empty() {
+            return Ok(());
         }
-
-        if unresolved {
-            if entry {
-                self.entry_points.push(id);
+        let pattern = *ast_data.sons(sons[2]).last().unwrap();
+        let (kind, _, pattern_token) = ast_data.ent(pattern).parts();
+        let is_pointer = self.pointer_base(builder.type_of_value(args[0])).is_some();
+        match kind {
+            ast::Kind::Ref(mutable) if !is_pointer => {
+                args[0] = self.reference(module, args[0], mutable, token, builder)?;
+            }
+            ast::Kind::Instantiation | ast::Kind::Array if is_pointer => {
+                args[0] = self.dereference(args[0], token, builder)?;
+            }
+            ast::Kind::Ident if is_pointer && self.display_token(pattern_token) == "Self" => {
+                args[0] = self.dereference(args[0], token, builder)?;
             }
-            self.unresolved.push(id);
+            _ => (),
         }
 
         Ok(())
     }
 
-    crate::impl_item_adder!(
-        add_fun, funs, FunEnt, Fun
-        add_global, globals, GlobalEnt, Global 
-    );
+    /// Returns the bound if receiver of the dot call is a pointer to bound.
+    fn dyn_receiver(&self, args: &[Local], builder: &Builder) -> Option<Ty> {
+        let &receiver = args.first()?;
+        let bound = self.pointer_base(builder.type_of_value(receiver))?;
+        if self.is_bound(bound) {
+            Some(bound)
+        } else {
+            None
+        }
+    }
 
-    pub fn add_entry(&mut self, id: Fun, builder: &mut Builder) -> Result {
-        let MainFunData {
-            arg1,
-            arg2,
-            return_value,
-            ..
-        } = self.entry_point_data;
-        let FunEnt {
-            module, sig, hint, ..
-        } = self.funs[id];
+    /// Calls method of the `bound` through vtable. Fat pointer in `args[0]`
+    /// is split, data pointer becomes the receiver and function pointer
+    /// is loaded from vtable.
+    fn dyn_call(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        bound: Ty,
+        name: Token,
+        args: &mut Vec<Local>,
+        token: Token,
+        builder: &mut Builder,
+    ) -> ExprResult {
+        let id = self.hash_token(name);
+        let (index, method, len) = {
+            let vtable = self.ctx.bound_vtable(ast_data, bound).map_err(Into::into)?;
+            match vtable.iter().position(|method| method.id == id) {
+                Some(index) => (index, vtable[index], vtable.len()),
+                None => {
+                    let types = args.iter().map(|&a| builder.type_of_value(a)).collect();
+                    return Err(Error::new(
+                        error::Kind::FunctionNotFound(name.span(), types),
+                        name,
+                    ));
+                }
+            }
+        };
+
+        let mut arg_types = self.temp_vec();
+        arg_types.extend_from_slice(self.type_slice(method.sig.args));
+        let receiver = match arg_types.first() {
+            Some(&receiver) if self.dyn_receiver_type(receiver) => receiver,
+            _ => return Err(Error::new(error::Kind::NotDispatchable(method.name), token)),
+        };
+
+        let pointer = args[0];
+        let mutable = self.pointer_mutability(receiver);
+        if mutable && !self.pointer_mutability(builder.type_of_value(pointer)) {
+            return Err(Error::new(error::Kind::MutableToImmutable, token));
+        }
+
+        let data_ty = self.erased_pointer(module, mutable);
+        let table_ty = self.vtable_type(module, len);
+        let data = builder.offset_value(pointer, data_ty, Size::ZERO);
+        let table = builder.offset_value(pointer, table_ty, Size::POINTER);
+        let table = self.dereference(table, token, builder)?;
+
+        arg_types[0] = data_ty;
+        let sig = Signature {
+            args: self.add_type_slice(arg_types.as_slice()),
+            ..method.sig
+        };
+        let fun_ty = self.function_type_of(module, sig);
+        let offset = Size::POINTER.mul(Size::new(index as u32, index as u32));
+        let fun_pointer = builder.offset_value(table, fun_ty, offset);
+
+        args[0] = data;
+        for (arg, &expected) in args[1..].iter_mut().zip(arg_types[1..].iter()) {
+            *arg = self.coerce(ast_data, module, *arg, expected, token, builder)?;
+        }
+
+        let types = args
+            .iter()
+            .map(|&a| builder.type_of_value(a))
+            .collect::<Vec<_>>();
+        if types.as_slice() != arg_types.as_slice() {
+            return Err(Error::new(
+                error::Kind::FunPointerArgMismatch(fun_ty, types),
+                token,
+            ));
+        }
 
-        let value = if sig.ret.is_none() {
+        let args = builder.add_locals(args);
+        let kind = cmd::Kind::FunPointerCall(fun_pointer, args);
+        Ok(if let Some(ret) = sig.ret.expand() {
+            let value = builder.add_temp_local(ret);
+            builder.add_cmd(kind, value);
+            Some(value)
+        } else {
+            builder.add_valueless_cmd(kind);
             None
+        })
+    }
+
+    /// Converts `value` to `expected` type if implicit conversion exists,
+    /// otherwise value is returned unchanged and caller reports the mismatch.
+    /// Mutable pointer can be weakened and pointer to type can become
+    /// pointer to bound the type implements.
+    fn coerce(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        value: Local,
+        expected: Ty,
+        token: Token,
+        builder: &mut Builder,
+    ) -> Result<Local> {
+        let actual = builder.type_of_value(value);
+        if actual == expected {
+            return Ok(value);
+        }
+
+        if self.is_pointer_weakening(actual, expected) {
+            return Ok(builder.cast(value, expected));
+        }
+
+        let (base, bound) = match (self.pointer_base(actual), self.pointer_base(expected)) {
+            (Some(base), Some(bound)) if self.is_bound(bound) && !self.is_bound(base) => {
+                (base, bound)
+            }
+            _ => return Ok(value),
+        };
+
+        let mutable = self.pointer_mutability(expected);
+        if mutable && !self.pointer_mutability(actual) {
+            return Ok(value);
+        }
+
+        let vtable = self.vtable(ast_data, module, bound, base, token)?;
+        let len = self.vtables[vtable].methods.len(&self.fun_slices);
+
+        let zero = builder.add_zero_value(expected);
+        let result = builder.add_local(expected, true);
+        builder.add_var_decl(zero, result);
+
+        let data_ty = self.erased_pointer(module, mutable);
+        let value = builder.cast(value, data_ty);
+        let data = builder.offset_value(result, data_ty, Size::ZERO);
+        builder.assign(data, value);
+
+        let table_ty = self.vtable_type(module, len);
+        let table = builder.add_temp_local(table_ty);
+        builder.add_cmd(cmd::Kind::VTable(vtable), table);
+        let field = builder.offset_value(result, table_ty, Size::POINTER);
+        builder.assign(field, table);
+
+        Ok(result)
+    }
+
+    /// Returns vtable of `bound` implemented by `ty`. Table is created
+    /// on first use and shared afterwards.
+    fn vtable(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        bound: Ty,
+        ty: Ty,
+        token: Token,
+    ) -> Result<VTable> {
+        let id = self.type_id(bound).add(self.type_id(ty));
+        if let Some(&vtable) = self.vtable_lookup.get(id) {
+            return Ok(vtable);
+        }
+
+        self.ctx
+            .check_bound(ast_data, ty, bound, token)
+            .map_err(Into::into)?;
+
+        let mut layout = self.temp_vec();
+        layout.extend_from_slice(self.ctx.bound_vtable(ast_data, bound).map_err(Into::into)?);
+
+        let mut methods = self.temp_vec();
+        for &method in layout.iter() {
+            methods.push(self.vtable_method(ast_data, module, bound, ty, method, token)?);
+        }
+
+        let vtable = self.vtables.push(VTableEnt {
+            bound,
+            ty,
+            methods: EntityList::from_slice(methods.as_slice(), &mut self.fun_slices),
+        });
+        self.vtable_lookup.insert(id, vtable);
+
+        Ok(vtable)
+    }
+
+    /// Finds function implementing bound `method` for `ty`. Aliased methods
+    /// are resolved to their target. Signature has to match the declaration
+    /// with `Self` replaced by `ty`.
+    fn vtable_method(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        bound: Ty,
+        ty: Ty,
+        method: BoundMethod,
+        token: Token,
+    ) -> Result<Fun> {
+        let mut expected = self.temp_vec();
+        expected.extend_from_slice(self.type_slice(method.sig.args));
+        let receiver = match expected.first() {
+            Some(&receiver) if self.dyn_receiver_type(receiver) => receiver,
+            _ => return Err(Error::new(error::Kind::NotDispatchable(method.name), token)),
+        };
+        let mutable = self.pointer_mutability(receiver);
+        expected[0] = self.pointer_of(module, ty, mutable);
+
+        let name = match self.bound_method_impl(bound, ty, method.id) {
+            Some(name) => name,
+            None => {
+                return Err(Error::new(
+                    error::Kind::FunctionNotFound(method.name, expected.to_vec()),
+                    token,
+                ))
+            }
+        };
+        let id = name.add(self.type_base_id(ty));
+        let fun = match self.find_fun(module, None, id, token)? {
+            Some(fun) => fun,
+            None => {
+                return Err(Error::new(
+                    error::Kind::FunctionNotFound(method.name, expected.to_vec()),
+                    token,
+                ))
+            }
+        };
+
+        let fun = if self.funs[fun].kind == FKind::Generic {
+            match self.create(ast_data, module, fun, &[], &expected)? {
+                Some(fun) => fun,
+                None => {
+                    return Err(Error::new(
+                        error::Kind::GenericMismatch(self.funs[fun].name, expected.to_vec()),
+                        token,
+                    ))
+                }
+            }
         } else {
-            Some(builder.add_temp_local(sig.ret.unwrap()))
+            fun
         };
 
-        let args = match self.type_slice(sig.args) {
-            &[] => EntityList::new(),
-            &[count, args] => {
-                let temp_ptr = self.pointer_of(module, INT_TY, false);
-                if count != INT_TY || args != self.pointer_of(module, temp_ptr, false) {
-                    return Err(Error::new(error::Kind::InvalidEntrySignature, hint));
+        let sig = self.funs[fun].sig;
+        if self.type_slice(sig.args) != expected.as_slice()
+            || sig.ret != method.sig.ret
+            || sig.call_conv != method.sig.call_conv
+        {
+            return Err(Error::new(
+                error::Kind::BoundMethodMismatch(fun, method.name),
+                token,
+            ));
+        }
+
+        Ok(fun)
+    }
+
+    /// Only methods taking pointer to `Self` can be called through vtable.
+    fn dyn_receiver_type(&self, receiver: Ty) -> bool {
+        self.pointer_base(receiver)
+            .map_or(false, |base| self.is_bound(base))
+    }
+
+    /// Pointer type used for data half of the fat pointer.
+    fn erased_pointer(&mut self, module: Mod, mutable: bool) -> Ty {
+        self.pointer_of(module, U8_TY, mutable)
+    }
+
+    /// Pointer type used for vtable half of the fat pointer.
+    fn vtable_type(&mut self, module: Mod, len: usize) -> Ty {
+        let table = self.array_of(module, UINT_TY, len);
+        self.pointer_of(module, table, false)
+    }
+
+    fn is_pointer_weakening(&self, actual: Ty, expected: Ty) -> bool {
+        self.pointer_base(actual).is_some()
+            && self.pointer_base(actual) == self.pointer_base(expected)
+            && self.pointer_mutability(actual)
+            && !self.pointer_mutability(expected)
+    }
+
+    fn push_frame(&mut self, module: Mod, token: Token, builder: &mut Builder) -> Result {
+        let fun = self
+            .find_fun(BUILTIN_MODULE, None, ID::new("push_frame"), token)?
+            .unwrap();
+        let line = self.add_constant(constant::Kind::Int(
+            token.line_data().line() as i64,
+            POINTER_WIDTH_MARKER,
+        ));
+        let column = self.add_constant(constant::Kind::Int(
+            token.line_data().column() as i64,
+            POINTER_WIDTH_MARKER,
+        ));
+        let file = self.source(token.source()).name().to_string();
+        let file = self.builtin_span(&file);
+        let file = self.add_constant(constant::Kind::Str(file));
+        let args = [
+            self.lit(module, line, builder),
+            self.lit(module, column, builder),
+            self.lit(module, file, builder),
+        ];
+        builder.add_valueless_call(fun, &args);
+        Ok(())
+    }
+
+    fn pop_frame(&mut self, module: Mod, token: Token, builder: &mut Builder) -> Result {
+        let fun = self
+            .find_fun(BUILTIN_MODULE, None, ID::new("pop_frame"), token)?
+            .unwrap();
+        let _ = module;
+        builder.add_valueless_call(fun, &[]);
+        Ok(())
+    }
+
+    fn binary_expr(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        builder: &mut Builder,
+    ) -> ExprResult {
+        let (_, sons, token) = ast_data.ent(ast).parts();
+        let sons = ast_data.slice(sons);
+        let (op, left, right) = (sons[0], sons[1], sons[2]);
+        let op = ast_data.token(op);
+
+        match self.display_token(op) {
+            "=" => return self.assign(ast_data, module, left, right, builder).map(Some),
+            "as" => return self.cast_expr(ast_data, module, left, right, builder).map(Some),
+            _ => (),
+        }
+
+        let mut left = self.expr(ast_data, module, left, builder)?;
+        let mut right = self.expr(ast_data, module, right, builder)?;
+        let ty = builder.type_of_value(left);
+
+        let fun = match self.find_op(module, op, ty, ID::new("-b-"))? {
+            Some(fun) => Some(fun),
+            // enums are compared by their repr
+            None => match self.type_kind(ty) {
+                ty::Kind::Enumeration(repr, _)
+                    if builder.type_of_value(right) == ty
+                        && matches!(
+                            self.display_token(op),
+                            "==" | "!=" | "<" | ">" | "<=" | ">="
+                        ) =>
+                {
+                    left = builder.cast(left, repr);
+                    right = builder.cast(right, repr);
+                    self.find_op(module, op, repr, ID::new("-b-"))?
                 }
-                builder.add_locals(&[arg1, arg2])
+                _ => None,
+            },
+        };
+
+        let fun = match fun {
+            Some(fun) => fun,
+            None => {
+                let types = vec![ty, builder.type_of_value(right)];
+                return Err(Error::new(
+                    error::Kind::FunctionNotFound(op.span(), types),
+                    op,
+                ));
             }
-            _ => {
-                return Err(Error::new(error::Kind::InvalidEntrySignature, hint));
+        };
+
+        let mut args = self.temp_vec();
+        args.extend_from_slice(&[left, right]);
+        self.call_low(ast_data, module, fun, &[], &mut args, false, token, builder)
+    }
+
+    fn unary_expr(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        builder: &mut Builder,
+    ) -> ExprResult {
+        let (_, sons, token) = ast_data.ent(ast).parts();
+        let sons = ast_data.slice(sons);
+        let op = ast_data.token(sons[0]);
+
+        // negative literals are folded so that minimal values are representable
+        let literal = ast_data.token(sons[1]);
+        if ast_data.kind(sons[1]) == ast::Kind::Lit
+            && matches!(literal.kind(), token::Kind::Int(_))
+            && self.display_token(op) == "-"
+        {
+            let constant = constant::Kind::from_literal(self, literal, true).map_err(Into::into)?;
+            let constant = self.add_constant(constant);
+            return Ok(Some(self.lit(module, constant, builder)));
+        }
+
+        let value = self.expr(ast_data, module, sons[1], builder)?;
+        let ty = builder.type_of_value(value);
+
+        let fun = self
+            .find_op(module, op, ty, ID::new("-u-"))?
+            .ok_or_else(|| Error::new(error::Kind::FunctionNotFound(op.span(), vec![ty]), op))?;
+
+        let mut args = self.temp_vec();
+        args.push(value);
+        self.call_low(ast_data, module, fun, &[], &mut args, false, token, builder)
+    }
+
+    fn assign(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        target: Ast,
+        value: Ast,
+        builder: &mut Builder,
+    ) -> Result<Local> {
+        let value_token = ast_data.token(value);
+        let value = self.expr(ast_data, module, value, builder)?;
+
+        let target_token = ast_data.token(target);
+        let prev = std::mem::replace(&mut self.in_assign, true);
+        let target = self.expr(ast_data, module, target, builder);
+        self.in_assign = prev;
+        let target = target?;
+
+        if !builder.is_mutable(target) {
+            return Err(Error::new(error::Kind::AssignToImmutable, target_token));
+        }
+
+        let ty = builder.type_of_value(target);
+        let value = self.coerce(ast_data, module, value, ty, value_token, builder)?;
+        assert_type(builder.type_of_value(value), ty, value_token)?;
+
+        builder.assign(target, value);
+
+        Ok(value)
+    }
+
+    fn cast_expr(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        value: Ast,
+        ty: Ast,
+        builder: &mut Builder,
+    ) -> Result<Local> {
+        let token = ast_data.token(value);
+        let value = self.expr(ast_data, module, value, builder)?;
+        let ty = self.parse_type(ast_data, module, ty)?;
+
+        let actual = self.type_size(builder.type_of_value(value));
+        let expected = self.type_size(ty);
+        if actual != expected {
+            return Err(Error::new(
+                error::Kind::InvalidBitCast(actual, expected),
+                token,
+            ));
+        }
+
+        Ok(builder.cast(value, ty))
+    }
+
+    fn ref_expr(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        mutable: bool,
+        builder: &mut Builder,
+    ) -> Result<Local> {
+        let token = ast_data.token(ast);
+        let prev = std::mem::replace(&mut self.in_var_ref, mutable);
+        let value = self.expr(ast_data, module, ast_data.son(ast, 0), builder);
+        self.in_var_ref = prev;
+
+        self.reference(module, value?, mutable, token, builder)
+    }
+
+    fn reference(
+        &mut self,
+        module: Mod,
+        value: Local,
+        mutable: bool,
+        token: Token,
+        builder: &mut Builder,
+    ) -> Result<Local> {
+        if mutable && !builder.is_mutable(value) {
+            return Err(Error::new(error::Kind::MutableToImmutable, token));
+        }
+
+        let ty = self.pointer_of(module, builder.type_of_value(value), mutable);
+        Ok(builder.reference(ty, value))
+    }
+
+    fn dereference(&mut self, value: Local, token: Token, builder: &mut Builder) -> Result<Local> {
+        let ty = builder.type_of_value(value);
+        let base = self.expect_pointer_base(ty, token)?;
+        let mutable = self.pointer_mutability(ty);
+        let result = builder.add_local(base, mutable);
+        builder.add_cmd(cmd::Kind::Deref(value, self.in_assign), result);
+        Ok(result)
+    }
+
+    fn dot_expr(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        builder: &mut Builder,
+    ) -> Result<Local> {
+        let sons = ast_data.sons(ast);
+        let (header, field) = (sons[0], sons[1]);
+        let token = ast_data.token(field);
+        let header = self.expr(ast_data, module, header, builder)?;
+
+        let ty = builder.type_of_value(header);
+        let id = self.hash_token(token);
+        let mut path = self.temp_vec();
+        if !self.find_field(ty, id, &mut path) {
+            let ty = self.pointer_base(ty).unwrap_or(ty);
+            return Err(Error::new(error::Kind::UnknownField(ty), token));
+        }
+
+        self.field_path(module, header, &path, token, builder)
+    }
+
+    /// Finds field by `id` on the type and collects path of embedded fields
+    /// leading to it. Pointers are transparent.
+    fn find_field(&self, ty: Ty, id: ID, path: &mut Vec<Field>) -> bool {
+        let mut frontier = vec![(ty, usize::MAX, Field::reserved_value())];
+        let mut i = 0;
+        while i < frontier.len() {
+            let ty = frontier[i].0;
+            let ty = self.pointer_base(ty).unwrap_or(ty);
+            if let ty::Kind::Structure(_, fields) = self.type_kind(ty) {
+                for &field in self.field_slice(fields) {
+                    let field_ent = self.field(field);
+                    if field_ent.id() == id {
+                        path.push(field);
+                        let mut j = i;
+                        while j != 0 {
+                            path.push(frontier[j].2);
+                            j = frontier[j].1;
+                        }
+                        path.reverse();
+                        return true;
+                    }
+                    if field_ent.embedded() {
+                        frontier.push((field_ent.ty(), i, field));
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        false
+    }
+
+    /// Offsets the `header` along the field path, pointers are dereferenced
+    /// on the way.
+    fn field_path(
+        &mut self,
+        module: Mod,
+        mut header: Local,
+        path: &[Field],
+        token: Token,
+        builder: &mut Builder,
+    ) -> Result<Local> {
+        for &field in path {
+            let ty = builder.type_of_value(header);
+            if self.pointer_base(ty).is_some() {
+                header = self.dereference(header, token, builder)?;
+            }
+            let ty = builder.type_of_value(header);
+            let field = self.field(field);
+            if !self.can_access(module, self.type_module(ty), field.vis()) {
+                return Err(Error::new(error::Kind::FieldVisibilityViolation, token));
+            }
+            header = builder.offset_value(header, field.ty(), field.offset());
+        }
+
+        Ok(header)
+    }
+
+    fn index_expr(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        builder: &mut Builder,
+    ) -> Result<Local> {
+        let (_, sons, token) = ast_data.ent(ast).parts();
+        let sons = ast_data.slice(sons);
+
+        let assign = std::mem::replace(&mut self.in_assign, false);
+        let target = self.expr(ast_data, module, sons[0], builder);
+        let index = target.and_then(|target| {
+            self.expr(ast_data, module, sons[1], builder)
+                .map(|index| (target, index))
+        });
+        self.in_assign = assign;
+        let (target, index) = index?;
+
+        let name = if assign || self.in_var_ref {
+            "__index_var__"
+        } else {
+            "__index__"
+        };
+        let id = ID::new(name);
+
+        let mut args = self.temp_vec();
+        args.extend_from_slice(&[target, index]);
+        let fun = match self.find_method(module, id, &mut args, token, builder)? {
+            Some(fun) => fun,
+            None => {
+                let types = args.iter().map(|&a| builder.type_of_value(a)).collect();
+                let name = self.builtin_span(name);
+                return Err(Error::new(
+                    error::Kind::FunctionNotFound(name, types),
+                    token,
+                ));
+            }
+        };
+
+        let pointer = self
+            .call_low(ast_data, module, fun, &[], &mut args, true, token, builder)?
+            .ok_or_else(|| Error::new(error::Kind::ExpectedValue, token))?;
+
+        self.dereference(pointer, token, builder)
+    }
+
+    fn ident_expr(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        builder: &mut Builder,
+    ) -> Result<Local> {
+        let token = ast_data.token(ast);
+        if ast_data.kind(ast) == ast::Kind::Ident {
+            let id = self.hash_token(token);
+            if let Some(&(_, var)) = self.vars.iter().rev().find(|&&(var, _)| var == id) {
+                return Ok(var);
+            }
+        }
+
+        let (target, scope, name) = self.parse_ident(ast_data, module, ast)?;
+        let mut id = self.hash_token(name).add(scope);
+        if let Some(target) = target {
+            let collided = id.add(self.module_id(target));
+            if self.find_item_unchecked(module, collided).is_some() {
+                id = collided;
+            }
+        }
+
+        if self.find_item_unchecked(module, id).is_none() {
+            return Err(Error::new(error::Kind::UndefinedVariable, token));
+        }
+
+        match self.find_item(module, id, token).map_err(Into::into)?.kind() {
+            item::Kind::Global(global) => {
+                let GlobalEnt {
+                    vis,
+                    module: global_module,
+                    ty,
+                    mutable,
+                    ..
+                } = self.globals[global];
+                if !self.can_access(module, global_module, vis) {
+                    return Err(Error::new(error::Kind::GlobalVisibilityViolation, token));
+                }
+                let value = builder.add_local(ty, mutable);
+                builder.add_cmd(cmd::Kind::GlobalLoad(global), value);
+                Ok(value)
+            }
+            item::Kind::Const(constant) => Ok(self.lit(module, constant, builder)),
+            item::Kind::Fun(fun) if self.funs[fun].kind != FKind::Generic => {
+                let FunEnt {
+                    vis,
+                    module: fun_module,
+                    sig,
+                    ..
+                } = self.funs[fun];
+                if !self.can_access(module, fun_module, vis) {
+                    return Err(Error::new(error::Kind::VisibilityViolation, token));
+                }
+                let ty = self.function_type_of(module, sig);
+                let value = builder.add_temp_local(ty);
+                builder.add_cmd(cmd::Kind::FunPointer(fun), value);
+                Ok(value)
             }
-        };
-
-        if let Some(value) = value {
-            builder.add_cmd(cmd::Kind::Call(id, args), value);
-            builder.add_cmd(cmd::Kind::Assign(return_value), value);
-        } else {
-            builder.add_valueless_cmd(cmd::Kind::Call(id, args));
+            _ => Err(Error::new(error::Kind::UndefinedVariable, token)),
         }
-
-        Ok(())
     }
 
-    pub fn parse_signature(
+    /// Translates lambda into anonymous function right away. Lambda that does not
+    /// capture anything evaluates to function pointer, otherwise to closure that
+    /// carries the environment and pointer to the code taking the environment
+    /// as its first argument. Mutable variables are captured by reference,
+    /// immutable ones by copy.
+    fn lambda_expr(
         &mut self,
         ast_data: &ast::DataSwitch,
         module: Mod,
-        header: Ast,
-        call_conv: CallConv,
-    ) -> Result<Signature> {
-        let mut args = self.temp_vec();
-        let sons = ast_data.sons(header);
-
-        for &argument in &sons[1..sons.len() - 1] {
-            let sons = ast_data.sons(argument);
-            let ty = self.parse_type(ast_data, module, sons[sons.len() - 1])?;
-            args.extend(std::iter::repeat(ty).take(sons.len() - 1));
+        ast: Ast,
+        builder: &mut Builder,
+    ) -> Result<Local> {
+        let token = ast_data.token(ast);
+        let (header, body) = (ast_data.son(ast, 0), ast_data.son(ast, 1));
+        let call_conv = match ast_data.kind(header) {
+            ast::Kind::FunHeader(_, _, call_conv) => call_conv,
+            _ => unreachable!(),
+        };
+        if !ast_data.son(header, 0).is_reserved_value() {
+            return Err(Error::new(error::Kind::GenericLambda, token));
+        }
+        let sig = self.parse_signature(ast_data, module, header, call_conv)?;
+
+        let mut declared = self.temp_vec();
+        self.lambda_params(ast_data, header, &mut declared);
+
+        let mut captured = self.temp_vec();
+        self.collect_captures(ast_data, body, &mut declared, &mut captured);
+
+        let mut captures = self.temp_vec();
+        let mut fields = self.temp_vec();
+        for &(id, var) in captured.iter() {
+            let ty = builder.type_of_value(var);
+            let by_ref = builder.is_mutable(var);
+            captures.push((id, by_ref));
+            fields.push(if by_ref {
+                self.pointer_of(module, ty, true)
+            } else {
+                ty
+            });
         }
 
-        let raw_ret_ty = sons[sons.len() - 1];
-        let ret = if raw_ret_ty.is_reserved_value() {
-            PackedOption::default()
+        let (env, sig) = if captures.is_empty() {
+            (None, sig)
         } else {
-            PackedOption::from(self.parse_type(ast_data, module, raw_ret_ty)?)
+            let env = self.env_of(module, fields.as_slice()).map_err(Into::into)?;
+            let mut args = self.temp_vec();
+            args.push(self.pointer_of(module, env, false));
+            args.extend_from_slice(self.type_slice(sig.args));
+            let args = self.add_type_slice(args.as_slice());
+            (Some(env), Signature { args, ..sig })
+        };
+        let code = self.function_type_of(module, sig);
+
+        // position alone is not unique, equal lambdas are at the same offset
+        // in other files and in every instance of generic function
+        let id = ID::new("-lambda-")
+            .add(self.fun_id)
+            .add(ID(token.source().0 as u64))
+            .add(ID(token.span().range().start as u64))
+            .add(self.type_id(code));
+        let fun_ent = FunEnt {
+            id,
+            module,
+            hint: token,
+            kind: FKind::Normal,
+            name: token.span(),
+            attrs: Ast::reserved_value(),
+            scope: Ast::reserved_value(),
+            linkage: Linkage::Local,
+            sig,
+            ast,
+
+            ..Default::default()
+        };
+        let fun = self.funs.push(fun_ent);
+
+        let vars = std::mem::take(&mut self.vars);
+        let frames = std::mem::take(&mut self.frames);
+        let loops = std::mem::take(&mut self.loops);
+        let ret_ty = self.ret_ty;
+        let in_assign = std::mem::replace(&mut self.in_assign, false);
+        let in_var_ref = std::mem::replace(&mut self.in_var_ref, false);
+        let fun_id = std::mem::replace(&mut self.fun_id, id);
+
+        let mut fun_body = Body::default();
+        let result = self.translate_fun_low(
+            ast_data,
+            module,
+            header,
+            body,
+            sig,
+            &captures,
+            &mut Builder::new(&mut *builder.data, &mut fun_body),
+        );
+        self.funs[fun].body = fun_body;
+
+        self.vars = vars;
+        self.frames = frames;
+        self.loops = loops;
+        self.ret_ty = ret_ty;
+        self.in_assign = in_assign;
+        self.in_var_ref = in_var_ref;
+        self.fun_id = fun_id;
+        result?;
+
+        let pointer = builder.add_temp_local(code);
+        builder.add_cmd(cmd::Kind::FunPointer(fun), pointer);
+        let env = match env {
+            Some(env) => env,
+            None => return Ok(pointer),
         };
 
-        Ok(Signature {
-            call_conv,
-            args: self.ctx.add_type_slice(args.as_slice()),
-            ret,
-        })
+        let ty = self.closure_of(module, env, code).map_err(Into::into)?;
+        let closure = builder.add_local(ty, true);
+        let zeroed = builder.add_zero_value(ty);
+        builder.add_var_decl(zeroed, closure);
+
+        let env = self.nth_field(closure, 0, builder);
+        for (i, (&(_, var), &(_, by_ref))) in captured.iter().zip(captures.iter()).enumerate() {
+            let value = if by_ref {
+                self.reference(module, var, true, token, builder)?
+            } else {
+                var
+            };
+            let field = self.nth_field(env, i, builder);
+            builder.assign(field, value);
+        }
+        let field = self.nth_field(closure, 1, builder);
+        builder.assign(field, pointer);
+
+        Ok(closure)
     }
 
-    pub fn load_arg(
-        &mut self,
+    /// Collects variables of the enclosing function referred to from lambda
+    /// body. Names in `declared` are arguments and variables of the lambda
+    /// visible at `ast`, they shadow the variables of enclosing function.
+    fn collect_captures(
+        &self,
         ast_data: &ast::DataSwitch,
-        module: Mod,
-        scope: Ast,
         ast: Ast,
-        params: &[ID],
-        buffer: &mut Vec<GenericElement>,
-    ) -> Result {
-        let mut stack = self.temp_vec();
-        let scope_type = ast_data.son(scope, 1);
-        stack.push((ast, false));
-
-        while let Some(&(ast, done)) = stack.last() {
-            let (kind, _, token) = ast_data.ent(ast).parts();
-            if done {
-                if kind == ast::Kind::Instantiation {
-                    buffer.push(GenericElement::ScopeEnd);
+        declared: &mut Vec<ID>,
+        captured: &mut Vec<(ID, Local)>,
+    ) {
+        let (kind, sons, token) = ast_data.ent(ast).parts();
+        let sons = ast_data.slice(sons);
+        match kind {
+            ast::Kind::Ident => {
+                let id = self.hash_token(token);
+                if declared.contains(&id) || captured.iter().any(|&(other, _)| other == id) {
+                    return;
+                }
+                if let Some(&capture) = self.vars.iter().rev().find(|&&(var, _)| var == id) {
+                    captured.push(capture);
                 }
-                stack.pop();
-                continue;
             }
-            stack.last_mut().unwrap().1 = true;
-            let sons = ast_data.sons(ast);
-            match kind {
-                ast::Kind::Ident => {
-                    if self.display_token(token) == "Self" && !scope.is_reserved_value() {
-                        stack.push((scope_type, false));
-                    } else {
-                        let id = self.hash_token(token);
-                        if let Some(index) = params.iter().position(|&p| p == id) {
-                            buffer.push(GenericElement::Parameter(index as u32));
-                        } else {
-                            let ty = self.parse_type(ast_data, module, ast)?;
-                            buffer.push(GenericElement::Element(ty, None.into()));
-                        }
+            // field names are not variables
+            ast::Kind::Dot => self.collect_captures(ast_data, sons[0], declared, captured),
+            // variables stay declared until the end of enclosing block
+            ast::Kind::VarStatement(..) => {
+                for &line in sons {
+                    let sons = ast_data.sons(line);
+                    let (ident_group, values) = (sons[0], sons[2]);
+                    if !values.is_reserved_value() {
+                        self.collect_captures(ast_data, values, declared, captured);
+                    }
+                    for &ident in ast_data.sons(ident_group) {
+                        declared.push(self.hash_token(ast_data.token(ident)));
                     }
                 }
-                ast::Kind::Instantiation => {
-                    let ty = self.parse_type(ast_data, module, sons[0])?;
-                    buffer.push(GenericElement::Element(ty, None.into()));
-                    buffer.push(GenericElement::ScopeStart);
-                    stack.extend(sons[1..].iter().map(|&a| (a, false)));
-                }
-                ast::Kind::Ref(mutable) => {
-                    let pointed = sons[0];
-                    buffer.push(GenericElement::Pointer(mutable));
-                    stack.push((pointed, false));
-                }
-                ast::Kind::Array => {
-                    let element = sons[0];
-                    let len = sons[1];
-                    buffer.push(GenericElement::Array(Err(len)));
-                    stack.push((element, false));
-                    stack.push((len, false));
+            }
+            ast::Kind::ForIn => {
+                let (item, iterator, body) = (sons[1], sons[2], sons[3]);
+                self.collect_captures(ast_data, iterator, declared, captured);
+                let len = declared.len();
+                declared.push(self.hash_token(ast_data.token(item)));
+                self.collect_captures(ast_data, body, declared, captured);
+                declared.truncate(len);
+            }
+            ast::Kind::Lambda => {
+                let len = declared.len();
+                self.lambda_params(ast_data, sons[0], declared);
+                self.collect_captures(ast_data, sons[1], declared, captured);
+                declared.truncate(len);
+            }
+            _ => {
+                let len = declared.len();
+                for &son in sons {
+                    if !son.is_reserved_value() {
+                        self.collect_captures(ast_data, son, declared, captured);
+                    }
                 }
-                _ => todo!("{}", ast::Display::new(self, ast_data, ast)),
+                declared.truncate(len);
             }
         }
+    }
 
-        Ok(())
+    /// Pushes names of arguments declared by lambda `header` to `buffer`.
+    fn lambda_params(&self, ast_data: &ast::DataSwitch, header: Ast, buffer: &mut Vec<ID>) {
+        let sons = ast_data.sons(header);
+        for &arg in &sons[2..sons.len() - 1] {
+            let sons = ast_data.sons(arg);
+            for &name in &sons[..sons.len() - 1] {
+                buffer.push(self.hash_token(ast_data.token(name)));
+            }
+        }
     }
 
-    pub fn load_arg_from_datatype(
-        &mut self,
-        ty: Ty,
-        arg_buffer: &mut Vec<GenericElement>,
-        stack: &mut Vec<(Ty, bool)>,
-    ) {
-        debug_assert!(stack.is_empty());
-        stack.push((ty, false));
+    /// Returns field of structure `target` at `index`.
+    fn nth_field(&self, target: Local, index: usize, builder: &mut Builder) -> Local {
+        let ty = builder.type_of_value(target);
+        let field = match self.type_kind(ty) {
+            ty::Kind::Structure(_, fields) => self.field(self.field_slice(fields)[index]),
+            _ => unreachable!(),
+        };
+        builder.offset_value(target, field.ty(), field.offset())
+    }
 
-        while let Some((ty, done)) = stack.last_mut() {
-            let ty = *ty;
-            let params = self.type_params(ty);
-            let kind = self.type_kind(ty);
+    fn lit(&mut self, module: Mod, constant: Const, builder: &mut Builder) -> Local {
+        let ty = self.constant_type(module, constant);
+        let value = builder.add_temp_local(ty);
+        builder.add_cmd(cmd::Kind::Lit(constant), value);
+        value
+    }
 
-            if *done {
-                if !params.is_empty() {
-                    arg_buffer.push(GenericElement::ScopeEnd);
-                }
-                stack.pop();
-                continue;
+    fn array_expr(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        builder: &mut Builder,
+    ) -> Result<Local> {
+        let sons = ast_data.sons(ast);
+        if sons.is_empty() {
+            return Err(Error::new(error::Kind::EmptyArray, ast_data.token(ast)));
+        }
+
+        let mut values = self.temp_vec();
+        for &element in sons {
+            let token = ast_data.token(element);
+            let value = self.expr(ast_data, module, element, builder)?;
+            if let Some(&first) = values.first() {
+                let ty = builder.type_of_value(first);
+                assert_type(builder.type_of_value(value), ty, token)?;
             }
+            values.push(value);
+        }
 
-            *done = true;
+        let element = builder.type_of_value(values[0]);
+        let ty = self.array_of(module, element, values.len());
+        let zero = builder.add_zero_value(ty);
+        let result = builder.add_local(ty, true);
+        builder.add_var_decl(zero, result);
+
+        let size = self.type_size(element);
+        for (i, &value) in values.iter().enumerate() {
+            let offset = size.mul(Size::new(i as u32, i as u32));
+            let element = builder.offset_value(result, element, offset);
+            builder.assign(element, value);
+        }
 
-            if params.is_empty() {
-                match kind {
-                    ty::Kind::Pointer(pointed, mutable) => {
-                        arg_buffer.push(GenericElement::Pointer(mutable));
-                        stack.push((pointed, false));
-                    }
-                    ty::Kind::Array(element, size) => {
-                        arg_buffer.push(GenericElement::Array(Ok(size)));
-                        stack.push((element, false));
-                    }
-                    _ => {
-                        arg_buffer.push(GenericElement::Element(ty, Some(ty).into()));
-                    }
-                }
-                continue;
-            }
+        Ok(result)
+    }
 
-            let slice = self.type_slice(params);
-            arg_buffer.push(GenericElement::Element(slice[0], Some(ty).into()));
+    /// Returns functions of the `vtable` in the order of bound methods.
+    pub fn vtable_methods(&self, vtable: VTable) -> &[Fun] {
+        self.vtables[vtable].methods.as_slice(&self.fun_slices)
+    }
 
-            arg_buffer.push(GenericElement::ScopeStart);
-            stack.extend(slice[1..].iter().map(|&p| (p, false)));
-        }
+    /// Function that calls all entry points, generator exports it as `main`.
+    pub fn entry_point(&self) -> Fun {
+        self.entry_point_data.id
     }
 
-    pub fn pointer_base(&mut self, ty: Ty, token: Token) -> Result<Ty> {
+    pub fn fun_ent(&self, fun: Fun) -> &FunEnt {
+        &self.funs[fun]
+    }
+
+    pub fn global_ent(&self, global: Global) -> &GlobalEnt {
+        &self.globals[global]
+    }
+
+    /// Intermediate representation shared by all function bodies.
+    pub fn data(&self) -> &Data {
+        &self.data
+    }
+
+    pub fn expect_pointer_base(&mut self, ty: Ty, token: Token) -> Result<Ty> {
         self.ctx
             .pointer_base(ty)
             .ok_or_else(|| Error::new(error::Kind::NonPointerDereference, token))
@@ -2454,7 +3339,7 @@ impl Ctx {
         for &i in ALL_BUILTIN_TYPES {
             for &j in ALL_BUILTIN_TYPES {
                 let name = self.type_name(i);
-                self.create_builtin_fun(Some(j), name, &[j], Some(i));
+                self.create_builtin_fun(j, name, ast::OpKind::Normal, &[j], Some(i));
             }
         }
 
@@ -2476,8 +3361,9 @@ impl Ctx {
                 let op = self.builtin_span(op);
                 for &datatype in types.iter() {
                     self.create_builtin_fun(
-                        Some(datatype),
-                        op.clone(),
+                        datatype,
+                        op,
+                        ast::OpKind::Unary,
                         &[datatype],
                         Some(datatype),
                     );
@@ -2489,6 +3375,7 @@ impl Ctx {
             ("+ - * / % == != >= <= > < ^ | & >> <<", integer_types),
             ("+ - * / == != >= <= > <", &[F32_TY, F64_TY][..]),
             ("&& || ^ | &", &[BOOL_TY][..]),
+            ("== != >= <= > <", &[CHAR_TY][..]),
         ];
 
         for &(operators, types) in builtin_bin_ops.iter() {
@@ -2500,7 +3387,13 @@ impl Ctx {
                     } else {
                         ty
                     };
-                    self.create_builtin_fun(Some(ty), op_span, &[ty, ty], Some(return_type));
+                    self.create_builtin_fun(
+                        ty,
+                        op_span,
+                        ast::OpKind::Binary,
+                        &[ty, ty],
+                        Some(return_type),
+                    );
                 }
             }
         }
@@ -2508,34 +3401,37 @@ impl Ctx {
 
     pub fn create_builtin_fun(
         &mut self,
-        caller: Option<Ty>,
+        caller: Ty,
         name: Span,
+        op_kind: ast::OpKind,
         args: &[Ty],
         ret: Option<Ty>,
     ) {
-        /*let id = salt.add(name.hash).add(self.types[args[0]].id);
-        let module_ent = &mut self.modules[module];
-        let id = id.add(module_ent.id);
-        let args = module_ent.add_type_slice(args);
+        let id = self.hash_span(name).add(self.type_id(caller));
+        let id = match op_kind {
+            ast::OpKind::Normal => id,
+            ast::OpKind::Unary => id.add(ID::new("-u-")),
+            ast::OpKind::Binary => id.add(ID::new("-b-")),
+        };
+        let args = self.add_type_slice(args);
         let sig = Signature {
             call_conv: CallConv::Fast,
             args,
-            ret,
+            ret: ret.into(),
         };
 
         let fun_ent = FunEnt {
             id,
             name,
             vis: Vis::Public,
-            module,
+            module: BUILTIN_MODULE,
             kind: FKind::Builtin,
             sig,
 
             ..Default::default()
         };
 
-        assert!(self.add_fun(module, fun_ent).0.is_none());
-        */
+        self.add_fun(BUILTIN_MODULE, fun_ent).unwrap();
     }
 }
 
@@ -2553,7 +3449,7 @@ impl DerefMut for Ctx {
     }
 }
 
-crate::impl_entity!(Cmd, Section);
+crate::impl_entity!(Cmd, Section, VTable);
 
 pub fn assert_type(actual: Ty, expected: Ty, token: Token) -> Result {
     if actual == expected {
@@ -2566,6 +3462,42 @@ pub fn assert_type(actual: Ty, expected: Ty, token: Token) -> Result {
     }
 }
 
+/// Returns type expression the impl block is for, methods
+/// of 'impl Bound for Type' belong to the type.
+pub fn impl_target(ast_data: &ast::DataSwitch, scope: Ast) -> Ast {
+    let sons = ast_data.sons(scope);
+    if sons[2].is_reserved_value() {
+        sons[1]
+    } else {
+        sons[2]
+    }
+}
+
+pub fn is_integer(ty: Ty) -> bool {
+    ty == INT_TY || ty == UINT_TY || INTEGER_TYPES.contains(&ty)
+}
+
+/// Returns true if inclusive `ranges` together cover whole `target` range.
+fn range_covered(ranges: &[(i128, i128)], (low, high): (i128, i128)) -> bool {
+    let mut ranges = ranges.to_vec();
+    ranges.sort_unstable();
+
+    let mut next = low;
+    for &(start, end) in ranges.iter() {
+        if start > next {
+            break;
+        }
+        if end >= next {
+            next = end + 1;
+        }
+        if next > high {
+            return true;
+        }
+    }
+
+    next > high
+}
+
 pub struct Builder<'a> {
     data: &'a mut Data,
     body: &'a mut Body,
@@ -2656,11 +3588,21 @@ impl<'a> Builder<'a> {
         list.as_slice(&self.data.local_slices)
     }
 
-    pub fn push_swection_arg(&mut self, block: Section, arg: Local) {
+    pub fn push_section_arg(&mut self, block: Section, arg: Local) {
         let block = &mut self.data.sections[block];
         block.args.push(arg, &mut self.data.local_slices);
     }
 
+    pub fn is_closed(&self) -> bool {
+        self.body.current_block.is_none()
+    }
+
+    /// Detaches the current block so other block can be selected,
+    /// returns none if block was already closed.
+    pub fn take_block(&mut self) -> Option<Section> {
+        self.body.current_block.take()
+    }
+
     pub fn set_block_args(&mut self, entry_block: Section, args: EntityList<Local>) {
         self.data.sections[entry_block].args = args;
     }
@@ -2764,12 +3706,26 @@ impl<'a> Builder<'a> {
 
 pub struct ScopeState {
     previous: Ast,
+    saved: bool,
     generics: Ast,
     id: ID,
     shadow: Option<(ID, Option<Item>)>,
     vis: Vis,
 }
 
+impl Default for ScopeState {
+    fn default() -> Self {
+        Self {
+            previous: Ast::reserved_value(),
+            saved: false,
+            generics: Ast::reserved_value(),
+            id: ID(0),
+            shadow: None,
+            vis: Vis::None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default, QuickSer)]
 pub struct Data {
     locals: PrimaryMap<Local, LocalEnt>,
@@ -2778,6 +3734,24 @@ pub struct Data {
     local_slices: ListPool<Local>,
 }
 
+impl Data {
+    pub fn local(&self, local: Local) -> &LocalEnt {
+        &self.locals[local]
+    }
+
+    pub fn cmd(&self, cmd: Cmd) -> &CmdEnt {
+        &self.cmds[cmd]
+    }
+
+    pub fn section(&self, section: Section) -> &SectionEnt {
+        &self.sections[section]
+    }
+
+    pub fn local_slice(&self, list: EntityList<Local>) -> &[Local] {
+        list.as_slice(&self.local_slices)
+    }
+}
+
 #[derive(Debug, Clone, Default, Copy, RealQuickSer)]
 pub struct LocalEnt {
     pub ty: Ty,
@@ -2813,12 +3787,13 @@ pub mod cmd {
         NoOp,
         FunPointer(Fun),
         FunPointerCall(Local, EntityList<Local>),
+        VTable(VTable),
         GlobalLoad(Global),
         Call(Fun, EntityList<Local>),
         VarDecl(Local),
         Zeroed,
         Uninitialized,
-        Lit(token::Kind),
+        Lit(Const),
         Return(PackedOption<Local>),
         Assign(Local),
         Jump(Section, EntityList<Local>),
@@ -2827,11 +3802,12 @@ pub mod cmd {
         Deref(Local, bool),
         Ref(Local),
         Cast(Local),
+        Unreachable,
     }
 
     impl Kind {
         pub fn is_closing(&self) -> bool {
-            matches!(self, Kind::Jump(..) | Kind::Return(..))
+            matches!(self, Kind::Jump(..) | Kind::Return(..) | Kind::Unreachable)
         }
     }
 
@@ -2998,6 +3974,9 @@ impl ErrorDisplayState<Error> for Ctx {
             error::Kind::BreakOutsideLoop => {
                 writeln!(f, "cannot use 'break' outside of loop")?;
             }
+            error::Kind::BreakValueInConditionalLoop => {
+                writeln!(f, "loop with condition or iterator can end without 'break', so 'break' cannot carry a value")?;
+            }
             error::Kind::WrongLabel => {
                 writeln!(f, "parent loop with this label does not exist")?;
             }
@@ -3120,6 +4099,77 @@ impl ErrorDisplayState<Error> for Ctx {
             error::Kind::ExpectedFunctionPointer => {
                 writeln!(f, "only dereferenced function pointer can be called")?;
             }
+            error::Kind::UnsupportedExpression => {
+                writeln!(f, "expression is not supported in this context")?;
+            }
+            &error::Kind::InvalidMatchType(ty) => {
+                writeln!(
+                    f,
+                    "cannot match on '{}', only integers, booleans and enums are supported",
+                    TypeDisplay::new(&self, ty)
+                )?;
+            }
+            &error::Kind::InvalidPattern(ty) => {
+                writeln!(
+                    f,
+                    "pattern must be a constant of type '{}'",
+                    TypeDisplay::new(&self, ty)
+                )?;
+            }
+            &error::Kind::NonAsciiChar(value, ty) => {
+                writeln!(
+                    f,
+                    "character {:?} is not ASCII and does not fit into '{}'",
+                    value,
+                    TypeDisplay::new(&self, ty)
+                )?;
+            }
+            &error::Kind::PatternOutOfRange(ty) => {
+                writeln!(
+                    f,
+                    "pattern value does not fit into '{}'",
+                    TypeDisplay::new(&self, ty)
+                )?;
+            }
+            error::Kind::InvalidRangePattern => {
+                writeln!(f, "range pattern is empty or matched type is not ordered")?;
+            }
+            error::Kind::UnreachableArm => {
+                writeln!(f, "arm is unreachable, previous arms already cover it")?;
+            }
+            error::Kind::NonExhaustiveMatch(missing) => {
+                writeln!(f, "match is not exhaustive, add '_' arm to cover the rest")?;
+                if !missing.is_empty() {
+                    writeln!(
+                        f,
+                        "missing variants: {}",
+                        missing
+                            .iter()
+                            .map(|&span| self.display(span))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )?;
+                }
+            }
+            &error::Kind::NotDispatchable(name) => {
+                writeln!(
+                    f,
+                    "method '{}' cannot be called through vtable, first argument has to be '&Self' or '&var Self'",
+                    self.display(name)
+                )?;
+            }
+            &error::Kind::BoundMethodMismatch(fun, name) => {
+                writeln!(
+                    f,
+                    "signature of '{}' does not match method '{}' declared in bound, definition is here:\n{}",
+                    self.display(self.funs[fun].name),
+                    self.display(name),
+                    token::Display::new(self, &self.funs[fun].hint)
+                )?;
+            }
+            error::Kind::GenericLambda => {
+                writeln!(f, "lambda cannot have generic parameters")?;
+            }
         }
 
         Ok(())
@@ -3204,20 +4254,26 @@ mod error {
         MissingElseBranch,
         ContinueOutsideLoop,
         BreakOutsideLoop,
+        BreakValueInConditionalLoop,
         WrongLabel,
         NonPointerDereference,
         InvalidFunctionHeader,
         AmbiguousFunction(Fun, Fun),
         AmbiguousGlobal(Global, Global),
+        UnsupportedExpression,
+        InvalidMatchType(Ty),
+        InvalidPattern(Ty),
+        PatternOutOfRange(Ty),
+        NonAsciiChar(char, Ty),
+        InvalidRangePattern,
+        UnreachableArm,
+        NonExhaustiveMatch(Vec<Span>),
+        NotDispatchable(Span),
+        BoundMethodMismatch(Fun, Span),
+        GenericLambda,
     }
 }
 
-pub enum DotInstr {
-    None,
-    Deref,
-    Ref,
-}
-
 /// Bound can be in three states, default is blueprint that is 
 /// used for instantiation. When structure binds to bound, and 
 /// bound and bind-impl is generic, the ast of this block is 
@@ -3252,6 +4308,41 @@ pub struct FunEnt {
     linkage: Linkage,
     untraced: bool,
     inline: bool,
+    terminating: bool,
+}
+
+impl FunEnt {
+    pub fn id(&self) -> ID {
+        self.id
+    }
+
+    pub fn params(&self) -> EntityList<Ty> {
+        self.params
+    }
+
+    pub fn sig(&self) -> Signature {
+        self.sig
+    }
+
+    pub fn body(&self) -> Body {
+        self.body
+    }
+
+    pub fn kind(&self) -> FKind {
+        self.kind
+    }
+
+    pub fn name(&self) -> Span {
+        self.name
+    }
+
+    pub fn alias(&self) -> Option<Span> {
+        self.alias
+    }
+
+    pub fn linkage(&self) -> Linkage {
+        self.linkage
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, RealQuickSer)]
@@ -3327,6 +4418,34 @@ pub struct GlobalEnt {
     linkage: Linkage,
 }
 
+impl GlobalEnt {
+    pub fn id(&self) -> ID {
+        self.id
+    }
+
+    pub fn ty(&self) -> Ty {
+        self.ty
+    }
+
+    pub fn alias(&self) -> Option<Span> {
+        self.alias
+    }
+
+    pub fn linkage(&self) -> Linkage {
+        self.linkage
+    }
+}
+
+/// Functions implementing bound for concrete type, order of methods
+/// matches [`types::Ctx::bound_vtable`]. Generator lowers the table
+/// into static data that [`cmd::Kind::VTable`] refers to.
+#[derive(Debug, Clone, Default, Copy, RealQuickSer)]
+pub struct VTableEnt {
+    bound: Ty,
+    ty: Ty,
+    methods: EntityList<Fun>,
+}
+
 #[derive(Debug, Clone, Default, QuickSer)]
 pub struct GFun {
     id: Fun,
@@ -3343,36 +4462,15 @@ impl SparseMapValue<Fun> for GFun {
 #[derive(Debug, Clone, Default, QuickSer)]
 pub struct GenericSignature {
     params: Vec<ID>,
-    elements: Vec<GenericElement>,
     arg_count: usize,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Copy, RealQuickSer)]
-pub enum GenericElement {
-    ScopeStart,
-    ScopeEnd,
-    Pointer(bool), // true if mutable
-    Array(std::result::Result<u32, Ast>),
-    Element(Ty, PackedOption<Ty>),
-    Parameter(u32),
-    NextArgument(u32, u32), // amount of arguments, amount of elements for representation
-}
-
-impl GenericElement {
-    pub fn compare(&self, other: &Self) -> bool {
-        match (self, other) {
-            (GenericElement::Element(id1, _), GenericElement::Element(id2, _)) => id1 == id2,
-            (GenericElement::Array(_), GenericElement::Array(_)) => true,
-            _ => self == other,
-        }
-    }
-}
-
 #[derive(Debug, Clone)]
 pub struct Loop {
     name: ID,
     start_block: Section,
     end_block: Section,
+    conditional: bool,
 }
 
 #[derive(Debug, Clone, Copy, RealQuickSer)]
@@ -3407,93 +4505,74 @@ impl<'a> FunDisplay<'a> {
 
 impl std::fmt::Display for FunDisplay<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        /*let fun = &self.ctx.funs[self.fun];
-        let module_ent = &self.ctx.modules[fun.module];
+        let fun = &self.ctx.funs[self.fun];
+        let data = &self.ctx.data;
 
-        writeln!(f, "{}", self.ctx.display(&fun.hint.span))?;
-        writeln!(f)?;
+        writeln!(f, "{}", self.ctx.display(fun.name))?;
 
-        let mut current = fun.self.body.entry_block;
-        while current.is_some() {
-            let block = &module_ent.blocks[current.unwrap()];
+        let mut current = fun.body.entry_block;
+        while let Some(section) = current.expand() {
+            let section_ent = &data.sections[section];
             writeln!(
                 f,
                 "  {} {:?}:",
-                current.unwrap(),
-                module_ent.block_args(current.unwrap())
+                section,
+                section_ent.args.as_slice(&data.local_slices)
             )?;
-            let mut current_inst = block.start;
-            while current_inst.is_some() {
-                let inst = &module_ent.insts[current_inst.unwrap()];
-                if inst.value.is_none() {
-                    writeln!(f, "    {:?}", inst.kind)?;
+            let mut current_cmd = section_ent.start;
+            while let Some(cmd) = current_cmd.expand() {
+                let cmd_ent = &data.cmds[cmd];
+                if let Some(value) = cmd_ent.value.expand() {
+                    writeln!(f, "    {} = {:?}", value, cmd_ent.kind)?;
                 } else {
-                    writeln!(f, "    {} = {:?}", inst.value.unwrap(), inst.kind)?;
+                    writeln!(f, "    {:?}", cmd_ent.kind)?;
                 }
-                current_inst = inst.next;
+                current_cmd = cmd_ent.next;
             }
-            current = block.next;
-        }*/
+            current = section_ent.next;
+        }
 
         Ok(())
     }
 }
 
 pub fn test() {
-    /*panic!("{}", std::mem::size_of::<Span>());
-
     const PATH: &str = "src/functions/test_project";
 
-    let now = std::time::Instant::now();
-    let (mut state, hint) = Ctx::load_data(PATH, ID(0)).unwrap_or_default();
-    let mut context = Ctx::default();
-    println!("{}", now.elapsed().as_secs_f32());
-
-    MTParser::new(&mut state, &mut context)
-        .parse(PATH)
-        .map_err(|e| panic!("\n{}", MTErrorDisplay::new(&state, &e)))
+    let mut ctx = Ctx::default();
+    ctx.compile_project(PATH)
+        .map_err(|e| panic!("\n{}", ErrorDisplay::new(&ctx, &e)))
         .unwrap();
 
-    for global in context.globals_to_remove.drain(..) {
-        let id = state.globals[global].id;
-        state.globals.remove(id);
-    }
-
-    let order = std::mem::take(&mut state.module_order);
+    ctx.finish_entry_point();
 
-    // mark all unchanged module type dependencies as used
-    let mut used_types = EntitySet::with_capacity(state.types.len());
-    let mut used_funs = EntitySet::with_capacity(state.funs.len());
-    for &module in &order {
-        if state.modules[module].clean {
-            let module_ent = &state.t_state.mt_state.modules[module];
-            for &ty in module_ent.used_types() {
-                used_types.insert(ty);
-            }
-            for &fun in module_ent.used_functions() {
-                used_funs.insert(fun);
-            }
-        }
-    }
+    println!("{}", FunDisplay::new(&ctx, ctx.entry_point_data.id));
 
-    state.t_state.remove_garbage(&used_types);
-    state.remove_garbage(&used_funs);
+    // generator names symbols by ID
+    let mut ids = ctx.funs.iter().map(|(_, fun)| fun.id).collect::<Vec<_>>();
+    let count = ids.len();
+    ids.sort_by_key(|id| id.0);
+    ids.dedup();
+    assert_eq!(ids.len(), count, "functions with equal ID");
 
-    for &module in &order {
-        if state.modules[module].clean {
-            continue;
-        }
+    let error = compile_error("const C: char = '\u{e9}'\nfun main:\n  let b = C.u8()\n");
+    assert!(matches!(error.kind(), error::Kind::NonAsciiChar('\u{e9}', U8_TY)));
 
-        FParser::new(&mut state, &mut context, I64)
-            .parse(module)
-            .map_err(|e| panic!("\n{}", FErrorDisplay::new(&mut state, &e)))
-            .unwrap();
+    let error = compile_error("fun main:\n  let a = 1\n  let f = fun(): a = 2\n");
+    assert!(matches!(error.kind(), error::Kind::AssignToImmutable));
 
+    let error = compile_error("fun main:\n  let a = match true:\n    false..=true: 1\n");
+    assert!(matches!(error.kind(), error::Kind::InvalidRangePattern));
+}
 
-    }
+/// Compiles single file project with `source` that is expected to fail.
+fn compile_error(source: &str) -> Error {
+    let path = std::env::temp_dir().join("metaflow_functions_error");
+    std::fs::create_dir_all(&path).unwrap();
+    std::fs::write(path.join("project.mfm"), "root = \"root.mf\"\n").unwrap();
+    std::fs::write(path.join("root.mf"), source).unwrap();
 
-    println!("{}", now.elapsed().as_secs_f32());
-    state.save_data(PATH, ID(0), Some(hint)).unwrap();
-    println!("{}", now.elapsed().as_secs_f64());
-    */
+    let mut ctx = Ctx::default();
+    ctx.compile_project(path.to_str().unwrap())
+        .expect_err(source)
 }
@@ -1,1638 +1,1113 @@
-use cranelift::codegen::binemit::NullTrapSink;
-use cranelift::codegen::ir::{AbiParam, Function};
-use cranelift::entity::EntitySet;
-use cranelift::frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
-use cranelift::jit::JITModule;
-use cranelift::module::{DataContext, DataId, FuncOrDataId, Linkage, Module, RelocRecord, ModuleDeclarations};
-use cranelift::object::ObjectModule;
-use cranelift::{
-    codegen::{
-        binemit::RelocSink,
-        binemit::{Addend, CodeOffset, NullStackMapSink, Reloc, TrapSink},
-        entity::EntityRef,
-        ir::{
-            condcodes::{FloatCC, IntCC},
-            types::*,
-            Block, ExternalName, FuncRef, GlobalValue, Inst, InstBuilder, MemFlags, SigRef,
-            Signature as CrSignature, SourceLoc, StackSlot, StackSlotData, StackSlotKind, TrapCode,
-            Type, Value,
-        },
-        isa::CallConv as CrCallConv,
-        packed_option::{PackedOption, ReservedValue},
-        Context,
-    },
-    entity::{SecondaryMap, SparseMap, SparseMapValue},
-    module::FuncId,
-};
-use quick_proc::{QuickDefault, QuickSer, RealQuickSer};
-
-use std::ops::{Deref, DerefMut};
-use crate::entities::{BlockEnt, TKind, CrTypeWr, TypeEnt, AnonString, AnonStringEnt};
-use crate::incr::IncrementalData;
-use crate::types::GarbageTarget;
-use crate::util::storage::Table;
-use crate::{
-    entities::{Fun, IKind, InstEnt, Mod, Ty, ValueEnt},
-    functions::{FContext, FKind, FParser, FState, FunEnt, GlobalEnt},
-    lexer::{Span, TKind as LTKind, Token},
-    types::TypeDisplay,
-    util::{
-        sdbm::{SdbmHash, ID},
-        storage::Map,
-        write_radix, Size,
-    },
+//! Lowering of [`functions::Data`](crate::functions::Data) into cranelift
+//! IR. Locals are mapped to [`FinalValue`]s lazily, fields of values held
+//! in registers are extracted with shifts, everything that does not fit
+//! into register lives on the stack or behind the pointer.
+
+use cranelift::codegen::binemit::{NullStackMapSink, NullTrapSink};
+use cranelift::codegen::entity::{EntityList, EntityRef};
+use cranelift::codegen::ir::condcodes::{FloatCC, IntCC};
+use cranelift::codegen::ir::types::*;
+use cranelift::codegen::ir::{
+    Block, FuncRef, GlobalValue, InstBuilder, MemFlags, SigRef, Signature as CrSignature,
+    StackSlot, StackSlotData, StackSlotKind, TrapCode, Type, Value,
 };
+use cranelift::codegen::packed_option::PackedOption;
+use cranelift::codegen::Context;
+use cranelift::frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift::module::{DataId, FuncId, FuncOrDataId, Linkage, Module};
 
-use super::{GEKind, GError};
+use crate::functions::{cmd, FKind, LocalEnt, Section, VTable};
+use crate::lexer::{Span, Token};
+use crate::modules::{Const, Fun, Global, Local, Ty};
+use crate::types::{constant, ty, Signature, *};
+use crate::util::sdbm::SdbmHash;
+use crate::util::write_radix;
 
-type Result<T = ()> = std::result::Result<T, GError>;
+use super::{error, Ctx, Error, FinalValue, Result};
 
 pub const STRING_SALT: u64 = 0xDEADBEEF; // just a random number
 
-pub struct Generator<'a, M: Module> {
-    module: &'a mut M,
-    state: &'a mut GState,
-    context: &'a mut GContext,
-    s32: bool,
-    jit: bool,
-    ptr_ty: Type,
-}
+impl Ctx {
+    /// Declares the entry point and defines all functions reachable
+    /// from it.
+    pub fn generate<M: Module>(&mut self, module: &mut M) -> Result {
+        self.ptr_ty = module.isa().pointer_type();
+        self.s32 = self.ptr_ty == I32;
 
-crate::inherit!(Generator<'a, M: Module>, state, GState);
-
-impl<'a, M: Module> Generator<'a, M> {
-    pub fn new(
-        module: &'a mut M,
-        state: &'a mut GState,
-        context: &'a mut GContext,
-        jit: bool,
-    ) -> Self {
-        // TODO: move this to better place so we don't repeat it pointlessly
-        let ptr_ty = module.isa().pointer_type();
-        let int = state.builtin_repo.int;
-        let uint = state.builtin_repo.uint;
-        state.types[int].kind = TKind::Builtin(CrTypeWr(ptr_ty));
-        state.types[uint].kind = TKind::Builtin(CrTypeWr(ptr_ty));
-
-        Self {
-            jit,
-            s32: ptr_ty.bytes() == 4,
-            ptr_ty,
-            module,
-            state,
-            context,
-        }
-    }
+        let mut ctx = module.make_context();
+        let mut builder_ctx = FunctionBuilderContext::new();
 
-    pub fn generate(&mut self, order: &[Mod]) -> Result {
-        let mut functions = self.context.pool.get();
-        let mut globals = self.context.pool.get();
-        let mut declarations = std::mem::take(&mut self.state.bin_declarations);
-        for &module in order {
-            if self.modules[module].clean {
-                continue;
-            }
-            
-            // remove strings
-            let mut strings = std::mem::take(&mut self.modules[module].anon_strings);
-            for (id, _) in strings.drain(..) {
-                declarations.remove_data(id);
-            }
-            self.modules[module].anon_strings = strings;
+        let entry_point = self.entry_point();
+        self.declare_fun(module, entry_point)?;
+        while let Some(fun) = self.frontier.pop() {
+            self.define_fun(module, fun, &mut ctx, &mut builder_ctx)?;
         }
 
-        self.module.load_declarations(declarations);
+        Ok(())
+    }
 
-        for &module in order.iter() {
-            let module_ent = &mut self.state.modules[module];
-            if module_ent.clean {
-                functions.clear();
-                globals.clear();
-                functions.extend_from_slice(module_ent.used_functions());
-                globals.extend_from_slice(module_ent.used_globals());
-                let strings = std::mem::take(&mut module_ent.anon_strings);
+    /// Declares function in the module, functions with body are scheduled
+    /// for definition. Declaration is done only once per function.
+    fn declare_fun<M: Module>(&mut self, module: &mut M, fun: Fun) -> Result<FuncId> {
+        if let Some(id) = self.compiled_funs[fun].expand() {
+            return Ok(id);
+        }
 
-                self.load_funs(&functions);
-                self.load_globals(&globals);
+        let fun_ent = *self.fun_ent(fun);
+        let mut sig = module.make_signature();
+        fun_ent.sig().to_cr_signature(self, module.isa(), &mut sig);
+
+        let mut buffer = String::new();
+        let name = if let Some(alias) = fun_ent.alias() {
+            self.display(alias)
+        } else if fun_ent.linkage() == Linkage::Import {
+            self.display(fun_ent.name())
+        } else if fun == self.entry_point() {
+            "main"
+        } else {
+            write_radix(fun_ent.id().0, 36, &mut buffer);
+            buffer.as_str()
+        };
 
-                let module_ent = &mut self.modules[module];
-                module_ent.anon_strings = strings;
-                continue;
-            }
+        let id = module
+            .declare_function(name, fun_ent.linkage(), &sig)
+            .map_err(Into::into)?;
+        self.compiled_funs[fun] = id.into();
 
-            FParser::new(self.state, self.context, self.ptr_ty)
-                .parse(module)
-                .map_err(|err| GError::new(GEKind::FError(err), Token::default()))?;
-            
-            let mut represented = std::mem::take(&mut self.context.represented);
-            self.declare_funs(&represented);
-            
-            let mut resolved_globals = std::mem::take(&mut self.context.resolved_globals);
-            self.declare_and_define_globals(&resolved_globals);
-            resolved_globals.clear();
-            self.context.resolved_globals = resolved_globals;
-            
-            self.define_funs(&represented);
-            represented.clear();
-            self.context.represented = represented;
+        if fun_ent.linkage() != Linkage::Import && fun_ent.body().entry_block.is_some() {
+            self.frontier.push(fun);
         }
 
-        self.finalize(order);
+        Ok(id)
+    }
 
-        self.state.bin_declarations = self.module.take_declarations();
+    fn define_fun<M: Module>(
+        &mut self,
+        module: &mut M,
+        fun: Fun,
+        ctx: &mut Context,
+        builder_ctx: &mut FunctionBuilderContext,
+    ) -> Result {
+        let id = self.compiled_funs[fun].unwrap();
+        module.clear_context(ctx);
+        self.fun_ent(fun)
+            .sig()
+            .to_cr_signature(self, module.isa(), &mut ctx.func.signature);
+
+        self.imported_funs.clear();
+        self.imported_data.clear();
+        self.imported_signatures.clear();
+        self.stack_locals.clear();
+
+        let mut builder = FunctionBuilder::new(&mut ctx.func, builder_ctx);
+        self.body(module, fun, &mut builder)?;
+        builder.seal_all_blocks();
+        builder.finalize();
+
+        module
+            .define_function(id, ctx, &mut NullTrapSink {}, &mut NullStackMapSink {})
+            .map_err(Into::into)?;
 
         Ok(())
     }
 
-    pub fn load_strings(&mut self, strings: &[(DataId, Span)]) {
-        for &(id, span) in strings {
-            let data = self.state.display(&span);
-            self.context.data_context.define(data.deref().as_bytes().to_owned().into_boxed_slice());
-            self.module.define_data(id, &self.context.data_context).unwrap();
-            self.context.data_context.clear();
-        }
-    }
+    fn body<M: Module>(
+        &mut self,
+        module: &mut M,
+        fun: Fun,
+        builder: &mut FunctionBuilder,
+    ) -> Result {
+        let fun_ent = *self.fun_ent(fun);
+        let body = fun_ent.body();
+        self.mark_stack_locals(body.entry_block);
 
-    pub fn load_globals(&mut self, globals: &[GlobalValue]) {
-        for &global in globals {
-            let GlobalEnt { ty, linkage, .. } = self.globals[global];
-            if linkage == Linkage::Import {
-                continue;
+        let mut current = body.entry_block;
+        while let Some(section) = current.expand() {
+            let block = builder.create_block();
+            self.blocks[section] = block.into();
+            let mut args = self.temp_vec();
+            args.extend_from_slice(self.data().local_slice(self.data().section(section).args));
+            for &arg in args.iter() {
+                let ty = self.data().local(arg).ty;
+                let value = builder.append_block_param(block, self.repr(ty));
+                let value = if self.on_stack(ty) {
+                    FinalValue::Pointer(value)
+                } else {
+                    FinalValue::Value(value)
+                };
+                self.set_value(arg, value);
             }
-            let id = self.compiled_globals[global].id;
-            self.context
-                .data_context
-                .define_zeroinit(self.types[ty].size.pick(self.s32) as usize);
-            self.module.define_data(id, &self.context.data_context).unwrap();
-            self.context.data_context.clear();
+            current = self.data().section(section).next;
         }
-    }
 
-    pub fn load_funs(&mut self, functions: &[Fun]) {
-        for &fun_id in functions {
-            let FunEnt {
-                linkage,
-                body,
-                kind,
-                ..
-            } = self.funs[fun_id];
-            
-            if linkage == Linkage::Import || kind != FKind::Represented || body.entry_block.is_none() {
-                continue;
+        let entry_block = self.block(body.entry_block.unwrap());
+        let sret = match fun_ent.sig().ret.expand() {
+            Some(ret) if self.on_stack(ret) => {
+                Some(builder.append_block_param(entry_block, self.ptr_ty))
             }
+            _ => None,
+        };
 
-            let data = std::mem::take(&mut self.compiled_funs[fun_id]);
+        let mut current = body.entry_block;
+        while let Some(section) = current.expand() {
+            let block = self.block(section);
+            builder.switch_to_block(block);
+            if section == body.entry_block.unwrap() {
+                self.spill_mutable_args(section, builder);
+            }
 
-            if self.jit {
-                let jit_data = std::mem::take(self.jit_funs.get_mut(fun_id).unwrap());
-                self.module.define_function_bytes(
-                    data.id, 
-                    &jit_data.bin.bytes, 
-                    &jit_data.bin.relocs
-                ).unwrap();
-                self.jit_funs.insert(jit_data);
-            } else {
-                self.module.define_function_bytes(
-                    data.id, 
-                    &data.bin.bytes, 
-                    &data.bin.relocs
-                ).unwrap();
+            let section_ent = *self.data().section(section);
+            let mut current_cmd = section_ent.start;
+            let mut closed = false;
+            while let Some(cmd) = current_cmd.expand() {
+                let cmd_ent = *self.data().cmd(cmd);
+                self.lower_cmd(module, cmd_ent.kind, cmd_ent.value, sret, builder)?;
+                closed = cmd_ent.kind.is_closing();
+                current_cmd = cmd_ent.next;
+            }
+
+            // sections without terminator are never reached
+            if !closed {
+                builder.ins().trap(TrapCode::UnreachableCodeReached);
             }
 
-            self.compiled_funs[fun_id] = data;
+            current = section_ent.next;
         }
+
+        Ok(())
     }
 
-    pub fn finalize(&mut self, order: &[Mod]) {
-        let mut context = Context::new();
-        context.func.signature = CrSignature {
-            params: vec![AbiParam::new(self.ptr_ty), AbiParam::new(self.ptr_ty)],
-            returns: vec![AbiParam::new(self.ptr_ty)],
-            call_conv: self.module.isa().default_call_conv(),
-        };
-        let mut f_context = FunctionBuilderContext::new();
-        let mut builder = FunctionBuilder::new(&mut context.func, &mut f_context);
-        let entry_block = builder.create_block();
-        builder.switch_to_block(entry_block);
-        builder.append_block_params_for_function_params(entry_block);
-        let args = builder.block_params(entry_block);
-        let (arg1, arg2) = (args[0], args[1]);
-        
-        let mut return_value = None;
-
-        for &module in order {
-            let module_ent = &self.modules[module];
-            let id = self.compiled_funs[module_ent.entry_point.unwrap()].id;
-            let func_id = self.module.declare_func_in_func(id, builder.func);
-            let inst = builder.ins().call(func_id, &[arg1, arg2]);
-            return_value = Some(builder.inst_results(inst)[0]);
+    /// Finds locals which address is taken, they have to be allocated
+    /// on the stack. Offsets and casts share the memory with their target.
+    fn mark_stack_locals(&mut self, entry_block: PackedOption<Section>) {
+        let mut referenced = vec![];
+        let mut current = entry_block;
+        while let Some(section) = current.expand() {
+            let mut current_cmd = self.data().section(section).start;
+            while let Some(cmd) = current_cmd.expand() {
+                let cmd_ent = *self.data().cmd(cmd);
+                match cmd_ent.kind {
+                    cmd::Kind::Offset(target) | cmd::Kind::Cast(target) => {
+                        self.parents[cmd_ent.value.unwrap()] = target.into();
+                    }
+                    cmd::Kind::Ref(target) => referenced.push(target),
+                    _ => (),
+                }
+                current_cmd = cmd_ent.next;
+            }
+            current = self.data().section(section).next;
         }
 
-        let return_value = return_value
-            .unwrap_or_else(|| builder.ins().iconst(self.ptr_ty, 0));
-
-        builder.ins().return_(&[return_value]);
+        for mut local in referenced {
+            while let Some(parent) = self.parents[local].expand() {
+                local = parent;
+            }
+            self.stack_locals.insert(local);
+        }
+    }
 
-        builder.seal_block(entry_block);
+    /// Arguments of the entry block that are mutated or referenced need
+    /// a storage that can be written.
+    fn spill_mutable_args(&mut self, section: Section, builder: &mut FunctionBuilder) {
+        let mut args = self.temp_vec();
+        args.extend_from_slice(self.data().local_slice(self.data().section(section).args));
+        for &arg in args.iter() {
+            let LocalEnt { ty, mutable, .. } = *self.data().local(arg);
+            if self.on_stack(ty) {
+                // caller passes pointer to its own memory
+                if mutable {
+                    let src = self.address(arg, builder);
+                    let slot = self.stack_slot(ty, builder);
+                    let dst = builder.ins().stack_addr(self.ptr_ty, slot, 0);
+                    static_memcpy(dst, src, self.size_of(ty), builder);
+                    self.set_value(arg, FinalValue::StackSlot(slot));
+                }
+            } else if self.stack_locals.contains(arg) {
+                let value = self.unwrap_val(arg, builder);
+                let slot = self.stack_slot(ty, builder);
+                let address = builder.ins().stack_addr(self.ptr_ty, slot, 0);
+                store_value(address, value, self.size_of(ty), builder);
+                self.set_value(arg, FinalValue::StackSlot(slot));
+            } else if mutable {
+                let var = Variable::new(arg.index());
+                builder.declare_var(var, self.repr(ty));
+                let value = self.unwrap_val(arg, builder);
+                builder.def_var(var, value);
+                self.set_value(arg, FinalValue::Var(var));
+            }
+        }
+    }
 
-        builder.finalize();
+    fn lower_cmd<M: Module>(
+        &mut self,
+        module: &mut M,
+        kind: cmd::Kind,
+        value: PackedOption<Local>,
+        sret: Option<Value>,
+        builder: &mut FunctionBuilder,
+    ) -> Result {
+        match kind {
+            cmd::Kind::NoOp => (),
+            cmd::Kind::FunPointer(fun) => {
+                let fun_ref = self.import_fun(module, fun, builder)?;
+                let pointer = builder.ins().func_addr(self.ptr_ty, fun_ref);
+                self.set_value(value.unwrap(), FinalValue::Value(pointer));
+            }
+            cmd::Kind::FunPointerCall(pointer, args) => {
+                let ty = self.data().local(pointer).ty;
+                let sig = match self.type_kind(ty) {
+                    ty::Kind::FunPointer(sig) => sig,
+                    _ => unreachable!(),
+                };
+                let sig_ref = self.import_signature(module, ty, sig, builder);
+                let callee = self.unwrap_val(pointer, builder);
+                let (mut values, slot) = self.call_args(sig, args, builder);
+                let inst = builder.ins().call_indirect(sig_ref, callee, &values);
+                values.clear();
+                values.extend_from_slice(builder.inst_results(inst));
+                self.call_result(value, slot, &values);
+            }
+            cmd::Kind::VTable(vtable) => {
+                let data = self.declare_vtable(module, vtable)?;
+                let data = self.import_data(module, data, builder);
+                let pointer = builder.ins().global_value(self.ptr_ty, data);
+                self.set_value(value.unwrap(), FinalValue::Value(pointer));
+            }
+            cmd::Kind::GlobalLoad(global) => {
+                let data = self.declare_global(module, global)?;
+                let data = self.import_data(module, data, builder);
+                let pointer = builder.ins().global_value(self.ptr_ty, data);
+                self.set_value(value.unwrap(), FinalValue::Pointer(pointer));
+            }
+            cmd::Kind::Call(fun, args) => {
+                let fun_ent = *self.fun_ent(fun);
+                if fun_ent.kind() == FKind::Builtin {
+                    return self.call_builtin(fun, args, value, builder);
+                }
+                let fun_ref = self.import_fun(module, fun, builder)?;
+                let (mut values, slot) = self.call_args(fun_ent.sig(), args, builder);
+                let inst = builder.ins().call(fun_ref, &values);
+                values.clear();
+                values.extend_from_slice(builder.inst_results(inst));
+                self.call_result(value, slot, &values);
+            }
+            cmd::Kind::VarDecl(init) => self.var_decl(init, value.unwrap(), builder),
+            cmd::Kind::Zeroed => self.set_value(value.unwrap(), FinalValue::Zero),
+            cmd::Kind::Uninitialized => {
+                let value = value.unwrap();
+                let ty = self.data().local(value).ty;
+                if self.on_stack(ty) {
+                    let slot = self.stack_slot(ty, builder);
+                    self.set_value(value, FinalValue::StackSlot(slot));
+                } else {
+                    self.set_value(value, FinalValue::Zero);
+                }
+            }
+            cmd::Kind::Lit(constant) => self.lit(module, constant, value.unwrap(), builder)?,
+            cmd::Kind::Return(ret) => match (ret.expand(), sret) {
+                (Some(ret), Some(sret)) => {
+                    self.write(sret, ret, builder);
+                    builder.ins().return_(&[sret]);
+                }
+                (Some(ret), None) => {
+                    let ret = self.unwrap_val(ret, builder);
+                    builder.ins().return_(&[ret]);
+                }
+                (None, _) => {
+                    builder.ins().return_(&[]);
+                }
+            },
+            cmd::Kind::Assign(target) => self.assign(target, value.unwrap(), builder),
+            cmd::Kind::Jump(section, args) => {
+                let block = self.block(section);
+                let args = self.unwrap_vals(args, builder);
+                builder.ins().jump(block, &args);
+            }
+            cmd::Kind::JumpIfTrue(condition, section, args) => {
+                let block = self.block(section);
+                let condition = self.unwrap_val(condition, builder);
+                let args = self.unwrap_vals(args, builder);
+                builder.ins().brnz(condition, block, &args);
+            }
+            cmd::Kind::Offset(target) => {
+                let value = value.unwrap();
+                let offset = self.data().local(value).offset.pick(self.s32);
+                self.values[value] = self.values[target];
+                self.offsets[value] = self.offsets[target] + offset;
+            }
+            cmd::Kind::Deref(target, _) => {
+                let pointer = self.unwrap_val(target, builder);
+                self.set_value(value.unwrap(), FinalValue::Pointer(pointer));
+            }
+            cmd::Kind::Ref(target) => {
+                let pointer = self.address(target, builder);
+                self.set_value(value.unwrap(), FinalValue::Value(pointer));
+            }
+            cmd::Kind::Cast(target) => self.cast(target, value.unwrap(), builder),
+            cmd::Kind::Unreachable => {
+                builder.ins().trap(TrapCode::UnreachableCodeReached);
+            }
+        }
 
-        let id = self.module.declare_function("main", Linkage::Export, &context.func.signature)
-            .unwrap();
-        
-        self.module.define_function(
-            id,
-            &mut context,
-            &mut NullTrapSink::default(),
-            &mut NullStackMapSink {}
-        ).unwrap();
+        Ok(())
     }
 
-    fn declare_funs(&mut self, represented: &Vec<Fun>) {
-        let mut signature = CrSignature::new(CrCallConv::Fast);
-        let mut name = String::new();
-
-        for &fun_id in represented {
-            let FunEnt {
-                id,
-                sig,
-                module,
-                alias,
-                linkage,
-                kind,
-                ..
-            } = self.state.funs[fun_id];
-
-            if kind != FKind::Represented {
-                continue;
+    /// Unwraps call arguments, the stack slot for returned value is
+    /// allocated if the return type does not fit into register.
+    fn call_args(
+        &self,
+        sig: Signature,
+        args: EntityList<Local>,
+        builder: &mut FunctionBuilder,
+    ) -> (Vec<Value>, Option<StackSlot>) {
+        let mut values = self.unwrap_vals(args, builder);
+        let slot = match sig.ret.expand() {
+            Some(ret) if self.on_stack(ret) => {
+                let slot = self.stack_slot(ret, builder);
+                values.push(builder.ins().stack_addr(self.ptr_ty, slot, 0));
+                Some(slot)
             }
+            _ => None,
+        };
+        (values, slot)
+    }
 
-            signature.clear(CrCallConv::Fast);
-            sig.to_cr_signature(module, self.state, self.module.isa(), &mut signature);
+    fn call_result(
+        &mut self,
+        target: PackedOption<Local>,
+        slot: Option<StackSlot>,
+        results: &[Value],
+    ) {
+        let target = match target.expand() {
+            Some(target) => target,
+            None => return,
+        };
+        if let Some(slot) = slot {
+            self.set_value(target, FinalValue::StackSlot(slot));
+        } else {
+            self.set_value(target, FinalValue::Value(results[0]));
+        }
+    }
 
-            name.clear();
-            write_radix(id.0, 16, &mut name);
+    fn var_decl(&mut self, init: Local, carrier: Local, builder: &mut FunctionBuilder) {
+        let LocalEnt { ty, mutable, .. } = *self.data().local(carrier);
+        if self.stack_locals.contains(carrier) || self.on_stack(ty) {
+            let slot = self.stack_slot(ty, builder);
+            self.set_value(carrier, FinalValue::StackSlot(slot));
+            self.assign(carrier, init, builder);
+        } else if mutable {
+            let var = Variable::new(carrier.index());
+            builder.declare_var(var, self.repr(ty));
+            let value = self.unwrap_val(init, builder);
+            builder.def_var(var, value);
+            self.set_value(carrier, FinalValue::Var(var));
+        } else {
+            let value = self.unwrap_val(init, builder);
+            self.set_value(carrier, FinalValue::Value(value));
+        }
+    }
 
-            let final_name = if let Some(alias) = alias {
-                self.state.display(&alias)
+    fn lit<M: Module>(
+        &mut self,
+        module: &mut M,
+        constant: Const,
+        target: Local,
+        builder: &mut FunctionBuilder,
+    ) -> Result {
+        let ty = self.data().local(target).ty;
+        let repr = self.repr(ty);
+        let value = match self.constant_kind(constant) {
+            constant::Kind::Int(value, _) => FinalValue::Value(builder.ins().iconst(repr, value)),
+            constant::Kind::Uint(value, _) => {
+                FinalValue::Value(builder.ins().iconst(repr, value as i64))
+            }
+            constant::Kind::Float(value, _) => FinalValue::Value(if repr == F32 {
+                builder.ins().f32const(value as f32)
             } else {
-                &name
-            };
-
-            if final_name == "main" {
-                todo!();
+                builder.ins().f64const(value)
+            }),
+            constant::Kind::Bool(value) => FinalValue::Value(builder.ins().bconst(B1, value)),
+            constant::Kind::Char(value) => {
+                FinalValue::Value(builder.ins().iconst(I32, value as i64))
+            }
+            constant::Kind::Enum(_, variant) => {
+                // bit pattern of the repr
+                let value = self.enum_variant(variant).value() as i64;
+                FinalValue::Value(builder.ins().iconst(repr, value))
+            }
+            constant::Kind::Str(span) => {
+                let mut bytes = self.display(span).as_bytes().to_vec();
+                // strings are passed to c functions
+                bytes.push(0);
+                let data = self.static_data(module, bytes)?;
+                let data = self.import_data(module, data, builder);
+                FinalValue::Value(builder.ins().global_value(self.ptr_ty, data))
             }
+            constant::Kind::Array(_) => {
+                let mut bytes = vec![];
+                self.const_bytes(constant, ty, &mut bytes)?;
+                let data = self.static_data(module, bytes)?;
+                let data = self.import_data(module, data, builder);
+                FinalValue::Pointer(builder.ins().global_value(self.ptr_ty, data))
+            }
+        };
+        self.set_value(target, value);
 
-            let id = self
-                .module
-                .declare_function(final_name, linkage, &signature)
-                .unwrap();
+        Ok(())
+    }
 
-            self.compiled_funs[fun_id].id = id;
+    /// Serializes `constant` of type `ty` in little endian.
+    fn const_bytes(&self, constant: Const, ty: Ty, bytes: &mut Vec<u8>) -> Result {
+        let size = self.size_of(ty) as usize;
+        match self.constant_kind(constant) {
+            constant::Kind::Int(value, _) => bytes.extend_from_slice(&value.to_le_bytes()[..size]),
+            constant::Kind::Uint(value, _) => bytes.extend_from_slice(&value.to_le_bytes()[..size]),
+            constant::Kind::Float(value, _) => {
+                if size == 4 {
+                    bytes.extend_from_slice(&(value as f32).to_le_bytes())
+                } else {
+                    bytes.extend_from_slice(&value.to_le_bytes())
+                }
+            }
+            constant::Kind::Bool(value) => bytes.push(value as u8),
+            constant::Kind::Char(value) => bytes.extend_from_slice(&(value as u32).to_le_bytes()),
+            constant::Kind::Enum(_, variant) => {
+                let value = self.enum_variant(variant).value();
+                bytes.extend_from_slice(&value.to_le_bytes()[..size])
+            }
+            constant::Kind::Array(elements) => {
+                let element = match self.type_kind(ty) {
+                    ty::Kind::Array(element, _) => element,
+                    _ => unreachable!(),
+                };
+                for &e in self.constant_slice(elements) {
+                    self.const_bytes(e, element, bytes)?;
+                }
+            }
+            constant::Kind::Str(_) => {
+                return Err(Error::new(
+                    error::Kind::UnsupportedConstant(constant),
+                    Token::default(),
+                ))
+            }
         }
+
+        Ok(())
     }
 
-    fn declare_and_define_globals(&mut self, resolved: &[GlobalValue]) {
+    /// Defines read only data named after the hash of `bytes`, so equal
+    /// literals share the data.
+    fn static_data<M: Module>(&mut self, module: &mut M, bytes: Vec<u8>) -> Result<DataId> {
         let mut name = String::new();
-        for &global in resolved {
-            let GlobalEnt {
-                id,
-                module,
-                ty,
-                linkage,
-                alias,
-                attrs,
-                mutable,
-                ..
-            } = self.globals[global];
-
-            let tls = self.modules[module].attr(attrs, ID::new("tls")).is_some();
-
-            name.clear();
-            write_radix(id.0, 36, &mut name);
-
-            let final_name = if let Some(alias) = alias {
-                self.state.display(&alias)
-            } else {
-                &name
-            };
-
-            let id = self
-                .module
-                .declare_data(final_name, linkage, mutable, tls)
-                .unwrap();
-            
-            self.compiled_globals[global].id = id;
-            
-            if linkage == Linkage::Import {
-                continue;
-            }
+        write_radix(
+            (&bytes).sdbm_hash().wrapping_add(STRING_SALT),
+            36,
+            &mut name,
+        );
+        if let Some(FuncOrDataId::Data(data)) = module.get_name(&name) {
+            return Ok(data);
+        }
 
+        let data = module
+            .declare_data(&name, Linkage::Local, false, false)
+            .map_err(Into::into)?;
+        self.data_context.define(bytes.into_boxed_slice());
+        let result = module.define_data(data, &self.data_context);
+        self.data_context.clear();
+        result.map_err(Into::into)?;
 
-            self.context
-                .data_context
-                .define_zeroinit(self.types[ty].size.pick(self.s32) as usize);
-            self.module.define_data(id, &self.context.data_context).unwrap();
-            self.context.data_context.clear();
-        }
+        Ok(data)
     }
 
-    fn define_funs(&mut self, represented: &[Fun]) {
-        let mut reloc_sink = MetaRelocSink::new();
-        let mut fun_ctx = FunctionBuilderContext::new();
-        let mut ctx = Context::new();
+    fn declare_global<M: Module>(&mut self, module: &mut M, global: Global) -> Result<DataId> {
+        if let Some(data) = self.compiled_globals[global].expand() {
+            return Ok(data);
+        }
 
-        for &fun_id in represented {
-            crate::test_println!("{}", crate::functions::FunDisplay::new(&self.state, fun_id));
+        let global_ent = *self.global_ent(global);
+        let mut buffer = String::new();
+        let name = if let Some(alias) = global_ent.alias() {
+            self.display(alias)
+        } else {
+            write_radix(global_ent.id().0, 36, &mut buffer);
+            buffer.as_str()
+        };
 
-            self.context.imported_funs.clear();
-            self.context.imported_globals.clear();
-            self.context.imported_signatures.clear();
+        // globals are initialized by the entry point so all of them are writable
+        let data = module
+            .declare_data(name, global_ent.linkage(), true, false)
+            .map_err(Into::into)?;
+        self.compiled_globals[global] = data.into();
+
+        if global_ent.linkage() != Linkage::Import {
+            let size = self.size_of(global_ent.ty()) as usize;
+            self.data_context.define_zeroinit(size);
+            let result = module.define_data(data, &self.data_context);
+            self.data_context.clear();
+            result.map_err(Into::into)?;
+        }
 
-            let FunEnt { sig, module, linkage, body, kind, .. } = self.state.funs[fun_id];
+        Ok(data)
+    }
 
-            if linkage == Linkage::Import || kind != FKind::Represented || body.entry_block.is_none() {
-                continue;
-            }
+    /// Defines the table of method addresses that bound pointers refer
+    /// to, addresses are filled by relocations.
+    fn declare_vtable<M: Module>(&mut self, module: &mut M, vtable: VTable) -> Result<DataId> {
+        if let Some(data) = self.compiled_vtables[vtable].expand() {
+            return Ok(data);
+        }
 
-            sig.to_cr_signature(
-                module,
-                self.state,
-                self.module.isa(),
-                &mut ctx.func.signature,
-            );
+        let mut name = String::from("vtable.");
+        write_radix(vtable.0 as u64, 36, &mut name);
+        let data = module
+            .declare_data(&name, Linkage::Local, false, false)
+            .map_err(Into::into)?;
+        self.compiled_vtables[vtable] = data.into();
+
+        let mut methods = self.temp_vec();
+        methods.extend_from_slice(self.vtable_methods(vtable));
+        let ptr_size = self.ptr_ty.bytes();
+        self.data_context
+            .define_zeroinit(ptr_size as usize * methods.len());
+        for (i, &fun) in methods.iter().enumerate() {
+            let id = self.declare_fun(module, fun)?;
+            let fun_ref = module.declare_func_in_data(id, &mut self.data_context);
+            self.data_context
+                .write_function_addr(i as u32 * ptr_size, fun_ref);
+        }
+        let result = module.define_data(data, &self.data_context);
+        self.data_context.clear();
+        result.map_err(Into::into)?;
 
-            let mut builder = FunctionBuilder::new(&mut ctx.func, &mut fun_ctx);
+        Ok(data)
+    }
 
-            self.body(fun_id, &mut builder);
+    fn import_fun<M: Module>(
+        &mut self,
+        module: &mut M,
+        fun: Fun,
+        builder: &mut FunctionBuilder,
+    ) -> Result<FuncRef> {
+        if let Some(fun_ref) = self.imported_funs[fun].expand() {
+            return Ok(fun_ref);
+        }
 
-            builder.finalize();
+        let id = self.declare_fun(module, fun)?;
+        let fun_ref = module.declare_func_in_func(id, builder.func);
+        self.imported_funs[fun] = fun_ref.into();
 
-            crate::test_println!("{}", ctx.func.display());
+        Ok(fun_ref)
+    }
 
-            let mut bytes = vec![];
+    fn import_data<M: Module>(
+        &mut self,
+        module: &M,
+        data: DataId,
+        builder: &mut FunctionBuilder,
+    ) -> GlobalValue {
+        if let Some(value) = self.imported_data[data].expand() {
+            return value;
+        }
 
-            ctx.compile_and_emit(
-                self.module.isa(),
-                &mut bytes,
-                &mut reloc_sink,
-                &mut MetaTrapSink {},
-                &mut NullStackMapSink {},
-            )
-            .unwrap();
-
-            let id = self.compiled_funs[fun_id].id;
-
-            self.module
-                .define_function_bytes(id, &bytes, &reloc_sink.relocs)
-                .unwrap();
-
-            if self.jit {
-                self.jit_funs.insert(JFun {
-                    id,
-                    self_id: fun_id,
-                    bin: CompiledData {
-                        bytes,
-                        relocs: unsafe { std::mem::transmute(reloc_sink.relocs.clone()) },
-                    },
-                });
-            } else {
-                self.compiled_funs[fun_id] = CFun {
-                    bin: CompiledData {
-                        bytes,
-                        relocs: unsafe { std::mem::transmute(reloc_sink.relocs.clone()) },
-                    },
-                    id,
-                };
-            }
+        let value = module.declare_data_in_func(data, builder.func);
+        self.imported_data[data] = value.into();
 
-            ctx.clear();
-            reloc_sink.clear();
-        }
+        value
     }
 
-    fn body(&mut self, fun: Fun, builder: &mut FunctionBuilder) {
-        let entry_block = self.funs[fun].body.entry_block;
-
-        self.load_blocks(fun, None, builder);
-
-        let mut arg_buffer = self.context.pool.get();
-        let mut inline_stack = self.context.pool.get();
-        inline_stack.push((
-            fun,
-            entry_block,
-            Option::<Block>::None,
-            Option::<Inst>::None,
-        ));
-
-        let mut bail = false;
-        while let Some((fun, raw_block, return_block, raw_inst)) = inline_stack.last_mut() {
-            let module = self.funs[*fun].module;
-            if raw_block.is_none() {
-                if let &mut Some(return_block) = return_block {
-                    builder.switch_to_block(return_block)
-                }
-                inline_stack.pop().unwrap();
-                if !inline_stack.is_empty() {
-                    self.context.bail();
-                }
-                bail = true;
-                continue;
-            }
-
-            let block = raw_block.unwrap();
-            let BlockEnt {
-                start,
-                next,
-                ..
-            } = self.modules[module].blocks[block];
-            let inst = if raw_inst.is_some() {
-                let inst = raw_inst.unwrap();;
-                *raw_inst = None;
-                self.modules[module].insts[inst].next.unwrap()
-            } else {
-                start.unwrap()
-            };
-            
-            if !bail {
-                builder.switch_to_block(self.context.block(block));
-            }
-            bail = false;
-
-            let inst = self.block(module, inst, *return_block, true, builder);
-            if let Some(inst) = inst {
-                let module_ent = &self.state.modules[module];
-                let inst_ent = &module_ent.insts[inst];
-                let (fun, args) = match inst_ent.kind {
-                    IKind::Call(id, args) => (id, args),
-                    _ => unreachable!(),
-                };
-                let return_block = *return_block;
-                if inline_stack.iter().any(|e| e.0 == fun) {
-                    self.block(module, inst, return_block, false, builder);
-                    inline_stack.last_mut().unwrap().1 = next;
-                    continue;
-                }
-                let value = inst_ent.value;
-                let entry_block = builder.create_block();
-                arg_buffer.clear();
-                arg_buffer.extend(
-                    module_ent
-                        .values(args)
-                        .iter()
-                        .map(|&a| self.unwrap_val(module, a, builder)),
-                );
-                builder.ins().jump(entry_block, arg_buffer.as_slice());
-                let return_block = builder.create_block();
-                if value.is_some() {
-                    let value = value.unwrap();
-                    let ty = module_ent.type_of_value(value);
-                    let repr = self.types[ty].to_cr_type(self.module.isa());
-                    let return_value = builder.append_block_param(return_block, repr);
-                    self.context
-                        .set_value(value, FinalValue::Value(return_value));
-                }
-                self.context.dive();
-                self.load_blocks(fun, Some(entry_block), builder);
-                let entry_block = self.funs[fun].body.entry_block;
-                inline_stack.last_mut().unwrap().3 = Some(inst);
-                inline_stack.push((
-                    fun,
-                    entry_block,
-                    Some(return_block),
-                    None,
-                ));
-            } else {
-                *raw_block = next;
-            }
+    fn import_signature<M: Module>(
+        &mut self,
+        module: &M,
+        ty: Ty,
+        sig: Signature,
+        builder: &mut FunctionBuilder,
+    ) -> SigRef {
+        if let Some(sig_ref) = self.imported_signatures[ty].expand() {
+            return sig_ref;
         }
 
-        builder.seal_all_blocks();
+        let mut cr_sig = CrSignature::new(module.isa().default_call_conv());
+        sig.to_cr_signature(self, module.isa(), &mut cr_sig);
+        let sig_ref = builder.import_signature(cr_sig);
+        self.imported_signatures[ty] = sig_ref.into();
+
+        sig_ref
     }
 
-    fn load_blocks(
+    fn call_builtin(
         &mut self,
-        fun_id: Fun,
-        mut injected: Option<Block>,
+        fun: Fun,
+        args: EntityList<Local>,
+        target: PackedOption<Local>,
         builder: &mut FunctionBuilder,
-    ) {
-        let FunEnt { module, body, .. } = self.state.funs[fun_id];
-        let module_ent = &self.state.modules[module];
+    ) -> Result {
+        let fun_ent = *self.fun_ent(fun);
+        let mut locals = self.temp_vec();
+        locals.extend_from_slice(self.data().local_slice(args));
+        let value = match *locals.as_slice() {
+            [] => self.call_generic_builtin(fun, builder),
+            [a] => self.call_unary_builtin(fun_ent.name(), a, fun_ent.sig().ret, builder),
+            [a, b] => self.call_binary_builtin(fun_ent.name(), a, b, builder),
+            _ => None,
+        };
 
-        let mut current = body.entry_block;
-        while current.is_some() {
-            let cur = current.unwrap();
-            let block = injected.take().unwrap_or_else(|| builder.create_block());
-            let block_ent = &module_ent.blocks[cur];
-            let args = module_ent.block_args(cur);
-
-            for &arg in args {
-                let ty = module_ent.type_of_value(arg);
-                let ty = self.types[ty].to_cr_type(self.module.isa());
-                let value = builder.append_block_param(block, ty);
-                self.context.set_value(arg, FinalValue::Value(value));
-            }
+        let value = value
+            .ok_or_else(|| Error::new(error::Kind::UnsupportedBuiltin(fun), Token::default()))?;
+        self.set_value(target.unwrap(), FinalValue::Value(value));
+
+        Ok(())
+    }
 
-            self.context.set_block(cur, block);
-            current = block_ent.next;
+    fn call_generic_builtin(&self, fun: Fun, builder: &mut FunctionBuilder) -> Option<Value> {
+        let fun_ent = self.fun_ent(fun);
+        match self.display(fun_ent.name()) {
+            "sizeof" => {
+                let size = self.size_of(self.type_slice(fun_ent.params())[0]);
+                Some(builder.ins().iconst(self.ptr_ty, size as i64))
+            }
+            _ => None,
         }
     }
 
-    fn block(
-        &mut self,
-        module: Mod,
-        from: Inst,
-        return_block: Option<Block>,
-        can_inline: bool,
+    fn call_unary_builtin(
+        &self,
+        name: Span,
+        arg: Local,
+        ret: PackedOption<Ty>,
         builder: &mut FunctionBuilder,
-    ) -> Option<Inst> {
-        let mut call_buffer = self.context.pool.get();
-        let mut arg_buffer = self.context.pool.get();
-
-        let mut current = PackedOption::from(from);
-
-        while current.is_some() {
-            let module_ent = &self.modules[module];
-            let InstEnt {
-                value, next, kind, ..
-            } = module_ent.insts[current.unwrap()];
-
-            match kind {
-                IKind::Call(id, args) => {
-                    let FunEnt {
-                        module: other_module,
-                        name,
-                        sig,
-                        kind,
-                        inline,
-                        ..
-                    } = self.funs[id];
-
-                    arg_buffer.clear();
-                    arg_buffer.extend_from_slice(module_ent.values(args));
-
-                    if let FKind::Builtin = kind {
-                        let name = name.clone();
-                        let return_type = sig.ret;
-                        self.call_builtin(module, name, return_type, &arg_buffer, value, builder);
-                    } else if other_module == Mod::new(0)
-                        && matches!(self.state.display(&name), "sizeof")
-                    {
-                        self.call_generic_builtin(id, &arg_buffer, value, builder);
-                    } else {
-                        let inline = inline && can_inline;
-                        let fun_id = self.compiled_funs[id].id;
-                        let map_id = ID(fun_id.as_u32() as u64);
-
-                        let mut struct_return = false;
-
-                        if sig.ret.is_some() {
-                            let ret_ty = sig.ret.unwrap();
-                            if self.on_stack(ret_ty) {
-                                let size = self.types[ret_ty].size;
-                                let last = arg_buffer[arg_buffer.len() - 1];
-                                let data = StackSlotData::new(
-                                    StackSlotKind::ExplicitSlot,
-                                    align(size.pick(self.s32)),
-                                );
-                                let slot = builder.create_stack_slot(data);
-                                self.context.set_value(last, FinalValue::StackSlot(slot));
-                                self.context
-                                    .set_value(value.unwrap(), FinalValue::StackSlot(slot));
-                                struct_return = true;
-                            }
-                        }
-
-                        if inline {
-                            return Some(current.unwrap());
-                        } else {
-                            let fun_ref =
-                                if let Some(&fun_ref) = self.context.imported_funs.get(map_id) {
-                                    fun_ref
-                                } else {
-                                    let fun_ref =
-                                        self.module.declare_func_in_func(fun_id, builder.func);
-                                    self.context.imported_funs.insert(map_id, fun_ref);
-                                    fun_ref
-                                };
-
-                            call_buffer.clear();
-                            call_buffer.extend(
-                                arg_buffer
-                                    .iter()
-                                    .map(|&a| self.unwrap_val(module, a, builder)),
-                            );
-
-                            let inst = builder.ins().call(fun_ref, &call_buffer);
-
-                            if !struct_return {
-                                if value.is_some() {
-                                    let val = builder.inst_results(inst)[0];
-                                    self.context
-                                        .set_value(value.unwrap(), FinalValue::Value(val));
-                                }
-                            }
-                        }
-                    }
-                }
-                IKind::VarDecl(init) => {
-                    let value = value.unwrap();
-                    let ValueEnt {
-                        on_stack, mutable, ty, ..
-                    } = module_ent.values[value];
-                    let size = self.types[ty].size.pick(self.s32);
-                    if on_stack || self.on_stack(ty) {
-                        let data = StackSlotData::new(StackSlotKind::ExplicitSlot, align(size));
-                        let slot = builder.create_stack_slot(data);
-                        self.context.set_value(value, FinalValue::StackSlot(slot));
-                        self.assign_val(module, value, init, builder)
-                    } else if mutable {
-                        let var = Variable::new(value.index());
-                        self.context.set_value(value, FinalValue::Var(var));
-                        builder.declare_var(var, self.repr(ty));
-                        self.assign_val(module, value, init, builder);
-                    } else {
-                        let val = self.unwrap_val(module, init, builder);
-                        self.context.set_value(value, FinalValue::Value(val));
+    ) -> Option<Value> {
+        let name = self.display(name);
+        let LocalEnt { ty, mutable, .. } = *self.data().local(arg);
+        let kind = BTKind::of(ty)?;
+        let a = self.unwrap_val(arg, builder);
+        let to = self.repr(ret.expand()?);
+
+        let value = match kind {
+            BTKind::Int | BTKind::Uint => match (name, kind) {
+                ("+", _) => a,
+                ("-", BTKind::Int) => builder.ins().ineg(a),
+                ("~", _) => builder.ins().bnot(a),
+                ("++" | "--", _) => {
+                    let value = builder.ins().iadd_imm(a, if name == "++" { 1 } else { -1 });
+                    if mutable {
+                        self.assign_value(arg, value, builder);
                     }
+                    value
                 }
-                IKind::Zeroed => {
-                    self.context.set_value(value.unwrap(), FinalValue::Zero);
+                ("abs", BTKind::Int) => {
+                    let condition = builder.ins().icmp_imm(IntCC::SignedGreaterThan, a, 0);
+                    let neg = builder.ins().ineg(a);
+                    builder.ins().select(condition, a, neg)
                 }
-                IKind::Uninitialized => {
-                    let value = value.unwrap();
-                    let ty = module_ent.type_of_value(value);
-                    if self.on_stack(ty) {
-                        let size = self.types[ty].size.pick(self.s32);
-                        let data = StackSlotData::new(StackSlotKind::ExplicitSlot, align(size));
-                        let slot = builder.create_stack_slot(data);
-                        self.context.set_value(value, FinalValue::StackSlot(slot));
+                ("f32" | "f64", BTKind::Int) => builder.ins().fcvt_from_sint(to, a),
+                ("f32" | "f64", BTKind::Uint) => builder.ins().fcvt_from_uint(to, a),
+                ("bool", _) => builder.ins().icmp_imm(IntCC::NotEqual, a, 0),
+                (
+                    "i8" | "i16" | "i32" | "i64" | "int" | "u8" | "u16" | "u32" | "u64" | "uint"
+                    | "char",
+                    _,
+                ) => {
+                    let from = builder.func.dfg.value_type(a);
+                    if to.bytes() < from.bytes() {
+                        builder.ins().ireduce(to, a)
+                    } else if to.bytes() == from.bytes() {
+                        a
+                    } else if kind == BTKind::Int {
+                        builder.ins().sextend(to, a)
                     } else {
-                        self.context.set_value(value, FinalValue::Zero);
+                        builder.ins().uextend(to, a)
                     }
                 }
-                IKind::Lit(lit) => {
-                    let value = value.unwrap();
-                    let ty = module_ent.type_of_value(value);
-                    let repr = self.repr(ty);
-                    let lit = match lit {
-                        LTKind::Int(val, _) => builder.ins().iconst(repr, val),
-                        LTKind::Uint(val, _) => builder.ins().iconst(repr, val as i64),
-                        LTKind::Float(val, _) => {
-                            if repr == F32 {
-                                builder.ins().f32const(val as f32)
-                            } else {
-                                builder.ins().f64const(val)
-                            }
-                        }
-                        LTKind::Bool(val) => builder.ins().bconst(B1, val),
-                        LTKind::Char(val) => builder.ins().iconst(I32, val as i64),
-                        LTKind::String(string) => {
-                            let data = unsafe {
-                                std::mem::transmute::<&[u8], &[u8]>(
-                                    self.state.display(&string).as_bytes(),
-                                )
-                            };
-                            let data = self.make_static_data(data, false, false);
-                            self.add_anon_string(module, data, string);
-                            let value = self.declare_data_in_func(data, builder.func);
-                            builder.ins().global_value(repr, value)
-                        }
-                        lit => todo!("{:?}", lit),
-                    };
-                    self.context.set_value(value, FinalValue::Value(lit));
-                }
-                IKind::Return(value) => {
-                    if let Some(return_block) = return_block {
-                        if value.is_some() {
-                            let value = self.unwrap_val(module, value.unwrap(), builder);
-                            builder.ins().jump(return_block, &[value]);
-                        } else {
-                            builder.ins().jump(return_block, &[]);
-                        }
-                    } else {                        
-                        if value.is_some() {
-                            let value = self.unwrap_val(module, value.unwrap(), builder);
-                            builder.ins().return_(&[value]);
-                        } else {
-                            builder.ins().return_(&[]);
-                        }
-                    }
-                }
-                IKind::Assign(other) => {
-                    self.assign_val(module, other, value.unwrap(), builder);
+                _ => return None,
+            },
+            BTKind::Float(bits) => match name {
+                "+" => a,
+                "-" => builder.ins().fneg(a),
+                "abs" => builder.ins().fabs(a),
+                "u8" | "u16" | "u32" | "u64" | "uint" | "char" => builder.ins().fcvt_to_uint(to, a),
+                "i8" | "i16" | "i32" | "i64" | "int" => builder.ins().fcvt_to_sint(to, a),
+                "f32" if bits == 64 => builder.ins().fdemote(F32, a),
+                "f64" if bits == 32 => builder.ins().fpromote(F64, a),
+                "f32" | "f64" => a,
+                "bool" => {
+                    let zero = self.zero(builder.func.dfg.value_type(a), builder);
+                    builder.ins().fcmp(FloatCC::NotEqual, a, zero)
                 }
-                IKind::Jump(block, values) => {
-                    call_buffer.clear();
-                    call_buffer.extend(
-                        module_ent
-                            .values(values)
-                            .iter()
-                            .map(|&a| self.unwrap_val(module, a, builder)),
-                    );
-                    let block = self.context.block(block);
-                    builder.ins().jump(block, &call_buffer);
+                _ => return None,
+            },
+            BTKind::Bool => match name {
+                "!" => {
+                    let value = builder.ins().bint(I8, a);
+                    builder.ins().icmp_imm(IntCC::Equal, value, 0)
                 }
-                IKind::JumpIfTrue(condition, block, values) => {
-                    call_buffer.clear();
-                    call_buffer.extend(
-                        module_ent
-                            .values(values)
-                            .iter()
-                            .map(|&a| self.unwrap_val(module, a, builder)),
-                    );
-                    let block = self.context.block(block);
-                    let value = self.unwrap_val(module, condition, builder);
-                    builder.ins().brnz(value, block, &call_buffer);
+                "i8" | "i16" | "i32" | "i64" | "int" | "u8" | "u16" | "u32" | "u64" | "uint"
+                | "char" => builder.ins().bint(to, a),
+                "f32" | "f64" => {
+                    let value = builder.ins().bint(I32, a);
+                    builder.ins().fcvt_from_uint(to, value)
                 }
-                IKind::Offset(other) => self.pass(other, value.unwrap()),
-                IKind::Deref(other, assign) => {
-                    let value = value.unwrap();
-                    let val = self.unwrap_val_low(module, other, builder, assign);
-                    self.context.set_value(value, FinalValue::Pointer(val));
-                }
-                IKind::Ref(val) => {
-                    let val = self.unwrap_val_low(module, val, builder, true);
-                    self.context
-                        .set_value(value.unwrap(), FinalValue::Value(val));
-                }
-                IKind::Cast(other) => {
-                    let value = value.unwrap();
-                    let ValueEnt { on_stack, ty, .. } = module_ent.values[value];
-                    if on_stack || self.on_stack(ty) {
-                        self.pass(other, value);
-                    } else {
-                        let target = self.repr(ty);
-                        let other_ty = self.repr(module_ent.values[other].ty);
-                        if other_ty != target {
-                            let other_value = self.unwrap_val(module, other, builder);
-                            let val = builder.ins().bitcast(target, other_value);
-                            self.context.set_value(value, FinalValue::Value(val));
-                        } else {
-                            self.pass(other, value);
-                        }
-                    }
-                }
-                IKind::GlobalLoad(global) => {
-                    let id = self.compiled_globals[global].id;
-                    let ty = self.globals[global].ty;
-                    let map_id = ID(id.as_u32() as u64);
+                "bool" => a,
+                _ => return None,
+            },
+        };
 
-                    let data = if let Some(&data) = self.context.imported_globals.get(map_id) {
-                        data
-                    } else {
-                        let data = self.module.declare_data_in_func(id, builder.func);
-                        self.context.imported_globals.insert(map_id, data);
-                        data
+        Some(value)
+    }
+
+    fn call_binary_builtin(
+        &self,
+        name: Span,
+        a: Local,
+        b: Local,
+        builder: &mut FunctionBuilder,
+    ) -> Option<Value> {
+        let name = self.display(name);
+        let kind = BTKind::of(self.data().local(a).ty)?;
+        let a = self.unwrap_val(a, builder);
+        let b = self.unwrap_val(b, builder);
+
+        let value = match kind {
+            BTKind::Int | BTKind::Uint => match (name, kind) {
+                ("+", _) => builder.ins().iadd(a, b),
+                ("-", _) => builder.ins().isub(a, b),
+                ("*", _) => builder.ins().imul(a, b),
+                ("<<", _) => builder.ins().ishl(a, b),
+
+                ("min", _) => builder.ins().imin(a, b),
+                ("max", _) => builder.ins().imax(a, b),
+
+                ("/", BTKind::Int) => builder.ins().sdiv(a, b),
+                ("%", BTKind::Int) => builder.ins().srem(a, b),
+                (">>", BTKind::Int) => builder.ins().sshr(a, b),
+
+                ("/", BTKind::Uint) => builder.ins().udiv(a, b),
+                ("%", BTKind::Uint) => builder.ins().urem(a, b),
+                (">>", BTKind::Uint) => builder.ins().ushr(a, b),
+
+                ("&", _) => builder.ins().band(a, b),
+                ("|", _) => builder.ins().bor(a, b),
+                ("^", _) => builder.ins().bxor(a, b),
+
+                ("==" | "!=" | ">=" | "<=" | "<" | ">", _) => {
+                    let strategy = match (name, kind) {
+                        ("==", _) => IntCC::Equal,
+                        ("!=", _) => IntCC::NotEqual,
+                        ("<", BTKind::Int) => IntCC::SignedLessThan,
+                        ("<", _) => IntCC::UnsignedLessThan,
+                        (">", BTKind::Int) => IntCC::SignedGreaterThan,
+                        (">", _) => IntCC::UnsignedGreaterThan,
+                        ("<=", BTKind::Int) => IntCC::SignedLessThanOrEqual,
+                        ("<=", _) => IntCC::UnsignedLessThanOrEqual,
+                        (">=", BTKind::Int) => IntCC::SignedGreaterThanOrEqual,
+                        (">=", _) => IntCC::UnsignedGreaterThanOrEqual,
+                        _ => unreachable!(),
                     };
-                    let val = builder.ins().global_value(self.repr(ty), data);
-                    self.context
-                        .set_value(value.unwrap(), FinalValue::Pointer(val))
+
+                    builder.ins().icmp(strategy, a, b)
                 }
-                IKind::FunPointer(id) => {
-                    let fun_id = self.compiled_funs[id].id;
-                    let map_id = ID(fun_id.as_u32() as u64);
 
-                    let fun_ref = if let Some(&fun_ref) = self.context.imported_funs.get(map_id) {
-                        fun_ref
-                    } else {
-                        let fun_ref = self.module.declare_func_in_func(fun_id, builder.func);
-                        self.context.imported_funs.insert(map_id, fun_ref);
-                        fun_ref
+                _ => return None,
+            },
+            BTKind::Float(_) => match name {
+                "+" => builder.ins().fadd(a, b),
+                "-" => builder.ins().fsub(a, b),
+                "*" => builder.ins().fmul(a, b),
+                "/" => builder.ins().fdiv(a, b),
+
+                "max" => builder.ins().fmax(a, b),
+                "min" => builder.ins().fmin(a, b),
+
+                "==" | "!=" | ">=" | "<=" | "<" | ">" => {
+                    let strategy = match name {
+                        "==" => FloatCC::Equal,
+                        "!=" => FloatCC::NotEqual,
+                        "<" => FloatCC::LessThan,
+                        ">" => FloatCC::GreaterThan,
+                        "<=" => FloatCC::LessThanOrEqual,
+                        ">=" => FloatCC::GreaterThanOrEqual,
+                        _ => unreachable!(),
                     };
 
-                    let pointer = builder.ins().func_addr(self.ptr_ty, fun_ref);
-
-                    self.context
-                        .set_value(value.unwrap(), FinalValue::Value(pointer));
+                    builder.ins().fcmp(strategy, a, b)
                 }
-                IKind::FunPointerCall(pointer, args) => {
-                    let pointer_type = module_ent.type_of_value(pointer);
-                    let pointer = self.unwrap_val(module, pointer, builder);
-                    call_buffer.clear();
-                    call_buffer.extend(
-                        module_ent
-                            .values(args)
-                            .iter()
-                            .map(|&a| self.unwrap_val(module, a, builder)),
-                    );
-
-                    let mut struct_return = false;
-                    if value.is_some() {
-                        let value = value.unwrap();
-                        let ret_ty = module_ent.type_of_value(value);
-                        if self.on_stack(ret_ty) {
-                            let size = self.types[ret_ty].size.pick(self.s32);
-                            let last = arg_buffer[arg_buffer.len() - 1];
-                            let data = StackSlotData::new(StackSlotKind::ExplicitSlot, align(size));
-                            let slot = builder.create_stack_slot(data);
-                            self.context.set_value(last, FinalValue::StackSlot(slot));
-                            self.context.set_value(value, FinalValue::StackSlot(slot));
-                            struct_return = true;
-                        }
-                    }
 
-                    let TypeEnt {
-                        id,
-                        kind,
-                        module: ptr_module,
-                        ..
-                    } = &self.types[pointer_type];
-                    let id = *id;
-                    let sig = if let Some(&sig) = self.context.imported_signatures.get(id) {
-                        sig
+                _ => return None,
+            },
+            BTKind::Bool => match name {
+                "|" | "||" => builder.ins().bor(a, b),
+                "&" | "&&" => builder.ins().band(a, b),
+                "^" => builder.ins().bxor(a, b),
+                "==" | "!=" => {
+                    let strategy = if name == "==" {
+                        IntCC::Equal
                     } else {
-                        let ptr_ty = kind.fun_pointer();
-                        let mut sig = CrSignature::new(CrCallConv::Fast);
-                        ptr_ty.to_cr_signature(
-                            *ptr_module,
-                            self.state,
-                            self.module.isa(),
-                            &mut sig,
-                        );
-                        let sig = builder.import_signature(sig);
-
-                        self.context.imported_signatures.insert(id, sig);
-                        sig
+                        IntCC::NotEqual
                     };
 
-                    let call = builder.ins().call_indirect(sig, pointer, &call_buffer);
-                    if !struct_return {
-                        if value.is_some() {
-                            self.context.set_value(
-                                value.unwrap(),
-                                FinalValue::Value(builder.inst_results(call)[0]),
-                            );
-                        }
-                    }
-                }
-                value => unreachable!("{:?}", value),
-            }
+                    let a = builder.ins().bint(I8, a);
+                    let b = builder.ins().bint(I8, b);
 
-            current = next;
-        }
+                    builder.ins().icmp(strategy, a, b)
+                }
+                _ => return None,
+            },
+        };
 
-        None
+        Some(value)
     }
 
-    fn call_generic_builtin(
-        &mut self,
-        other: Fun,
-        _args: &[Value],
-        target: PackedOption<Value>,
-        builder: &mut FunctionBuilder,
-    ) {
-        let FunEnt {
-            name,
-            module,
-            params,
-            ..
-        } = self.state.funs[other];
-        let module_ent = &self.modules[module];
-        match self.state.display(&name) {
-            "sizeof" => {
-                let value = target.unwrap();
-                let size = self.types[module_ent.type_slice(params)[0]]
-                    .size
-                    .pick(self.s32);
-                let size = builder.ins().iconst(self.ptr_ty, size as i64);
-                self.context.set_value(value, FinalValue::Value(size));
-            }
-            name => unreachable!("{}", name),
+    fn cast(&mut self, target: Local, result: Local, builder: &mut FunctionBuilder) {
+        let ty = self.data().local(result).ty;
+        let repr = self.repr(ty);
+        let target_ty = self.data().local(target).ty;
+        if self.on_stack(ty) || repr == self.repr(target_ty) {
+            self.values[result] = self.values[target];
+            self.offsets[result] = self.offsets[target];
+        } else {
+            let value = self.unwrap_val(target, builder);
+            let value = self.convert(value, repr, builder);
+            self.set_value(result, FinalValue::Value(value));
         }
     }
 
-    fn call_builtin(
-        &mut self,
-        module: Mod,
-        name: Span,
-        return_type: PackedOption<Ty>,
-        args: &[Value],
-        target: PackedOption<Value>,
-        builder: &mut FunctionBuilder,
-    ) {
-        let module_ent = &self.modules[module];
-        let name = self.state.display(&name);
-        match args.len() {
-            1 => {
-                let a = self.unwrap_val(module, args[0], builder);
-                let val = &module_ent.values[args[0]];
-                let ty = val.ty;
-                let kind = BTKind::of(ty);
-                let repr = self.repr(ty);
-
-                let value = match kind {
-                    BTKind::Int | BTKind::Uint => match (name, kind) {
-                        ("+", _) => a,
-                        ("-", BTKind::Int) => builder.ins().ineg(a),
-                        ("~", _) => builder.ins().bnot(a),
-                        ("++" | "--", _) => {
-                            let value =
-                                builder.ins().iadd_imm(a, if name == "++" { 1 } else { -1 });
-                            if val.mutable {
-                                self.assign_raw_val(module, args[0], value, builder)
-                            }
-                            value
-                        }
-                        ("f32", BTKind::Int) => builder.ins().fcvt_from_sint(F32, a),
-                        ("f64", BTKind::Int) => builder.ins().fcvt_from_sint(F64, a),
-                        ("f32", BTKind::Uint) => builder.ins().fcvt_from_sint(F32, a),
-                        ("f64", BTKind::Uint) => builder.ins().fcvt_from_sint(F64, a),
-
-                        ("abs", BTKind::Int) => {
-                            let condition = builder.ins().icmp_imm(IntCC::SignedGreaterThan, a, 0);
-                            let neg = builder.ins().ineg(a);
-                            builder.ins().select(condition, a, neg)
-                        }
-                        ("bool", _) => builder.ins().icmp_imm(IntCC::NotEqual, a, 0),
-                        ("i8" | "i16" | "i32" | "i64" | "int", _) => {
-                            let to = self.repr(return_type.unwrap());
-                            match to.bytes().cmp(&repr.bytes()) {
-                                std::cmp::Ordering::Less => builder.ins().ireduce(to, a),
-                                std::cmp::Ordering::Equal => a,
-                                std::cmp::Ordering::Greater => builder.ins().sextend(to, a),
-                            }
-                        }
-                        ("u8" | "u16" | "u32" | "u64" | "uint", _) => {
-                            let to = self.repr(return_type.unwrap());
-                            match to.bytes().cmp(&repr.bytes()) {
-                                std::cmp::Ordering::Less => builder.ins().ireduce(to, a),
-                                std::cmp::Ordering::Equal => a,
-                                std::cmp::Ordering::Greater => builder.ins().uextend(to, a),
-                            }
-                        }
-                        name => todo!("{:?}", name),
-                    },
-                    BTKind::Float(bits) => {
-                        let target = if bits == 32 { F32 } else { F64 };
-                        match name {
-                            "+" => a,
-                            "-" => builder.ins().fneg(a),
-                            "abs" => builder.ins().fabs(a),
-                            "u8" | "u16" | "u32" | "u64" | "uint" | "ptr" => {
-                                let to = self.repr(return_type.unwrap());
-                                builder.ins().fcvt_to_uint(to, a)
-                            }
-                            "i8" | "i16" | "i32" | "i64" | "int" => {
-                                let to = self.repr(return_type.unwrap());
-                                builder.ins().fcvt_to_sint(to, a)
-                            }
-                            "f32" => {
-                                if target != F32 {
-                                    builder.ins().fpromote(target, a)
-                                } else {
-                                    a
-                                }
-                            }
-                            "f64" => {
-                                if target != F64 {
-                                    builder.ins().fdemote(target, a)
-                                } else {
-                                    a
-                                }
-                            }
-                            "bool" => {
-                                let value = builder.ins().f32const(0.0);
-                                builder.ins().fcmp(FloatCC::NotEqual, a, value)
-                            }
-                            todo => todo!("{}", todo),
-                        }
-                    }
-                    BTKind::Bool => match name {
-                        "!" => {
-                            let value = builder.ins().bint(I8, a);
-                            builder.ins().icmp_imm(IntCC::Equal, value, 0)
-                        }
-                        "i64" | "u64" => builder.ins().bint(I64, a),
-                        "i32" | "u32" => builder.ins().bint(I32, a),
-                        "i16" | "u16" => builder.ins().bint(I16, a),
-                        "i8" | "u8" => builder.ins().bint(I8, a),
-                        "uint" | "int" => builder.ins().bint(self.ptr_ty, a),
-                        "f32" => {
-                            let value = builder.ins().bint(I32, a);
-                            builder.ins().fcvt_from_uint(F32, value)
-                        }
-                        "f64" => {
-                            let value = builder.ins().bint(I64, a);
-                            builder.ins().fcvt_from_uint(F64, value)
-                        }
-                        "bool" => a,
-                        name => todo!("{}", name),
-                    },
-                };
-
-                self.context
-                    .set_value(target.unwrap(), FinalValue::Value(value));
+    fn assign(&self, target: Local, source: Local, builder: &mut FunctionBuilder) {
+        match self.values[target] {
+            FinalValue::Var(_) => {
+                let value = self.unwrap_val(source, builder);
+                self.assign_value(target, value, builder);
             }
-            2 => {
-                let ty = module_ent.type_of_value(args[0]);
-                let a = self.unwrap_val(module, args[0], builder);
-                let b = self.unwrap_val(module, args[1], builder);
-                let kind = BTKind::of(ty);
-                let value = match kind {
-                    BTKind::Int | BTKind::Uint => match (name, kind) {
-                        ("+", _) => builder.ins().iadd(a, b),
-                        ("-", _) => builder.ins().isub(a, b),
-                        ("*", _) => builder.ins().imul(a, b),
-                        ("<<", _) => builder.ins().ishl(a, b),
-
-                        ("min", _) => builder.ins().imin(a, b),
-                        ("max", _) => builder.ins().imax(a, b),
-
-                        ("/", BTKind::Int) => builder.ins().sdiv(a, b),
-                        ("%", BTKind::Int) => builder.ins().srem(a, b),
-                        (">>", BTKind::Int) => builder.ins().sshr(a, b),
-
-                        ("/", BTKind::Uint) => builder.ins().udiv(a, b),
-                        ("%", BTKind::Uint) => builder.ins().urem(a, b),
-                        (">>", BTKind::Uint) => builder.ins().ushr(a, b),
-
-                        ("&", _) => builder.ins().band(a, b),
-                        ("|", _) => builder.ins().bor(a, b),
-                        ("^", _) => builder.ins().bxor(a, b),
-
-                        ("==" | "!=" | ">=" | "<=" | "<" | ">", _) => {
-                            let strategy = match (name, kind) {
-                                ("==", _) => IntCC::Equal,
-                                ("!=", _) => IntCC::NotEqual,
-                                ("<", BTKind::Int) => IntCC::SignedLessThan,
-                                ("<", BTKind::Uint) => IntCC::UnsignedLessThan,
-                                (">", BTKind::Int) => IntCC::SignedGreaterThan,
-                                (">", BTKind::Uint) => IntCC::UnsignedGreaterThan,
-                                ("<=", BTKind::Int) => IntCC::SignedLessThanOrEqual,
-                                ("<=", BTKind::Uint) => IntCC::UnsignedLessThanOrEqual,
-                                (">=", BTKind::Int) => IntCC::SignedGreaterThanOrEqual,
-                                (">=", BTKind::Uint) => IntCC::UnsignedGreaterThanOrEqual,
-                                _ => unreachable!(),
-                            };
-
-                            builder.ins().icmp(strategy, a, b)
-                        }
-
-                        name => todo!("{:?}", name),
-                    },
-                    BTKind::Float(_) => match name {
-                        "+" => builder.ins().fadd(a, b),
-                        "-" => builder.ins().fsub(a, b),
-                        "*" => builder.ins().fmul(a, b),
-                        "/" => builder.ins().fdiv(a, b),
-
-                        "max" => builder.ins().fmax(a, b),
-                        "min" => builder.ins().fmin(a, b),
-
-                        "==" | "!=" | ">=" | "<=" | "<" | ">" => {
-                            let strategy = match name {
-                                "==" => FloatCC::Equal,
-                                "!=" => FloatCC::NotEqual,
-                                "<" => FloatCC::LessThan,
-                                ">" => FloatCC::GreaterThan,
-                                "<=" => FloatCC::LessThanOrEqual,
-                                ">=" => FloatCC::GreaterThanOrEqual,
-                                _ => unreachable!(),
-                            };
-
-                            builder.ins().fcmp(strategy, a, b)
-                        }
-
-                        name => todo!("{:?}", name),
-                    },
-                    BTKind::Bool => match name {
-                        "|" => builder.ins().bor(a, b),
-                        "&" => builder.ins().band(a, b),
-                        "^" => builder.ins().bxor(a, b),
-                        "==" | "!=" => {
-                            let strategy = match name {
-                                "==" => IntCC::Equal,
-                                "!=" => IntCC::NotEqual,
-                                _ => unreachable!(),
-                            };
-
-                            let a = builder.ins().bint(I8, a);
-                            let b = builder.ins().bint(I8, b);
-
-                            builder.ins().icmp(strategy, a, b)
-                        }
-                        name => todo!("{:?}", name),
-                    },
-                };
-                self.context
-                    .set_value(target.unwrap(), FinalValue::Value(value));
+            FinalValue::StackSlot(_) | FinalValue::Pointer(_) => {
+                let address = self.address(target, builder);
+                self.write(address, source, builder);
             }
-            len => todo!("{}", len),
+            value => unreachable!("{:?}", value),
         }
     }
 
-    fn assign_val(
-        &self,
-        module: Mod,
-        target_value: Value,
-        source_value: Value,
-        builder: &mut FunctionBuilder,
-    ) {
-        let module_ent = &self.modules[module];
-
-        let target = module_ent.values[target_value];
-        let final_target_value = self.context.value(target_value);
-        let source = module_ent.values[source_value];
-        let final_source_value = self.context.value(source_value);
-        let ty = source.ty;
-
-        debug_assert!(
-            target.ty == ty,
-            "{} {} {:?} {:?}",
-            TypeDisplay::new(&self.state, ty),
-            TypeDisplay::new(&self.state, target.ty),
-            target,
-            module_ent.insts[source.inst.unwrap()].kind
-        );
-
-        match final_target_value {
-            FinalValue::StackSlot(slot) => {
-                let size = self.types[ty].size.pick(self.s32);
-                match final_source_value {
-                    FinalValue::Zero => {
-                        static_stack_memset(slot, target.offset.pick(self.s32), 0, size, builder)
-                    }
-                    FinalValue::Var(_) | FinalValue::Value(_) => {
-                        let value = match final_source_value {
-                            FinalValue::Var(var) => builder.use_var(var),
-                            FinalValue::Value(value) => value,
-                            _ => unreachable!(),
-                        };
-                        let value = if target.ty == self.state.builtin_repo.bool {
-                            builder.ins().bint(I8, value)
-                        } else {
-                            value
-                        };
-                        static_value_stack_memcpy(
-                            slot, 
-                            target.offset.pick(self.s32), 
-                            value,
-                            size,
-                            builder,
-                        );
-                    }
-                    FinalValue::StackSlot(other) => static_stack_memcpy(
-                        slot,
-                        target.offset.pick(self.s32),
-                        other,
-                        source.offset.pick(self.s32),
-                        size,
-                        builder,
-                    ),
-                    FinalValue::Pointer(pointer) => {
-                        let pt = self.ptr_ty;
-                        let target_ptr = builder.ins().stack_addr(pt, slot, 0);
-                        static_memcpy(
-                            target_ptr,
-                            target.offset.pick(self.s32),
-                            pointer,
-                            source.offset.pick(self.s32),
-                            size,
-                            builder,
-                        )
-                    }
-                    FinalValue::None => unreachable!(),
-                }
-            }
+    /// Assigns already unwrapped `value` to the `target`.
+    fn assign_value(&self, target: Local, value: Value, builder: &mut FunctionBuilder) {
+        let ty = self.data().local(target).ty;
+        match self.values[target] {
             FinalValue::Var(var) => {
-                let mut value = self.unwrap_val(module, source_value, builder);
-                let value_type = builder.func.dfg.value_type(value);
-                let val = builder.use_var(var);
-                let var_type = builder.func.dfg.value_type(val);
-                if value_type != var_type {
-                    let mut mask = 0i64;
-                    for i in 0..value_type.bytes() {
-                        mask |= 0xFF << (i * 8);
-                    }
-                    mask <<= target.offset.pick(self.s32) * 8;
-                    mask = !mask;
-                    let mask_value = builder.ins().iconst(var_type, mask);
-                    let target_val = builder.ins().band(val, mask_value);
-                    let source_value = builder.ins().uextend(var_type, value);
-                    let source_value = builder
-                        .ins()
-                        .ishl_imm(source_value, target.offset.pick(self.s32) as i64 * 8);
-                    value = builder.ins().bor(target_val, source_value);
-                }
+                let current = builder.use_var(var);
+                let value = self.insert(current, value, self.offsets[target], ty, builder);
                 builder.def_var(var, value);
             }
-            FinalValue::Pointer(pointer) => {
-                let size = self.types[ty].size.pick(self.s32);
-                if final_source_value == FinalValue::Zero {
-                    static_memset(
-                        pointer,
-                        target.offset.pick(self.s32),
-                        0,
-                        size,
-                        builder,
-                    );
-                } else {
-                    let mut value = self.unwrap_val(module, source_value, builder);
-                    if source.on_stack || self.on_stack(source.ty) {
-                        static_memcpy(
-                            pointer,
-                            target.offset.pick(self.s32),
-                            value,
-                            source.offset.pick(self.s32),
-                            size,
-                            builder,
-                        );
-                    } else {
-                        if target.ty == self.state.builtin_repo.bool {
-                            value = builder.ins().bint(I8, value)
-                        }
-                        static_value_memcpy(
-                            pointer, 
-                            target.offset.pick(self.s32), 
-                            value,
-                            size,
-                            builder,
-                        );
-                    }
-                }
+            FinalValue::StackSlot(_) | FinalValue::Pointer(_) => {
+                let address = self.address(target, builder);
+                store_value(address, value, self.size_of(ty), builder);
             }
-            kind => {
-                unreachable!("{:?} {:?}", kind, target_value)
-            }
-        };
+            value => unreachable!("{:?}", value),
+        }
     }
 
-    fn assign_raw_val(
-        &mut self,
-        module: Mod,
-        target: Value,
-        mut source: Value,
-        builder: &mut FunctionBuilder,
-    ) {
-        let target_value = self.context.value(target);
-        let target = &self.modules[module].values[target];
+    /// Copies value of `source` to memory at `dst`.
+    fn write(&self, dst: Value, source: Local, builder: &mut FunctionBuilder) {
+        let ty = self.data().local(source).ty;
+        let size = self.size_of(ty);
+        if self.values[source] == FinalValue::Zero {
+            static_memset(dst, size, builder);
+        } else if self.on_stack(ty) {
+            let src = self.address(source, builder);
+            static_memcpy(dst, src, size, builder);
+        } else {
+            let value = self.unwrap_val(source, builder);
+            store_value(dst, value, size, builder);
+        }
+    }
 
-        match target_value {
+    /// Returns address of the local, values held in registers are
+    /// spilled to the stack.
+    fn address(&self, local: Local, builder: &mut FunctionBuilder) -> Value {
+        let offset = self.offsets[local];
+        match self.values[local] {
             FinalValue::StackSlot(slot) => {
-                if target.ty == self.state.builtin_repo.bool {
-                    source = builder.ins().bint(I8, source)
-                }
-                builder
-                    .ins()
-                    .stack_store(source, slot, target.offset.pick(self.s32) as i32);
+                builder.ins().stack_addr(self.ptr_ty, slot, offset as i32)
             }
-            FinalValue::Pointer(pointer) => {
-                if target.ty == self.state.builtin_repo.bool {
-                    source = builder.ins().bint(I8, source)
-                }
-                builder.ins().store(
-                    MemFlags::new(),
-                    source,
-                    pointer,
-                    target.offset.pick(self.s32) as i32,
-                );
-            }
-            FinalValue::Var(var) => {
-                builder.def_var(var, source);
-            }
-            _ => unreachable!(),
-        };
-    }
-
-    fn unwrap_val(&self, module: Mod, value: Value, builder: &mut FunctionBuilder) -> Value {
-        self.unwrap_val_low(module, value, builder, false)
-    }
-
-    fn unwrap_val_low(
-        &self,
-        module: Mod,
-        value: Value,
-        builder: &mut FunctionBuilder,
-        assign: bool,
-    ) -> Value {
-        let module_ent = &self.modules[module];
-        let value_value = self.context.value(value);
-        let value = module_ent.values[value];
-        let ty = value.ty;
-        match value_value {
+            FinalValue::Pointer(pointer) if offset == 0 => pointer,
+            FinalValue::Pointer(pointer) => builder.ins().iadd_imm(pointer, offset as i64),
             FinalValue::None => unreachable!(),
-            FinalValue::Zero => {
-                let repr = self.repr(ty);
-                match self.types[ty].kind {
-                    TKind::Pointer(..) => builder.ins().null(repr),
-                    TKind::Structure(..) => builder.ins().iconst(repr, 0),
-                    TKind::Array(..) => builder.ins().iconst(repr, 0),
-                    _ => match BTKind::of(ty) {
-                        BTKind::Int | BTKind::Uint => builder.ins().iconst(repr, 0),
-                        BTKind::Float(32) => builder.ins().f32const(0.0),
-                        BTKind::Float(64) => builder.ins().f64const(0.0),
-                        BTKind::Bool => builder.ins().bconst(B1, false),
-                        _ => unreachable!("{:?}", ty),
-                    },
-                }
-            }
-            FinalValue::Value(value) => value,
-            FinalValue::Var(var) => {
-                let repr = self.repr(ty);
-                let mut val = builder.use_var(var);
-                let value_type = builder.func.dfg.value_type(val);
-                if repr != value_type {
-                    val = builder
-                        .ins()
-                        .ushr_imm(val, value.offset.pick(self.s32) as i64 * 8);
-                    val = builder.ins().ireduce(repr, val);
-                }
-                val
-            }
-            FinalValue::Pointer(pointer) => {
-                if !assign && (!value.on_stack || !self.on_stack(value.ty)) {
-                    let repr = self.repr(ty);
-                    builder.ins().load(
-                        repr,
-                        MemFlags::new(),
-                        pointer,
-                        value.offset.pick(self.s32) as i32,
-                    )
-                } else if value.offset != Size::ZERO {
-                    let ptr_type = self.ptr_ty;
-                    let offset = builder
-                        .ins()
-                        .iconst(ptr_type, value.offset.pick(self.s32) as i64);
-                    builder.ins().iadd(pointer, offset)
-                } else {
-                    pointer
-                }
-            }
-            FinalValue::StackSlot(slot) => {
-                if !assign && !self.on_stack(value.ty) {
-                    if value.ty == self.state.builtin_repo.bool {
-                        let value =
-                            builder
-                                .ins()
-                                .stack_load(I8, slot, value.offset.pick(self.s32) as i32);
-                        builder.ins().icmp_imm(IntCC::NotEqual, value, 0)
-                    } else {
-                        builder.ins().stack_load(
-                            self.repr(value.ty),
-                            slot,
-                            value.offset.pick(self.s32) as i32,
-                        )
-                    }
-                } else {
-                    builder
-                        .ins()
-                        .stack_addr(self.ptr_ty, slot, value.offset.pick(self.s32) as i32)
-                }
+            _ => {
+                let ty = self.data().local(local).ty;
+                let slot = self.stack_slot(ty, builder);
+                let pointer = builder.ins().stack_addr(self.ptr_ty, slot, 0);
+                self.write(pointer, local, builder);
+                pointer
             }
         }
     }
 
-    fn pass(&mut self, from: Value, to: Value) {
-        let from = self.context.value(from);
-        self.context.set_value(to, from);
-    }
-
-    fn on_stack(&self, ty: Ty) -> bool {
-        self.types[ty].on_stack(self.ptr_ty)
+    fn unwrap_vals(&self, list: EntityList<Local>, builder: &mut FunctionBuilder) -> Vec<Value> {
+        self.data()
+            .local_slice(list)
+            .iter()
+            .map(|&local| self.unwrap_val(local, builder))
+            .collect()
     }
 
-    fn repr(&self, ty: Ty) -> Type {
-        self.types[ty].to_cr_type(self.module.isa())
-    }
+    /// Returns the value that can be passed to instructions, types that
+    /// do not fit into register are passed by pointer.
+    fn unwrap_val(&self, local: Local, builder: &mut FunctionBuilder) -> Value {
+        let ty = self.data().local(local).ty;
+        if self.on_stack(ty) {
+            return self.address(local, builder);
+        }
 
-    pub fn add_anon_string(&mut self, module: Mod, id: DataId, data: Span) {
-        if let Some(string) = self.anon_strings.get_mut(data.hash) {
-            if self.jit {
-                string.jit_id = Some(id).into();
-            } else {
-                string.id = Some(id).into();
+        let offset = self.offsets[local];
+        match self.values[local] {
+            FinalValue::None => unreachable!("{:?} is not initialized", local),
+            FinalValue::Zero => self.zero(self.repr(ty), builder),
+            FinalValue::Value(value) => self.extract(value, ty, offset, builder),
+            FinalValue::Var(var) => {
+                let value = builder.use_var(var);
+                self.extract(value, ty, offset, builder)
+            }
+            FinalValue::Pointer(pointer) => self.load(ty, pointer, offset, builder),
+            FinalValue::StackSlot(slot) => {
+                let pointer = builder.ins().stack_addr(self.ptr_ty, slot, 0);
+                self.load(ty, pointer, offset, builder)
             }
         }
-
-        let (id, jit_id) = if self.jit {
-            (id.into(), None.into())
-        } else {
-            (None.into(), Some(id).into())
-        };
-
-        let string = AnonStringEnt {
-            id,
-            jit_id,
-            data,
-        };
-
-        self.anon_strings.insert(data.hash, string);
     }
 
-    pub fn make_static_data(&mut self, data: &[u8], mutable: bool, tls: bool) -> DataId {
-        let id = data.sdbm_hash().wrapping_add(STRING_SALT);
-        let mut name = String::new();
-        write_radix(id, 36, &mut name);
-        if let Some(FuncOrDataId::Data(data_id)) = self.module.get_name(&name) {
-            data_id
+    fn load(&self, ty: Ty, pointer: Value, offset: u32, builder: &mut FunctionBuilder) -> Value {
+        let repr = self.repr(ty);
+        if repr == B1 {
+            let value = builder
+                .ins()
+                .load(I8, MemFlags::new(), pointer, offset as i32);
+            builder.ins().icmp_imm(IntCC::NotEqual, value, 0)
         } else {
-            let data_id = self
-                .module
-                .declare_data(&name, Linkage::Export, mutable, tls)
-                .unwrap();
-            self.context.data_context.define(data.deref().to_owned().into_boxed_slice());
-            self.module.define_data(data_id, &self.context.data_context).unwrap();
-            self.context.data_context.clear();
-            data_id
+            builder
+                .ins()
+                .load(repr, MemFlags::new(), pointer, offset as i32)
         }
     }
 
-    pub fn declare_data_in_func(&mut self, data: DataId, fun: &mut Function) -> GlobalValue {
-        let map_id = ID(data.as_u32() as u64);
-        if let Some(&value) =
-            self.context.imported_globals.get(map_id)
-        {
+    /// Extracts value of type `ty` at `offset` from value held in register.
+    fn extract(&self, value: Value, ty: Ty, offset: u32, builder: &mut FunctionBuilder) -> Value {
+        let value = if offset == 0 {
             value
         } else {
-            let value = self.module.declare_data_in_func(data, fun);
-            self.context.imported_globals.insert(map_id, value);
-            value
-        }
-    }
-}
-
-impl SdbmHash for &[u8] {
-    fn bytes(&self) -> &[u8] {
-        self
-    }
-}
-
-#[derive(Default, QuickSer)]
-pub struct GState {
-    pub f_state: FState,
-    pub anon_strings: Table<AnonString, AnonStringEnt>,
-    pub used_strings: EntitySet<AnonString>,
-    pub compiled_funs: SecondaryMap<Fun, CFun>,
-    pub compiled_globals: SecondaryMap<GlobalValue, CompiledGlobal>,
-    pub jit_funs: SparseMap<Fun, JFun>,
-    pub bin_declarations: ModuleDeclarations,
-    pub jit_declarations: ModuleDeclarations,
-}
-
-crate::inherit!(GState, f_state, FState);
-
-impl GarbageTarget<Fun, FunEnt> for GState {
-    fn state(&mut self) -> &mut Table<Fun, FunEnt> {
-        &mut self.f_state.funs
+            builder.ins().ushr_imm(value, offset as i64 * 8)
+        };
+        self.convert(value, self.repr(ty), builder)
     }
 
-    fn remove(&mut self, k: Fun, ent: &mut FunEnt) -> bool {
-        if !self.f_state.remove(k, ent) {
-            return false;
-        }
-
-        if ent.kind.is_compiled() {
-            let data = std::mem::take(&mut self.compiled_funs[k]);
-            self.bin_declarations.remove_function(data.id);
+    /// Replaces bytes of `current` at `offset` with `value`, used for
+    /// fields of structures that fit into register.
+    fn insert(
+        &self,
+        current: Value,
+        value: Value,
+        offset: u32,
+        ty: Ty,
+        builder: &mut FunctionBuilder,
+    ) -> Value {
+        let current_ty = builder.func.dfg.value_type(current);
+        if offset == 0 && builder.func.dfg.value_type(value) == current_ty {
+            return value;
         }
 
-        if ent.kind.is_jit_compiled() {
-            let data = self.jit_funs.remove(k).unwrap();
-            self.jit_declarations.remove_function(data.id);
-        } 
-
-        true
-    }
-}
-
-impl GarbageTarget<AnonString, AnonStringEnt> for GState {
-    fn state(&mut self) -> &mut Table<AnonString, AnonStringEnt> {
-        &mut self.anon_strings
+        let size = self.size_of(ty);
+        let mask = if size >= 8 {
+            -1i64
+        } else {
+            (1i64 << (size * 8)) - 1
+        };
+        let value = self.convert(value, current_ty, builder);
+        let value = builder.ins().band_imm(value, mask);
+        let value = builder.ins().ishl_imm(value, offset as i64 * 8);
+        let current = builder.ins().band_imm(current, !(mask << (offset * 8)));
+        builder.ins().bor(current, value)
     }
 
-    fn remove(&mut self, k: AnonString, ent: &mut AnonStringEnt) -> bool {
-        if ent.id.is_some() {
-            self.bin_declarations.remove_data(ent.id.unwrap());
+    /// Reinterprets bits of `value` as type `to`, integers are truncated
+    /// or zero extended.
+    fn convert(&self, value: Value, to: Type, builder: &mut FunctionBuilder) -> Value {
+        let from = builder.func.dfg.value_type(value);
+        if from == to {
+            return value;
         }
 
-        if ent.jit_id.is_some() {
-            self.jit_declarations.remove_data(ent.jit_id.unwrap());
-        }
-
-        true
-    }
-}
-
-impl IncrementalData for GState {
-    fn prepare(&mut self) {
-        self.mt_state.prepare();
-    }
-}
+        let value = if from == B1 {
+            builder.ins().bint(I8, value)
+        } else if from.is_float() {
+            builder
+                .ins()
+                .bitcast(Type::int(from.bits() as u16).unwrap(), value)
+        } else {
+            value
+        };
 
-#[derive(Debug, Clone, Copy, RealQuickSer)]
-pub struct CompiledGlobal {
-    pub id: DataId,
-}
+        let int = if to == B1 {
+            I8
+        } else if to.is_float() {
+            Type::int(to.bits() as u16).unwrap()
+        } else {
+            to
+        };
+        let from = builder.func.dfg.value_type(value);
+        let value = if int.bytes() < from.bytes() {
+            builder.ins().ireduce(int, value)
+        } else if int.bytes() > from.bytes() {
+            builder.ins().uextend(int, value)
+        } else {
+            value
+        };
 
-impl Default for CompiledGlobal {
-    fn default() -> Self {
-        Self {
-            id: DataId::reserved_value(),
+        if to == B1 {
+            builder.ins().icmp_imm(IntCC::NotEqual, value, 0)
+        } else if to.is_float() {
+            builder.ins().bitcast(to, value)
+        } else {
+            value
         }
     }
-}
-
-#[derive(Debug, Clone, QuickDefault, QuickSer)]
-pub struct CFun {
-    pub bin: CompiledData,
-    #[default(FuncId::from_u32(0))]
-    pub id: FuncId,
-}
 
-#[derive(Debug, Clone, QuickDefault, QuickSer)]
-pub struct JFun {
-    pub self_id: Fun,
-    #[default(FuncId::from_u32(0))]
-    pub id: FuncId,
-    pub bin: CompiledData,
-}
-
-impl SparseMapValue<Fun> for JFun {
-    fn key(&self) -> Fun {
-        self.self_id
-    }
-}
-
-#[derive(Clone, Default, QuickSer)]
-pub struct CompiledData {
-    pub bytes: Vec<u8>,
-    pub relocs: Vec<RelocRecord>,
-}
-
-impl std::fmt::Debug for CompiledData {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("CompiledData")
-            .field("bytes", &self.bytes)
-            .field("relocs", &"...")
-            .finish()
-    }
-}
-
-#[derive(QuickDefault)]
-pub struct GContext {
-    #[default(DataContext::new())]
-    pub data_context: DataContext,
-    pub f_context: FContext,
-    pub imported_signatures: Map<SigRef>,
-    pub imported_funs: Map<FuncRef>,
-    pub imported_globals: Map<GlobalValue>,
-    #[default(vec![Default::default()])]
-    pub b_contexts: Vec<BContext>,
-    pub current_b_context: usize,
-}
-
-crate::inherit!(GContext, f_context, FContext);
-
-impl GContext {
-    pub fn dive(&mut self) {
-        self.current_b_context += 1;
-        if self.current_b_context >= self.b_contexts.len() {
-            self.b_contexts.push(BContext::default());
+    fn zero(&self, repr: Type, builder: &mut FunctionBuilder) -> Value {
+        if repr == B1 {
+            builder.ins().bconst(B1, false)
+        } else if repr == F32 {
+            builder.ins().f32const(0.0)
+        } else if repr == F64 {
+            builder.ins().f64const(0.0)
+        } else {
+            builder.ins().iconst(repr, 0)
         }
     }
 
-    pub fn bail(&mut self) {
-        self.current_b_context -= 1;
+    fn stack_slot(&self, ty: Ty, builder: &mut FunctionBuilder) -> StackSlot {
+        let size = align(self.size_of(ty).max(1));
+        builder.create_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, size))
     }
 
-    pub fn block(&self, block: Block) -> Block {
-        self.b_contexts[self.current_b_context].blocks[block]
+    fn set_value(&mut self, local: Local, value: FinalValue) {
+        self.values[local] = value;
+        self.offsets[local] = 0;
     }
 
-    pub fn set_block(&mut self, block: Block, new_block: Block) {
-        self.b_contexts[self.current_b_context].blocks[block] = new_block;
+    fn block(&self, section: Section) -> Block {
+        self.blocks[section].unwrap()
     }
 
-    pub fn value(&self, value: Value) -> FinalValue {
-        self.b_contexts[self.current_b_context].values[value]
+    fn repr(&self, ty: Ty) -> Type {
+        self.type_ent(ty).to_cr_type(self.ptr_ty)
     }
 
-    pub fn set_value(&mut self, value: Value, new_value: FinalValue) {
-        self.b_contexts[self.current_b_context].values[value] = new_value;
+    fn on_stack(&self, ty: Ty) -> bool {
+        self.type_ent(ty).on_stack(self.ptr_ty)
     }
-}
-
-pub struct BContext {
-    pub values: SecondaryMap<Value, FinalValue>,
-    pub blocks: SecondaryMap<Block, Block>,
-}
 
-impl Default for BContext {
-    fn default() -> Self {
-        Self {
-            values: SecondaryMap::new(),
-            blocks: SecondaryMap::new(),
-        }
+    fn size_of(&self, ty: Ty) -> u32 {
+        self.type_size(ty).pick(self.s32)
     }
 }
 
-const MOVERS: &'static [Type] = &[I8, I16, I32, I64];
-const MOVER_SIZES: &'static [u32] = &[1, 2, 4, 8];
-const MAX_MOVER_SIZE: u32 = 64;
+const MOVERS: &[Type] = &[I8, I16, I32, I64];
+const MOVER_SIZES: &[u32] = &[1, 2, 4, 8];
 
 fn find_best_mover(size: u32) -> (Type, u32) {
-    let size = size.min(MAX_MOVER_SIZE);
     MOVER_SIZES
         .iter()
         .rev()
@@ -1646,155 +1121,63 @@ fn find_best_mover(size: u32) -> (Type, u32) {
         .unwrap()
 }
 
-fn static_memset(
-    pointer: Value,
-    pointer_offset: u32,
-    value: i8,
-    size: u32,
-    builder: &mut FunctionBuilder,
-) {
-    let (mover, _) = find_best_mover(size);
-
+/// Stores `value` held in register that represents `size` bytes.
+fn store_value(dst: Value, value: Value, size: u32, builder: &mut FunctionBuilder) {
     let flags = MemFlags::new();
+    let value = if builder.func.dfg.value_type(value) == B1 {
+        builder.ins().bint(I8, value)
+    } else {
+        value
+    };
+    let ty = builder.func.dfg.value_type(value);
+    if ty.bytes() == size {
+        builder.ins().store(flags, value, dst, 0);
+        return;
+    }
 
-    let value = builder.ins().iconst(mover, value as i64);
-
-    walk_mem(size, |_, offset| {
-        builder
-            .ins()
-            .store(flags, value, pointer, (offset + pointer_offset) as i32);
+    walk_mem(size, |mover, offset| {
+        let part = if offset == 0 {
+            value
+        } else {
+            builder.ins().ushr_imm(value, offset as i64 * 8)
+        };
+        let part = if mover.bytes() < ty.bytes() {
+            builder.ins().ireduce(mover, part)
+        } else {
+            part
+        };
+        builder.ins().store(flags, part, dst, offset as i32);
     });
 }
 
-fn static_stack_memset(
-    pointer: StackSlot,
-    pointer_offset: u32,
-    value: i8,
-    size: u32,
-    builder: &mut FunctionBuilder,
-) {
-    let (mover, _) = find_best_mover(size);
-
-    let value = builder.ins().iconst(mover, value as i64);
+fn static_memset(pointer: Value, size: u32, builder: &mut FunctionBuilder) {
+    if size == 0 {
+        return;
+    }
 
+    let (mover, _) = find_best_mover(size);
+    let value = builder.ins().iconst(mover, 0);
     walk_mem(size, |_, offset| {
         builder
             .ins()
-            .stack_store(value, pointer, (offset + pointer_offset) as i32);
+            .store(MemFlags::new(), value, pointer, offset as i32);
     });
 }
 
-fn static_memcpy(
-    dst_pointer: Value,
-    dst_pointer_offset: u32,
-    src_pointer: Value,
-    src_pointer_offset: u32,
-    size: u32,
-    builder: &mut FunctionBuilder,
-) {
+fn static_memcpy(dst: Value, src: Value, size: u32, builder: &mut FunctionBuilder) {
     let flags = MemFlags::new();
-
-    walk_mem(size, |mover, offset| {
-        let value = builder.ins().load(
-            mover,
-            flags,
-            src_pointer,
-            (offset + src_pointer_offset) as i32,
-        );
-        builder.ins().store(
-            flags,
-            value,
-            dst_pointer,
-            (offset + dst_pointer_offset) as i32,
-        );
-    });
-}
-
-fn static_stack_memcpy(
-    dst_pointer: StackSlot,
-    dst_pointer_offset: u32,
-    src_pointer: StackSlot,
-    src_pointer_offset: u32,
-    size: u32,
-    builder: &mut FunctionBuilder,
-) {
     walk_mem(size, |mover, offset| {
-        let value =
-            builder
-                .ins()
-                .stack_load(mover, src_pointer, (offset + src_pointer_offset) as i32);
-        builder
-            .ins()
-            .stack_store(value, dst_pointer, (offset + dst_pointer_offset) as i32);
+        let value = builder.ins().load(mover, flags, src, offset as i32);
+        builder.ins().store(flags, value, dst, offset as i32);
     });
 }
 
-fn static_value_stack_memcpy(
-    dst_pointer: StackSlot,
-    dst_pointer_offset: u32,
-    src_value: Value,
-    size: u32,
-    builder: &mut FunctionBuilder,
-) {
-    static_value_memcpy_base(
-        dst_pointer, 
-        dst_pointer_offset, 
-        src_value, 
-        size, 
-        builder, 
-        |dst, src, offset, builder| 
-            builder.ins().stack_store(src, dst, offset)
-    );
-}
-
-fn static_value_memcpy(
-    dst_pointer: Value,
-    dst_pointer_offset: u32,
-    src_value: Value,
-    size: u32,
-    builder: &mut FunctionBuilder,
-) {
-    static_value_memcpy_base(
-        dst_pointer, 
-        dst_pointer_offset, 
-        src_value, 
-        size, 
-        builder, 
-        |dst, src, offset, builder| 
-            builder.ins().store(MemFlags::new(), src, dst, offset)
-    );
-}
-
-fn static_value_memcpy_base<T: Copy, F: Fn(
-    T, 
-    Value, 
-    i32, 
-    &mut FunctionBuilder
-) -> Inst>(
-    dst_pointer: T,
-    dst_pointer_offset: u32,
-    src_value: Value,
-    size: u32,
-    builder: &mut FunctionBuilder,
-    f: F,
-) {
-    walk_mem(size, |mover, offset| {
-        let src_ty = builder.func.dfg.value_type(src_value);
-        let value = if src_ty.bits() != mover.bits() {
-            let mut mask = (1i64 << mover.bits() as i64) - 1; // turn first 'mover.bits()' bits on
-            mask <<= offset * 8;
-            let mask = builder.ins().iconst(I64, mask);
-            let value = builder.ins().band(src_value, mask);
-            let value = builder.ins().ushr_imm(value, (offset * 8) as i64);
-            builder.ins().ireduce(mover, value)
-        } else {
-            src_value
-        };
-        f(dst_pointer, value, (offset + dst_pointer_offset) as i32, builder);
-    })
-}
-
+/// Calls `fun` with the biggest mover and offsets covering `size` bytes.
 fn walk_mem<F: FnMut(Type, u32)>(size: u32, mut fun: F) {
+    if size == 0 {
+        return;
+    }
+
     let (mover, mover_size) = find_best_mover(size);
 
     let mut offset = 0u32;
@@ -1814,62 +1197,6 @@ fn walk_mem<F: FnMut(Type, u32)>(size: u32, mut fun: F) {
     }
 }
 
-pub struct MetaTrapSink {}
-
-impl TrapSink for MetaTrapSink {
-    fn trap(&mut self, _offset: CodeOffset, _loc: SourceLoc, _code: TrapCode) {
-        //println!("{:?} {:?} {:?}", _offset, _loc, _code);
-    }
-}
-
-pub struct MetaRelocSink {
-    pub relocs: Vec<RelocRecord>,
-}
-
-impl MetaRelocSink {
-    pub fn new() -> Self {
-        Self { relocs: Vec::new() }
-    }
-
-    pub fn clear(&mut self) {
-        self.relocs.clear();
-    }
-}
-
-impl RelocSink for MetaRelocSink {
-    fn reloc_external(
-        &mut self,
-        offset: CodeOffset,
-        _source_loc: SourceLoc,
-        reloc: Reloc,
-        name: &ExternalName,
-        addend: Addend,
-    ) {
-        self.relocs.push(RelocRecord {
-            offset,
-            reloc,
-            name: name.clone(),
-            addend,
-        });
-    }
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq, RealQuickSer)]
-pub enum FinalValue {
-    None,
-    Zero,
-    Value(Value),
-    Pointer(Value),
-    Var(Variable),
-    StackSlot(StackSlot),
-}
-
-impl Default for FinalValue {
-    fn default() -> Self {
-        Self::None
-    }
-}
-
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum BTKind {
     Int,
@@ -1879,15 +1206,15 @@ pub enum BTKind {
 }
 
 impl BTKind {
-    pub fn of(tp: Ty) -> Self {
-        match EntityRef::index(tp) {
-            0..=3 | 11 => Self::Int,
-            4..=7 | 12 => Self::Uint,
-            8 => Self::Float(32),
-            9 => Self::Float(64),
-            10 => Self::Bool,
-            _ => panic!("cannot get builtin type of {:?}", tp),
-        }
+    pub fn of(ty: Ty) -> Option<Self> {
+        Some(match ty {
+            I8_TY | I16_TY | I32_TY | I64_TY | INT_TY => Self::Int,
+            U8_TY | U16_TY | U32_TY | U64_TY | UINT_TY | CHAR_TY => Self::Uint,
+            F32_TY => Self::Float(32),
+            F64_TY => Self::Float(64),
+            BOOL_TY => Self::Bool,
+            _ => return None,
+        })
     }
 }
 
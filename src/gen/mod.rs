@@ -1,264 +1,360 @@
-pub mod generator;
-
-use generator::*;
+//! Module gen is the last stage of the compiler. It lowers functions
+//! reachable from the entry point into object file and links the
+//! executable.
 
-use cranelift::{codegen::{
-    isa::{self, LookupError},
-    settings::{self, Configurable, SetError},
-}, entity::EntitySet};
+pub mod generator;
 
-use cranelift::object::{ObjectBuilder, ObjectModule};
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
 use std::process::Command;
+use std::str::FromStr;
 
-use crate::{
-    ast::{AError, AErrorDisplay},
-    functions::{FError, FErrorDisplay},
-    lexer::{Token, TokenDisplay},
-    module_tree::{MTError, MTErrorDisplay, MTParser},
-    util::cli::Arguments, entities::{AnonString, Ty, Fun}, types::GarbageTarget,
+use cranelift::codegen::ir::{
+    types::INVALID, Block, FuncRef, GlobalValue, SigRef, StackSlot, Type, Value,
 };
+use cranelift::codegen::isa::{self, LookupError, TargetIsa};
+use cranelift::codegen::packed_option::PackedOption;
+use cranelift::codegen::settings::{self, Configurable, SetError};
+use cranelift::entity::{EntitySet, SecondaryMap};
+use cranelift::frontend::Variable;
+use cranelift::module::{DataContext, DataId, FuncId, ModuleError};
+use cranelift::object::{ObjectBuilder, ObjectModule};
+use quick_proc::QuickDefault;
+use target_lexicon::{OperatingSystem, ParseError, Triple};
+
+use crate::functions::{self, Section, VTable};
+use crate::lexer::{self, DisplayError, ErrorDisplay, ErrorDisplayState, Token};
+use crate::modules::{Const, Fun, Global, Local, Ty};
+use crate::types::{ConstDisplay, TypeDisplay};
+use crate::util::cli::Arguments;
+
+type Result<T = ()> = std::result::Result<T, Error>;
+
+/// Last layer of the context, it lowers bodies from [`functions::Ctx`]
+/// with cranelift. Most of the state is cleared for each function.
+#[derive(QuickDefault)]
+pub struct Ctx {
+    ctx: functions::Ctx,
+
+    frontier: Vec<Fun>,
+    compiled_funs: SecondaryMap<Fun, PackedOption<FuncId>>,
+    compiled_globals: SecondaryMap<Global, PackedOption<DataId>>,
+    compiled_vtables: SecondaryMap<VTable, PackedOption<DataId>>,
+
+    values: SecondaryMap<Local, FinalValue>,
+    offsets: SecondaryMap<Local, u32>,
+    parents: SecondaryMap<Local, PackedOption<Local>>,
+    stack_locals: EntitySet<Local>,
+    blocks: SecondaryMap<Section, PackedOption<Block>>,
+    imported_funs: SecondaryMap<Fun, PackedOption<FuncRef>>,
+    imported_data: SecondaryMap<DataId, PackedOption<GlobalValue>>,
+    imported_signatures: SecondaryMap<Ty, PackedOption<SigRef>>,
+
+    #[default(DataContext::new())]
+    data_context: DataContext,
+    #[default(INVALID)]
+    ptr_ty: Type,
+    s32: bool,
+}
 
-use crate::incr::IncrementalData; 
-
-type Result<T> = std::result::Result<T, (Option<GState>, GError)>;
+impl Ctx {
+    /// Generates object file and links it with `cc` (or `--linker`)
+    /// unless `-obj` flag is present. Output is named after the project
+    /// or by `--output` flag. Returns the path to created file.
+    pub fn build(&mut self, args: &Arguments) -> Result<String> {
+        let triple = target_triple(args)?;
+        let isa = target_isa(args, triple.clone())?;
+
+        let builder = ObjectBuilder::new(isa, "all", cranelift::module::default_libcall_names())
+            .map_err(Into::into)?;
+        let mut module = ObjectModule::new(builder);
+        self.generate(&mut module)?;
+        let obj_file = module
+            .finish()
+            .emit()
+            .map_err(|e| Error::new(error::Kind::ObjectEmit(e.to_string()), Token::default()))?;
+
+        let name = Path::new(&args[0])
+            .file_stem()
+            .and_then(|name| name.to_str())
+            .unwrap_or("main");
+        let output_name = args
+            .get_flag("o")
+            .or_else(|| args.get_flag("output"))
+            .unwrap_or(name);
+
+        let obj_name = format!("{}.o", output_name);
+        std::fs::write(&obj_name, obj_file).map_err(|e| {
+            Error::new(
+                error::Kind::IoError("failed to write temporary object file", e),
+                Token::default(),
+            )
+        })?;
 
-pub fn compile(args: Arguments) -> Result<usize> {
-    if args.len() < 1 {
-        return Err((None, GEKind::NoFiles.into()));
-    }
+        if args.enabled("obj") {
+            return Ok(obj_name);
+        }
 
-    let (obj_file, lines_of_code) = generate_obj_file(&args)?;
+        let exe_name = executable_name(output_name, &triple);
+        let link_with = args
+            .get_flag("lv")
+            .or_else(|| args.get_flag("link-with"))
+            .unwrap_or("")
+            .split(';')
+            .filter(|s| !s.is_empty());
+        let linker = args.get_flag("linker").unwrap_or("cc");
 
-    let name = args[0]
-        .split("/")
-        .last()
-        .unwrap()
-        .split(".")
-        .next()
-        .unwrap();
+        let status = Command::new(linker)
+            .args(
+                ["-o", exe_name.as_str(), obj_name.as_str()]
+                    .iter()
+                    .copied()
+                    .chain(link_with),
+            )
+            .status()
+            .map_err(|e| {
+                Error::new(
+                    error::Kind::IoError("problem with linker", e),
+                    Token::default(),
+                )
+            })?;
+
+        if !status.success() {
+            return Err(Error::new(
+                error::Kind::LinkerFailed(linker.to_string(), status.code()),
+                Token::default(),
+            ));
+        }
 
-    let output_name = args
-        .get_flag("o")
-        .or(args.get_flag("output"))
-        .unwrap_or(name);
+        std::fs::remove_file(&obj_name).map_err(|e| {
+            Error::new(
+                error::Kind::IoError("failed to remove temporary object file", e),
+                Token::default(),
+            )
+        })?;
 
-    let obj_name = format!("{}.o", output_name);
+        Ok(exe_name)
+    }
+}
 
-    std::fs::write(&obj_name, obj_file).map_err(|e| (None, GEKind::IoError(
-        "failed to write temporary object file", e
-    ).into()))?;
+impl Deref for Ctx {
+    type Target = functions::Ctx;
 
-    if args.enabled("obj") {
-        return Ok(lines_of_code);
+    fn deref(&self) -> &Self::Target {
+        &self.ctx
     }
+}
 
-    let link_with = args
-        .get_flag("lv")
-        .or(args.get_flag("link-with"))
-        .unwrap_or("")
-        .split(";")
-        .filter(|s| !s.is_empty());
-
-    let linker = args
-        .get_flag("linker")
-        .unwrap_or("cc");
+impl DerefMut for Ctx {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.ctx
+    }
+}
 
-    assert_eq!(
-        Command::new(linker)
-            .args(
-                ["-o", &format!("{}.exe", output_name), &obj_name]
-                    .iter()
-                    .map(|a| *a)
-                    .chain(link_with),
-            )
-            .status()
-            .map_err(|e| (None, GEKind::IoError(
-                "problem with linker", e
-            ).into()))?
-            .code()
-            .unwrap(),
-        0,
-    );
-
-    std::fs::remove_file(&obj_name).map_err(|e| (None, GEKind::IoError(
-       "failed to remove temporary object file", e
-    ).into()))?;
-
-    Ok(lines_of_code)
+/// Returns triple specified by `--triplet` flag or host triple.
+pub fn target_triple(args: &Arguments) -> Result<Triple> {
+    if let Some(triplet) = args.get_flag("triplet") {
+        Triple::from_str(triplet)
+            .map_err(|e| Error::new(error::Kind::InvalidTriplet(e), Token::default()))
+    } else {
+        Ok(Triple::host())
+    }
 }
 
-pub fn generate_obj_file(args: &Arguments) -> Result<(Vec<u8>, usize)> {
+/// Builds target isa with flags from `--compiler-options` and `--opt_level`.
+/// Host isa is detected natively so that available cpu features are used.
+pub fn target_isa(args: &Arguments, triple: Triple) -> Result<Box<dyn TargetIsa>> {
     let mut settings = settings::builder();
-    if let Some(s) = args.get_flag("co").or(args.get_flag("compiler-options")) {
-        for value in s.split(" ") {
-            let mut value = value.split("=");
+    if let Some(s) = args
+        .get_flag("co")
+        .or_else(|| args.get_flag("compiler-options"))
+    {
+        for value in s.split(' ') {
+            let mut value = value.split('=');
             let flag = value.next().unwrap();
             if let Some(value) = value.next() {
                 settings.set(flag, value)
             } else {
                 settings.enable(flag)
             }
-            .map_err(|e| (None, GEKind::CompilationFlagError(e).into()))?;
+            .map_err(|e| Error::new(error::Kind::CompilationFlagError(e), Token::default()))?;
         }
     }
 
-    if let Some(opt_level) = args.get_flag("opt_level").or(args.get_flag("ol")) {
+    if let Some(opt_level) = args.get_flag("opt_level").or_else(|| args.get_flag("ol")) {
         settings
             .set("opt_level", opt_level)
-            .map_err(|e| (None, GEKind::CompilationFlagError(e).into()))?;
+            .map_err(|e| Error::new(error::Kind::CompilationFlagError(e), Token::default()))?;
     }
 
     let flags = settings::Flags::new(settings);
 
-    let isa = if let Some(triplet) = args.get_flag("triplet") {
-        isa::lookup_by_name(triplet)
-            .map_err(|e| (None, GEKind::InvalidTriplet(e).into()))?
-            .finish(flags)
+    // object module picks the binary format (ELF, COFF, Mach-O) from the triple
+    let builder = if triple == Triple::host() {
+        cranelift::native::builder()
+            .map_err(|e| Error::new(error::Kind::UnsupportedHost(e), Token::default()))?
     } else {
-        cranelift::native::builder().unwrap().finish(flags)
+        isa::lookup(triple)
+            .map_err(|e| Error::new(error::Kind::UnsupportedTriplet(e), Token::default()))?
     };
 
-    let builder =
-        ObjectBuilder::new(isa, "all", cranelift::module::default_libcall_names()).unwrap();
-
-    let stacktrace = args.enabled("trace");
-
-    let mut module = ObjectModule::new(builder);
-
-    let (mut state, size_hint) = GState::load_data(&args[0], args.hash()).unwrap_or_default();
-
-    state.do_stacktrace = stacktrace;
-    let mut context = GContext::default();
-
-    if let Err(e) = MTParser::new(&mut state, &mut context).parse(&args[0]) {
-        return Err((Some(state), GEKind::MTError(e).into()));
-    }
-
-    let order = std::mem::take(&mut state.module_order);
-
-    let mut used_strings = EntitySet::<AnonString>::with_capacity(state.anon_strings.len());
-    let mut used_functions = EntitySet::<Fun>::with_capacity(state.funs.len());
-    let mut used_types = EntitySet::<Ty>::with_capacity(state.types.len());
-
-    for &module in &order {
-        let module = &state.modules[module];
-        if module.clean {
-            for &fun in module.used_functions() {
-                used_functions.insert(fun);
-            }
-            for &ty in module.used_types() {
-                used_types.insert(ty);
-            }
-            for &string in module.used_strings() {
-                used_strings.insert(string);
-            }
-        }
-    }
-
-    state.remove_garbage(&used_strings);
-    state.remove_garbage(&used_functions);
-    state.t_state.remove_garbage(&used_types);
+    Ok(builder.finish(flags))
+}
 
-    if let Err(e) = Generator::new(&mut module, &mut state, &mut context, false)
-        .generate(&order) {
-        return Err((Some(state), e));
+/// Executables have no extension except on windows.
+pub fn executable_name(name: &str, triple: &Triple) -> String {
+    match triple.operating_system {
+        OperatingSystem::Windows => format!("{}.exe", name),
+        _ => name.to_string(),
     }
-
-    state.save_data(&args[0], args.hash(), Some(size_hint)).unwrap();
-
-    Ok((module.finish().emit().unwrap(), context.lines_of_code))
 }
 
-pub struct GErrorDisplay<'a> {
-    pub state: Option<&'a GState>,
-    pub error: &'a GError,
+/// Storage of the local in generated function, fields share the storage
+/// with the structure and differ only in offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinalValue {
+    None,
+    Zero,
+    Value(Value),
+    Pointer(Value),
+    Var(Variable),
+    StackSlot(StackSlot),
 }
 
-impl<'a> GErrorDisplay<'a> {
-    pub fn new(state: Option<&'a GState>, error: &'a GError) -> Self {
-        Self { state, error }
+impl Default for FinalValue {
+    fn default() -> Self {
+        Self::None
     }
 }
 
-impl std::fmt::Display for GErrorDisplay<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if let Some(state) = self.state {
-            writeln!(f, "{}", TokenDisplay::new(state, &self.error.token))?;
-        }
-
-        match &self.error.kind {
-            GEKind::FError(error) => {
-                write!(f, "{}", FErrorDisplay::new(&self.state.unwrap(), error))?;
-            }
-            GEKind::MTError(error) => {
-                write!(f, "{}", MTErrorDisplay::new(&self.state.unwrap(), error))?;
+impl ErrorDisplayState<Error> for Ctx {
+    fn fmt(&self, e: &Error, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match e.kind() {
+            error::Kind::Module(error) => {
+                writeln!(f, "{}", error)?;
             }
-            GEKind::AError(error) => {
-                write!(f, "{}", AErrorDisplay::new(&self.state.unwrap(), error))?;
+            error::Kind::ObjectEmit(error) => {
+                writeln!(f, "failed to emit object file: {}", error)?;
             }
-            GEKind::IoError(message, err) => {
-                writeln!(f, "{}: {}", message, err)?;
+            error::Kind::IoError(message, error) => {
+                writeln!(f, "{}: {}", message, error)?;
             }
-            GEKind::InvalidTriplet(error) => {
+            error::Kind::InvalidTriplet(error) => {
                 writeln!(f, "invalid triplet: {}", error)?;
             }
-            GEKind::CompilationFlagError(err) => {
-                writeln!(f, "invalid compilation flag: {}", err)?;
+            error::Kind::UnsupportedTriplet(error) => {
+                writeln!(f, "unsupported triplet: {}", error)?;
             }
-            GEKind::NoFiles => {
-                writeln!(f, "first argument is missing <FILE>")?;
+            error::Kind::UnsupportedHost(error) => {
+                writeln!(f, "host is not supported: {}", error)?;
+            }
+            error::Kind::CompilationFlagError(error) => {
+                writeln!(f, "invalid compilation flag: {}", error)?;
+            }
+            error::Kind::LinkerFailed(linker, code) => {
+                write!(f, "linker '{}' failed", linker)?;
+                if let Some(code) = code {
+                    write!(f, " with exit code {}", code)?;
+                }
+                writeln!(f)?;
+            }
+            &error::Kind::UnsupportedBuiltin(fun) => {
+                let fun_ent = self.fun_ent(fun);
+                write!(
+                    f,
+                    "builtin '{}' is not supported by generator, signature: (",
+                    self.display(fun_ent.name())
+                )?;
+                for (i, &ty) in self.type_slice(fun_ent.sig().args).iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", TypeDisplay::new(self, ty))?;
+                }
+                writeln!(f, ")")?;
+            }
+            &error::Kind::UnsupportedConstant(constant) => {
+                writeln!(
+                    f,
+                    "constant '{}' cannot be stored in static data",
+                    ConstDisplay::new(self, constant)
+                )?;
             }
         }
 
         Ok(())
     }
+
+    fn sources(&self) -> &lexer::Ctx {
+        self.ctx.sources()
+    }
 }
 
 #[derive(Debug)]
-pub struct GError {
-    kind: GEKind,
+pub struct Error {
+    kind: error::Kind,
     token: Token,
 }
 
-impl GError {
-    pub fn new(kind: GEKind, token: Token) -> Self {
-        Self { kind, token }
+impl Error {
+    pub fn new(kind: error::Kind, token: Token) -> Self {
+        Error { kind, token }
+    }
+
+    pub fn kind(&self) -> &error::Kind {
+        &self.kind
     }
 }
 
-#[derive(Debug)]
-pub enum GEKind {
-    MTError(MTError),
-    FError(FError),
-    AError(AError),
-    IoError(&'static str, std::io::Error),
-    InvalidTriplet(LookupError),
-    CompilationFlagError(SetError),
-    NoFiles,
+impl Into<Error> for ModuleError {
+    fn into(self) -> Error {
+        Error::new(error::Kind::Module(self), Token::default())
+    }
 }
 
-impl Into<GError> for GEKind {
-    fn into(self) -> GError {
-        GError {
-            kind: self,
-            token: Token::default(),
-        }
+impl DisplayError for Error {
+    fn token(&self) -> Token {
+        self.token
+    }
+}
+
+mod error {
+    use super::*;
+
+    #[derive(Debug)]
+    pub enum Kind {
+        Module(ModuleError),
+        ObjectEmit(String),
+        IoError(&'static str, std::io::Error),
+        InvalidTriplet(ParseError),
+        UnsupportedTriplet(LookupError),
+        UnsupportedHost(&'static str),
+        CompilationFlagError(SetError),
+        LinkerFailed(String, Option<i32>),
+        UnsupportedBuiltin(Fun),
+        UnsupportedConstant(Const),
     }
 }
 
 pub fn test() {
-    println!("gen-test");
-    let args = Arguments::from_str("root src/gen/test_project -trace").unwrap();
+    const PATH: &str = "src/gen/test_project";
 
-    compile(args)
-        .map_err(|(state, e)| panic!("{}", GErrorDisplay::new(state.as_ref(), &e)))
+    let args = Arguments::from_str(&format!("mf {}", PATH)).unwrap();
+    let mut ctx = Ctx::default();
+    ctx.compile_project(PATH)
+        .map_err(|e| panic!("\n{}", ErrorDisplay::new(&*ctx, &e)))
         .unwrap();
 
-    println!("output:");
-    let output = Command::new(".\\test_project.exe").status().unwrap();
-    println!("\n:end");
+    ctx.finish_entry_point();
+
+    let exe_name = ctx
+        .build(&args)
+        .map_err(|e| panic!("\n{}", ErrorDisplay::new(&ctx, &e)))
+        .unwrap();
 
-    assert_eq!(output.code().unwrap(), 0);
+    let path = Path::new(".").join(&exe_name);
+    let status = Command::new(&path).status().unwrap();
+    std::fs::remove_file(&path).unwrap();
 
-    std::fs::remove_file(".\\test_project.exe").unwrap();
-}
\ No newline at end of file
+    assert_eq!(status.code(), Some(0));
+}
@@ -8,6 +8,8 @@ use cranelift::entity::{packed_option::ReservedValue, EntityRef, PrimaryMap};
 
 use crate::util::{sdbm::ID, storage::Map};
 use std::{fmt::Debug, ops::Range};
+use unicode_security::{is_potential_mixed_script_confusable_char, skeleton, MixedScript};
+use unicode_xid::UnicodeXID;
 
 pub use token::Display;
 
@@ -17,10 +19,16 @@ type Result<T = Token> = std::result::Result<T, Error>;
 /// originate from user defined source files.
 pub const BUILTIN_SOURCE: Source = Source(0);
 pub const POINTER_WIDTH_MARKER: u8 = 100;
+/// Amount of spaces that make one indentation level if not configured otherwise.
+pub const DEFAULT_INDENT_WIDTH: u8 = 2;
+
+/// Operators that are always known to lexer. Each of them
+/// also has the assignment variant ('{op}=').
+pub const BUILTIN_OPERATORS: &str = "+ - * / % == != >= <= > < ^ | & >> << && || ~ ++ -- ! =";
 
 /// Next token parses one token from `source` based of `state`.
-pub fn next_token(source: &str, state: &mut State) -> Result {
-    Lexer::new(source, state).advance()
+pub fn next_token(source: &str, state: &mut State, operators: &Operators) -> Result {
+    Lexer::new(source, state, operators).advance()
 }
 
 /// Lexer modifies [`LState`] and yields token. If you want to peek,
@@ -30,12 +38,18 @@ pub fn next_token(source: &str, state: &mut State) -> Result {
 pub struct Lexer<'a> {
     source: &'a str,
     state: &'a mut State,
+    operators: &'a Operators,
 }
 
 impl<'a> Lexer<'a> {
     /// Create lexer from source and state. No modifications to state are performed.
-    pub fn new(source: &'a str, state: &'a mut State) -> Self {
-        Lexer { source, state }
+    /// `operators` are used for splitting sequences of operator characters.
+    pub fn new(source: &'a str, state: &'a mut State, operators: &'a Operators) -> Self {
+        Lexer {
+            source,
+            state,
+            operators,
+        }
     }
 
     /// Next parses next token, returning it and preparing state for next token.
@@ -43,13 +57,32 @@ impl<'a> Lexer<'a> {
         loop {
             let start = self.progress();
             let char = self.peek().unwrap_or('\0');
-            if char.is_alphabetic() || char == '_' {
+            let string = match char {
+                '"' if self.peek_nth(1) == Some('"') && self.peek_nth(2) == Some('"') => {
+                    Some((token::Kind::MultilineString, self.multiline_string(None)))
+                }
+                '"' => Some((token::Kind::String, self.string(None))),
+                'r' if self.is_raw_string() => {
+                    Some((token::Kind::RawString, self.raw_string(None)))
+                }
+                'b' if self.peek_nth(1) == Some('"') => {
+                    Some((token::Kind::ByteString, self.byte_string(None)))
+                }
+                _ => None,
+            };
+            if let Some((kind, err)) = string {
+                return match err {
+                    Some(err) => Err(self.error(err, start..self.progress())),
+                    None => Ok(self.token(kind, start)),
+                };
+            }
+            if char.is_xid_start() || char == '_' {
                 return self.ident();
             }
             if char.is_operator() {
                 return self.op();
             }
-            if char.is_numeric() {
+            if char.is_ascii_digit() {
                 return self
                     .number()
                     .map(|value| {
@@ -60,9 +93,7 @@ impl<'a> Lexer<'a> {
                         };
                         self.token(kind, start)
                     })
-                    .ok_or_else(|| {
-                        self.error(error::Kind::InvalidCharacter, start..self.progress())
-                    });
+                    .map_err(|kind| self.error(kind, start..self.progress()));
             }
 
             let kind = match char {
@@ -74,12 +105,6 @@ impl<'a> Lexer<'a> {
                     continue;
                 }
                 '\'' => return self.char_or_tag(),
-                '"' => {
-                    return match self.string(None) {
-                        Some(err) => return Err(self.error(err, start..self.progress())),
-                        None => Ok(self.token(token::Kind::String, start)),
-                    }
-                }
                 '#' => {
                     let comment = self.comment()?;
                     if comment.kind() == token::Kind::Comment(false) {
@@ -96,7 +121,10 @@ impl<'a> Lexer<'a> {
                 ']' => token::Kind::RBra,
                 '.' => token::Kind::Dot,
                 '\0' => token::Kind::Eof,
-                _ => return Err(self.error(error::Kind::UnknownCharacter, start..start + 1)),
+                _ => {
+                    let end = start + char.len_utf8();
+                    return Err(self.error(error::Kind::UnknownCharacter, start..end));
+                }
             };
 
             self.next();
@@ -104,18 +132,39 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    /// Same as [`Self::advance`] but never fails. Error is pushed to `errors`
+    /// and [`token::Kind::Error`] token spanning the invalid source is returned
+    /// instead. Lexer always skips the invalid part so the stream eventually
+    /// ends with [`token::Kind::Eof`].
+    pub fn advance_recovering(&mut self, errors: &mut Vec<Error>) -> Token {
+        match self.advance() {
+            Ok(token) => token,
+            Err(error) => {
+                let token = error.token();
+                let range = token.range();
+                if self.progress() < range.end {
+                    self.state.advance(range.end - self.progress());
+                }
+                errors.push(error);
+                Token::new(
+                    token::Kind::Error,
+                    self.span(range.start..self.progress()),
+                    token.line_data(),
+                )
+            }
+        }
+    }
+
     /// Parses identifier token, but can return keyword token or
-    /// even operator for some reserved words.
+    /// even operator for some reserved words. Identifier starts with
+    /// XID_Start or '_' and continues with XID_Continue characters.
     /// ```regex
-    /// \b[a-zA-Z_][a-zA-Z0-9_]+\b
+    /// \b[\p{XID_Start}_]\p{XID_Continue}*\b
     /// ```
     fn ident(&mut self) -> Result<Token> {
         let start = self.progress();
-        loop {
-            let char = self.peek().unwrap_or('\0');
-            if !char.is_alphanumeric() && char != '_' {
-                break;
-            }
+        self.next();
+        while self.peek().map_or(false, |c| c.is_xid_continue()) {
             self.next();
         }
         let kind = match &self.source[start..self.progress()] {
@@ -132,6 +181,10 @@ impl<'a> Lexer<'a> {
             "let" => token::Kind::Let,
             "var" => token::Kind::Var,
             "for" => token::Kind::For,
+            "in" => token::Kind::In,
+            "match" => token::Kind::Match,
+            "const" => token::Kind::Const,
+            "type" => token::Kind::Type,
             "break" => token::Kind::Break,
             "continue" => token::Kind::Continue,
             "struct" => token::Kind::Struct,
@@ -148,15 +201,24 @@ impl<'a> Lexer<'a> {
         Ok(self.token(kind, start))
     }
 
-    /// Parses operator token but can return keyword token.
+    /// Parses operator token but can return keyword token. Sequence of
+    /// operator characters is split into known [`Operators`], this token
+    /// is the first one of the split that prefers longer operators.
     /// ```regex
     /// \b[+-*/%^=<>!&|?|:~]+\b
     /// ```
     fn op(&mut self) -> Result<Token> {
         let start = self.progress();
-        while self.peek().unwrap_or('\0').is_operator() {
-            self.next();
-        }
+        let run = &self.source[start..];
+        let run = &run[..run.find(|c: char| !c.is_operator()).unwrap_or(run.len())];
+        let len = match self.operators.split(run) {
+            Some(len) => len,
+            None => {
+                let end = start + run.len();
+                return Err(self.error(error::Kind::UnknownOperator, start..end));
+            }
+        };
+        self.state.advance(len);
         let kind = match &self.source[start..self.progress()] {
             ":" => token::Kind::Colon,
             "::" => token::Kind::DoubleColon,
@@ -167,24 +229,28 @@ impl<'a> Lexer<'a> {
     }
 
     /// Indent parses indent token, all of the characters of ident are
-    /// navigation and space characters. Indentation value of ' ' is 0.5 and
-    /// '\t' is 1 and sum is floored.
+    /// navigation and space characters. '\t' is one level and indent width
+    /// of spaces (configured in [`State`]) is one level. First indented line
+    /// decides whether file uses tabs or spaces, mixing them is an error, so
+    /// is amount of spaces that is not multiple of width and dedent to level
+    /// that was never opened. Blank lines are not checked and comment lines
+    /// are checked only for mixing.
     /// ```regex
     /// \n[ \t]*
     /// ```
     fn indent(&mut self) -> Result<Token> {
         let start = self.progress();
         self.next();
-        let mut indentation = 0;
+        let (mut spaces, mut tabs) = (0u16, 0u16);
         loop {
             match self.peek() {
                 Some(' ') => {
                     self.next();
-                    indentation += 1;
+                    spaces += 1;
                 }
                 Some('\t') => {
                     self.next();
-                    indentation += 2;
+                    tabs += 1;
                 }
                 Some('\r') => {
                     self.next();
@@ -192,7 +258,44 @@ impl<'a> Lexer<'a> {
                 _ => break,
             }
         }
-        Ok(self.token(token::Kind::Indent(indentation / 2), start))
+        let end = self.progress();
+        let width = self.state.indent_width() as u16;
+        let level = tabs + spaces / width;
+
+        if matches!(self.peek(), None | Some('\n')) {
+            return Ok(self.token(token::Kind::Indent(level), start));
+        }
+
+        if spaces != 0 && tabs != 0 {
+            return Err(self.error(error::Kind::MixedIndentation, start + 1..end));
+        }
+
+        if spaces != 0 || tabs != 0 {
+            let style = if tabs != 0 { b'\t' } else { b' ' };
+            match self.state.indent_style {
+                0 => self.state.indent_style = style,
+                expected if expected != style => {
+                    let kind = error::Kind::InconsistentIndentation(expected == b'\t');
+                    return Err(self.error(kind, start + 1..end));
+                }
+                _ => (),
+            }
+        }
+
+        if self.peek() == Some('#') {
+            return Ok(self.token(token::Kind::Indent(level), start));
+        }
+
+        if spaces % width != 0 {
+            let kind = error::Kind::IndentNotMultiple(width as u8);
+            return Err(self.error(kind, start + 1..end));
+        }
+
+        if !self.state.enter_level(level) {
+            return Err(self.error(error::Kind::UnmatchedDedent, start + 1..end));
+        }
+
+        Ok(self.token(token::Kind::Indent(level), start))
     }
 
     /// Parses character or label. Character can be escaped. `<char_escape>` refers
@@ -209,6 +312,9 @@ impl<'a> Lexer<'a> {
             let start = self.progress();
             if self.char_escape().is_none() {
                 let end = self.progress();
+                if self.peek() == Some('\'') {
+                    self.next();
+                }
                 return Err(self.error(error::Kind::InvalidCharacter, start..end));
             }
             false
@@ -227,7 +333,7 @@ impl<'a> Lexer<'a> {
             self.next();
             token::Kind::Char
         } else {
-            while self.peek().unwrap_or('\0').is_alphanumeric() {
+            while self.peek().map_or(false, |c| c.is_xid_continue()) {
                 self.next();
             }
 
@@ -236,6 +342,15 @@ impl<'a> Lexer<'a> {
         Ok(self.token(kind, start))
     }
 
+    /// Returns true if `r` at the current position opens a raw string.
+    fn is_raw_string(&self) -> bool {
+        let mut i = 1;
+        while self.peek_nth(i) == Some('#') {
+            i += 1;
+        }
+        self.peek_nth(i) == Some('"')
+    }
+
     /// Parses comment token. As long as [`str::chars`] can iterate over the characters,
     /// commend content is valid.
     fn comment(&mut self) -> Result {
@@ -318,6 +433,10 @@ impl LexerBase for Lexer<'_> {
         Iterator::next(&mut self.source[self.progress()..].chars())
     }
 
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        self.source[self.progress()..].chars().nth(n)
+    }
+
     fn next(&mut self) -> Option<char> {
         let char = self.peek();
 
@@ -332,6 +451,20 @@ impl LexerBase for Lexer<'_> {
     }
 }
 
+impl LexerBase for std::str::Chars<'_> {
+    fn next(&mut self) -> Option<char> {
+        Iterator::next(self)
+    }
+
+    fn peek(&self) -> Option<char> {
+        Iterator::next(&mut self.clone())
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        self.clone().nth(n)
+    }
+}
+
 impl IsOperator for char {
     fn to_char(&self) -> char {
         *self
@@ -360,17 +493,63 @@ pub struct State {
     progress: u32,
     line: u32,
     last_n_line: u32,
+    indent_width: u8,
+    /// '\t' or ' ' once first indented line is lexed, 0 before.
+    indent_style: u8,
+    /// Bit set of indentation levels opened above current line.
+    levels: u64,
 }
 
 impl State {
     /// Creates a new state with source.
     pub fn new(source: Source) -> Self {
+        Self::with_indent_width(source, DEFAULT_INDENT_WIDTH)
+    }
+
+    /// Creates a new state with source, where `indent_width`
+    /// spaces make one indentation level.
+    pub fn with_indent_width(source: Source, indent_width: u8) -> Self {
         Self {
             source,
             progress: 0,
             line: 1,
             last_n_line: 0,
+            indent_width,
+            indent_style: 0,
+            levels: 1,
+        }
+    }
+
+    /// Returns amount of spaces that make one indentation level.
+    pub fn indent_width(&self) -> u8 {
+        if self.indent_width == 0 {
+            DEFAULT_INDENT_WIDTH
+        } else {
+            self.indent_width
+        }
+    }
+
+    /// Returns true if file is indented with tabs, false if with
+    /// spaces and none if no indented line was lexed yet.
+    pub fn uses_tabs(&self) -> Option<bool> {
+        match self.indent_style {
+            0 => None,
+            style => Some(style == b'\t'),
+        }
+    }
+
+    /// Records that line with `level` is entered. Deeper level is opened,
+    /// shallower closes all levels above it but it has to be opened
+    /// before, otherwise false is returned.
+    fn enter_level(&mut self, level: u16) -> bool {
+        let level = level.min(63);
+        let levels = self.levels | 1;
+        let current = 63 - levels.leading_zeros() as u16;
+        if level < current && levels & (1 << level) == 0 {
+            return false;
         }
+        self.levels = (levels & (u64::MAX >> (63 - level))) | (1 << level);
+        true
     }
 
     /// Returns line data corresponding to current position.
@@ -401,6 +580,20 @@ impl State {
     pub fn advance(&mut self, amount: usize) {
         self.progress += amount as u32;
     }
+
+    /// Moves the state by `delta` bytes and `line_delta` lines.
+    fn shift(&mut self, delta: isize, line_delta: isize) {
+        self.progress = (self.progress as isize + delta) as u32;
+        self.last_n_line = (self.last_n_line as isize + delta) as u32;
+        self.line = (self.line as isize + line_delta) as u32;
+    }
+
+    /// Returns true if indentation tracking of both states is same.
+    fn same_indentation(&self, other: &Self) -> bool {
+        self.indent_width == other.indent_width
+            && self.indent_style == other.indent_style
+            && self.levels == other.levels
+    }
 }
 
 /// Struct manages builtin spans and sores file contents.
@@ -410,6 +603,7 @@ pub struct Ctx {
     sources: PrimaryMap<Source, SourceEnt>,
     builtin_source: Source,
     builtin_spans: Map<(u32, u32)>,
+    operators: Operators,
 }
 
 impl Ctx {
@@ -425,6 +619,7 @@ impl Ctx {
             sources,
             builtin_source,
             builtin_spans: Map::new(),
+            operators: Operators::new(),
         }
     }
 
@@ -463,7 +658,340 @@ impl Ctx {
 
     /// Calls [`next_token`], providing source string.
     pub fn token<'a>(&'a self, state: &'a mut State) -> Result<Token> {
-        next_token(self.sources[state.source()].content(), state)
+        next_token(self.sources[state.source()].content(), state, &self.operators)
+    }
+
+    /// Calls [`Lexer::advance_recovering`], providing source string.
+    pub fn token_recovering(&self, state: &mut State, errors: &mut Vec<Error>) -> Token {
+        Lexer::new(self.sources[state.source()].content(), state, &self.operators)
+            .advance_recovering(errors)
+    }
+
+    /// Returns warning if `token` is identifier that mixes scripts and
+    /// contains characters confusable with characters of other scripts.
+    pub fn check_ident(&self, token: Token) -> Option<Warning> {
+        if token.kind() != token::Kind::Ident {
+            return None;
+        }
+        let name = self.display_token(token);
+        if name.is_ascii()
+            || name.is_single_script()
+            || !name.chars().any(is_potential_mixed_script_confusable_char)
+        {
+            return None;
+        }
+        Some(Warning::new(warning::Kind::ConfusableIdent, token))
+    }
+
+    /// Writes `tokens` as JSON lines, one object per token with kind,
+    /// byte span, line, column and text.
+    pub fn write_tokens_json(
+        &self,
+        tokens: &[Token],
+        out: &mut impl std::fmt::Write,
+    ) -> std::fmt::Result {
+        for token in tokens {
+            let range = token.range();
+            write!(
+                out,
+                "{{\"kind\":\"{:?}\",\"start\":{},\"end\":{},\"line\":{},\"column\":{},\"text\":\"",
+                token.kind(),
+                range.start,
+                range.end,
+                token.line(),
+                token.column(),
+            )?;
+            for char in self.display_token(*token).chars() {
+                match char {
+                    '"' => out.write_str("\\\"")?,
+                    '\\' => out.write_str("\\\\")?,
+                    '\n' => out.write_str("\\n")?,
+                    '\r' => out.write_str("\\r")?,
+                    '\t' => out.write_str("\\t")?,
+                    c if c.is_control() => write!(out, "\\u{:04x}", c as u32)?,
+                    c => out.write_char(c)?,
+                }
+            }
+            out.write_str("\"}\n")?;
+        }
+        Ok(())
+    }
+
+    /// Reconstructs content of `source` from its `tokens` and trivia
+    /// between them. Trivia has to contain only whitespace and ignored
+    /// comments. On failure, byte offset of the problem is returned.
+    pub fn round_trip(&self, source: Source, tokens: &[Token]) -> std::result::Result<(), usize> {
+        let content = self.sources[source].content();
+        let mut result = String::with_capacity(content.len());
+        for token in tokens {
+            let range = token.range();
+            if range.start < result.len() {
+                return Err(range.start);
+            }
+            let trivia = &content[result.len()..range.start];
+            let mut state = State::default();
+            match Lexer::new(trivia, &mut state, &self.operators).advance() {
+                Ok(token) if token.kind() == token::Kind::Eof => (),
+                _ => return Err(result.len()),
+            }
+            result.push_str(trivia);
+            result.push_str(&content[range]);
+        }
+
+        if result != content {
+            return Err(result.len());
+        }
+
+        Ok(())
+    }
+
+    /// Lexes whole source into `stream` starting from `state`. Errors do
+    /// not stop the lexing, they are collected into `errors`. Trivia is
+    /// computed as well if `stream` was created with [`TokenStream::with_trivia`].
+    pub fn lex_stream(&self, mut state: State, stream: &mut TokenStream, errors: &mut Vec<Error>) {
+        stream.tokens.clear();
+        stream.states.clear();
+        stream.trivia.clear();
+        loop {
+            stream.states.push(state);
+            let token = self.token_recovering(&mut state, errors);
+            stream.tokens.push(token);
+            if token.kind() == token::Kind::Eof {
+                break;
+            }
+        }
+        if stream.keep_trivia {
+            stream.trivia.resize(stream.tokens.len(), Trivia::default());
+            stream.compute_trivia(0..stream.tokens.len());
+        }
+    }
+
+    /// Collects spans of comments inside `trivia` span into `comments`.
+    pub fn trivia_comments(&self, trivia: Span, comments: &mut Vec<Span>) {
+        let range = trivia.range();
+        let mut state = State {
+            source: trivia.source(),
+            progress: range.start as u32,
+            ..State::default()
+        };
+        let content = self.sources[trivia.source()].content();
+        let mut lexer = Lexer::new(content, &mut state, &self.operators);
+        while lexer.progress() < range.end {
+            match lexer.peek() {
+                Some('#') => match lexer.comment() {
+                    Ok(comment) => comments.push(comment.span()),
+                    Err(_) => break,
+                },
+                Some(_) => {
+                    lexer.next();
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Replaces `range` of `source` content with `replacement` and updates
+    /// the `stream` previously lexed by [`Self::lex_stream`]. Lexing restarts
+    /// from the last indentation token before the edit and stops at first
+    /// indentation token after the edit where lexer state matches the old
+    /// one, following tokens are only moved. Errors of `source` in `errors`
+    /// are updated the same way, errors from re-lexed part are replaced.
+    /// Returns range of tokens in `stream` that changed. `range` comes from
+    /// editor so it is validated against content.
+    pub fn edit(
+        &mut self,
+        source: Source,
+        range: Range<usize>,
+        replacement: &str,
+        stream: &mut TokenStream,
+        errors: &mut Vec<Error>,
+    ) -> Result<Range<usize>> {
+        let content = self.sources[source].content();
+        if range.start > range.end
+            || range.end > content.len()
+            || !content.is_char_boundary(range.start)
+            || !content.is_char_boundary(range.end)
+        {
+            return Err(Error {
+                kind: error::Kind::InvalidEditRange,
+                token: Token::default(),
+            });
+        }
+        let removed = &content[range.clone()];
+        let line_delta = replacement.matches('\n').count() as isize
+            - removed.matches('\n').count() as isize;
+        let delta = replacement.len() as isize - removed.len() as isize;
+        self.sources[source].replace(range.clone(), replacement);
+
+        let restart = stream
+            .tokens
+            .iter()
+            .rposition(|t| {
+                matches!(t.kind(), token::Kind::Indent(_)) && t.range().start < range.start
+            })
+            .unwrap_or(0);
+        let edit_end = range.start + replacement.len();
+
+        let mut state = stream.states[restart];
+        let mut tokens = vec![];
+        let mut states = vec![];
+        let mut new_errors = vec![];
+        let mut old = restart;
+        let end = loop {
+            states.push(state);
+            let token = self.token_recovering(&mut state, &mut new_errors);
+            tokens.push(token);
+            match token.kind() {
+                token::Kind::Eof => break stream.tokens.len(),
+                token::Kind::Indent(_) if token.range().start >= edit_end => {
+                    let old_start = (token.range().start as isize - delta) as usize;
+                    while stream.tokens[old].range().start < old_start
+                        && stream.tokens[old].kind() != token::Kind::Eof
+                    {
+                        old += 1;
+                    }
+                    let candidate = stream.tokens[old];
+                    if candidate.range().start == old_start
+                        && candidate.kind() == token.kind()
+                        && stream.states[old + 1].same_indentation(&state)
+                    {
+                        break old + 1;
+                    }
+                }
+                _ => (),
+            }
+        };
+
+        // errors are located by old positions of re-lexed tokens
+        let relexed_start = if restart == 0 {
+            0
+        } else {
+            stream.tokens[restart].range().start
+        };
+        let relexed_end = stream
+            .tokens
+            .get(end)
+            .map_or(usize::MAX, |token| token.range().start);
+        let in_source = |error: &Error| error.token.source() == source;
+        errors.retain(|error| {
+            let start = error.token.range().start;
+            !in_source(error) || start < relexed_start || start >= relexed_end
+        });
+        let at = errors
+            .iter()
+            .position(|error| in_source(error) && error.token.range().start >= relexed_end)
+            .unwrap_or(errors.len());
+        for error in errors[at..].iter_mut() {
+            if in_source(error) {
+                error.token.shift(delta, line_delta);
+            }
+        }
+        errors.splice(at..at, new_errors);
+
+        for (token, state) in stream.tokens[end..]
+            .iter_mut()
+            .zip(stream.states[end..].iter_mut())
+        {
+            token.shift(delta, line_delta);
+            state.shift(delta, line_delta);
+        }
+
+        let len = tokens.len();
+        stream.tokens.splice(restart..end, tokens);
+        stream.states.splice(restart..end, states);
+
+        if stream.keep_trivia {
+            for trivia in stream.trivia[end..].iter_mut() {
+                trivia.shift(delta);
+            }
+            stream
+                .trivia
+                .splice(restart..end, std::iter::repeat(Trivia::default()).take(len));
+            let changed = restart.saturating_sub(1)..(restart + len + 1).min(stream.tokens.len());
+            stream.compute_trivia(changed);
+        }
+
+        Ok(restart..restart + len)
+    }
+
+    /// Makes lexer recognize operator `span` points to. Used when
+    /// operator function is declared.
+    pub fn add_operator(&mut self, span: Span) {
+        let name = self.display(span).to_string();
+        self.operators.add(&name);
+    }
+
+    /// Forgets all operators added by [`Self::add_operator()`] so
+    /// operators of one module do not leak into other modules.
+    pub fn reset_operators(&mut self) {
+        self.operators = Operators::new();
+    }
+
+    /// Collects operators declared by function headers of `source` into
+    /// `buffer`. Declarations are found before parsing so that operators
+    /// can be used before they are declared. Operator that is not known
+    /// yet is lexed in pieces that are joined, same as parser does.
+    pub fn declared_operators(&self, source: Source, buffer: &mut Vec<Span>) {
+        let mut tokens = vec![];
+        self.tokenize(source, &mut tokens, &mut vec![]);
+
+        let mut i = 0;
+        while i < tokens.len() {
+            i += 1;
+            if tokens[i - 1].kind() != token::Kind::Fun {
+                continue;
+            }
+
+            if matches!(tokens[i].kind(), token::Kind::Pub | token::Kind::Priv) {
+                i += 1;
+            }
+
+            if tokens[i].kind() == token::Kind::LBra {
+                let mut depth = 0;
+                while tokens[i].kind() != token::Kind::Eof {
+                    match tokens[i].kind() {
+                        token::Kind::LBra => depth += 1,
+                        token::Kind::RBra => depth -= 1,
+                        _ => (),
+                    }
+                    i += 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+            }
+
+            if tokens[i].kind() != token::Kind::Op {
+                continue;
+            }
+            let mut op = tokens[i];
+            while tokens[i + 1].kind() == token::Kind::Op
+                && tokens[i + 1].range().start == op.range().end
+            {
+                i += 1;
+                op = op.join(tokens[i]);
+            }
+            buffer.push(op.span());
+        }
+    }
+
+    /// Operators getter.
+    pub fn operators(&self) -> &Operators {
+        &self.operators
+    }
+
+    /// Lexes whole `source` into `tokens`, including the final [`token::Kind::Eof`].
+    /// Errors do not stop the lexing, they are all collected into `errors` and
+    /// represented by [`token::Kind::Error`] in the stream.
+    pub fn tokenize(&self, source: Source, tokens: &mut Vec<Token>, errors: &mut Vec<Error>) {
+        let mut state = State::new(source);
+        loop {
+            let token = self.token_recovering(&mut state, errors);
+            tokens.push(token);
+            if token.kind() == token::Kind::Eof {
+                break;
+            }
+        }
     }
 
     /// Frees the content of all sources. Should be called to
@@ -486,6 +1014,277 @@ impl Default for Ctx {
     }
 }
 
+/// Strips the common indentation from lines of multiline string content.
+/// Indentation is counted in characters, so lines have to be indented
+/// consistently with either tabs or spaces, otherwise [`None`] is returned.
+/// Blank lines do not affect the common indentation and are left empty.
+/// Blank first line (right after opening quotes) and blank last line
+/// (holding the closing quotes) are removed.
+pub fn dedent(raw: &str) -> Option<String> {
+    let mut content = raw;
+    if let Some(first) = content.find('\n') {
+        if content[..first].trim().is_empty() {
+            content = &content[first + 1..];
+        }
+    }
+    if let Some(last) = content.rfind('\n') {
+        if content[last + 1..].trim().is_empty() {
+            content = &content[..last];
+        }
+    }
+
+    let width = |char: char| match char {
+        ' ' | '\t' => Some(1),
+        '\r' => Some(0),
+        _ => None,
+    };
+
+    let mut style = None;
+    let mut common = usize::MAX;
+    for line in content.split('\n').filter(|line| !line.trim().is_empty()) {
+        let mut indent = 0;
+        for char in line.chars().take_while(|&char| width(char).is_some()) {
+            if char == '\r' {
+                continue;
+            }
+            match style {
+                None => style = Some(char),
+                Some(style) if style != char => return None,
+                _ => (),
+            }
+            indent += 1;
+        }
+        common = common.min(indent);
+    }
+
+    let mut result = String::with_capacity(content.len());
+    for (i, line) in content.split('\n').enumerate() {
+        if i != 0 {
+            result.push('\n');
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut stripped = 0;
+        let mut rest = line;
+        for char in line.chars() {
+            match width(char) {
+                Some(w) if stripped + w <= common => {
+                    stripped += w;
+                    rest = &rest[char.len_utf8()..];
+                }
+                _ => break,
+            }
+        }
+        result.push_str(rest);
+    }
+
+    Some(result)
+}
+
+/// Tokens of a source that can be updated with [`Ctx::edit`].
+/// Lexer state before each token is remembered to restart lexing.
+#[derive(Debug, Clone, Default)]
+pub struct TokenStream {
+    tokens: Vec<Token>,
+    states: Vec<State>,
+    keep_trivia: bool,
+    trivia: Vec<Trivia>,
+}
+
+impl TokenStream {
+    /// Creates stream that also tracks [`Trivia`] of each token.
+    pub fn with_trivia() -> Self {
+        Self {
+            keep_trivia: true,
+            ..Self::default()
+        }
+    }
+
+    /// Tokens getter.
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    /// Trivia getter, parallel to [`Self::tokens`]. Empty unless stream
+    /// was created with [`Self::with_trivia`].
+    pub fn trivia(&self) -> &[Trivia] {
+        &self.trivia
+    }
+
+    /// Returns trivia of token starting at the same position as `token`.
+    pub fn trivia_of(&self, token: Token) -> Option<Trivia> {
+        let start = token.range().start;
+        let index = self
+            .tokens
+            .binary_search_by(|t| t.range().start.cmp(&start))
+            .ok()?;
+        self.trivia.get(index).copied()
+    }
+
+    /// Recomputes trivia of tokens in `range`.
+    fn compute_trivia(&mut self, range: Range<usize>) {
+        for i in range {
+            self.trivia[i] = Trivia::of(&self.tokens, i);
+        }
+    }
+}
+
+/// Whitespace and ignored comments surrounding a token. Gap before
+/// indentation token (end of line) is trailing trivia of previous token,
+/// any other gap is leading trivia of following token. Together with tokens,
+/// trivia covers whole source.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, RealQuickSer)]
+pub struct Trivia {
+    leading: Span,
+    trailing: Span,
+}
+
+impl Trivia {
+    /// Computes trivia of `tokens[index]` from gaps between tokens.
+    fn of(tokens: &[Token], index: usize) -> Self {
+        let previous = index.checked_sub(1).map(|i| tokens[i]);
+        Self::around(previous, tokens[index], tokens.get(index + 1).copied())
+    }
+
+    /// Computes trivia of `token` from gaps to its neighbors. `previous`
+    /// is `None` for the first token of the source.
+    pub fn around(previous: Option<Token>, token: Token, next: Option<Token>) -> Self {
+        let range = token.range();
+        let source = token.source();
+
+        let leading = match previous {
+            Some(_) if Self::ends_line(token) => range.start..range.start,
+            Some(prev) => prev.range().end..range.start,
+            None => 0..range.start,
+        };
+        let trailing = match next {
+            Some(next) if Self::ends_line(next) => range.end..next.range().start,
+            _ => range.end..range.end,
+        };
+
+        Self {
+            leading: Span::new(source, leading),
+            trailing: Span::new(source, trailing),
+        }
+    }
+
+    /// Returns true if gap before `token` belongs to previous token.
+    fn ends_line(token: Token) -> bool {
+        matches!(token.kind(), token::Kind::Indent(_) | token::Kind::Eof)
+    }
+
+    /// Leading trivia getter.
+    pub fn leading(&self) -> Span {
+        self.leading
+    }
+
+    /// Trailing trivia getter.
+    pub fn trailing(&self) -> Span {
+        self.trailing
+    }
+
+    /// Moves both spans by `delta` bytes.
+    fn shift(&mut self, delta: isize) {
+        self.leading.shift(delta);
+        self.trailing.shift(delta);
+    }
+}
+
+/// Operators lexer knows. Sequences of operator characters are split
+/// into them so `a*-b` lexes as `*` and `-`.
+#[derive(Debug, Clone, QuickSer)]
+pub struct Operators {
+    lookup: Map<bool>,
+    list: Vec<String>,
+    max_len: u32,
+}
+
+impl Operators {
+    /// Creates table with [`BUILTIN_OPERATORS`] and punctuation.
+    pub fn new() -> Self {
+        let mut operators = Self {
+            lookup: Map::new(),
+            list: vec![],
+            max_len: 0,
+        };
+        for op in BUILTIN_OPERATORS.split(' ') {
+            operators.add(op);
+        }
+        for op in [":", "::", "->"] {
+            operators.insert(op);
+        }
+        operators
+    }
+
+    /// Adds the operator along with its assignment variant.
+    pub fn add(&mut self, op: &str) {
+        self.insert(op);
+        if !op.ends_with('=') {
+            self.insert(&format!("{}=", op));
+        }
+    }
+
+    fn insert(&mut self, op: &str) {
+        if self.lookup.insert(ID::new(op), true).is_none() {
+            self.list.push(op.to_string());
+            self.max_len = self.max_len.max(op.len() as u32);
+        }
+    }
+
+    /// Returns true if `op` is known.
+    pub fn contains(&self, op: &str) -> bool {
+        self.lookup.get(ID::new(op)).is_some()
+    }
+
+    /// Returns length of the first operator of `run` split into known
+    /// operators, or none if `run` cannot be split. Longer operators
+    /// are preferred.
+    pub fn split(&self, run: &str) -> Option<usize> {
+        // greedy split is enough for all runs of builtin operators
+        let mut rest = run;
+        while let Some(len) = self.longest_prefix(rest) {
+            rest = &rest[len..];
+        }
+        if rest.is_empty() {
+            return self.longest_prefix(run);
+        }
+
+        // splittable[i] is true if run[i..] can be split
+        let mut splittable = vec![false; run.len() + 1];
+        splittable[run.len()] = true;
+        for i in (0..run.len()).rev() {
+            splittable[i] = (1..=self.max_len as usize)
+                .filter(|&len| i + len <= run.len())
+                .any(|len| splittable[i + len] && self.contains(&run[i..i + len]));
+        }
+
+        (1..=(self.max_len as usize).min(run.len()))
+            .rev()
+            .find(|&len| splittable[len] && self.contains(&run[..len]))
+    }
+
+    fn longest_prefix(&self, run: &str) -> Option<usize> {
+        (1..=(self.max_len as usize).min(run.len()))
+            .rev()
+            .find(|&len| self.contains(&run[..len]))
+    }
+
+    /// Returns known operators that start with `char`.
+    pub fn alternatives(&self, char: char) -> impl Iterator<Item = &str> {
+        self.list
+            .iter()
+            .map(|op| op.as_str())
+            .filter(move |op| op.starts_with(char))
+    }
+}
+
+impl Default for Operators {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Represents numeric literal with its value and number of bits.
 pub enum Number {
     /// Integer literal.
@@ -502,21 +1301,28 @@ pub trait LexerBase {
     fn next(&mut self) -> Option<char>;
     /// Return same character ans peek, but do not advance.
     fn peek(&self) -> Option<char>;
+    /// Return character `n` places after the peeked one, but do not advance.
+    fn peek_nth(&self, n: usize) -> Option<char>;
 
     /// Parses the string literal, literal can be on multiple lines.
-    /// The `"` is expected as first character.
+    /// The `"` is expected as first character. Invalid escape is reported
+    /// after whole literal is consumed.
     /// ```regex
     /// "([^"]|<char_escape>)*"
     /// ```
     fn string(&mut self, mut buffer: Option<&mut String>) -> Option<error::Kind> {
         self.next();
 
+        let mut error = None;
         loop {
             match self.peek() {
                 Some('\\') => {
                     let char = match self.char_escape() {
                         Some(c) => c,
-                        None => return Some(error::Kind::InvalidCharacter),
+                        None => {
+                            error = Some(error::Kind::InvalidCharacter);
+                            continue;
+                        }
                     };
                     if let Some(buf) = buffer {
                         buf.push(char);
@@ -525,6 +1331,43 @@ pub trait LexerBase {
                 }
                 Some('"') => {
                     self.next();
+                    return error;
+                }
+                Some(_) => {
+                    let char = self.next().unwrap();
+                    if let Some(buf) = buffer {
+                        buf.push(char);
+                        buffer = Some(buf);
+                    }
+                }
+                None => {
+                    return Some(error::Kind::UnclosedString);
+                }
+            }
+        }
+    }
+
+    /// Parses the raw string literal. No escapes are processed and
+    /// literal ends with `"` followed by same amount of `#` as it started with.
+    /// The `r` is expected as first character.
+    /// ```regex
+    /// r(#*)".*?"\1
+    /// ```
+    fn raw_string(&mut self, mut buffer: Option<&mut String>) -> Option<error::Kind> {
+        self.next();
+        let mut hashes = 0;
+        while self.peek() == Some('#') {
+            self.next();
+            hashes += 1;
+        }
+        self.next();
+
+        loop {
+            match self.peek() {
+                Some('"') if (1..=hashes).all(|i| self.peek_nth(i) == Some('#')) => {
+                    for _ in 0..=hashes {
+                        self.next();
+                    }
                     return None;
                 }
                 Some(_) => {
@@ -541,6 +1384,105 @@ pub trait LexerBase {
         }
     }
 
+    /// Parses the byte string literal. Escapes are processed as in
+    /// [`Self::string`] but `\x` and octal escapes produce single byte,
+    /// other characters are utf8 encoded. The `b` is expected as first character.
+    /// ```regex
+    /// b"([^"]|<char_escape>)*"
+    /// ```
+    fn byte_string(&mut self, mut buffer: Option<&mut Vec<u8>>) -> Option<error::Kind> {
+        self.next();
+        self.next();
+
+        let mut error = None;
+        loop {
+            let (char, is_byte) = match self.peek() {
+                Some('\\') => {
+                    let is_byte = matches!(self.peek_nth(1), Some('x' | '0'..='7'));
+                    match self.char_escape() {
+                        Some(c) => (c, is_byte),
+                        None => {
+                            error = Some(error::Kind::InvalidCharacter);
+                            continue;
+                        }
+                    }
+                }
+                Some('"') => {
+                    self.next();
+                    return error;
+                }
+                Some(_) => (self.next().unwrap(), false),
+                None => {
+                    return Some(error::Kind::UnclosedString);
+                }
+            };
+            if let Some(buf) = buffer {
+                if is_byte {
+                    buf.push(char as u8);
+                } else {
+                    let mut bytes = [0; 4];
+                    buf.extend_from_slice(char.encode_utf8(&mut bytes).as_bytes());
+                }
+                buffer = Some(buf);
+            }
+        }
+    }
+
+    /// Parses the multiline string literal enclosed in `"""`. Escapes are
+    /// processed after the content is dedented by [`dedent`], so escaped
+    /// whitespace does not count as indentation. Content indented with both
+    /// tabs and spaces is rejected.
+    /// ```regex
+    /// """([^"]|<char_escape>)*"""
+    /// ```
+    fn multiline_string(&mut self, mut buffer: Option<&mut String>) -> Option<error::Kind> {
+        for _ in 0..3 {
+            self.next();
+        }
+
+        let mut raw = String::new();
+        loop {
+            match self.peek() {
+                Some('"') if self.peek_nth(1) == Some('"') && self.peek_nth(2) == Some('"') => {
+                    for _ in 0..3 {
+                        self.next();
+                    }
+                    break;
+                }
+                Some('\\') => {
+                    raw.extend(self.next());
+                    raw.extend(self.next());
+                }
+                Some(_) => raw.push(self.next().unwrap()),
+                None => {
+                    return Some(error::Kind::UnclosedString);
+                }
+            }
+        }
+
+        let dedented = match dedent(&raw) {
+            Some(dedented) => dedented,
+            None => return Some(error::Kind::MixedIndentation),
+        };
+        let mut chars = dedented.chars();
+        while let Some(char) = chars.peek() {
+            let char = if char == '\\' {
+                match chars.char_escape() {
+                    Some(char) => char,
+                    None => return Some(error::Kind::InvalidCharacter),
+                }
+            } else {
+                Iterator::next(&mut chars);
+                char
+            };
+            if let Some(buffer) = &mut buffer {
+                buffer.push(char);
+            }
+        }
+
+        None
+    }
+
     /// Gets value of valid character.
     fn character(&mut self) -> char {
         self.next();
@@ -559,7 +1501,7 @@ pub trait LexerBase {
 
     /// Parses character whether it is escaped or not.
     /// ```regex
-    /// ([^\\']|\\([ abefnrtv\\'"0]|[0-7]{3}|x[0-9a-fA-F]{2}|u[0-9a-fA-F]{4}|U[0-9a-fA-F]{8}))
+    /// ([^\\']|\\([ abefnrtv\\'"0]|[0-7]{3}|x[0-9a-fA-F]{2}|u[0-9a-fA-F]{4}|U[0-9a-fA-F]{8}|u\{[0-9a-fA-F]{1,6}\}))
     fn char_escape(&mut self) -> Option<char> {
         self.next();
         let current = self.next().unwrap_or('\0');
@@ -580,11 +1522,33 @@ pub trait LexerBase {
                 }
             }
             '0'..='7' => {
-                let mut res = 0u8 + current as u8 - '0' as u8;
+                let mut res = current as u32 - '0' as u32;
                 for _ in 0..2 {
-                    res = res * 8 + self.next()?.to_digit(8)? as u8;
+                    res = res * 8 + self.peek()?.to_digit(8)?;
+                    self.next();
+                }
+                if res > u8::MAX as u32 {
+                    return None;
                 }
-                res as char
+                res as u8 as char
+            }
+            'u' if self.peek() == Some('{') => {
+                self.next();
+                let mut res = 0u32;
+                let mut digits = 0;
+                while self.peek()? != '}' {
+                    if digits == 6 {
+                        return None;
+                    }
+                    res = res * 16 + self.peek()?.to_digit(16)?;
+                    self.next();
+                    digits += 1;
+                }
+                self.next();
+                if digits == 0 {
+                    return None;
+                }
+                return char::from_u32(res);
             }
             'x' | 'u' | 'U' => {
                 let len = match current {
@@ -596,7 +1560,8 @@ pub trait LexerBase {
 
                 let mut res = 0u32;
                 for _ in 0..len {
-                    res = res * 16 + self.next()?.to_digit(16)?;
+                    res = res * 16 + self.peek()?.to_digit(16)?;
+                    self.next();
                 }
                 return char::from_u32(res);
             }
@@ -604,89 +1569,257 @@ pub trait LexerBase {
         })
     }
 
-    /// Parses number token. Literals allow underscores,
-    /// hex and bin literals are also supported. After the
-    /// value, type can also be specified.
+    /// Parses number token. Literals allow underscores, hex and bin
+    /// literals are also supported. Decimal floats can have exponent,
+    /// hex floats require binary exponent. After the value, type can
+    /// also be specified. Value has to fit into the specified type and
+    /// floats are correctly rounded. Signed literals can hold the magnitude
+    /// of the minimal value so that `-128i8` is representable.
     /// ```regex
-    /// ([0-9_]+([0-9_])?|0x[0-9a-fA-F_]+|0b[01_]+)((i|u|f)[0-9]{0, 2})?
+    /// ([0-9_]+(\.[0-9_]*)?([eE][+-]?[0-9_]+)?|0x[0-9a-fA-F_]+(\.[0-9a-fA-F_]*)?([pP][+-]?[0-9_]+)?|0b[01_]+)((i|u|f)[0-9]{0, 2})?
     /// ```
-    fn number(&mut self) -> Option<Number> {
-        let mut number = self.parse_number(10)?.0;
-        let (fraction, exponent, is_float) = match self.peek() {
-            Some('.') => {
-                self.next();
-                let (f, e) = self.parse_number(10)?;
-                (f, e, true)
-            }
-            Some('x') if number == 0 => {
-                self.next();
-                number = self.parse_number(16)?.0;
-                (0, 0, false)
+    fn number(&mut self) -> std::result::Result<Number, error::Kind> {
+        let radix = match (self.peek(), self.peek_nth(1)) {
+            (Some('0'), Some('x')) => 16,
+            (Some('0'), Some('b')) => 2,
+            _ => 10,
+        };
+        if radix != 10 {
+            self.next();
+            self.next();
+        }
+
+        // decimal floats are parsed from text by std that rounds correctly,
+        // binary floats are rounded from mantissa and exponent
+        let mut text = String::new();
+        let mut value = Some(0u64);
+        let mut mantissa = 0u64;
+        let mut sticky = false;
+        let mut exponent = 0i32;
+        let mut digit_count = 0;
+        let mut is_float = false;
+
+        let digit_bits = if radix == 16 { 4 } else { 1 };
+        let mut in_fraction = false;
+        loop {
+            let char = match self.peek() {
+                Some('_') => {
+                    self.next();
+                    continue;
+                }
+                // '..' is a range, not a fraction
+                Some('.')
+                    if radix != 2
+                        && !in_fraction
+                        && self.peek_nth(1) != Some('.')
+                        && !self
+                            .peek_nth(1)
+                            .map_or(false, |c| c.is_alphabetic() && !c.is_digit(radix)) =>
+                {
+                    self.next();
+                    text.push('.');
+                    in_fraction = true;
+                    is_float = true;
+                    continue;
+                }
+                Some(char) => char,
+                None => break,
+            };
+            let digit = match char.to_digit(radix) {
+                Some(digit) => digit as u64,
+                None => break,
+            };
+            self.next();
+            text.push(char);
+            digit_count += 1;
+
+            if !in_fraction {
+                value = value
+                    .and_then(|v| v.checked_mul(radix as u64))
+                    .and_then(|v| v.checked_add(digit));
             }
-            Some('b') if number == 0 => {
-                self.next();
-                number = self.parse_number(2)?.0;
-                (0, 0, false)
+
+            if mantissa >> 60 == 0 {
+                mantissa = mantissa * radix as u64 + digit;
+                if in_fraction {
+                    exponent -= digit_bits;
+                }
+            } else {
+                sticky |= digit != 0;
+                if !in_fraction {
+                    exponent += digit_bits;
+                }
             }
-            _ => (0, 0, false),
-        };
-        let next_char = self.peek().unwrap_or('\0');
-        Some(match next_char {
-            'i' | 'u' | 'f' => {
+        }
+
+        if digit_count == 0 {
+            return Err(error::Kind::InvalidNumber);
+        }
+
+        let exponent_char = if radix == 16 { 'p' } else { 'e' };
+        if radix != 2 && self.peek().map(|c| c.to_ascii_lowercase()) == Some(exponent_char) {
+            let sign = match self.peek_nth(1) {
+                Some(sign @ ('+' | '-')) => Some(sign),
+                _ => None,
+            };
+            let first = self.peek_nth(1 + sign.is_some() as usize);
+            if first.map_or(false, |c| c.is_digit(10)) {
                 self.next();
-                let base = self.parse_number(10)?.0 as u8;
-                let base = if base == 0 {
-                    POINTER_WIDTH_MARKER
-                } else {
-                    base
-                };
-                match next_char {
-                    'i' => Number::Int(number as i64, base),
-                    'u' => Number::Uint(number, base),
-                    'f' => Number::Float(number as f64 + (fraction as f64 / exponent as f64), base),
-                    _ => unreachable!(),
+                text.push('e');
+                if let Some(sign) = sign {
+                    self.next();
+                    text.push(sign);
                 }
-            }
-            _ => {
-                if fraction == 0 && !is_float {
-                    Number::Int(number as i64, POINTER_WIDTH_MARKER)
-                } else {
-                    Number::Float(
-                        number as f64 + (fraction as f64 / exponent as f64),
-                        POINTER_WIDTH_MARKER,
-                    )
+                let mut binary_exponent = 0i32;
+                loop {
+                    match self.peek() {
+                        Some('_') => {
+                            self.next();
+                        }
+                        Some(char) if char.is_digit(10) => {
+                            self.next();
+                            text.push(char);
+                            binary_exponent = binary_exponent
+                                .saturating_mul(10)
+                                .saturating_add(char.to_digit(10).unwrap() as i32);
+                        }
+                        _ => break,
+                    }
+                }
+                if sign == Some('-') {
+                    binary_exponent = -binary_exponent;
                 }
+                exponent = exponent.saturating_add(binary_exponent);
+                is_float = true;
+            } else if radix == 16 {
+                return Err(error::Kind::InvalidNumber);
             }
-        })
-    }
+        } else if radix == 16 && is_float {
+            // hex fraction without exponent is ambiguous
+            return Err(error::Kind::InvalidNumber);
+        }
 
-    /// Parses simple integer number with underscores. Second value is
-    /// exponent of the number, which is closes power of 10
-    /// smaller the first element.
-    /// ```regex
-    /// [0-9_]+
-    /// ```
-    fn parse_number(&mut self, base: u64) -> Option<(u64, u64)> {
-        let mut number = 0u64;
-        let mut exponent = 1u64;
-        let base32 = base as u32;
-        loop {
-            let ch = self.peek().unwrap_or('\0');
-            if ch == '_' {
+        let suffix = match self.peek() {
+            Some(char @ ('i' | 'u' | 'f')) => {
                 self.next();
-                continue;
+                let mut width = 0u32;
+                while let Some(digit) = self.peek().and_then(|c| c.to_digit(10)) {
+                    self.next();
+                    width = width.saturating_mul(10).saturating_add(digit);
+                }
+                Some((char, width))
             }
+            _ => None,
+        };
 
-            let char = self.peek().map(|d| d.to_digit(base32)).flatten();
-            if char.is_none() {
-                return Some((number, exponent));
+        let width = match suffix {
+            Some((_, 0)) | None => POINTER_WIDTH_MARKER,
+            Some(('f', width @ (32 | 64))) => width as u8,
+            Some(('i' | 'u', width @ (8 | 16 | 32 | 64))) => width as u8,
+            _ => return Err(error::Kind::InvalidNumber),
+        };
+
+        let suffix = suffix.map(|(char, _)| char);
+        if is_float || suffix == Some('f') {
+            if suffix.map_or(false, |char| char != 'f') {
+                return Err(error::Kind::InvalidNumber);
             }
-            self.next();
 
-            number = number * base + char.unwrap() as u64;
-            exponent *= base;
+            let single = width == 32;
+            let value = if radix == 10 {
+                if single {
+                    text.parse::<f32>().map(|v| v as f64).ok()
+                } else {
+                    text.parse::<f64>().ok()
+                }
+            } else {
+                round_binary_float(mantissa, sticky, exponent, single)
+            };
+
+            return match value {
+                Some(value) if value.is_finite() => Ok(Number::Float(value, width)),
+                _ => Err(error::Kind::FloatOutOfRange(width)),
+            };
+        }
+
+        // signed literals may reach the magnitude of the minimal value, the
+        // range is checked once the negation is folded
+        let signed = suffix != Some('u');
+        let bits = if width == POINTER_WIDTH_MARKER { 64 } else { width as u32 };
+        let max = if signed {
+            1 << (bits - 1)
+        } else {
+            u64::MAX >> (64 - bits)
+        };
+        match value {
+            Some(value) if value <= max => Ok(if signed {
+                Number::Int(value as i64, width)
+            } else {
+                Number::Uint(value, width)
+            }),
+            _ => Err(error::Kind::IntegerOutOfRange(width, signed)),
+        }
+    }
+}
+
+/// Rounds `mantissa * 2^exponent` to nearest f32 (`single`) or f64,
+/// ties to even. `sticky` indicates nonzero digits that did not fit
+/// into mantissa. Returns [`None`] on overflow.
+fn round_binary_float(mantissa: u64, sticky: bool, exponent: i32, single: bool) -> Option<f64> {
+    if mantissa == 0 {
+        return Some(0.0);
+    }
+
+    let (precision, min_exp, max_exp): (i32, i32, i32) = if single {
+        (24, -126, 127)
+    } else {
+        (53, -1022, 1023)
+    };
+
+    // value is now 1.xxx * 2^exp with leading one at bit 63
+    let shift = mantissa.leading_zeros();
+    let normalized = mantissa << shift;
+    let mut exp = exponent.saturating_add(63 - shift as i32);
+    if exp > max_exp {
+        return None;
+    }
+
+    // subnormals lose precision
+    let precision = if exp < min_exp {
+        precision - min_exp.saturating_sub(exp).min(precision + 1)
+    } else {
+        precision
+    };
+    if precision < 0 {
+        return Some(0.0);
+    }
+
+    let (mut rounded, remainder) = if precision == 0 {
+        (0, normalized)
+    } else {
+        (normalized >> (64 - precision), normalized << precision)
+    };
+    let half = 1u64 << 63;
+    if remainder > half || (remainder == half && (sticky || rounded & 1 == 1)) {
+        rounded += 1;
+        if precision > 0 && rounded >> precision != 0 {
+            rounded >>= 1;
+            exp += 1;
+            if exp > max_exp {
+                return None;
+            }
         }
     }
+
+    // scaling is split so that both factors are normal and product exact
+    let scale = exp - (precision - 1);
+    let half_scale = scale / 2;
+    Some(rounded as f64 * pow2(half_scale) * pow2(scale - half_scale))
+}
+
+/// Returns `2^exp`, `exp` has to be in range of normal f64 exponents.
+fn pow2(exp: i32) -> f64 {
+    f64::from_bits(((exp + 1023) as u64) << 52)
 }
 
 crate::impl_entity!(Source);
@@ -734,6 +1867,11 @@ impl SourceEnt {
     pub fn clear(&mut self) {
         self.content.clear()
     }
+
+    /// Replaces the `range` of content with `replacement`.
+    pub fn replace(&mut self, range: Range<usize>, replacement: &str) {
+        self.content.replace_range(range, replacement);
+    }
 }
 
 /// Token is basic lex element that points to the sequence of source code.
@@ -755,6 +1893,12 @@ impl Token {
         }
     }
 
+    /// Moves the token by `delta` bytes and `line_delta` lines.
+    fn shift(&mut self, delta: isize, line_delta: isize) {
+        self.span.shift(delta);
+        self.line_data.line = (self.line_data.line as isize + line_delta) as u32;
+    }
+
     /// Returns kind of the token.
     pub fn kind(&self) -> token::Kind {
         self.kind
@@ -876,6 +2020,14 @@ pub mod token {
         Let,
         /// Keyword 'for' indicates any loop.
         For,
+        /// Keyword 'in' separates item and iterator in for loop.
+        In,
+        /// Keyword 'match' indicates match expression.
+        Match,
+        /// Keyword 'const' indicates compile time constant.
+        Const,
+        /// Keyword 'type' indicates type alias.
+        Type,
         /// Keyword 'break' indicates break statement but also file break.
         Break,
         /// Keyword 'continue' indicates continue statement.
@@ -935,6 +2087,12 @@ pub mod token {
         Char,
         /// string literal
         String,
+        /// raw string literal, no escapes are processed
+        RawString,
+        /// byte string literal, folds into array of bytes
+        ByteString,
+        /// multiline string literal, its content is dedented
+        MultilineString,
 
         /// Comment can be Documentation comment (true) or juts ignored comment (false).
         Comment(bool),
@@ -965,6 +2123,10 @@ pub mod token {
                 Self::Var => "'var'",
                 Self::Let => "'let'",
                 Self::For => "'for'",
+                Self::In => "'in'",
+                Self::Match => "'match'",
+                Self::Const => "'const'",
+                Self::Type => "'type'",
                 Self::Break => "'break'",
                 Self::Continue => "'continue'",
                 Self::Struct => "'struct'",
@@ -996,6 +2158,9 @@ pub mod token {
                 Self::Bool(_) => "boolean",
                 Self::Char => "character",
                 Self::String => "string",
+                Self::RawString => "raw string",
+                Self::ByteString => "byte string",
+                Self::MultilineString => "multiline string",
                 Self::Eof => "end of file",
                 Self::None => "nothing",
                 Self::Error => "error",
@@ -1106,6 +2271,58 @@ impl ErrorDisplayState<Error> for Ctx {
             error::Kind::UnclosedString => {
                 writeln!(f, "unclosed string literal")?;
             }
+            error::Kind::InvalidNumber => {
+                writeln!(
+                    f,
+                    "invalid number literal, valid suffixes are i, u, i8-i64, u8-u64, f, f32 and f64"
+                )?;
+            }
+            error::Kind::IntegerOutOfRange(width, signed) => {
+                let name = match (width, signed) {
+                    (POINTER_WIDTH_MARKER, true) => "int".to_string(),
+                    (POINTER_WIDTH_MARKER, false) => "uint".to_string(),
+                    (width, true) => format!("i{}", width),
+                    (width, false) => format!("u{}", width),
+                };
+                writeln!(f, "integer literal does not fit into '{}'", name)?;
+            }
+            error::Kind::UnknownOperator => {
+                let run = self.display_token(e.token());
+                writeln!(f, "sequence of operators cannot be split into known operators")?;
+                let unknown = run
+                    .chars()
+                    .find(|&c| self.operators.alternatives(c).next().is_none());
+                if let Some(char) = unknown {
+                    writeln!(f, "no operator starts with '{}'", char)?;
+                } else {
+                    let first = Iterator::next(&mut run.chars()).unwrap();
+                    write!(f, "valid operators starting with '{}':", first)?;
+                    for op in self.operators.alternatives(first) {
+                        write!(f, " {}", op)?;
+                    }
+                    writeln!(f)?;
+                }
+            }
+            error::Kind::MixedIndentation => {
+                writeln!(f, "indentation mixes tabs and spaces")?;
+            }
+            error::Kind::InconsistentIndentation(tabs) => {
+                let (expected, found) = if tabs { ("tabs", "spaces") } else { ("spaces", "tabs") };
+                writeln!(f, "file is indented with {} but this line uses {}", expected, found)?;
+            }
+            error::Kind::IndentNotMultiple(width) => {
+                writeln!(f, "indentation is not a multiple of {} spaces", width)?;
+            }
+            error::Kind::UnmatchedDedent => {
+                writeln!(f, "dedent does not match any enclosing indentation level")?;
+            }
+            error::Kind::InvalidEditRange => {
+                writeln!(f, "edited range is out of bounds or splits a character")?;
+            }
+            error::Kind::FloatOutOfRange(width) => {
+                let width = if width == POINTER_WIDTH_MARKER { 64 } else { width };
+                writeln!(f, "float literal is too large for 'f{}'", width)?;
+            }
         }
 
         Ok(())
@@ -1116,6 +2333,66 @@ impl ErrorDisplayState<Error> for Ctx {
     }
 }
 
+impl ErrorDisplayState<Warning> for Ctx {
+    fn fmt(&self, e: &Warning, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match e.kind() {
+            warning::Kind::ConfusableIdent => {
+                let name = self.display_token(e.token());
+                writeln!(
+                    f,
+                    "identifier mixes scripts and can be confused with '{}'",
+                    skeleton(name).collect::<String>()
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn sources(&self) -> &Ctx {
+        self
+    }
+}
+
+/// Warning reported by lexer, it does not stop the compilation.
+#[derive(Debug, Clone, Copy)]
+pub struct Warning {
+    kind: warning::Kind,
+    token: Token,
+}
+
+impl Warning {
+    /// Because of private fields.
+    pub fn new(kind: warning::Kind, token: Token) -> Self {
+        Self { kind, token }
+    }
+
+    /// Returns the kind of the warning.
+    pub fn kind(&self) -> warning::Kind {
+        self.kind
+    }
+
+    /// Returns the token warning points to.
+    pub fn token(&self) -> Token {
+        self.token
+    }
+}
+
+impl DisplayError for Warning {
+    fn token(&self) -> Token {
+        self.token
+    }
+}
+
+mod warning {
+    /// Kind of warning that was encountered.
+    #[derive(Debug, Clone, Copy)]
+    pub enum Kind {
+        /// Identifier mixes scripts and contains confusable characters.
+        ConfusableIdent,
+    }
+}
+
 /// Error returned by lexer.
 #[derive(Debug)]
 pub struct Error {
@@ -1161,6 +2438,26 @@ mod error {
         UnclosedCharacter,
         /// Unclosed string literal.
         UnclosedString,
+        /// Malformed number literal or invalid type suffix.
+        InvalidNumber,
+        /// Integer literal does not fit into width (8 - 64 or pointer
+        /// width marker), bool indicates signed type.
+        IntegerOutOfRange(u8, bool),
+        /// Float literal is too large for width.
+        FloatOutOfRange(u8),
+        /// Sequence of operator characters cannot be split into known operators.
+        UnknownOperator,
+        /// Line indentation contains both tabs and spaces.
+        MixedIndentation,
+        /// Line is indented differently from the rest of the file,
+        /// bool indicates the file uses tabs.
+        InconsistentIndentation(bool),
+        /// Amount of spaces is not multiple of indent width.
+        IndentNotMultiple(u8),
+        /// Dedent to level that is not enclosing the line.
+        UnmatchedDedent,
+        /// Edited range is out of bounds of the source or splits a character.
+        InvalidEditRange,
     }
 }
 
@@ -1183,6 +2480,12 @@ impl Span {
         }
     }
 
+    /// Moves the span by `delta` bytes.
+    fn shift(&mut self, delta: isize) {
+        self.start = (self.start as isize + delta) as u32;
+        self.end = (self.end as isize + delta) as u32;
+    }
+
     /// performs slicing operation on the span.
     pub fn slice(&self, range: Range<usize>) -> Span {
         Self {
@@ -1303,4 +2606,278 @@ pub fn test() {
         }
         println!("{}", Display::new(&main_state, &token));
     }
+
+    let mut tokens = vec![];
+    let mut errors = vec![];
+    main_state.tokenize(source, &mut tokens, &mut errors);
+    assert!(errors.is_empty());
+    main_state.round_trip(source, &tokens).unwrap();
+    let mut json = String::new();
+    main_state.write_tokens_json(&tokens, &mut json).unwrap();
+    assert_eq!(json.lines().count(), tokens.len());
+    assert!(json.lines().all(|line| line.starts_with("{\"kind\":") && line.ends_with("\"}")));
+
+    for &(literal, expected) in [
+        ("1.5e-3", 1.5e-3),
+        ("2E+2", 200.0),
+        ("0x1p4", 16.0),
+        ("0x1.8p-1", 0.75),
+        ("1_000.000_1", 1000.0001),
+        ("0.1f32", 0.1f32 as f64),
+        ("3f", 3.0),
+        ("0x1p-1074", f64::from_bits(1)),
+        ("0x1.fffffffffffff8p0", 2.0),
+    ]
+    .iter()
+    {
+        let mut state = State::default();
+        match Lexer::new(literal, &mut state, main_state.operators()).number() {
+            Ok(Number::Float(value, _)) => assert_eq!(value, expected, "{}", literal),
+            _ => panic!("{} is not a valid float", literal),
+        }
+    }
+
+    for literal in ["128i8", "9223372036854775808i64"] {
+        let mut state = State::default();
+        let mut lexer = Lexer::new(literal, &mut state, main_state.operators());
+        assert!(lexer.number().is_ok(), "{}", literal);
+    }
+
+    for literal in ["300u8", "129i8", "18446744073709551616u", "0x1.8", "1e400", "1e-2i32", "5i7"] {
+        let mut state = State::default();
+        let mut lexer = Lexer::new(literal, &mut state, main_state.operators());
+        assert!(lexer.number().is_err(), "{}", literal);
+    }
+
+    let mut buffer = String::new();
+    assert!(r##"r#"a"\n"#"##.chars().raw_string(Some(&mut buffer)).is_none());
+    assert_eq!(buffer, "a\"\\n");
+
+    buffer.clear();
+    let literal = "\"\"\"\n    a\n\n      b\\tc\n    d\n    \"\"\"";
+    assert!(literal.chars().multiline_string(Some(&mut buffer)).is_none());
+    assert_eq!(buffer, "a\n\n  b\tc\nd");
+
+    buffer.clear();
+    let literal = "\"\"\"\n\t\ta\n\tb\n\t\"\"\"";
+    assert!(literal.chars().multiline_string(Some(&mut buffer)).is_none());
+    assert_eq!(buffer, "\ta\nb");
+
+    let literal = "\"\"\"\n    a\n\t  b\n    \"\"\"";
+    assert!(matches!(
+        literal.chars().multiline_string(None),
+        Some(error::Kind::MixedIndentation)
+    ));
+
+    let mut bytes = vec![];
+    assert!(r#"b"a\xff\u00e9""#.chars().byte_string(Some(&mut bytes)).is_none());
+    assert_eq!(bytes, [b'a', 0xff, 0xc3, 0xa9]);
+
+    for (literal, expected) in [
+        (r"'\u{41}'", Some('A')),
+        (r"'\u{1F600}'", Some('\u{1F600}')),
+        (r"'\u00e9'", Some('é')),
+        (r"'\u{}'", None),
+        (r"'\u{1234567}'", None),
+        (r"'\u{D800}'", None),
+        (r"'\u{110000}'", None),
+        (r"'\777'", None),
+        (r"'\101'", Some('A')),
+    ] {
+        let mut chars = literal.chars();
+        Iterator::next(&mut chars);
+        assert_eq!(chars.char_escape(), expected, "{}", literal);
+    }
+
+    for (literal, kind) in [
+        (r#""a""#, token::Kind::String),
+        (r##"r#"a"#"##, token::Kind::RawString),
+        (r#"b"a""#, token::Kind::ByteString),
+        ("\"\"\"a\"\"\"", token::Kind::MultilineString),
+        ("r#", token::Kind::Ident),
+    ] {
+        let mut state = State::default();
+        let mut lexer = Lexer::new(literal, &mut state, main_state.operators());
+        let token = lexer.advance().unwrap();
+        assert_eq!(token.kind(), kind, "{}", literal);
+    }
+
+    let source = main_state.add_source(SourceEnt::new(
+        "recovery.mf".to_string(),
+        "var a = 300u8 \u{a7} b\"\\q\" $ '\\q' c\n\"unclosed".to_string(),
+    ));
+    let mut tokens = vec![];
+    let mut errors = vec![];
+    main_state.tokenize(source, &mut tokens, &mut errors);
+    for error in errors.iter() {
+        println!("{}", ErrorDisplay::new(&main_state, error));
+    }
+    assert_eq!(errors.len(), 6);
+    assert_eq!(
+        tokens.iter().filter(|t| t.kind() == token::Kind::Error).count(),
+        errors.len()
+    );
+    assert!(tokens.iter().any(|t| main_state.display_token(*t) == "c"));
+    assert_eq!(tokens.last().unwrap().kind(), token::Kind::Eof);
+
+    let lex = |source: &str, width: u8| {
+        let mut state = State::with_indent_width(BUILTIN_SOURCE, width);
+        let mut levels = vec![];
+        loop {
+            let token = Lexer::new(source, &mut state, main_state.operators()).advance()?;
+            match token.kind() {
+                token::Kind::Indent(level) => levels.push(level),
+                token::Kind::Eof => break,
+                _ => (),
+            }
+        }
+        Ok::<_, Error>(levels)
+    };
+
+    assert_eq!(lex("a:\n  b\n\n      c\n  d\n # x\ne", 2).unwrap(), [1, 0, 3, 1, 0, 0]);
+    for (source, width) in [
+        ("a:\n  b\n\tc", 2),
+        ("a:\n \tb", 2),
+        ("a:\n   b", 2),
+        ("a:\n  b", 4),
+        ("a:\n    b\n  c", 2),
+    ] {
+        assert!(lex(source, width).is_err(), "{:?}", source);
+    }
+    assert_eq!(lex("a:\n    b\n\n  # c\n        d", 4).unwrap(), [1, 0, 0, 2]);
+
+    let mut operators = Operators::new();
+    let split = |source: &str, operators: &Operators| {
+        let mut state = State::default();
+        let mut ops = vec![];
+        loop {
+            let token = Lexer::new(source, &mut state, operators).advance()?;
+            match token.kind() {
+                token::Kind::Eof => break,
+                token::Kind::Ident => (),
+                _ => ops.push(source[token.range()].to_string()),
+            }
+        }
+        Ok::<_, Error>(ops)
+    };
+
+    assert_eq!(split("a*-b", &operators).unwrap(), ["*", "-"]);
+    assert_eq!(split("x=-a", &operators).unwrap(), ["=", "-"]);
+    assert_eq!(split("x+=-a", &operators).unwrap(), ["+=", "-"]);
+    assert_eq!(split("x++=a", &operators).unwrap(), ["++="]);
+    assert_eq!(split("a<+>b", &operators).unwrap(), ["<", "+", ">"]);
+    assert!(split("a+?b", &operators).is_err());
+    operators.add("<+>");
+    assert_eq!(split("a<+>b", &operators).unwrap(), ["<+>"]);
+    assert_eq!(split("a<+>=-b", &operators).unwrap(), ["<+>=", "-"]);
+
+    let source = main_state.add_source(SourceEnt::new(
+        "operators.mf".to_string(),
+        "fun a: 1 <+> 2\nfun pub [T] <+>(a, b: T) -> T: a\nfun -(a: int): a\nfun(a: int): a".to_string(),
+    ));
+    let mut declared = vec![];
+    main_state.declared_operators(source, &mut declared);
+    let declared = declared.iter().map(|&op| main_state.display(op)).collect::<Vec<_>>();
+    assert_eq!(declared, ["<+>", "-"]);
+    let op = main_state.builtin_span("<+>");
+    main_state.add_operator(op);
+    assert!(main_state.operators().contains("<+>="));
+    main_state.reset_operators();
+    assert!(!main_state.operators().contains("<+>"));
+
+    for (source, kind) in [
+        ("jm\u{e9}no", token::Kind::Ident),
+        ("_\u{3b1}1", token::Kind::Ident),
+        ("\u{663}a", token::Kind::Error),
+    ] {
+        let mut state = State::default();
+        let kind_of = match Lexer::new(source, &mut state, &operators).advance() {
+            Ok(token) => token.kind(),
+            Err(_) => token::Kind::Error,
+        };
+        assert_eq!(kind_of, kind, "{}", source);
+    }
+
+    let source = main_state.add_source(SourceEnt::new(
+        "confusable.mf".to_string(),
+        "p\u{430}ss pass \u{43f}\u{430}\u{441}".to_string(),
+    ));
+    let mut tokens = vec![];
+    let mut errors = vec![];
+    main_state.tokenize(source, &mut tokens, &mut errors);
+    let warnings = tokens
+        .iter()
+        .filter_map(|&token| main_state.check_ident(token))
+        .collect::<Vec<_>>();
+    for warning in warnings.iter() {
+        println!("{}", ErrorDisplay::new(&main_state, warning));
+    }
+    assert_eq!(warnings.len(), 1);
+
+    let text = "fun a:\n  let b = 1\n  return b\n\nfun c:\n  pass\n";
+    let source = main_state.add_source(SourceEnt::new("edit.mf".to_string(), text.to_string()));
+    let mut stream = TokenStream::with_trivia();
+    let mut edit_errors = vec![];
+    main_state.lex_stream(State::new(source), &mut stream, &mut edit_errors);
+    let edits = [
+        (17..18, "10 + 2"),
+        (5..5, "bc"),
+        (35..35, "\n  x = \"\n\""),
+        (1..1, "\u{1}"),
+        (17..17, "\u{2}"),
+        (1..2, ""),
+    ];
+    for (range, replacement) in edits {
+        let changed = main_state
+            .edit(source, range, replacement, &mut stream, &mut edit_errors)
+            .unwrap();
+        let mut fresh = TokenStream::with_trivia();
+        let mut fresh_errors = vec![];
+        main_state.lex_stream(State::new(source), &mut fresh, &mut fresh_errors);
+        assert!(changed.len() < fresh.tokens().len(), "{:?}", changed);
+        for (a, b) in stream.tokens().iter().zip(fresh.tokens()) {
+            assert_eq!((a.kind(), a.range(), a.line_data()), (b.kind(), b.range(), b.line_data()));
+        }
+        assert_eq!(stream.tokens().len(), fresh.tokens().len());
+        assert_eq!(stream.trivia(), fresh.trivia());
+        let ranges = |errors: &[Error]| {
+            errors
+                .iter()
+                .map(|e| (e.token().range(), e.token().line_data()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ranges(&edit_errors), ranges(&fresh_errors));
+    }
+    assert_eq!(edit_errors.len(), 1);
+
+    let len = main_state.source(source).content().len();
+    for range in [0..len + 1, 2..1] {
+        assert!(main_state.edit(source, range, "", &mut stream, &mut edit_errors).is_err());
+    }
+    main_state.edit(source, 0..0, "\u{e9}", &mut stream, &mut edit_errors).unwrap();
+    assert!(main_state.edit(source, 1..1, "", &mut stream, &mut edit_errors).is_err());
+
+    let text = "fun a: # first\n  # own line\n  pass #[ block ]# # end\n";
+    let source = main_state.add_source(SourceEnt::new("trivia.mf".to_string(), text.to_string()));
+    let mut stream = TokenStream::with_trivia();
+    main_state.lex_stream(State::new(source), &mut stream, &mut errors);
+    let mut printed = String::new();
+    let mut comments = vec![];
+    for (&token, trivia) in stream.tokens().iter().zip(stream.trivia()) {
+        printed.push_str(main_state.display(trivia.leading()));
+        printed.push_str(main_state.display_token(token));
+        printed.push_str(main_state.display(trivia.trailing()));
+        main_state.trivia_comments(trivia.leading(), &mut comments);
+        main_state.trivia_comments(trivia.trailing(), &mut comments);
+    }
+    assert_eq!(printed, text);
+    let comments = comments
+        .iter()
+        .map(|&span| main_state.display(span))
+        .collect::<Vec<_>>();
+    assert_eq!(comments, ["# first", "# own line", "#[ block ]#", "# end"]);
+    let pass = stream.tokens()[stream.tokens().len() - 3];
+    assert_eq!(main_state.display_token(pass), "pass");
+    let trailing = stream.trivia_of(pass).unwrap().trailing();
+    assert_eq!(main_state.display(trailing), " #[ block ]# # end");
 }
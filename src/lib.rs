@@ -0,0 +1,20 @@
+//! Stages of the metaflow compiler. The executable
+//! lives in `main.rs`, library exists so that the stages can be driven
+//! from other targets, fuzzing harness for example.
+
+#![feature(vec_into_raw_parts)]
+//#![warn(missing_docs)]
+
+pub mod ast;
+//pub mod entities;
+pub mod format;
+pub mod functions;
+pub mod gen;
+pub mod incr;
+pub mod lexer;
+pub mod modules;
+pub mod types;
+pub mod util;
+
+/// Crate version used for validating incremental data.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -1,24 +1,17 @@
 //! Crate metaflow is a compiler executable for metaflow language.
 
-#![feature(vec_into_raw_parts)]
-//#![warn(missing_docs)]
-
-//use std::time::Instant;
-
-//use gen::GErrorDisplay;
+#[cfg(not(feature = "testing"))]
+use std::{
+    path::{Path, PathBuf},
+    time::Instant,
+};
 
-pub mod ast;
-//pub mod entities;
-//pub mod functions;
-//pub mod gen;
-pub mod incr;
-pub mod lexer;
-pub mod modules;
-pub mod types;
-pub mod util;
+use metaflow::{ast, format, gen, lexer, modules, util};
+#[cfg(feature = "testing")]
+use metaflow::{functions, types};
 
-/// Crate version used for validating incremental data.
-pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+#[cfg(not(feature = "testing"))]
+use lexer::ErrorDisplay;
 
 fn main() {
     #[cfg(feature = "testing")]
@@ -29,7 +22,7 @@ fn main() {
 
 #[cfg(not(feature = "testing"))]
 fn run() {
-    /*let args = match util::cli::Arguments::new(std::env::args()) {
+    let args = match util::cli::Arguments::new(std::env::args()) {
         Ok(args) => args,
         Err(e) => {
             println!("{:?}", e);
@@ -37,21 +30,222 @@ fn run() {
         }
     };
 
+    if args.get(0).map(String::as_str) == Some("tokens") {
+        if let Err(message) = tokens(&args) {
+            println!("Failed to lex:\n{}", message);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if args.get(0).map(String::as_str) == Some("fmt") {
+        if let Err(message) = fmt(&args) {
+            println!("Failed to format:\n{}", message);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let now = Instant::now();
 
-    match gen::compile(args) {
-        Ok(line_count) => {
-            println!(
-                "Successfully compiled! ({} lines of code in {}s)",
-                line_count,
-                now.elapsed().as_secs_f32()
-            );
-        },
-        Err((state, err)) => println!(
-            "Failed to compile:\n {}",
-            GErrorDisplay::new(state.as_ref(), &err)
+    match compile(&args) {
+        Ok(()) => println!(
+            "Successfully compiled! ({}s)",
+            now.elapsed().as_secs_f32()
+        ),
+        Err(message) => {
+            println!("Failed to compile:\n{}", message);
+            std::process::exit(1);
+        }
+    };
+}
+
+/// Drives the compilation of project that root manifest is in `args[0]`.
+/// Stages up to functions are run by
+/// [`metaflow::functions::Ctx::compile_project`], then the generator emits
+/// and links the executable. Error is already formatted with
+/// [`ErrorDisplay`].
+#[cfg(not(feature = "testing"))]
+fn compile(args: &util::cli::Arguments) -> std::result::Result<(), String> {
+    let root = args
+        .get(0)
+        .ok_or_else(|| String::from("first argument is missing <PROJECT>"))?;
+
+    let mut ctx = gen::Ctx::default();
+
+    let result = ctx.compile_project(root);
+
+    let warnings = ctx.take_warnings();
+    let sources: &lexer::Ctx = &ctx;
+    for warning in warnings.iter() {
+        println!("warning: {}", ErrorDisplay::new(sources, warning));
+    }
+
+    result.map_err(|e| format!("{}", ErrorDisplay::new(&*ctx, &e)))?;
+
+    ctx.finish_entry_point();
+
+    ctx.build(args)
+        .map_err(|e| format!("{}", ErrorDisplay::new(&ctx, &e)))?;
+
+    Ok(())
+}
+
+/// Prints tokens of file in `args[1]` as JSON lines. With `-check` flag,
+/// it also verifies that tokens with skipped trivia reproduce the file.
+/// Lexer errors are printed to stderr and make the command fail.
+#[cfg(not(feature = "testing"))]
+fn tokens(args: &util::cli::Arguments) -> std::result::Result<(), String> {
+    let path = args
+        .get(1)
+        .ok_or_else(|| String::from("second argument is missing <FILE>"))?;
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| format!("error reading '{}', original error: {}", path, err))?;
+
+    let mut ctx = lexer::Ctx::new();
+    let source = ctx.add_source(lexer::SourceEnt::new(path.clone(), content));
+    let mut tokens = vec![];
+    let mut errors = vec![];
+    ctx.tokenize(source, &mut tokens, &mut errors);
+
+    let mut out = String::new();
+    ctx.write_tokens_json(&tokens, &mut out).unwrap();
+    print!("{}", out);
+
+    for error in errors.iter() {
+        eprintln!("{}", ErrorDisplay::new(&ctx, error));
+    }
+
+    if args.enabled("check") {
+        ctx.round_trip(source, &tokens).map_err(|offset| {
+            format!("tokens do not reproduce the file at byte {}", offset)
+        })?;
+    }
+
+    if !errors.is_empty() {
+        return Err(format!("file contains {} invalid tokens", errors.len()));
+    }
+
+    Ok(())
+}
+
+/// Formats files and directories in `args[1..]` in place. With `-check`
+/// flag, files are not written and error lists files that would change.
+/// `--indent-width` overrides amount of spaces per level, otherwise it is
+/// taken from the manifest of the package file belongs to.
+#[cfg(not(feature = "testing"))]
+fn fmt(args: &util::cli::Arguments) -> std::result::Result<(), String> {
+    if args.len() < 2 {
+        return Err(String::from("second argument is missing <FILE>"));
+    }
+    let indent_width = match args.get_flag("indent-width") {
+        Some(width) => Some(
+            width
+                .parse::<u8>()
+                .map_err(|err| format!("invalid indent width '{}': {}", width, err))?,
         ),
-    };*/
+        None => None,
+    };
+
+    let mut paths = vec![];
+    for path in &args[1..] {
+        collect_sources(Path::new(path), &mut paths)
+            .map_err(|err| format!("error reading '{}', original error: {}", path, err))?;
+    }
+
+    let mut ctx = ast::Ctx::default();
+    let mut changed = vec![];
+    for path in paths {
+        let name = path.display().to_string();
+        let content = std::fs::read_to_string(&path)
+            .map_err(|err| format!("error reading '{}', original error: {}", name, err))?;
+        let indent_width = match indent_width {
+            Some(width) => width,
+            None => manifest_indent_width(&mut ctx, &path)?,
+        };
+        let source = ctx.add_source(lexer::SourceEnt::new(name.clone(), content.clone()));
+        let mut out = String::new();
+        format::format(&mut ctx, source, indent_width, &mut out)
+            .map_err(|e| format!("{}", ErrorDisplay::new(&ctx, &e)))?;
+        if out == content {
+            continue;
+        }
+        if !args.enabled("check") {
+            std::fs::write(&path, out)
+                .map_err(|err| format!("error writing '{}', original error: {}", name, err))?;
+        }
+        changed.push(name);
+    }
+
+    if args.enabled("check") && !changed.is_empty() {
+        return Err(format!("files are not formatted:\n{}", changed.join("\n")));
+    }
+
+    Ok(())
+}
+
+/// Returns `indent_width` of the closest manifest in directories
+/// enclosing `path`, zero if manifest does not specify it.
+#[cfg(not(feature = "testing"))]
+fn manifest_indent_width(ctx: &mut ast::Ctx, path: &Path) -> std::result::Result<u8, String> {
+    let manifest_path = match path
+        .ancestors()
+        .skip(1)
+        .map(|dir| dir.join("project").with_extension(modules::MANIFEST_EXT))
+        .find(|manifest_path| manifest_path.is_file())
+    {
+        Some(manifest_path) => manifest_path,
+        None => return Ok(0),
+    };
+
+    let name = manifest_path.display().to_string();
+    let content = std::fs::read_to_string(&manifest_path)
+        .map_err(|err| format!("error reading '{}', original error: {}", name, err))?;
+    let source = ctx.add_source(lexer::SourceEnt::new(name.clone(), content));
+
+    let mut temp_data = ast::Data::default();
+    let mut saved_data = ast::Data::default();
+    let mut reloc = ast::Reloc::default();
+    let mut collector = ast::Collector::default();
+    let manifest = ast::State::new(source, ctx).and_then(|mut state| {
+        let mut data = ast::DataCollector::new(&mut temp_data, &mut saved_data, &mut reloc);
+        ast::Parser::new(&mut state, &mut data, ctx, &mut collector).parse_manifest()
+    });
+    let manifest = manifest.map_err(|e| format!("{}", ErrorDisplay::new(&*ctx, &e)))?;
+
+    match manifest.find_attr(util::sdbm::ID::new("indent_width")) {
+        Some(span) => match ctx.display(span).parse::<u8>() {
+            Ok(width) if width != 0 => Ok(width),
+            _ => Err(format!(
+                "invalid indent_width '{}' in '{}', expected number from 1 to 255",
+                ctx.display(span),
+                name
+            )),
+        },
+        None => Ok(0),
+    }
+}
+
+/// Pushes `path` to `paths` if it is a file, or all `.mf` files
+/// inside if it is a directory.
+#[cfg(not(feature = "testing"))]
+fn collect_sources(path: &Path, paths: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if !path.is_dir() {
+        paths.push(path.to_path_buf());
+        return Ok(());
+    }
+
+    let mut entries = std::fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() || entry.extension().map_or(false, |ext| ext == "mf") {
+            collect_sources(&entry, paths)?;
+        }
+    }
+
+    Ok(())
 }
 
 #[cfg(feature = "testing")]
@@ -59,8 +253,9 @@ fn test() {
     util::test();
     lexer::test();
     ast::test();
+    format::test();
     modules::test();
     types::test();
-    //functions::test();
-    //gen::test();
+    functions::test();
+    gen::test();
 }
@@ -106,7 +106,12 @@ impl Ctx {
                 continue;
             }
 
-            let mut ast_state = ast::State::new(source, &self.ctx).map_err(Into::into)?;
+            self.ctx
+                .declared_operators(source, &mut self.module_ctxs[module].operators);
+
+            let indent_width = self.manifests[manifest].indent_width;
+            let mut ast_state = ast::State::with_indent_width(source, indent_width, &self.ctx)
+                .map_err(Into::into)?;
             {
                 let mut data = ast::DataCollector::new(&mut temp_data, &mut saved_data, &mut reloc);
                 ast::Parser::new(&mut ast_state, &mut data, &mut self.ctx, &mut collector)
@@ -123,7 +128,7 @@ impl Ctx {
                     .as_os_str()
                     .to_str()
                     .unwrap();
-                let id = ID::new(head);
+                let id = ID::ident(head);
                 let manifest_ent = &self.manifests[manifest];
                 // here we see that first segment of path sets manifest
                 let manifest = if id == self.hash_span(manifest_ent.name) {
@@ -188,11 +193,15 @@ impl Ctx {
         let module_path = Path::new(root);
 
         // finding module name span
-        let name_len = module_path.file_stem().unwrap().len();
-        let whole_len = module_path.file_name().unwrap().len();
+        let (name_len, whole_len) = match (module_path.file_stem(), module_path.file_name()) {
+            (Some(stem), Some(name)) if root.ends_with(name.to_str().unwrap()) => {
+                (stem.len(), name.len())
+            }
+            _ => return Err(Error::new(error::Kind::MissingPathStem, token)),
+        };
 
         let len = in_code_path.len();
-        let name = in_code_path.slice(len - whole_len..len - name_len + whole_len);
+        let name = in_code_path.slice(len - whole_len..len - whole_len + name_len);
 
         // now we have to strip first path segment from root span and replace it with real name
         let module_path = module_path
@@ -345,9 +354,23 @@ impl Ctx {
             let name = root_file_span.slice(len - whole_len..len - whole_len + name_len);
             let root_path = root_file_span.slice(0..parent_len);
 
+            let indent_width = match manifest.find_attr(ID::new("indent_width")) {
+                Some(span) => match self.display(span).parse::<u8>() {
+                    Ok(width) if width != 0 => width,
+                    _ => {
+                        return Err(Error::new(
+                            error::Kind::InvalidIndentWidth(span),
+                            import.token(),
+                        ))
+                    }
+                },
+                None => DEFAULT_INDENT_WIDTH,
+            };
+
             let manifest_ent = &mut self.manifests[manifest_id];
             manifest_ent.name = name;
             manifest_ent.root_path = root_path;
+            manifest_ent.indent_width = indent_width;
 
             for dep in manifest.deps() {
                 path_buffer.clear();
@@ -437,14 +460,14 @@ impl Ctx {
         )
     }
 
-    /// Computes hash of span content.
+    /// Computes hash of identifier in span, see [`ID::ident`].
     pub fn hash_span(&self, span: Span) -> ID {
-        ID::new(self.display(span))
+        ID::ident(self.display(span))
     }
 
-    /// Computes hash fo token content.
+    /// Computes hash of identifier in token, see [`ID::ident`].
     pub fn hash_token(&self, token: Token) -> ID {
-        ID::new(self.display_token(token))
+        ID::ident(self.display_token(token))
     }
 
     /// Creates a module order fro given root. It returns the sequence
@@ -486,21 +509,57 @@ impl Ctx {
     }
 
     /// Computes ast of module. If true is returned, parsing was
-    /// interrupted by top level 'break'.
+    /// interrupted by top level 'break'. Lexer knows operators declared
+    /// by the module and by modules it imports, the same goes for
+    /// precedence declarations.
     pub fn compute_ast<'a>(
         &'a mut self,
         module: Mod,
         buffer: &'a mut ast::DataCollector<'a>,
         collector: &'a mut ast::Collector,
     ) -> Result<bool> {
-        ast::Parser::new(
+        self.ctx.reset_operators();
+        let module_ctx = &self.module_ctxs[module];
+        let scopes = std::iter::once(module).chain(module_ctx.deps.iter().map(|dep| dep.1));
+        for scope in scopes {
+            for &op in self.module_ctxs[scope].operators.iter() {
+                self.ctx.add_operator(op);
+            }
+        }
+        self.module_ctxs[module]
+            .ast_state
+            .relex(&self.ctx)
+            .map_err(|err| Error::new(error::Kind::AError(err), Token::default()))?;
+
+        let precedence = match self.module_ctxs[module].precedence.take() {
+            Some(precedence) => precedence,
+            None => {
+                let mut precedence = ast::Precedence::new();
+                for &(_, dep) in self.module_ctxs[module].deps.iter() {
+                    if let Some(declared) = &self.module_ctxs[dep].precedence {
+                        precedence.import(declared).map_err(|err| {
+                            Error::new(error::Kind::AError(err), Token::default())
+                        })?;
+                    }
+                }
+                precedence
+            }
+        };
+        self.ctx.swap_precedence(precedence);
+
+        let result = ast::Parser::new(
             &mut self.module_ctxs[module].ast_state,
             buffer,
             &mut self.ctx,
             collector,
         )
         .parse()
-        .map_err(|err| Error::new(error::Kind::AError(err), Token::default()))
+        .map_err(|err| Error::new(error::Kind::AError(err), Token::default()));
+
+        let precedence = self.ctx.swap_precedence(ast::Precedence::new());
+        self.module_ctxs[module].precedence = Some(precedence);
+
+        result
     }
 
     pub fn collect_imported_items(&self, module: Mod, buffer: &mut Vec<(ID, Item)>) {
@@ -584,6 +643,19 @@ impl Ctx {
         Ok(())
     }
 
+    /// Points owned item to a different entity. This is used when item
+    /// is only a placeholder until it gets resolved.
+    pub fn redirect_item(&mut self, module: Mod, id: ID, kind: item::Kind) {
+        for (item_id, item) in self.modules[module].owned_items.iter_mut() {
+            if *item_id == id {
+                item.kind = kind;
+            }
+        }
+        if let Some(item) = self.module_ctxs[module].scope.get_mut(id) {
+            item.kind = kind;
+        }
+    }
+
     pub fn push_item(&mut self, module: Mod, id: ID, item: item::Kind) -> Option<Item> {
         let item = Item::new(item, module, Token::default());
         self.module_ctxs[module].scope.insert(id, item)
@@ -606,6 +678,10 @@ impl Ctx {
     }
 
     pub fn find_attribute(&self, ast_data: &ast::Data, attributes: Ast, name: &str) -> Option<Ast> {
+        if attributes.is_reserved_value() {
+            return None;
+        }
+
         let id = ID::new(name);
         for &attr in ast_data.sons(attributes) {
             let attr_id = self.hash_token(ast_data.son_ent(attr, 0).token());
@@ -703,6 +779,7 @@ pub struct ManifestEnt {
     root_path: Span,
     deps: Vec<ManifestDep>,
     source: Source,
+    indent_width: u8,
 }
 
 impl ManifestEnt {
@@ -808,6 +885,13 @@ impl ErrorDisplayState<Error> for Ctx {
             error::Kind::DownloadFailed => {
                 writeln!(f, "failed to download dependency")?;
             }
+            error::Kind::InvalidIndentWidth(span) => {
+                writeln!(
+                    f,
+                    "invalid indent_width '{}' in manifest, expected number from 1 to 255",
+                    self.display(*span)
+                )?;
+            }
         }
 
         Ok(())
@@ -843,6 +927,12 @@ pub struct ModCtx {
     ast_state: ast::State,
     deps: Vec<(Span, Mod)>,
     used: Vec<Mod>,
+    /// Operators declared in the module, lexer knows them
+    /// only inside the module and modules that import it.
+    operators: Vec<Span>,
+    /// Operator bindings used while parsing the module, created
+    /// when parsing of the module starts.
+    precedence: Option<ast::Precedence>,
 }
 
 impl TreeStorage<Mod> for Ctx {
@@ -924,6 +1014,7 @@ mod error {
         MissingDependency(PathBuf),
         DownloadError(std::io::Error),
         DownloadFailed,
+        InvalidIndentWidth(Span),
     }
 }
 
@@ -6,6 +6,7 @@ use crate::lexer::{
 };
 use crate::modules::{self, item, Const, Item, Mod, TreeStorage, Ty, BUILTIN_MODULE};
 use crate::util::sdbm::ID;
+use crate::util::storage::Map;
 use crate::util::Size;
 use cranelift::codegen::ir::types::Type;
 use cranelift::codegen::ir::Signature as CrSignature;
@@ -17,7 +18,6 @@ use cranelift::entity::{EntityList, ListPool, PoolMap, PrimaryMap};
 use quick_proc::{QuickEnumGets, QuickSer, RealQuickSer};
 use std::fmt::Write;
 use std::ops::{Deref, DerefMut};
-use std::str::Chars;
 
 type Result<T = ()> = std::result::Result<T, Error>;
 
@@ -79,13 +79,19 @@ pub struct Ctx {
 
     enum_slices: ListPool<EnumVariant>,
     enum_variants: PrimaryMap<EnumVariant, EnumVariantEnt>,
-    constants: PoolMap<Const, constant::Kind>,
+    constants: PoolMap<Const, ConstEnt>,
     constant_slices: ListPool<Const>,
     types: PoolMap<Ty, TyEnt>,
     type_slices: ListPool<Ty>,
     fields: PoolMap<Field, FieldEnt>,
     field_slices: ListPool<Field>,
     type_cycle_map: Vec<(bool, bool)>,
+    unresolved_consts: Vec<(Const, Ast, bool)>,
+    const_stack: Vec<Const>,
+    unresolved_aliases: Vec<(Ty, ID)>,
+    alias_stack: Vec<Ty>,
+    bound_impls: Map<BoundImpl>,
+    bound_infos: Map<BoundInfo>,
 }
 
 impl Ctx {
@@ -154,7 +160,7 @@ impl Ctx {
 
                 if align != Size::ZERO {
                     match kind {
-                        StructureKind::Struct | StructureKind::Tuple => {
+                        StructureKind::Struct | StructureKind::Tuple | StructureKind::Closure => {
                             let calc = move |offset: Size| {
                                 let temp = Size::new(
                                     offset.s32() & (align.s32() - 1),
@@ -195,13 +201,21 @@ impl Ctx {
                 let element_size = self.types[element].size;
                 self.types[ty].size = Size::new(size, size).mul(element_size);
             }
-            ty::Kind::Enumeration(_)
-            | ty::Kind::Pointer(..)
+            ty::Kind::Enumeration(repr, _) => {
+                let TyEnt { size, align, .. } = self.types[repr];
+                let ty_ent = &mut self.types[ty];
+                ty_ent.size = size;
+                ty_ent.align = align;
+            }
+            ty::Kind::Pointer(..)
             | ty::Kind::Builtin(..)
             | ty::Kind::FunPointer(..) 
             | ty::Kind::Bound(_) => (),
 
-            ty::Kind::Constant(_) | ty::Kind::Generic(_) | ty::Kind::Unresolved(_) => {
+            ty::Kind::Constant(_)
+            | ty::Kind::Generic(_)
+            | ty::Kind::Unresolved(_)
+            | ty::Kind::Alias(_) => {
                 unreachable!("{:?}", ty_ent.kind)
             }
         }
@@ -230,7 +244,7 @@ impl Ctx {
 
             ast_data.set_swapped(!params.is_empty());
             match kind {
-                ty::Kind::Unresolved(ast) => {
+                ty::Kind::Unresolved(ast) | ty::Kind::Bound(ast) => {
                     self.connect_type(&ast_data, module, id, ast, depth)?
                 }
                 kind => unreachable!("{:?}", kind),
@@ -262,6 +276,7 @@ impl Ctx {
             }
             ast::Kind::Bound(_) => {
                 self.types[id].kind = ty::Kind::Bound(ast);
+                self.bound_info(ast_data, id)?;
             }
             kind => unreachable!("{:?}", kind),
         }
@@ -547,6 +562,20 @@ impl Ctx {
             return Ok(id);
         }
 
+        let is_alias = match kind {
+            ty::Kind::Generic(generic_ast) => {
+                let mut ast_data = *ast_data;
+                ast_data.set_swapped(true);
+                let generics = ast_data.son(generic_ast, 0);
+                self.check_bounds(&ast_data, original_module, generics, &params[1..], token)?;
+                matches!(ast_data.kind(generic_ast), ast::Kind::TypeAlias(_))
+            }
+            _ => false,
+        };
+        if is_alias {
+            return self.alias_instance(ast_data, ty, params.as_slice(), id, depth);
+        }
+
         let ty_ent = &self.types[ty];
 
         let ast = match ty_ent.kind {
@@ -559,12 +588,22 @@ impl Ctx {
             }
         };
 
+        let kind = {
+            let mut ast_data = *ast_data;
+            ast_data.set_swapped(true);
+            if let ast::Kind::Bound(_) = ast_data.kind(ast) {
+                ty::Kind::Bound(ast)
+            } else {
+                ty::Kind::Unresolved(ast)
+            }
+        };
+
         let type_ent = TyEnt {
             id,
             module: original_module,
             vis,
             params: EntityList::from_slice(params.as_slice(), &mut self.type_slices),
-            kind: ty::Kind::Unresolved(ast),
+            kind,
             hint,
             attrs,
             size: Size::ZERO,
@@ -595,7 +634,89 @@ impl Ctx {
             id = id.add(self.module_id(module));
         }
 
-        self.find_type(module, id, token).map_err(Into::into)
+        let ty = self.find_type(module, id, token)?;
+        self.resolve_alias(ast_data, ty)
+    }
+
+    /// Returns the aliased type if `ty` is an alias placeholder, the
+    /// placeholder is then replaced by aliased type in module scope.
+    /// Aliases are resolved on demand so order of declarations does
+    /// not matter.
+    fn resolve_alias(&mut self, ast_data: &ast::DataSwitch, ty: Ty) -> Result<Ty> {
+        let TyEnt {
+            kind, module, id, hint, ..
+        } = self.types[ty];
+        let ast = match kind {
+            ty::Kind::Alias(ast) => ast,
+            _ => return Ok(ty),
+        };
+
+        if self.alias_stack.contains(&ty) {
+            return Err(Error::new(error::Kind::SelfReferentialAlias, hint));
+        }
+
+        self.alias_stack.push(ty);
+        let mut ast_data = *ast_data;
+        ast_data.set_swapped(false);
+        let target = ast_data.sons(ast)[2];
+        let result = self.ty(&ast_data, module, target, 0);
+        self.alias_stack.pop().unwrap();
+        let target = result?;
+
+        self.redirect_item(module, id, item::Kind::Ty(target));
+        self.types.remove(ty);
+
+        Ok(target)
+    }
+
+    /// Instantiates generic alias, result is the aliased type with
+    /// parameters substituted. Instance is cached under the `id`.
+    fn alias_instance(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        alias: Ty,
+        params: &[Ty],
+        id: ID,
+        depth: usize,
+    ) -> Result<Ty> {
+        let TyEnt {
+            kind, module, hint, ..
+        } = self.types[alias];
+        let ast = match kind {
+            ty::Kind::Generic(ast) => ast,
+            _ => unreachable!("{:?}", kind),
+        };
+
+        if self.alias_stack.contains(&alias) {
+            return Err(Error::new(error::Kind::SelfReferentialAlias, hint));
+        }
+
+        let mut ast_data = *ast_data;
+        ast_data.set_swapped(true);
+        let sons = ast_data.sons(ast);
+        let (generics, target) = (sons[0], sons[2]);
+
+        let mut shadowed = self.temp_vec();
+        for (&param, &son) in params[1..].iter().zip(ast_data.sons(generics).iter()) {
+            let name = ast_data.sons(son)[0];
+            let id = self.hash_token(ast_data.token(name));
+            let shadow = self.push_item(module, id, item::Kind::Ty(param));
+            shadowed.push((id, shadow));
+        }
+
+        self.alias_stack.push(alias);
+        let result = self.ty(&ast_data, module, target, depth + 1);
+        self.alias_stack.pop().unwrap();
+
+        for (id, ty) in shadowed.drain(..) {
+            self.pop_item(module, id, ty);
+        }
+
+        let target = result?;
+        let item = Item::new(item::Kind::Ty(target), module, hint);
+        self.add_item(module, id, item).map_err(Into::into)?;
+
+        Ok(target)
     }
 
     pub fn collect(
@@ -609,7 +730,8 @@ impl Ctx {
             self.add_builtin_types();
         }
 
-        let mut temp = self.ctx.temp_vec();
+        let mut temp = self.ctx.temp_vec::<EnumVariant>();
+        let mut enums = self.ctx.temp_vec();
 
         collector.use_types(|saved, ty, attrs| {
             ast_data.set_swapped(saved);
@@ -617,26 +739,19 @@ impl Ctx {
             let sons = ast_data.slice(sons);
             match kind {
                 ast::Kind::Enum(vis) => {
-                    let (name, variants) = (sons[0], sons[1]);
-                    let variants = if !variants.is_reserved_value() {
-                        temp.clear();
-                        for &var in ast_data.sons(variants) {
-                            temp.push(self.enum_variants.push(EnumVariantEnt {
-                                id: self.hash_token(ast_data.token(var)),
-                            }));
-                        }
-                        EntityList::from_slice(temp.as_slice(), &mut self.enum_slices)
+                    enums.push((saved, ty, attrs, vis));
+                }
+                ast::Kind::TypeAlias(vis) => {
+                    let hint = ast_data.token(sons[1]);
+                    let kind = if saved {
+                        ty::Kind::Generic(ty)
                     } else {
-                        EntityList::new()
+                        ty::Kind::Alias(ty)
                     };
 
-                    let hint = ast_data.token(name);
-                    let id = self.hash_token(hint);
-
-                    let kind = ty::Kind::Enumeration(variants);
                     let datatype = TyEnt {
                         vis,
-                        id,
+                        id: self.hash_token(hint),
                         module,
                         kind,
                         attrs,
@@ -645,44 +760,630 @@ impl Ctx {
                         ..Default::default()
                     };
 
-                    self.add_type(module, datatype).map_err(Into::into)?;
+                    let ty = self.add_type(module, datatype)?;
+
+                    if !saved {
+                        let id = self.types[ty].id;
+                        self.unresolved_aliases.push((ty, id));
+                    }
                 }
                 ast::Kind::Struct(vis) | ast::Kind::Union(vis) | ast::Kind::Bound(vis) => {
                     let ident = sons[1];
                     let kind = if saved {
                         ty::Kind::Generic(ty)
+                    } else if let ast::Kind::Bound(_) = kind {
+                        // pointers to bounds are fat so the kind has to be known early
+                        ty::Kind::Bound(ty)
                     } else {
                         ty::Kind::Unresolved(ty)
                     };
                     let hint = ast_data.token(ident);
                     let id = self.hash_token(hint);
 
-                    let datatype = TyEnt {
-                        vis,
-                        id,
-                        module,
-                        kind,
-                        attrs,
-                        hint,
+                    let datatype = TyEnt {
+                        vis,
+                        id,
+                        module,
+                        kind,
+                        attrs,
+                        hint,
+
+                        ..Default::default()
+                    };
+                    
+                    let id = self.add_type(module, datatype).map_err(Into::into)?;
+
+                    if let ty::Kind::Unresolved(_) | ty::Kind::Bound(_) = &self.types[id].kind {
+                        self.unresolved.push((id, 0));
+                    }
+                }
+                kind => unreachable!("{:?}", kind),
+            }
+
+            Ok(())
+        })?;
+
+        // enum values can refer to constants, they are folded on demand
+        collector.use_consts(|saved, ast, scope| {
+            ast_data.set_swapped(saved);
+            self.collect_const(&ast_data, module, ast, scope)
+        })?;
+
+        collector.use_bound_impls(|saved, ast| {
+            ast_data.set_swapped(saved);
+            self.collect_bound_impl(&ast_data, module, ast)
+        })?;
+
+        for &(saved, ty, attrs, vis) in enums.iter() {
+            ast_data.set_swapped(saved);
+            self.collect_enum(&ast_data, module, ty, attrs, vis, &mut temp)?;
+        }
+
+        while let Some((constant, ast, saved)) = self.unresolved_consts.pop() {
+            ast_data.set_swapped(saved);
+            self.resolve_const(&ast_data, module, constant, ast)?;
+        }
+
+        // aliases resolved on demand are already redirected and removed
+        while let Some((ty, id)) = self.unresolved_aliases.pop() {
+            if self.find_computed_type(module, id) == Some(ty) {
+                self.resolve_alias(&ast_data, ty)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Registers constant declaration, value is folded later so
+    /// constants can refer to each other regardless of order.
+    fn collect_const(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        scope: Ast,
+    ) -> Result {
+        let (kind, sons, _) = ast_data.ent(ast).parts();
+        let vis = match kind {
+            ast::Kind::Const(vis) => vis,
+            _ => unreachable!(),
+        };
+        let hint = ast_data.token(ast_data.slice(sons)[0]);
+        let mut id = self.hash_token(hint);
+
+        if !scope.is_reserved_value() {
+            let sons = ast_data.sons(scope);
+            // constants of generic impl are shared by all instances
+            let ty = self.scope_base(ast_data, module, sons[0], sons[1])?;
+            id = id.add(self.type_base_id(ty));
+        }
+
+        let const_ent = ConstEnt {
+            id,
+            module,
+            vis,
+            hint,
+
+            ..Default::default()
+        };
+
+        let constant = self.add_const(module, const_ent)?;
+        self.unresolved_consts
+            .push((constant, ast, ast_data.swapped()));
+
+        Ok(())
+    }
+
+    /// Registers 'impl Bound for Type' so that bounds of generic
+    /// parameters can be checked when instantiating.
+    fn collect_bound_impl(&mut self, ast_data: &ast::DataSwitch, module: Mod, ast: Ast) -> Result {
+        let sons = ast_data.sons(ast);
+        let (generics, bound, target, body) = (sons[0], sons[1], sons[2], sons[3]);
+        let bound = self.scope_base(ast_data, module, generics, bound)?;
+        let target = self.scope_base(ast_data, module, generics, target)?;
+
+        let mut methods = vec![];
+        if !body.is_reserved_value() {
+            for &method in ast_data.sons(body) {
+                let (name, target) = match ast_data.kind(method) {
+                    ast::Kind::BoundAlias => (ast_data.son(method, 0), ast_data.son(method, 1)),
+                    _ => (method, method),
+                };
+                let name = self.hash_token(ast_data.token(name));
+                let target = self.hash_token(ast_data.token(target));
+                methods.push((name, target));
+            }
+        }
+
+        let id = self.type_base_id(bound).add(self.type_base_id(target));
+        let hint = ast_data.token(ast);
+        if let Some(other) = self.bound_impls.get(id) {
+            return Err(Error::new(error::Kind::Redefinition(other.hint), hint));
+        }
+        self.bound_impls.insert(id, BoundImpl { hint, methods });
+
+        Ok(())
+    }
+
+    /// Parses type of impl block, generic impl refers to the base
+    /// of instantiated type.
+    fn scope_base(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        generics: Ast,
+        ast: Ast,
+    ) -> Result<Ty> {
+        let ast = if !generics.is_reserved_value()
+            && ast_data.kind(ast) == ast::Kind::Instantiation
+        {
+            ast_data.son(ast, 0)
+        } else {
+            ast
+        };
+        self.ty(ast_data, module, ast, 0)
+    }
+
+    /// Checks instantiation `params` against bounds listed in `generics`.
+    pub fn check_bounds(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        generics: Ast,
+        params: &[Ty],
+        token: Token,
+    ) -> Result {
+        if generics.is_reserved_value() {
+            return Ok(());
+        }
+
+        for (&param, &ty) in ast_data.sons(generics).iter().zip(params) {
+            let bounds = ast_data.son(param, 1);
+            if bounds.is_reserved_value() {
+                continue;
+            }
+            for &bound in ast_data.sons(bounds) {
+                let bound = self.ty(ast_data, module, bound, 0)?;
+                self.check_bound(ast_data, ty, bound, token)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether `ty` implements `bound` and all bounds it embeds.
+    /// Implementation has to provide all methods bound declares.
+    pub fn check_bound(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        ty: Ty,
+        bound: Ty,
+        token: Token,
+    ) -> Result {
+        self.bound_info(ast_data, bound)?;
+
+        let id = self.type_base_id(bound).add(self.type_base_id(ty));
+        let provided = match self.bound_impls.get(id) {
+            Some(bound_impl) => &bound_impl.methods,
+            None => {
+                return Err(Error::new(
+                    error::Kind::BoundNotImplemented(ty, bound),
+                    token,
+                ))
+            }
+        };
+
+        let info = self.bound_infos.get(self.types[bound].id).unwrap();
+        let missing = info
+            .methods
+            .iter()
+            .filter(|method| provided.iter().all(|&(name, _)| name != method.id))
+            .map(|method| method.name)
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(Error::new(
+                error::Kind::MissingBoundMethods(ty, bound, missing),
+                token,
+            ));
+        }
+
+        let mut embeds = self.temp_vec();
+        let info = self.bound_infos.get(self.types[bound].id).unwrap();
+        embeds.extend_from_slice(&info.embeds);
+        for &embed in embeds.iter() {
+            self.check_bound(ast_data, ty, embed, token)?;
+        }
+
+        Ok(())
+    }
+
+    /// Collects methods `bound` requires and bounds it embeds. Bound that
+    /// is not connected yet has to come from `ast_data`. Methods of embedded
+    /// bounds are appended to the vtable layout after own methods.
+    fn bound_info(&mut self, ast_data: &ast::DataSwitch, bound: Ty) -> Result {
+        let TyEnt {
+            id,
+            module,
+            params,
+            hint,
+            ..
+        } = self.types[bound];
+        if self.bound_infos.get(id).is_some() {
+            return Ok(());
+        }
+
+        let mut ast_data = *ast_data;
+        ast_data.set_swapped(!params.is_empty());
+        let ast = match self.types[bound].kind {
+            ty::Kind::Bound(ast) => ast,
+            ty::Kind::Unresolved(ast) if matches!(ast_data.kind(ast), ast::Kind::Bound(_)) => ast,
+            _ => return Err(Error::new(error::Kind::NotABound(bound), hint)),
+        };
+
+        // placeholder terminates cyclic embedding
+        self.bound_infos.insert(id, BoundInfo::default());
+
+        let generics = ast_data.son(ast, 0);
+        let mut shadowed = self.temp_vec();
+        let mut param_buffer = self.temp_vec();
+        param_buffer.extend_from_slice(self.type_slice(params));
+        if !generics.is_reserved_value() {
+            for (&param, &son) in param_buffer[1..].iter().zip(ast_data.sons(generics).iter()) {
+                let name = ast_data.sons(son)[0];
+                let id = self.hash_token(ast_data.token(name));
+                let shadow = self.push_item(module, id, item::Kind::Ty(param));
+                shadowed.push((id, shadow));
+            }
+        }
+        let self_id = ID::new("Self");
+        let shadow = self.push_item(module, self_id, item::Kind::Ty(bound));
+        shadowed.push((self_id, shadow));
+
+        let mut info = BoundInfo::default();
+        let result = self.bound_methods(&ast_data, module, ast, &mut info);
+
+        for (id, ty) in shadowed.drain(..).rev() {
+            self.pop_item(module, id, ty);
+        }
+        result?;
+
+        info.vtable.extend_from_slice(&info.methods);
+        for &embed in info.embeds.iter() {
+            self.bound_info(&ast_data, embed)?;
+            let embedded = &self.bound_infos.get(self.types[embed].id).unwrap().vtable;
+            for method in embedded {
+                if info.vtable.iter().all(|other| other.id != method.id) {
+                    info.vtable.push(*method);
+                }
+            }
+        }
+
+        self.bound_infos.insert(id, info);
+
+        Ok(())
+    }
+
+    /// Parses embedded bounds and method signatures of bound `ast`.
+    /// Generic parameters and `Self` have to be already in scope.
+    fn bound_methods(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        info: &mut BoundInfo,
+    ) -> Result {
+        let sons = ast_data.sons(ast);
+        let (embeds, body) = (sons[2], sons[3]);
+
+        if !embeds.is_reserved_value() {
+            for &embed in ast_data.sons(embeds) {
+                let embed = self.ty(ast_data, module, embed, 0)?;
+                info.embeds.push(embed);
+            }
+        }
+
+        if body.is_reserved_value() {
+            return Ok(());
+        }
+
+        let mut args = self.temp_vec();
+        for &fun in ast_data.sons(body) {
+            let header = ast_data.son(fun, 0);
+            let call_conv = match ast_data.kind(header) {
+                ast::Kind::FunHeader(_, _, call_conv) => call_conv,
+                kind => unreachable!("{:?}", kind),
+            };
+            let sons = ast_data.sons(header);
+            let name = ast_data.token(sons[1]);
+
+            for &arg in &sons[2..sons.len() - 1] {
+                let arg_sons = ast_data.sons(arg);
+                let ty = self.ty(ast_data, module, arg_sons[arg_sons.len() - 1], 0)?;
+                args.extend(std::iter::repeat(ty).take(arg_sons.len() - 1));
+            }
+
+            let ret = sons[sons.len() - 1];
+            let ret = if ret.is_reserved_value() {
+                PackedOption::default()
+            } else {
+                PackedOption::from(self.ty(ast_data, module, ret, 0)?)
+            };
+
+            let sig = Signature {
+                call_conv,
+                args: self.add_type_slice(args.as_slice()),
+                ret,
+            };
+            args.clear();
+
+            info.methods.push(BoundMethod {
+                id: self.hash_token(name),
+                name: name.span(),
+                sig,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Returns vtable layout of the `bound`. Index of the method in
+    /// returned slice is its index in vtable.
+    pub fn bound_vtable(&mut self, ast_data: &ast::DataSwitch, bound: Ty) -> Result<&[BoundMethod]> {
+        self.bound_info(ast_data, bound)?;
+        Ok(&self.bound_infos.get(self.types[bound].id).unwrap().vtable)
+    }
+
+    /// Returns name of the function that implements `method` of `bound`
+    /// for `ty`. Method can also come from embedded bound.
+    pub fn bound_method_impl(&self, bound: Ty, ty: Ty, method: ID) -> Option<ID> {
+        let id = self.type_base_id(bound).add(self.type_base_id(ty));
+        let target = self.bound_impls.get(id).and_then(|bound_impl| {
+            bound_impl
+                .methods
+                .iter()
+                .find(|&&(name, _)| name == method)
+                .map(|&(_, target)| target)
+        });
+        if target.is_some() {
+            return target;
+        }
+
+        self.bound_infos
+            .get(self.types[bound].id)?
+            .embeds
+            .iter()
+            .find_map(|&embed| self.bound_method_impl(embed, ty, method))
+    }
+
+    pub fn is_bound(&self, ty: Ty) -> bool {
+        matches!(self.types[ty].kind, ty::Kind::Bound(_))
+    }
+
+    /// Folds value of the constant and checks it against declared type.
+    fn resolve_const(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        constant: Const,
+        ast: Ast,
+    ) -> Result {
+        self.const_stack.push(constant);
+        let result = self.resolve_const_value(ast_data, module, constant, ast);
+        self.const_stack.pop().unwrap();
+        result
+    }
+
+    fn resolve_const_value(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        constant: Const,
+        ast: Ast,
+    ) -> Result {
+        let sons = ast_data.sons(ast);
+        let (ty, value) = (sons[1], sons[2]);
+        let ty = self.ty(ast_data, module, ty, 0)?;
+        let token = ast_data.token(value);
+        let value = self.fold_const(ast_data, module, value)?;
+
+        let actual = self.constant_type(module, value);
+        if actual != ty {
+            return Err(Error::new(error::Kind::ConstTypeMismatch(actual, ty), token));
+        }
+
+        // value is moved, not dropped, arrays share the element list
+        let ConstEnt { module: value_module, kind, .. } = self.constants[value];
+        if value_module.is_reserved_value() {
+            self.constants.remove(value);
+        }
+        self.constants[constant].kind = kind;
+
+        Ok(())
+    }
+
+    /// Makes sure named constant found while folding has its value,
+    /// constant that is being folded is a cycle.
+    fn ensure_const(&mut self, ast_data: &ast::DataSwitch, constant: Const, token: Token) -> Result<Const> {
+        if self.const_stack.contains(&constant) {
+            return Err(Error::new(error::Kind::ConstCycle, token));
+        }
+
+        if let Some(index) = self
+            .unresolved_consts
+            .iter()
+            .position(|&(c, ..)| c == constant)
+        {
+            let (constant, ast, saved) = self.unresolved_consts.remove(index);
+            let mut ast_data = *ast_data;
+            ast_data.set_swapped(saved);
+            let module = self.constants[constant].module;
+            self.resolve_const(&ast_data, module, constant, ast)?;
+        }
+
+        Ok(constant)
+    }
+
+    /// Collects enum and registers its variants as constants accessible
+    /// by `Enum::Variant` path. Values are folded, variant without value is
+    /// one greater then previous. Repr is taken from `attr repr(<int type>)`
+    /// or the smallest integer that can hold all values is chosen.
+    fn collect_enum(
+        &mut self,
+        ast_data: &ast::DataSwitch,
+        module: Mod,
+        ast: Ast,
+        attrs: Ast,
+        vis: Vis,
+        temp: &mut Vec<EnumVariant>,
+    ) -> Result {
+        let sons = ast_data.sons(ast);
+        let (name, variants) = (sons[0], sons[1]);
+
+        temp.clear();
+        // values are checked in wider type so that unsigned values and the
+        // implicit successor of the maximum do not wrap around
+        let mut values = self.temp_vec::<i128>();
+        let mut next_value = 0i128;
+        if !variants.is_reserved_value() {
+            for &variant in ast_data.sons(variants) {
+                let sons = ast_data.sons(variant);
+                let (variant_name, value) = (sons[0], sons[1]);
+                let value = if value.is_reserved_value() {
+                    next_value
+                } else {
+                    match self.fold_immediate_const(ast_data, module, value)? {
+                        constant::Kind::Int(value, _) => value as i128,
+                        constant::Kind::Uint(value, _) => value as i128,
+                        _ => {
+                            return Err(Error::new(
+                                error::Kind::ExpectedIntConstant,
+                                ast_data.token(value),
+                            ))
+                        }
+                    }
+                };
+                next_value = value + 1;
+
+                let hint = ast_data.token(variant_name);
+                let id = self.hash_token(hint);
+                if let Some(&other) = temp.iter().find(|&&v| self.enum_variants[v].id == id) {
+                    return Err(Error::new(
+                        error::Kind::Redefinition(self.enum_variants[other].hint),
+                        hint,
+                    ));
+                }
+
+                values.push(value);
+                temp.push(self.enum_variants.push(EnumVariantEnt { id, value, hint }));
+            }
+        }
+
+        let repr = if let Some(attr) = self.find_attribute(ast_data, attrs, "repr") {
+            let token = ast_data.token(attr);
+            let &repr = ast_data
+                .sons(attr)
+                .get(1)
+                .ok_or_else(|| Error::new(error::Kind::InvalidEnumRepr, token))?;
+            let token = ast_data.token(repr);
+            let id = self.hash_token(token);
+            let repr = self.find_type(module, id, token)?;
+            let repr = self.resolve_alias(ast_data, repr)?;
+            if !INTEGER_TYPES.contains(&repr) {
+                return Err(Error::new(error::Kind::InvalidEnumRepr, token));
+            }
+            repr
+        } else {
+            let (min, max) = values
+                .iter()
+                .fold((0, 0), |(min, max), &v| (v.min(min), v.max(max)));
+            let candidates = if min < 0 {
+                [I8_TY, I16_TY, I32_TY, I64_TY]
+            } else {
+                [U8_TY, U16_TY, U32_TY, U64_TY]
+            };
+            candidates
+                .iter()
+                .cloned()
+                .find(|&ty| self.fits_int(ty, min) && self.fits_int(ty, max))
+                .unwrap_or(candidates[3])
+        };
+
+        for (&variant, &value) in temp.iter().zip(values.iter()) {
+            let hint = self.enum_variants[variant].hint;
+            if !self.fits_int(repr, value) {
+                return Err(Error::new(
+                    error::Kind::EnumValueOutOfRange(value, repr),
+                    hint,
+                ));
+            }
+        }
+
+        let variants = EntityList::from_slice(temp.as_slice(), &mut self.enum_slices);
+        let hint = ast_data.token(name);
+        let id = self.hash_token(hint);
 
-                        ..Default::default()
-                    };
-                    
-                    let id = self.add_type(module, datatype).map_err(Into::into)?;
+        let kind = ty::Kind::Enumeration(repr, variants);
+        let datatype = TyEnt {
+            vis,
+            id,
+            module,
+            kind,
+            attrs,
+            hint,
 
-                    if let ty::Kind::Unresolved(_) = &self.types[id].kind {
-                        self.unresolved.push((id, 0));
-                    }
-                }
-                kind => unreachable!("{:?}", kind),
-            }
+            ..Default::default()
+        };
 
-            Ok(())
-        })?;
+        let ty = self.add_type(module, datatype)?;
+        self.resolved.push(ty);
+
+        for &variant in temp.iter() {
+            let EnumVariantEnt { id: name, hint, .. } = self.enum_variants[variant];
+            let const_ent = ConstEnt {
+                id: name.add(id),
+                module,
+                vis,
+                hint,
+                kind: constant::Kind::Enum(ty, variant),
+            };
+            self.add_const(module, const_ent)?;
+        }
 
         Ok(())
     }
 
+    /// Returns true if `value` can be represented by integer type `ty`.
+    fn fits_int(&self, ty: Ty, value: i128) -> bool {
+        let bits = self.types[ty].size.s64() * 8;
+        if matches!(ty, I8_TY | I16_TY | I32_TY | I64_TY) {
+            value >= -(1 << (bits - 1)) && value < (1 << (bits - 1))
+        } else {
+            value >= 0 && value < (1 << bits)
+        }
+    }
+
+    /// Returns variants of enum `ty` that are not in the `covered` set.
+    /// This is used to check exhaustiveness when enum is switched on.
+    pub fn missing_variants(&self, ty: Ty, covered: &[EnumVariant]) -> Vec<EnumVariant> {
+        match self.types[ty].kind {
+            ty::Kind::Enumeration(_, variants) => variants
+                .as_slice(&self.enum_slices)
+                .iter()
+                .filter(|v| !covered.contains(v))
+                .cloned()
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    pub fn enum_variant(&self, variant: EnumVariant) -> &EnumVariantEnt {
+        &self.enum_variants[variant]
+    }
+
+    pub fn enum_variant_slice(&self, variants: EntityList<EnumVariant>) -> &[EnumVariant] {
+        variants.as_slice(&self.enum_slices)
+    }
+
     pub fn tuple_of(&mut self, source_module: Mod, types: &[Ty]) -> Ty {
         let mut filed_name = String::with_capacity(2);
         let mut fields = Vec::with_capacity(types.len());
@@ -733,6 +1434,73 @@ impl Ctx {
         self.add_type(source_module, ty_ent).unwrap()
     }
 
+    /// Creates tuple holding values captured by a closure. Unlike [`Self::tuple_of`],
+    /// layout is computed right away since closures are created while translating
+    /// function bodies.
+    pub fn env_of(&mut self, source_module: Mod, captures: &[Ty]) -> Result<Ty> {
+        let ty = self.tuple_of(source_module, captures);
+        self.resolved.push(ty);
+        self.calc_sizes()?;
+        Ok(ty)
+    }
+
+    /// Creates closure type holding the captured `env` and the `code` pointer
+    /// that takes pointer to `env` as its first argument.
+    pub fn closure_of(&mut self, source_module: Mod, env: Ty, code: Ty) -> Result<Ty> {
+        let mut id = ID::new("closure");
+        let mut best_module = Mod::reserved_value();
+        let mut fields = Vec::with_capacity(2);
+        for &(name, ty) in [("env", env), ("code", code)].iter() {
+            let TyEnt {
+                id: ty_id, module, ..
+            } = self.types[ty];
+            if module != BUILTIN_MODULE && module.index() < best_module.index() {
+                best_module = module;
+            }
+            id = id.add(ty_id);
+            let field = FieldEnt {
+                id: ID::new(name),
+                ty,
+
+                embedded: false,
+                vis: Vis::Public,
+                offset: Size::ZERO,
+                hint: Token::default(),
+            };
+            fields.push(field);
+        }
+
+        if best_module.is_reserved_value() {
+            best_module = BUILTIN_MODULE;
+        }
+
+        if let Some(ty) = self.find_computed_type(best_module, id) {
+            return Ok(ty);
+        }
+
+        let fields = fields
+            .into_iter()
+            .map(|field| self.fields.push(field))
+            .collect::<Vec<_>>();
+        let ty_ent = TyEnt {
+            id,
+            module: best_module,
+            vis: Vis::Public,
+            kind: ty::Kind::Structure(
+                StructureKind::Closure,
+                EntityList::from_slice(fields.as_slice(), &mut self.field_slices),
+            ),
+
+            ..Default::default()
+        };
+
+        let ty = self.add_type(source_module, ty_ent)?;
+        self.resolved.push(ty);
+        self.calc_sizes()?;
+
+        Ok(ty)
+    }
+
     pub fn pointer_of(&mut self, source_module: Mod, ty: Ty, mutable: bool) -> Ty {
         let TyEnt {
             module, id, vis, ..
@@ -744,7 +1512,12 @@ impl Ctx {
             return ty;
         }
 
-        let size = Size::POINTER;
+        // pointer to bound also carries the vtable
+        let size = if self.is_bound(ty) {
+            Size::POINTER.mul(Size::new(2, 2))
+        } else {
+            Size::POINTER
+        };
 
         let pointer_type = TyEnt {
             id,
@@ -752,7 +1525,7 @@ impl Ctx {
             kind: ty::Kind::Pointer(ty, mutable),
             module,
             size,
-            align: size,
+            align: Size::POINTER,
 
             ..Default::default()
         };
@@ -792,13 +1565,10 @@ impl Ctx {
 
     /// If constant already exists, passed `constant` is dropped.
     pub fn constant_of(&mut self, source_module: Mod, constant: Const) -> Ty {
-        let id = self.constants[constant].hash(self);
+        let id = self.constants[constant].kind.hash(self);
 
         if let Some(ty) = self.find_computed_type(source_module, id) {
-            if let constant::Kind::Array(mut elements) = self.constants[constant] {
-                elements.clear(&mut self.constant_slices);
-            }
-            self.constants.remove(constant);
+            self.drop_constant(constant);
             return ty;
         }
 
@@ -906,7 +1676,10 @@ impl Ctx {
             .unwrap_or(ty)
     }
 
-    impl_item_adder!(add_type, types, TyEnt, Ty);
+    impl_item_adder!(
+        add_type, types, TyEnt, Ty
+        add_const, constants, ConstEnt, Const
+    );
     
     pub fn push_type(&mut self, list: &mut EntityList<Ty>, ty: Ty) {
         list.push(ty, &mut self.type_slices);
@@ -916,18 +1689,17 @@ impl Ctx {
         list.as_slice(&self.type_slices)
     }
 
-    fn fold_immediate_const(
+    /// Folds constant expression and returns its value without keeping
+    /// temporary constants around.
+    pub fn fold_immediate_const(
         &mut self,
         ast_data: &ast::DataSwitch,
         module: Mod,
         ast: Ast,
     ) -> Result<constant::Kind> {
         let constant = self.fold_const(ast_data, module, ast)?;
-        let value = self.constants[constant];
-        if let constant::Kind::Array(mut values) = value {
-            values.clear(&mut self.constant_slices);
-        }
-        self.constants.remove(constant);
+        let value = self.constants[constant].kind;
+        self.drop_constant(constant);
         Ok(value)
     }
 
@@ -936,16 +1708,25 @@ impl Ctx {
 
         let constant = self.fold_const_low(module, ast_data, ast, &mut garbage, true)?;
         for &garbage in garbage.iter() {
-            let value = self.constants[garbage];
-            if let constant::Kind::Array(mut values) = value {
-                values.clear(&mut self.constant_slices);
-            }
-            self.constants.remove(garbage);
+            self.drop_constant(garbage);
         }
 
         Ok(constant)
     }
 
+    /// Removes temporary constant, named constants are items
+    /// and they stay untouched.
+    fn drop_constant(&mut self, constant: Const) {
+        let ConstEnt { module, kind, .. } = self.constants[constant];
+        if !module.is_reserved_value() {
+            return;
+        }
+        if let constant::Kind::Array(mut values) = kind {
+            values.clear(&mut self.constant_slices);
+        }
+        self.constants.remove(constant);
+    }
+
     pub fn fold_const_low(
         &mut self,
         module: Mod,
@@ -961,7 +1742,7 @@ impl Ctx {
                 let header = self.fold_const_low(module, ast_data, sons[0], garbage, is_root)?;
                 let accessor = self.fold_const_low(module, ast_data, sons[1], garbage, is_root)?;
 
-                let accessed = match (self.constants[header], self.constants[accessor]) {
+                let accessed = match (self.constants[header].kind, self.constants[accessor].kind) {
                     (constant::Kind::Array(elements), constant::Kind::Int(value, _)) => elements
                         .get(value as usize, &self.constant_slices)
                         .ok_or_else(|| Error::new(error::Kind::IndexOutOfBounds, token))?,
@@ -977,7 +1758,7 @@ impl Ctx {
                 let a = self.fold_const_low(module, ast_data, sons[1], garbage, false)?;
                 let b = self.fold_const_low(module, ast_data, sons[2], garbage, false)?;
                 let op = self.display_token(ast_data.token(sons[0]));
-                let new = match (self.constants[a], self.constants[b]) {
+                let new = match (self.constants[a].kind, self.constants[b].kind) {
                     (constant::Kind::Int(a, base_a), constant::Kind::Int(b, base_b)) => {
                         if matches!(op, "==" | "!=" | "<" | ">" | "<=" | ">=") {
                             constant::Kind::Bool(match op {
@@ -992,25 +1773,26 @@ impl Ctx {
                         } else {
                             constant::Kind::Int(
                                 match op {
-                                    "+" => a + b,
-                                    "-" => a - b,
-                                    "*" => a * b,
-                                    "/" => a / b,
-                                    "%" => a % b,
-                                    "<<" => a << b,
-                                    ">>" => a >> b,
-                                    "&" => a & b,
-                                    "|" => a | b,
-                                    "^" => a ^ b,
-                                    "max" => a.max(b),
-                                    "min" => a.min(b),
+                                    "+" => a.checked_add(b),
+                                    "-" => a.checked_sub(b),
+                                    "*" => a.checked_mul(b),
+                                    "/" => a.checked_div(b),
+                                    "%" => a.checked_rem(b),
+                                    "<<" => (0..64).contains(&b).then(|| a << b),
+                                    ">>" => (0..64).contains(&b).then(|| a >> b),
+                                    "&" => Some(a & b),
+                                    "|" => Some(a | b),
+                                    "^" => Some(a ^ b),
+                                    "max" => Some(a.max(b)),
+                                    "min" => Some(a.min(b)),
                                     _ => {
                                         return Err(Error::new(
                                             error::Kind::UnsupportedConst,
                                             token,
                                         ))
                                     }
-                                },
+                                }
+                                .ok_or_else(|| Error::new(error::Kind::ConstOverflow, token))?,
                                 base_a.max(base_b),
                             )
                         }
@@ -1029,29 +1811,55 @@ impl Ctx {
                         } else {
                             constant::Kind::Uint(
                                 match op {
-                                    "+" => a + b,
-                                    "-" => a - b,
-                                    "*" => a * b,
-                                    "/" => a / b,
-                                    "%" => a % b,
-                                    "<<" => a << b,
-                                    ">>" => a >> b,
-                                    "&" => a & b,
-                                    "|" => a | b,
-                                    "^" => a ^ b,
-                                    "max" => a.max(b),
-                                    "min" => a.min(b),
+                                    "+" => a.checked_add(b),
+                                    "-" => a.checked_sub(b),
+                                    "*" => a.checked_mul(b),
+                                    "/" => a.checked_div(b),
+                                    "%" => a.checked_rem(b),
+                                    "<<" => (b < 64).then(|| a << b),
+                                    ">>" => (b < 64).then(|| a >> b),
+                                    "&" => Some(a & b),
+                                    "|" => Some(a | b),
+                                    "^" => Some(a ^ b),
+                                    "max" => Some(a.max(b)),
+                                    "min" => Some(a.min(b)),
                                     _ => {
                                         return Err(Error::new(
                                             error::Kind::UnsupportedConst,
                                             token,
                                         ))
                                     }
-                                },
+                                }
+                                .ok_or_else(|| Error::new(error::Kind::ConstOverflow, token))?,
                                 base_a.max(base_b),
                             )
                         }
                     }
+                    (constant::Kind::Enum(ty_a, a), constant::Kind::Enum(ty_b, b))
+                        if ty_a == ty_b =>
+                    {
+                        let (a, b) = (self.enum_variants[a].value, self.enum_variants[b].value);
+                        constant::Kind::Bool(match op {
+                            "==" => a == b,
+                            "!=" => a != b,
+                            "<" => a < b,
+                            ">" => a > b,
+                            "<=" => a <= b,
+                            ">=" => a >= b,
+                            _ => return Err(Error::new(error::Kind::UnsupportedConst, token)),
+                        })
+                    }
+                    (constant::Kind::Char(a), constant::Kind::Char(b)) => {
+                        constant::Kind::Bool(match op {
+                            "==" => a == b,
+                            "!=" => a != b,
+                            "<" => a < b,
+                            ">" => a > b,
+                            "<=" => a <= b,
+                            ">=" => a >= b,
+                            _ => return Err(Error::new(error::Kind::UnsupportedConst, token)),
+                        })
+                    }
                     (constant::Kind::Float(a, base_a), constant::Kind::Float(b, base_b)) => {
                         if matches!(op, "==" | "!=" | "<" | ">" | "<=" | ">=") {
                             constant::Kind::Bool(match op {
@@ -1087,7 +1895,7 @@ impl Ctx {
                     _ => return Err(Error::new(error::Kind::UnsupportedConst, token)),
                 };
 
-                let constant = self.constants.push(new);
+                let constant = self.constants.push(ConstEnt::new(new));
                 if !is_root {
                     garbage.push(constant);
                 }
@@ -1095,27 +1903,42 @@ impl Ctx {
             }
 
             ast::Kind::Unary => {
+                let literal = ast_data.token(sons[1]);
+                if ast_data.kind(sons[1]) == ast::Kind::Lit
+                    && matches!(literal.kind(), token::Kind::Int(_))
+                    && self.display_token(ast_data.token(sons[0])) == "-"
+                {
+                    let constant = constant::Kind::from_literal(self, literal, true)?;
+                    let constant = self.constants.push(ConstEnt::new(constant));
+                    if !is_root {
+                        garbage.push(constant);
+                    }
+                    return Ok(constant);
+                }
+
                 let constant = self.fold_const_low(module, ast_data, sons[1], garbage, false)?;
                 let op = self.display_token(ast_data.token(sons[0]));
-                let new = match self.constants[constant] {
+                let new = match self.constants[constant].kind {
                     constant::Kind::Int(value, base) => constant::Kind::Int(
                         match op {
-                            "-" => -value,
-                            "!" => !value,
-                            "++" => value + 1,
-                            "--" => value - 1,
-                            "abs" => value.abs(),
+                            "-" => value.checked_neg(),
+                            "!" => Some(!value),
+                            "++" => value.checked_add(1),
+                            "--" => value.checked_sub(1),
+                            "abs" => value.checked_abs(),
                             _ => return Err(Error::new(error::Kind::UnsupportedConst, token)),
-                        },
+                        }
+                        .ok_or_else(|| Error::new(error::Kind::ConstOverflow, token))?,
                         base,
                     ),
                     constant::Kind::Uint(value, base) => constant::Kind::Uint(
                         match op {
-                            "!" => !value,
-                            "++" => value + 1,
-                            "--" => value - 1,
+                            "!" => Some(!value),
+                            "++" => value.checked_add(1),
+                            "--" => value.checked_sub(1),
                             _ => return Err(Error::new(error::Kind::UnsupportedConst, token)),
-                        },
+                        }
+                        .ok_or_else(|| Error::new(error::Kind::ConstOverflow, token))?,
                         base,
                     ),
                     constant::Kind::Float(value, base) => constant::Kind::Float(
@@ -1130,12 +1953,15 @@ impl Ctx {
                         "!" => !value,
                         _ => return Err(Error::new(error::Kind::UnsupportedConst, token)),
                     }),
-                    constant::Kind::Str(_) | constant::Kind::Array(_) => {
+                    constant::Kind::Str(_)
+                    | constant::Kind::Char(_)
+                    | constant::Kind::Array(_)
+                    | constant::Kind::Enum(..) => {
                         return Err(Error::new(error::Kind::UnsupportedConst, token))
                     }
                 };
 
-                let constant = self.constants.push(new);
+                let constant = self.constants.push(ConstEnt::new(new));
                 if !is_root {
                     garbage.push(constant);
                 }
@@ -1150,7 +1976,7 @@ impl Ctx {
                     }
                     list.push(constant, &mut self.constant_slices);
                 }
-                let constant = self.constants.push(constant::Kind::Array(list));
+                let constant = self.constants.push(ConstEnt::new(constant::Kind::Array(list)));
                 if !is_root {
                     garbage.push(constant);
                 }
@@ -1196,18 +2022,23 @@ impl Ctx {
                         let token = ast_data.token(name);
                         self.hash_token(token).add(hash)
                     }
-                    _ => todo!(),
+                    _ => return Err(Error::new(error::Kind::UnsupportedConst, token)),
                 };
-                self.find_const(module, hash, token)
+                let constant = self.find_const(module, hash, token)?;
+                self.ensure_const(ast_data, constant, token)
             }
             ast::Kind::Ident => {
                 let hash = self.hash_token(token);
-                self.find_const(module, hash, token)
+                let constant = self.find_const(module, hash, token)?;
+                self.ensure_const(ast_data, constant, token)
             }
             ast::Kind::Lit => {
-                let constant = constant::Kind::from_token(self, token);
-                let constant = self.constants.push(constant);
+                let constant = constant::Kind::from_literal(self, token, false)?;
+                let constant = self.constants.push(ConstEnt::new(constant));
                 if !is_root {
+                    if let constant::Kind::Array(elements) = self.constants[constant].kind {
+                        garbage.extend_from_slice(elements.as_slice(&self.constant_slices));
+                    }
                     garbage.push(constant);
                 }
                 Ok(constant)
@@ -1253,6 +2084,69 @@ impl Ctx {
     pub fn type_base_id(&self, ty: Ty) -> ID {
         self.types[self.base_of(ty)].id
     }
+
+    pub fn type_id(&self, ty: Ty) -> ID {
+        self.types[ty].id
+    }
+
+    pub fn type_size(&self, ty: Ty) -> Size {
+        self.types[ty].size
+    }
+
+    pub fn type_align(&self, ty: Ty) -> Size {
+        self.types[ty].align
+    }
+
+    pub fn type_ent(&self, ty: Ty) -> &TyEnt {
+        &self.types[ty]
+    }
+
+    pub fn add_constant(&mut self, constant: constant::Kind) -> Const {
+        self.constants.push(ConstEnt::new(constant))
+    }
+
+    pub fn constant_kind(&self, constant: Const) -> constant::Kind {
+        self.constants[constant].kind
+    }
+
+    pub fn constant_slice(&self, list: EntityList<Const>) -> &[Const] {
+        list.as_slice(&self.constant_slices)
+    }
+
+    /// Returns the type constant has when used as a value. Numbers without
+    /// explicit base take the pointer width, empty array is an array of `u8`.
+    pub fn constant_type(&mut self, module: Mod, constant: Const) -> Ty {
+        match self.constants[constant].kind {
+            constant::Kind::Int(_, base) => match base {
+                8 => I8_TY,
+                16 => I16_TY,
+                32 => I32_TY,
+                64 => I64_TY,
+                _ => INT_TY,
+            },
+            constant::Kind::Uint(_, base) => match base {
+                8 => U8_TY,
+                16 => U16_TY,
+                32 => U32_TY,
+                64 => U64_TY,
+                _ => UINT_TY,
+            },
+            constant::Kind::Float(_, 32) => F32_TY,
+            constant::Kind::Float(..) => F64_TY,
+            constant::Kind::Bool(_) => BOOL_TY,
+            constant::Kind::Char(_) => CHAR_TY,
+            constant::Kind::Str(_) => self.pointer_of(module, U8_TY, false),
+            constant::Kind::Array(elements) => {
+                let length = elements.len(&self.constant_slices);
+                let element = match elements.first(&self.constant_slices) {
+                    Some(element) => self.constant_type(module, element),
+                    None => U8_TY,
+                };
+                self.array_of(module, element, length)
+            }
+            constant::Kind::Enum(ty, _) => ty,
+        }
+    }
 }
 
 impl Deref for Ctx {
@@ -1292,13 +2186,16 @@ impl<'a> TreeStorage<Ty> for Ctx {
         let node = &self.types[id];
 
         match &node.kind {
-            ty::Kind::Builtin(_) | ty::Kind::Pointer(..) | ty::Kind::Enumeration(_) | ty::Kind::Bound(_) => 0,
+            ty::Kind::Builtin(_) | ty::Kind::Pointer(..) | ty::Kind::Enumeration(..) | ty::Kind::Bound(_) => 0,
             ty::Kind::FunPointer(fun) => {
                 self.type_slice(fun.args).len() + fun.ret.is_some() as usize
             }
             ty::Kind::Array(..) => 1,
             ty::Kind::Structure(_, fields) => fields.len(&self.field_slices),
-            ty::Kind::Generic(_) | ty::Kind::Unresolved(_) | ty::Kind::Constant(_) => {
+            ty::Kind::Generic(_)
+            | ty::Kind::Unresolved(_)
+            | ty::Kind::Constant(_)
+            | ty::Kind::Alias(_) => {
                 unreachable!("{:?}", node)
             }
         }
@@ -1311,15 +2208,73 @@ impl<'a> TreeStorage<Ty> for Ctx {
 
 crate::impl_entity!(EnumVariant);
 
+/// Registration of 'impl Bound for Type', `methods` are pairs of
+/// method name and name of the function implementing it, they differ
+/// only for aliased methods.
+#[derive(Debug, Clone, Default)]
+pub struct BoundImpl {
+    hint: Token,
+    methods: Vec<(ID, ID)>,
+}
+
+/// Methods bound requires and bounds it embeds. `vtable` also
+/// contains methods of embedded bounds.
 #[derive(Debug, Clone, Default)]
+pub struct BoundInfo {
+    methods: Vec<BoundMethod>,
+    embeds: Vec<Ty>,
+    vtable: Vec<BoundMethod>,
+}
+
+/// Method declared in bound. `Self` in signature refers to
+/// the bound itself.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BoundMethod {
+    pub id: ID,
+    pub name: Span,
+    pub sig: Signature,
+}
+
+/// Constant value, named constants also carry the item information,
+/// temporary ones have `module` reserved.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConstEnt {
+    pub id: ID,
+    pub module: Mod,
+    pub vis: Vis,
+    pub hint: Token,
+    pub kind: constant::Kind,
+}
+
+impl ConstEnt {
+    pub fn new(kind: constant::Kind) -> Self {
+        Self {
+            kind,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
 pub struct EnumVariantEnt {
     id: ID,
+    value: i128,
+    hint: Token,
 }
 
 impl EnumVariantEnt {
     pub fn id(&self) -> ID {
         self.id
     }
+
+    /// Returns value of the variant, it fits into the repr of the enum.
+    pub fn value(&self) -> i128 {
+        self.value
+    }
+
+    pub fn hint(&self) -> Token {
+        self.hint
+    }
 }
 
 #[derive(Debug, Clone, Default)]
@@ -1327,22 +2282,23 @@ pub struct ModCtx {}
 
 impl Signature {
     pub fn to_cr_signature(&self, state: &Ctx, isa: &dyn TargetIsa, target: &mut CrSignature) {
+        let ptr_ty = isa.pointer_type();
         target.call_conv = self.call_conv.to_cr_call_conv(isa);
         target.params.extend(
             state
                 .type_slice(self.args)
                 .iter()
-                .map(|&ty| AbiParam::new(state.types[ty].to_cr_type(isa))),
+                .map(|&ty| AbiParam::new(state.types[ty].to_cr_type(ptr_ty))),
         );
         if self.ret.is_some() {
             let ret = self.ret.unwrap();
             let ty = &state.types[ret];
-            let param = if ty.on_stack(isa.pointer_type()) {
-                let param = AbiParam::special(ty.to_cr_type(isa), ArgumentPurpose::StructReturn);
+            let param = if ty.on_stack(ptr_ty) {
+                let param = AbiParam::special(ty.to_cr_type(ptr_ty), ArgumentPurpose::StructReturn);
                 target.params.push(param);
                 param
             } else {
-                AbiParam::new(ty.to_cr_type(isa))
+                AbiParam::new(ty.to_cr_type(ptr_ty))
             };
             target.returns.push(param);
         }
@@ -1395,9 +2351,15 @@ define_repo!(
     BOOL_TY, bool, B1, 1, 1, 10;
     INT_TY, int, INVALID, 4, 8, 11;
     UINT_TY, uint, INVALID, 4, 8, 12;
-    ARRAY_TY, array, INVALID, 0, 0, 13
+    ARRAY_TY, array, INVALID, 0, 0, 13;
+    CHAR_TY, char, I32, 4, 4, 14
 );
 
+/// Integer types with fixed width, only these can be used as enum repr.
+pub const INTEGER_TYPES: &[Ty] = &[
+    I8_TY, I16_TY, I32_TY, I64_TY, U8_TY, U16_TY, U32_TY, U64_TY,
+];
+
 pub struct TypeDisplay<'a> {
     state: &'a Ctx,
     type_id: Ty,
@@ -1420,6 +2382,26 @@ impl<'a> std::fmt::Display for TypeDisplay<'a> {
                     write!(f, "&{}", Self::new(self.state, id))
                 }
             }
+            &ty::Kind::Structure(StructureKind::Closure, fields) => {
+                let code = self.state.fields[fields.as_slice(&self.state.field_slices)[1]].ty;
+                let sig = match self.state.types[code].kind {
+                    ty::Kind::FunPointer(sig) => sig,
+                    _ => unreachable!(),
+                };
+                write!(
+                    f,
+                    "closure({})",
+                    self.state.type_slice(sig.args)[1..]
+                        .iter()
+                        .map(|id| format!("{}", Self::new(self.state, *id)))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )?;
+                if let Some(ret) = sig.ret.expand() {
+                    write!(f, " -> {}", Self::new(self.state, ret))?;
+                }
+                Ok(())
+            }
             ty::Kind::Structure(..) |ty::Kind::Bound(_) if !ty.params.is_empty() => {
                 let params = self.state.type_slice(ty.params);
                 write!(f, "{}", Self::new(self.state, params[0]))?;
@@ -1433,8 +2415,9 @@ impl<'a> std::fmt::Display for TypeDisplay<'a> {
             ty::Kind::Builtin(_)
             | ty::Kind::Unresolved(_)
             | ty::Kind::Generic(_)
+            | ty::Kind::Alias(_)
             | ty::Kind::Structure(..)
-            | ty::Kind::Enumeration(_)
+            | ty::Kind::Enumeration(..)
             | ty::Kind::Bound(_) => {
                 write!(f, "{}", self.state.display(ty.hint.span()))
             }
@@ -1536,10 +2519,10 @@ impl ErrorDisplayState<Error> for Ctx {
                 )?;
             }
             error::Kind::AccessingExternalPrivateType => {
-                todo!()
+                writeln!(f, "type is private to its package")?;
             }
             error::Kind::AccessingFilePrivateType => {
-                todo!()
+                writeln!(f, "type is private to its file")?;
             }
             error::Kind::InfiniteSize(cycle) => {
                 writeln!(f, "infinite size detected, cycle:")?;
@@ -1573,6 +2556,74 @@ impl ErrorDisplayState<Error> for Ctx {
             error::Kind::IndexOutOfBounds => {
                 writeln!(f, "index out of bounds inside a constant expression")?;
             }
+            error::Kind::ConstOverflow => {
+                writeln!(f, "constant operation overflows or divides by zero")?;
+            }
+            &error::Kind::IntegerOutOfRange(width) => {
+                if width == lexer::POINTER_WIDTH_MARKER {
+                    writeln!(f, "integer literal does not fit into 'int'")?;
+                } else {
+                    writeln!(f, "integer literal does not fit into 'i{}'", width)?;
+                }
+            }
+            error::Kind::InvalidEnumRepr => {
+                writeln!(
+                    f,
+                    "invalid enum repr, expected 'attr repr(<integer type>)', for example 'attr repr(u16)'"
+                )?;
+            }
+            &error::Kind::ConstTypeMismatch(actual, expected) => {
+                writeln!(
+                    f,
+                    "constant type mismatch, expected '{}' but got '{}'",
+                    TypeDisplay::new(self, expected),
+                    TypeDisplay::new(self, actual)
+                )?;
+            }
+            error::Kind::ConstCycle => {
+                writeln!(f, "constant depends on itself")?;
+            }
+            error::Kind::SelfReferentialAlias => {
+                writeln!(f, "type alias refers to itself, use struct to create recursive type")?;
+            }
+            &error::Kind::NotABound(ty) => {
+                writeln!(f, "'{}' is not a bound", TypeDisplay::new(self, ty))?;
+            }
+            &error::Kind::BoundNotImplemented(ty, bound) => {
+                writeln!(
+                    f,
+                    "'{}' does not implement '{}', bound is declared here:\n{}",
+                    TypeDisplay::new(self, ty),
+                    TypeDisplay::new(self, bound),
+                    token::Display::new(self.sources(), &self.types[bound].hint)
+                )?;
+            }
+            error::Kind::MissingBoundMethods(ty, bound, missing) => {
+                writeln!(
+                    f,
+                    "implementation of '{}' for '{}' is missing methods: {}",
+                    TypeDisplay::new(self, *bound),
+                    TypeDisplay::new(self, *ty),
+                    missing
+                        .iter()
+                        .map(|&name| self.display(name))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )?;
+                writeln!(
+                    f,
+                    "bound is declared here:\n{}",
+                    token::Display::new(self.sources(), &self.types[*bound].hint)
+                )?;
+            }
+            &error::Kind::EnumValueOutOfRange(value, repr) => {
+                writeln!(
+                    f,
+                    "value {} does not fit into enum repr '{}'",
+                    value,
+                    TypeDisplay::new(self, repr)
+                )?;
+            }
         }
 
         Ok(())
@@ -1633,6 +2684,16 @@ mod error {
         UnsupportedConst,
         Undefined,
         IndexOutOfBounds,
+        ConstOverflow,
+        IntegerOutOfRange(u8),
+        InvalidEnumRepr,
+        EnumValueOutOfRange(i128, Ty),
+        ConstTypeMismatch(Ty, Ty),
+        ConstCycle,
+        SelfReferentialAlias,
+        NotABound(Ty),
+        BoundNotImplemented(Ty, Ty),
+        MissingBoundMethods(Ty, Ty, Vec<Span>),
     }
 }
 
@@ -1650,15 +2711,22 @@ pub struct TyEnt {
 }
 
 impl TyEnt {
-    pub fn to_cr_type(&self, isa: &dyn TargetIsa) -> Type {
+    pub fn to_cr_type(&self, ptr_ty: Type) -> Type {
         match &self.kind {
             ty::Kind::Pointer(..)
             | ty::Kind::Array(..)
             | ty::Kind::Structure(..)
-            | ty::Kind::FunPointer(_) => isa.pointer_type(),
-            ty::Kind::Enumeration(_) => I8, //temporary solution
+            | ty::Kind::FunPointer(_) => ptr_ty,
+            // size is taken from repr type
+            ty::Kind::Enumeration(..) => Type::int((self.size.s64() * 8) as u16).unwrap(),
+            // int and uint are pointer sized
+            &ty::Kind::Builtin(ty) if ty.0 == INVALID => ptr_ty,
             &ty::Kind::Builtin(ty) => ty.0,
-            ty::Kind::Generic(_) | ty::Kind::Constant(_) | ty::Kind::Unresolved(_) | ty::Kind::Bound(_) => {
+            ty::Kind::Generic(_)
+            | ty::Kind::Constant(_)
+            | ty::Kind::Unresolved(_)
+            | ty::Kind::Alias(_)
+            | ty::Kind::Bound(_) => {
                 unreachable!()
             }
         }
@@ -1676,7 +2744,7 @@ pub mod ty {
     pub enum Kind {
         Builtin(CrTypeWr),
         Pointer(Ty, bool),
-        Enumeration(EntityList<EnumVariant>),
+        Enumeration(Ty, EntityList<EnumVariant>), // repr and variants
         Array(Ty, u32),
         FunPointer(Signature),
         Constant(Const),
@@ -1684,6 +2752,7 @@ pub mod ty {
         Bound(Ast),
         Generic(Ast),
         Unresolved(Ast),
+        Alias(Ast), // placeholder until alias is resolved
     }
 
     impl Default for ty::Kind {
@@ -1745,9 +2814,10 @@ pub enum StructureKind {
     Struct,
     Union,
     Tuple,
+    Closure,
 }
 
-mod constant {
+pub mod constant {
     use super::*;
 
     #[derive(Clone, Debug, Copy)]
@@ -1756,11 +2826,31 @@ mod constant {
         Uint(u64, u8),
         Float(f64, u8),
         Bool(bool),
+        Char(char),
         Str(Span),
         Array(EntityList<Const>),
+        Enum(Ty, EnumVariant),
     }
 
     impl Kind {
+        /// Builds constant from literal token and checks the range of signed
+        /// integers, `negated` literals may hold the magnitude of the minimal value.
+        pub fn from_literal(ctx: &mut Ctx, token: Token, negated: bool) -> Result<Self> {
+            let kind = match Self::from_token(ctx, token) {
+                Self::Int(value, base) => {
+                    let bits = if base == lexer::POINTER_WIDTH_MARKER { 64 } else { base as u32 };
+                    let magnitude = value as u64;
+                    let limit = 1u64 << (bits - 1);
+                    if magnitude > limit || (magnitude == limit && !negated) {
+                        return Err(Error::new(error::Kind::IntegerOutOfRange(base), token));
+                    }
+                    Self::Int(if negated { value.wrapping_neg() } else { value }, base)
+                }
+                kind => kind,
+            };
+            Ok(kind)
+        }
+
         pub fn from_token(ctx: &mut Ctx, token: Token) -> Self {
             let mut chars = ctx.display(token.span()).chars();
             match token.kind() {
@@ -1778,7 +2868,29 @@ mod constant {
                     let result = ctx.builtin_span(&result);
                     Self::Str(result)
                 }
-                token::Kind::Char => Self::Uint(chars.character() as u64, 32),
+                token::Kind::RawString => {
+                    let mut result = String::with_capacity(chars.as_str().len());
+                    chars.raw_string(Some(&mut result));
+                    let result = ctx.builtin_span(&result);
+                    Self::Str(result)
+                }
+                token::Kind::MultilineString => {
+                    let mut result = String::with_capacity(chars.as_str().len());
+                    chars.multiline_string(Some(&mut result));
+                    let result = ctx.builtin_span(&result);
+                    Self::Str(result)
+                }
+                token::Kind::ByteString => {
+                    let mut bytes = Vec::with_capacity(chars.as_str().len());
+                    chars.byte_string(Some(&mut bytes));
+                    let mut elements = EntityList::new();
+                    for byte in bytes {
+                        let element = ctx.constants.push(ConstEnt::new(Self::Uint(byte as u64, 8)));
+                        elements.push(element, &mut ctx.constant_slices);
+                    }
+                    Self::Array(elements)
+                }
+                token::Kind::Char => Self::Char(chars.character()),
                 _ => unreachable!(),
             }
         }
@@ -1789,14 +2901,19 @@ mod constant {
                 Kind::Uint(value, base) => ID(1).add(ID(value as u64)).add(ID(base as u64)),
                 Kind::Float(value, base) => ID(2).add(ID(value.to_bits())).add(ID(base as u64)),
                 Kind::Bool(value) => ID(3).add(ID(value as u64)),
-                Kind::Str(span) => ID(4).add(ctx.hash_span(span)),
+                // string content is compared byte by byte
+                Kind::Str(span) => ID(4).add(ID::new(ctx.display(span))),
                 Kind::Array(elements) => {
                     let mut id = ID(5);
                     for &element in elements.as_slice(&ctx.constant_slices) {
-                        id = id.add(ctx.constants[element].hash(ctx));
+                        id = id.add(ctx.constants[element].kind.hash(ctx));
                     }
                     id
                 }
+                Kind::Enum(ty, variant) => ID(6)
+                    .add(ctx.types[ty].id)
+                    .add(ctx.enum_variants[variant].id),
+                Kind::Char(value) => ID(7).add(ID(value as u64)),
             }
         }
     }
@@ -1815,8 +2932,9 @@ impl<'a> ConstDisplay<'a> {
 
 impl std::fmt::Display for ConstDisplay<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self.ctx.constants[self.constant] {
+        match self.ctx.constants[self.constant].kind {
             constant::Kind::Bool(b) => write!(f, "{}", b),
+            constant::Kind::Char(c) => write!(f, "'{}'", c.escape_default()),
             constant::Kind::Int(i, base) => write!(f, "{}i{}", i, base),
             constant::Kind::Uint(i, base) => write!(f, "{}u{}", i, base),
             constant::Kind::Float(float, base) => write!(f, "{}f{}", float, base),
@@ -1835,20 +2953,16 @@ impl std::fmt::Display for ConstDisplay<'_> {
                 }
                 write!(f, "]")
             }
+            constant::Kind::Enum(ty, variant) => write!(
+                f,
+                "{}::{}",
+                TypeDisplay::new(self.ctx, ty),
+                self.ctx.display(self.ctx.enum_variants[variant].hint.span())
+            ),
         }
     }
 }
 
-impl LexerBase for Chars<'_> {
-    fn next(&mut self) -> Option<char> {
-        Iterator::next(self)
-    }
-
-    fn peek(&self) -> Option<char> {
-        Iterator::next(&mut self.clone())
-    }
-}
-
 impl Default for constant::Kind {
     fn default() -> Self {
         Self::Bool(false)
@@ -1904,4 +3018,22 @@ pub fn test() {
 
         ctx.clear_after_module();
     }
+
+    let root = *order.last().unwrap();
+    for &(name, size) in [
+        ("Color", 1),
+        ("Wide", 2),
+        ("Signed", 1),
+        ("HasEnum", 6),
+        ("Sized", 20),
+        ("Bytes", 8),
+        ("Ints", 16),
+        ("Aliased", 32),
+        ("Held", 4),
+    ]
+    .iter()
+    {
+        let ty = ctx.find_type(root, ID::new(name), Token::default()).unwrap();
+        assert_eq!(ctx.types[ty].size, Size::new(size, size), "{}", name);
+    }
 }
@@ -2,6 +2,10 @@ use std::ops::Deref;
 
 use super::sdbm::ID;
 
+/// Flags that never take a value. They can be written with both `-`
+/// and `--` prefix, so `--check src` does not take `src` as the value.
+const SWITCHES: &[&str] = &["check", "obj"];
+
 #[derive(Debug)]
 pub struct Arguments {
     _filename: String,
@@ -32,6 +36,10 @@ impl Arguments {
         while let Some(mut arg) = args.next() {
             if arg.starts_with("--") {
                 arg.replace_range(0..2, "");
+                if SWITCHES.contains(&arg.as_str()) {
+                    result.flags.push(arg);
+                    continue;
+                }
                 let mut value = args
                     .next()
                     .ok_or_else(|| ArgumentError::MissingValue(arg.clone()))?;
@@ -117,4 +125,9 @@ pub fn test() {
         "{:?}",
         Arguments::new("file \"string value\"".split(" ").map(|s| s.to_string()))
     );
+
+    let args = Arguments::from_str("mf fmt --check src --indent-width 4").unwrap();
+    assert!(args.enabled("check"));
+    assert_eq!(args.get_flag("indent-width"), Some("4"));
+    assert_eq!(args[..], ["fmt", "src"]);
 }
@@ -112,4 +112,5 @@ pub fn test() {
     pool::test();
     cli::test();
     storage::test();
+    sdbm::test();
 }
@@ -1,15 +1,27 @@
 use std::cmp::Ordering;
 
 use quick_proc::RealQuickSer;
+use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Hash, RealQuickSer)]
 pub struct ID(pub u64);
 
 impl ID {
+    /// Hashes bytes of `data` as they are.
     pub fn new(data: &str) -> Self {
         Self(data.sdbm_hash())
     }
 
+    /// Hashes identifier `data` in NFC, so canonically equivalent
+    /// identifiers in different normalization forms have same id.
+    pub fn ident(data: &str) -> Self {
+        if data.is_ascii() || is_nfc_quick(data.chars()) == IsNormalized::Yes {
+            Self::new(data)
+        } else {
+            Self::new(&data.nfc().collect::<String>())
+        }
+    }
+
     #[inline]
     pub fn add(self, id: Self) -> Self {
         ID(id
@@ -72,3 +84,9 @@ impl SdbmHash for &Vec<u8> {
         self.as_slice()
     }
 }
+
+pub fn test() {
+    assert_eq!(ID::ident("caf\u{e9}"), ID::ident("cafe\u{301}"));
+    assert_ne!(ID::ident("cafe"), ID::ident("caf\u{e9}"));
+    assert_ne!(ID::new("caf\u{e9}"), ID::new("cafe\u{301}"));
+}